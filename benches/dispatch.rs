@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rpc2::{method::method, Request, Response, Result, Server, Service};
+use serde_json::json;
+use std::sync::Arc;
+
+const SERVICES: usize = 50;
+
+/// Service that hides the methods of the wrapped service so the server
+/// asks it for every method.
+struct Unlisted(Arc<dyn Service<Data = ()>>);
+
+impl Service for Unlisted {
+    type Data = ();
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.0.handle(request, ctx)
+    }
+}
+
+fn services() -> Vec<Arc<dyn Service<Data = ()>>> {
+    (0..SERVICES)
+        .map(|index| {
+            let name = format!("method_{}", index);
+            let service: Arc<dyn Service<Data = ()>> =
+                Arc::new(method(&name, move |_: (), _: &()| Ok(index)));
+            service
+        })
+        .collect()
+}
+
+fn dispatch(c: &mut Criterion) {
+    let request = Request::new_reply(
+        &format!("method_{}", SERVICES - 1),
+        Some(json!([])),
+    );
+
+    let server = Server::new_shared(services());
+    c.bench_function("dispatch 50 services routed", |b| {
+        b.iter(|| server.serve(black_box(&request), &()).unwrap())
+    });
+
+    let server = Server::new_shared(
+        services()
+            .into_iter()
+            .map(|service| {
+                let service: Arc<dyn Service<Data = ()>> =
+                    Arc::new(Unlisted(service));
+                service
+            })
+            .collect(),
+    );
+    c.bench_function("dispatch 50 services linear", |b| {
+        b.iter(|| server.serve(black_box(&request), &()).unwrap())
+    });
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,37 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rpc2::{from_str, Request, Response};
+
+fn error_response(c: &mut Criterion) {
+    c.bench_function("parse error response", |b| {
+        b.iter(|| {
+            let error =
+                from_str(black_box(r#"{"jsonrpc":"2.0","#)).unwrap_err();
+            let response: Response = error.into();
+            serde_json::to_string(&response).unwrap()
+        })
+    });
+
+    c.bench_function("invalid request response", |b| {
+        b.iter(|| {
+            let error =
+                from_str(black_box(r#"{"jsonrpc":"2.0","id":1}"#)).unwrap_err();
+            let response: Response = error.into();
+            serde_json::to_string(&response).unwrap()
+        })
+    });
+
+    let request = Request::new_reply("missing", None);
+    c.bench_function("method not found response", |b| {
+        b.iter(|| {
+            let error = json_rpc2::Error::MethodNotFound {
+                id: request.id().clone(),
+                name: request.method().to_string(),
+            };
+            let response: Response = (black_box(&request), error).into();
+            serde_json::to_string(&response).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, error_response);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rpc2::{from_slice, from_str, Request};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+const MINIMAL: &str = r#"{"jsonrpc":"2.0","method":"eth_chainId","id":1}"#;
+const TYPICAL: &str = r#"{"jsonrpc":"2.0","id":1,"method":"eth_getBalance","params":["0x407d73d8a49eeb85d32cf465507dd71d507100c1","latest"]}"#;
+
+/// Request fields deserialized with the generic derive as a baseline.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct Derived {
+    jsonrpc: String,
+    method: Arc<str>,
+    id: Option<Value>,
+    params: Option<Value>,
+    #[serde(rename = "_meta", default)]
+    meta: Option<Value>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, payload) in [("minimal", MINIMAL), ("typical", TYPICAL)] {
+        c.bench_function(&format!("parse {} request", name), |b| {
+            b.iter(|| serde_json::from_str::<Request>(black_box(payload)))
+        });
+        c.bench_function(&format!("parse {} request derived", name), |b| {
+            b.iter(|| serde_json::from_str::<Derived>(black_box(payload)))
+        });
+    }
+    c.bench_function("from_str minimal request", |b| {
+        b.iter(|| from_str(black_box(MINIMAL)).unwrap())
+    });
+    c.bench_function("from_slice minimal request", |b| {
+        b.iter(|| from_slice(black_box(MINIMAL.as_bytes())).unwrap())
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,78 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rpc2::{
+    method::method, raw::RawResponse, Request, Response, Result, Server,
+    Service,
+};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Item {
+    index: u32,
+    name: String,
+    tags: Vec<&'static str>,
+}
+
+/// Result of roughly 1 MB when serialized.
+fn items((count,): (u32,), _: &()) -> Result<Vec<Item>> {
+    Ok((0..count)
+        .map(|index| Item {
+            index,
+            name: format!("item-{:032}", index),
+            tags: vec!["alpha", "beta", "gamma"],
+        })
+        .collect())
+}
+
+const COUNT: u32 = 12_000;
+
+fn result(c: &mut Criterion) {
+    let request = Request::new_reply("items", None);
+    let value = items((COUNT,), &()).unwrap();
+    assert!(serde_json::to_vec(&value).unwrap().len() > 1_000_000);
+
+    c.bench_function("result via value", |b| {
+        b.iter(|| {
+            let result = serde_json::to_value(black_box(&value)).unwrap();
+            let response: Response = (&request, result).into();
+            serde_json::to_string(&response).unwrap()
+        })
+    });
+    c.bench_function("result via raw value", |b| {
+        b.iter(|| {
+            let response =
+                RawResponse::new(&request, black_box(&value)).unwrap();
+            serde_json::to_string(&response).unwrap()
+        })
+    });
+    c.bench_function("result written directly", |b| {
+        b.iter(|| {
+            let mut buffer = Vec::new();
+            Response::write_result(&request, black_box(&value), &mut buffer)
+                .unwrap();
+            buffer
+        })
+    });
+}
+
+fn serve(c: &mut Criterion) {
+    let service: Box<dyn Service<Data = ()>> = Box::new(method("items", items));
+    let server = Server::new(vec![&service]);
+    let payload = format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"items","params":[{}]}}"#,
+        COUNT
+    );
+
+    c.bench_function("serve and serialize", |b| {
+        b.iter(|| {
+            let request = json_rpc2::from_str(black_box(&payload)).unwrap();
+            let response = server.serve(&request, &()).unwrap();
+            serde_json::to_string(&response).unwrap()
+        })
+    });
+    c.bench_function("serve_str", |b| {
+        b.iter(|| server.serve_str(black_box(&payload), &()).unwrap())
+    });
+}
+
+criterion_group!(benches, result, serve);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rpc2::{
+    forward::Forwarder, from_str, from_value, from_value_ref,
+    intern::MethodTable,
+};
+use serde_json::{json, Value};
+
+const PAYLOAD: &str = r#"{"jsonrpc":"2.0","id":1,"method":"eth_getBalance","params":["0x407d73d8a49eeb85d32cf465507dd71d507100c1","latest"]}"#;
+
+fn round_trip(c: &mut Criterion) {
+    c.bench_function("parse and serialize", |b| {
+        b.iter(|| {
+            let request = from_str(black_box(PAYLOAD)).unwrap();
+            serde_json::to_string(&request).unwrap()
+        })
+    });
+
+    let table = MethodTable::new().method("eth_getBalance");
+    c.bench_function("parse and serialize interned", |b| {
+        b.iter(|| {
+            let request = table.from_str(black_box(PAYLOAD)).unwrap();
+            serde_json::to_string(&request).unwrap()
+        })
+    });
+}
+
+/// Request body with roughly 100 KB of params.
+fn large_body() -> Value {
+    let items: Vec<Value> = (0..2_000)
+        .map(|i| json!({"index": i, "name": format!("item-{:032}", i)}))
+        .collect();
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_sendBundle",
+        "params": {"items": items},
+    })
+}
+
+fn parse_value(c: &mut Criterion) {
+    let body = large_body();
+    c.bench_function("parse cloned value", |b| {
+        b.iter(|| from_value(black_box(&body).clone()).unwrap())
+    });
+    c.bench_function("parse borrowed value", |b| {
+        b.iter(|| from_value_ref(black_box(&body)).unwrap())
+    });
+}
+
+fn forward(c: &mut Criterion) {
+    let items: Vec<Value> = (0..1_000)
+        .map(|i| json!({"index": i, "name": format!("item-{:032}", i)}))
+        .collect();
+    // Roughly 50 KB of params.
+    let payload = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_sendBundle",
+        "params": {"items": items},
+    })
+    .to_string();
+    let id = json!("upstream-1");
+    c.bench_function("forward reserialized", |b| {
+        b.iter(|| {
+            let mut request = from_str(black_box(&payload)).unwrap();
+            *request.id_mut() = Some(id.clone());
+            serde_json::to_string(&request).unwrap()
+        })
+    });
+    c.bench_function("forward spliced", |b| {
+        b.iter(|| {
+            let forwarder = Forwarder::parse(black_box(&payload)).unwrap();
+            forwarder.with_id(&id).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, round_trip, parse_value, forward);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rpc2::{simd, Request};
+use serde_json::Value;
+
+const MINIMAL: &str = r#"{"jsonrpc":"2.0","method":"eth_chainId","id":1}"#;
+const TYPICAL: &str = r#"{"jsonrpc":"2.0","id":1,"method":"eth_getBalance","params":["0x407d73d8a49eeb85d32cf465507dd71d507100c1","latest"]}"#;
+
+const SYNTAX_ERROR: &str = r#"{"jsonrpc":"2.0","id":1,"method":"eth_getBalance","params":["0x407d73d8a49eeb85d32cf465507dd71d507100c1","latest"}"#;
+const INVALID_REQUEST: &str = r#"{"jsonrpc":"2.0","id":1,"params":["0x407d73d8a49eeb85d32cf465507dd71d507100c1","latest"]}"#;
+
+fn large() -> String {
+    let items: Vec<String> = (0..500)
+        .map(|i| {
+            format!(
+                r#"{{"index":{},"name":"item {}","tags":["a","b","c"],"price":{}.25,"active":true}}"#,
+                i, i, i
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"import","params":[{}]}}"#,
+        items.join(",")
+    )
+}
+
+fn backends(c: &mut Criterion) {
+    let large = large();
+    for (name, payload) in [
+        ("minimal", MINIMAL),
+        ("typical", TYPICAL),
+        ("large", large.as_str()),
+    ] {
+        c.bench_function(&format!("serde_json {} request", name), |b| {
+            b.iter(|| {
+                serde_json::from_slice::<Request>(black_box(payload.as_bytes()))
+                    .unwrap()
+            })
+        });
+        c.bench_function(&format!("simd-json {} request", name), |b| {
+            b.iter(|| simd::from_slice(black_box(payload.as_bytes())).unwrap())
+        });
+    }
+}
+
+/// The work of the `serde_json` backend for a rejected payload, which
+/// parses data errors again to recover the id.
+fn serde_json_error(payload: &[u8]) -> serde_json::Error {
+    let e = serde_json::from_slice::<Request>(payload).unwrap_err();
+    if e.is_data() {
+        let _ = black_box(serde_json::from_slice::<Value>(payload));
+    }
+    e
+}
+
+fn invalid(c: &mut Criterion) {
+    for (name, payload) in [
+        ("syntax error", SYNTAX_ERROR),
+        ("invalid request", INVALID_REQUEST),
+    ] {
+        c.bench_function(&format!("serde_json {}", name), |b| {
+            b.iter(|| serde_json_error(black_box(payload.as_bytes())))
+        });
+        c.bench_function(&format!("simd-json {}", name), |b| {
+            b.iter(|| {
+                simd::from_slice(black_box(payload.as_bytes())).unwrap_err()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, backends, invalid);
+criterion_main!(benches);
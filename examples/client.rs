@@ -0,0 +1,26 @@
+//! Call the `server` example over TCP.
+//!
+//! ```text
+//! cargo run --example client --features net [address]
+//! ```
+use json_rpc2::{futures::Client, net::TcpTransport, Result};
+use serde_json::{json, Value};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7878".to_string());
+    let client = Client::new(TcpTransport::connect(&address).await?);
+
+    let echoed: Value =
+        client.call("echo", Some(json!({"hello": "world"}))).await?;
+    println!("echo: {}", echoed);
+    let sum: f64 = client.call("sum", Some(json!([1, 2, 3.5]))).await?;
+    println!("sum: {}", sum);
+    let slept: u64 = client.call("sleep", Some(json!([250]))).await?;
+    println!("slept: {}ms", slept);
+    let methods: Vec<String> = client.call("rpc.methods", None).await?;
+    println!("methods: {}", methods.join(", "));
+    Ok(())
+}
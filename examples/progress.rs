@@ -0,0 +1,46 @@
+use json_rpc2::{notify::*, *};
+use serde_json::Value;
+
+struct ServiceHandler;
+impl Service for ServiceHandler {
+    type Data = Notifier;
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let response = match request.method() {
+            "hello" => {
+                let params: String = request.deserialize()?;
+                let progress = ctx.progress(request);
+                progress.report(33, "Preparing greeting");
+                progress.report(66, "Formatting greeting");
+                progress.report(100, "Done");
+                let message = format!("Hello, {}!", params);
+                Some((request, Value::String(message)).into())
+            }
+            _ => None,
+        };
+        Ok(response)
+    }
+}
+
+fn main() -> Result<()> {
+    let service: Box<dyn Service<Data = Notifier>> =
+        Box::new(ServiceHandler {});
+    let request =
+        Request::new_reply("hello", Some(Value::String("world".to_string())));
+    let server = Server::new(vec![&service]);
+    let (notifier, notifications) = Notifier::channel();
+    let response = server.serve(&request, &notifier);
+    for notification in notifications.try_iter() {
+        let progress: ProgressParams = notification.deserialize()?;
+        println!("{:?}", progress);
+    }
+    println!("{:?}", response.as_ref().unwrap().result());
+    assert_eq!(
+        Some(Value::String("Hello, world!".to_string())),
+        response.unwrap().into()
+    );
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! TCP server exposing `echo`, `sum` and `sleep`, stop it with Ctrl-C.
+//!
+//! ```text
+//! cargo run --example server --features net [address]
+//! ```
+//!
+//! Call it with the `client` example or by writing newline delimited
+//! requests to the connection, for example with `nc 127.0.0.1 7878`.
+use async_trait::async_trait;
+use json_rpc2::{
+    futures::{Server, Service},
+    net::serve_tcp,
+    Request, Response, Result,
+};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+
+struct DemoService;
+
+#[async_trait]
+impl Service for DemoService {
+    type Data = ();
+    async fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let result = match request.method() {
+            "echo" => request.params().clone().unwrap_or(Value::Null),
+            "sum" => {
+                let values: Vec<f64> = request.deserialize()?;
+                json!(values.iter().sum::<f64>())
+            }
+            "sleep" => {
+                let (millis,): (u64,) = request.deserialize()?;
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                json!(millis)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some((request, result).into()))
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec!["echo".into(), "sum".into(), "sleep".into()]
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7878".to_string());
+    let listener = TcpListener::bind(&address).await?;
+    println!("listening on {}, press Ctrl-C to stop", address);
+
+    let server = Server::new_shared(vec![Arc::new(DemoService)]).with_methods();
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let stats =
+        serve_tcp(Arc::new(server), Arc::new(()), listener, shutdown).await?;
+    println!(
+        "served {} requests with {} errors",
+        stats.requests, stats.errors
+    );
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use async_trait::async_trait;
+use json_rpc2::{
+    futures::*,
+    subscription::{SubscriptionId, Subscriptions},
+    Request, Response, Result,
+};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+type Sink = UnboundedSender<Request>;
+
+struct Connection {
+    subscriptions: Arc<Subscriptions<Sink>>,
+    sink: Sink,
+}
+
+struct ServiceHandler;
+
+#[async_trait]
+impl Service for ServiceHandler {
+    type Data = Connection;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let response = match request.method() {
+            "counter_subscribe" => {
+                let id = ctx.subscriptions.create(ctx.sink.clone());
+                let subscriptions = Arc::clone(&ctx.subscriptions);
+                let subscription = id.clone();
+                tokio::spawn(async move {
+                    let mut interval =
+                        tokio::time::interval(Duration::from_millis(100));
+                    let mut count = 0u64;
+                    loop {
+                        interval.tick().await;
+                        count += 1;
+                        if !subscriptions.notify(&subscription, json!(count)) {
+                            break;
+                        }
+                    }
+                });
+                Some((request, json!(id)).into())
+            }
+            "counter_unsubscribe" => {
+                let (id,): (SubscriptionId,) = request.deserialize()?;
+                let removed = ctx.subscriptions.remove(&id);
+                Some((request, Value::Bool(removed)).into())
+            }
+            _ => None,
+        };
+        Ok(response)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let service: Box<dyn Service<Data = Connection>> =
+        Box::new(ServiceHandler {});
+    let server = Server::new(vec![&service]);
+    let subscriptions = Arc::new(Subscriptions::new("counter_subscription"));
+
+    let (sink, mut notifications) = unbounded_channel();
+    let connection = Connection {
+        subscriptions: Arc::clone(&subscriptions),
+        sink,
+    };
+    let response = server
+        .serve(&Request::new_reply("counter_subscribe", None), &connection)
+        .await
+        .unwrap();
+    let id: SubscriptionId =
+        serde_json::from_value(response.result().clone().unwrap()).unwrap();
+    println!("subscribed {}", id);
+
+    for _ in 0..3 {
+        let notification = notifications.recv().await.unwrap();
+        println!("{:?}", notification.params());
+    }
+
+    let request = Request::new_reply("counter_unsubscribe", Some(json!([id])));
+    let response = server.serve(&request, &connection).await.unwrap();
+    assert_eq!(&Some(Value::Bool(true)), response.result());
+    assert!(subscriptions.is_empty());
+
+    // Dropping the receiver of a connection removes its subscriptions
+    // on the next notification.
+    server
+        .serve(&Request::new_reply("counter_subscribe", None), &connection)
+        .await;
+    drop(notifications);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(subscriptions.is_empty());
+    println!("subscriptions removed");
+    Ok(())
+}
@@ -0,0 +1,302 @@
+//! Procedural macros for the `json-rpc2` crate.
+//!
+//! Enable the `macros` feature of `json-rpc2` and use the re-exported
+//! `json_rpc2::rpc` attribute rather than depending on this crate.
+#![deny(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Error, FnArg, Ident, ItemTrait,
+    LitStr, Pat, ReturnType, TraitItem, TraitItemFn,
+};
+
+struct Method {
+    name: String,
+    ident: Ident,
+    args: Vec<(Ident, syn::Type)>,
+    output: syn::Type,
+}
+
+/// Generate a server and client from a trait definition.
+///
+/// For a trait named `Wallet` this generates a `WalletServer` that
+/// implements `Service` by delegating to an implementation of the
+/// trait, and a `WalletClient` with the same methods that sends
+/// requests using a `Client`.
+///
+/// Methods are named after the function unless a name is given with
+/// `#[rpc(name = "wallet.balance")]`; every method must take `&self`
+/// and return `json_rpc2::Result<T>`. When the methods are `async` the
+/// trait is declared with `async_trait` and the async `Service` and
+/// `Client` are used instead; this requires the `async` feature.
+#[proc_macro_attribute]
+pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "the rpc attribute on a trait does not take arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let item = parse_macro_input!(item as ItemTrait);
+    match expand(item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn method_name(method: &mut TraitItemFn) -> syn::Result<String> {
+    let mut name = method.sig.ident.to_string();
+    let mut error = None;
+    method.attrs.retain(|attr| {
+        if !attr.path().is_ident("rpc") {
+            return true;
+        }
+        let result = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                let value: LitStr = meta.value()?.parse()?;
+                name = value.value();
+                Ok(())
+            } else {
+                Err(meta.error("unsupported rpc attribute"))
+            }
+        });
+        if let Err(e) = result {
+            error = Some(e);
+        }
+        false
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(name),
+    }
+}
+
+fn parse_method(method: &mut TraitItemFn) -> syn::Result<Method> {
+    let name = method_name(method)?;
+    let sig = &method.sig;
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(receiver))
+            if receiver.reference.is_some()
+                && receiver.mutability.is_none() => {}
+        _ => {
+            return Err(Error::new(
+                sig.span(),
+                "rpc methods must take &self as the first argument",
+            ))
+        }
+    }
+    let mut args = Vec::new();
+    for input in inputs {
+        match input {
+            FnArg::Typed(arg) => match &*arg.pat {
+                Pat::Ident(pat) => {
+                    args.push((pat.ident.clone(), (*arg.ty).clone()))
+                }
+                _ => {
+                    return Err(Error::new(
+                        arg.pat.span(),
+                        "rpc method arguments must be identifiers",
+                    ))
+                }
+            },
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new(receiver.span(), "unexpected receiver"))
+            }
+        }
+    }
+    let output = match &sig.output {
+        ReturnType::Type(_, ty) => (**ty).clone(),
+        ReturnType::Default => {
+            return Err(Error::new(
+                sig.span(),
+                "rpc methods must return json_rpc2::Result<T>",
+            ))
+        }
+    };
+    Ok(Method {
+        name,
+        ident: sig.ident.clone(),
+        args,
+        output,
+    })
+}
+
+fn expand(mut item: ItemTrait) -> syn::Result<TokenStream2> {
+    let mut methods = Vec::new();
+    let mut asyncness = None;
+    for trait_item in item.items.iter_mut() {
+        if let TraitItem::Fn(method) = trait_item {
+            let is_async = method.sig.asyncness.is_some();
+            match asyncness {
+                Some(value) if value != is_async => {
+                    return Err(Error::new(
+                        method.sig.span(),
+                        "rpc methods must be all async or all blocking",
+                    ))
+                }
+                _ => asyncness = Some(is_async),
+            }
+            methods.push(parse_method(method)?);
+        }
+    }
+    let is_async = asyncness.unwrap_or(false);
+
+    let vis = &item.vis;
+    let trait_ident = &item.ident;
+    let server = format_ident!("{}Server", trait_ident);
+    let client = format_ident!("{}Client", trait_ident);
+    let server_doc = format!(
+        "Service delegating to an implementation of `{}`.",
+        trait_ident
+    );
+    let client_doc = format!("Client for the methods of `{}`.", trait_ident);
+
+    let (service_path, client_path, transport_path, attribute, await_token) =
+        if is_async {
+            (
+                quote!(::json_rpc2::futures::Service),
+                quote!(::json_rpc2::futures::Client),
+                quote!(::json_rpc2::futures::Transport),
+                quote!(#[::json_rpc2::__async_trait]),
+                quote!(.await),
+            )
+        } else {
+            (
+                quote!(::json_rpc2::Service),
+                quote!(::json_rpc2::client::Client),
+                quote!(::json_rpc2::client::Transport),
+                quote!(),
+                quote!(),
+            )
+        };
+    let asyncness = if is_async { quote!(async) } else { quote!() };
+    let data_bound = if is_async {
+        quote!(T: Send + Sync)
+    } else {
+        quote!(T)
+    };
+
+    let arms = methods.iter().map(|method| {
+        let name = &method.name;
+        let ident = &method.ident;
+        let params = method.args.iter().map(|(arg, ty)| {
+            let arg_name = arg.to_string();
+            quote! {
+                let #arg: #ty = ::json_rpc2::macros::param(
+                    request,
+                    &mut index,
+                    #arg_name,
+                )?;
+            }
+        });
+        let names = method.args.iter().map(|(arg, _)| arg);
+        quote! {
+            #name => {
+                #[allow(unused_mut)]
+                let mut index = 0usize;
+                #(#params)*
+                ::json_rpc2::macros::arity(request, index)?;
+                let value = ::json_rpc2::macros::result(
+                    self.inner.#ident(#(#names),*)#await_token?,
+                )?;
+                Ok(Some((request, value).into()))
+            }
+        }
+    });
+
+    let method_names = methods.iter().map(|method| &method.name);
+
+    let calls = methods.iter().map(|method| {
+        let asyncness = &asyncness;
+        let name = &method.name;
+        let ident = &method.ident;
+        let output = &method.output;
+        let args = method.args.iter().map(|(arg, ty)| quote!(#arg: #ty));
+        let values = method
+            .args
+            .iter()
+            .map(|(arg, _)| quote!(::json_rpc2::macros::arg(&#arg)?));
+        let doc = format!("Call the `{}` method.", name);
+        quote! {
+            #[doc = #doc]
+            pub #asyncness fn #ident(&self, #(#args),*) -> #output {
+                let params = ::json_rpc2::macros::positional(
+                    vec![#(#values),*],
+                );
+                self.client.call(#name, params)#await_token
+            }
+        }
+    });
+
+    Ok(quote! {
+        #attribute
+        #item
+
+        #[doc = #server_doc]
+        #vis struct #server<I, T = ()> {
+            inner: I,
+            marker: ::std::marker::PhantomData<fn() -> T>,
+        }
+
+        impl<I, T> #server<I, T> {
+            /// Create a service for an implementation.
+            pub fn new(inner: I) -> Self {
+                Self {
+                    inner,
+                    marker: ::std::marker::PhantomData,
+                }
+            }
+
+            /// The wrapped implementation.
+            pub fn inner(&self) -> &I {
+                &self.inner
+            }
+        }
+
+        #attribute
+        impl<I: #trait_ident + Send + Sync, #data_bound> #service_path
+            for #server<I, T>
+        {
+            type Data = T;
+            #[allow(unused_variables)]
+            #asyncness fn handle(
+                &self,
+                request: &::json_rpc2::Request,
+                ctx: &Self::Data,
+            ) -> ::json_rpc2::Result<Option<::json_rpc2::Response>> {
+                match request.method() {
+                    #(#arms)*
+                    _ => Ok(None),
+                }
+            }
+
+            fn methods(&self) -> Vec<String> {
+                vec![#(#method_names.to_string()),*]
+            }
+        }
+
+        #[doc = #client_doc]
+        #vis struct #client<C> {
+            client: #client_path<C>,
+        }
+
+        impl<C: #transport_path> #client<C> {
+            /// Create a typed client.
+            pub fn new(client: #client_path<C>) -> Self {
+                Self { client }
+            }
+
+            /// The underlying client.
+            pub fn client(&self) -> &#client_path<C> {
+                &self.client
+            }
+
+            #(#calls)*
+        }
+    })
+}
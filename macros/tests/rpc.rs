@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use json_rpc2::{
+    client::{Client, Transport},
+    futures, rpc, Error, Request, Response, Result, Server, Service,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Transfer {
+    pub from: String,
+    pub to: String,
+    pub amount: u64,
+}
+
+#[rpc]
+pub trait Wallet {
+    #[rpc(name = "wallet.balance")]
+    fn balance(&self, account: String) -> Result<u64>;
+    fn transfer(
+        &self,
+        transfer: Transfer,
+        memo: Option<String>,
+    ) -> Result<bool>;
+    fn version(&self) -> Result<String>;
+}
+
+struct MemoryWallet;
+impl Wallet for MemoryWallet {
+    fn balance(&self, account: String) -> Result<u64> {
+        Ok(account.len() as u64)
+    }
+    fn transfer(
+        &self,
+        transfer: Transfer,
+        _memo: Option<String>,
+    ) -> Result<bool> {
+        Ok(transfer.amount > 0)
+    }
+    fn version(&self) -> Result<String> {
+        Ok("1.0".to_string())
+    }
+}
+
+struct Local(Box<dyn Service<Data = ()>>);
+impl Transport for Local {
+    fn send(&self, request: &Request) -> Result<Option<Response>> {
+        Ok(Server::new(vec![&self.0]).serve(request, &()))
+    }
+}
+
+#[test]
+fn rpc_server_and_client() -> Result<()> {
+    let client = WalletClient::new(Client::new(Local(Box::new(
+        WalletServer::new(MemoryWallet),
+    ))));
+    assert_eq!(5, client.balance("alice".to_string())?);
+    let transfer = Transfer {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 10,
+    };
+    assert!(client.transfer(transfer, None)?);
+    assert_eq!("1.0", client.version()?);
+    Ok(())
+}
+
+#[test]
+fn rpc_methods() {
+    let service: Box<dyn Service<Data = ()>> =
+        Box::new(WalletServer::new(MemoryWallet));
+    assert_eq!(
+        vec!["transfer", "version", "wallet.balance"],
+        Server::new(vec![&service]).methods()
+    );
+}
+
+#[test]
+fn rpc_named_and_invalid_params() {
+    let service: Box<dyn Service<Data = ()>> =
+        Box::new(WalletServer::new(MemoryWallet));
+    let server = Server::new(vec![&service]);
+
+    let request =
+        Request::new_reply("wallet.balance", Some(json!({"account": "bob"})));
+    let response = server.serve(&request, &());
+    assert_eq!(Some(json!(3)), response.unwrap().into());
+
+    let request = Request::new_reply("wallet.balance", Some(json!([1])));
+    let response = server.serve(&request, &());
+    match response.unwrap().into_result() {
+        Err(Error::Rpc(error)) => {
+            assert_eq!(-32602, error.code);
+            // serde_json words the type differently with the
+            // arbitrary_precision feature
+            let data = error.data.unwrap();
+            let data = data.as_str().unwrap();
+            assert!(data.starts_with("params.account: invalid type: "));
+            assert!(data.ends_with(", expected a string"));
+        }
+        _ => panic!("expected invalid params"),
+    }
+
+    let request = Request::new_reply("balance", None);
+    let response = server.serve(&request, &());
+    assert_eq!(-32601, response.unwrap().error().as_ref().unwrap().code);
+}
+
+#[rpc]
+pub trait Greeter {
+    async fn greet(&self, name: String) -> Result<String>;
+}
+
+struct English;
+
+#[async_trait]
+impl Greeter for English {
+    async fn greet(&self, name: String) -> Result<String> {
+        Ok(format!("Hello, {}!", name))
+    }
+}
+
+struct AsyncLocal(Box<dyn futures::Service<Data = ()>>);
+
+#[async_trait]
+impl futures::Transport for AsyncLocal {
+    async fn send(&self, request: &Request) -> Result<Option<Response>> {
+        Ok(futures::Server::new(vec![&self.0])
+            .serve(request, &())
+            .await)
+    }
+}
+
+#[tokio::test]
+async fn rpc_async() -> Result<()> {
+    let client = GreeterClient::new(futures::Client::new(AsyncLocal(
+        Box::new(GreeterServer::new(English)),
+    )));
+    assert_eq!("Hello, world!", client.greet("world".to_string()).await?);
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! Send a request to several upstream servers and combine the answers.
+//!
+//! An [Aggregate](Aggregate) service dispatches every request to all
+//! of its upstream transports concurrently and replies according to a
+//! [Strategy](Strategy). Each upstream either yields its result value
+//! or an [RpcError](crate::RpcError) describing why it failed: the
+//! error response it sent, a transport error or a timeout.
+//!
+//! When the strategy cannot produce a result the error response has
+//! the internal error code, or the code set with `error_code()`, and
+//! the data is an array with an entry for each upstream in order:
+//! either `{"result": ...}` or `{"error": ...}`.
+//!
+//! Notifications are sent to every upstream and never answered.
+//!
+//! Only available with the `async` feature.
+
+use crate::{
+    futures::{Service, Transport},
+    Request, Response, Result, RpcError, INTERNAL_ERROR,
+};
+use async_trait::async_trait;
+use futures_util::{
+    future::join_all,
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde_json::{json, Value};
+use std::{marker::PhantomData, time::Duration};
+
+/// The outcome of sending a request to one upstream.
+pub type Outcome = std::result::Result<Value, RpcError>;
+
+/// Function combining the outcome of every upstream into a result.
+pub type Combine = dyn Fn(Vec<Outcome>) -> Result<Value> + Send + Sync;
+
+/// How the upstream outcomes are turned into a single response.
+pub enum Strategy {
+    /// Reply with the first successful result to arrive.
+    ///
+    /// Fails when every upstream fails.
+    FirstSuccess,
+    /// Reply with the result returned by more than half of the
+    /// upstreams.
+    ///
+    /// Fails when no result has a majority; failed upstreams count
+    /// against every result.
+    Majority,
+    /// Wait for every upstream and combine the outcomes, in the order
+    /// of the upstreams, with a function.
+    ///
+    /// Partial failures are passed to the function so it may report
+    /// them in the result; an error returned by the function is the
+    /// error for the request.
+    All(Box<Combine>),
+}
+
+/// Service that fans a request out to several upstream servers.
+pub struct Aggregate<T> {
+    upstreams: Vec<Box<dyn Transport>>,
+    strategy: Strategy,
+    timeout: Option<Duration>,
+    error_code: isize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Aggregate<T> {
+    /// Create an aggregate of upstream transports.
+    pub fn new(upstreams: Vec<Box<dyn Transport>>, strategy: Strategy) -> Self {
+        Self {
+            upstreams,
+            strategy,
+            timeout: None,
+            error_code: INTERNAL_ERROR,
+            marker: PhantomData,
+        }
+    }
+
+    /// Treat an upstream as failed when it has not responded
+    /// after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the error code used when the strategy fails.
+    pub fn error_code(mut self, code: isize) -> Self {
+        self.error_code = code;
+        self
+    }
+
+    async fn send(&self, index: usize, request: &Request) -> (usize, Outcome) {
+        let upstream = &self.upstreams[index];
+        let result = match self.timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, upstream.send(request))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(crate::Error::Timeout {
+                            method: Some(request.method().to_string()),
+                            elapsed: Some(timeout),
+                            id: request.id().clone(),
+                        })
+                    })
+            }
+            None => upstream.send(request).await,
+        };
+        let outcome = match result {
+            Ok(Some(response)) => response.into_result().map_err(|e| match e {
+                crate::Error::Rpc(error) => error,
+                e => RpcError::new(e.to_string(), None),
+            }),
+            Ok(None) => Err(RpcError::new("No response".to_string(), None)),
+            Err(e) => Err(RpcError::new(e.to_string(), None)),
+        };
+        (index, outcome)
+    }
+
+    /// Send to every upstream and collect the outcomes in order.
+    ///
+    /// With the first success strategy collection stops at the
+    /// first successful result which is returned on its own.
+    async fn dispatch(&self, request: &Request) -> Vec<Outcome> {
+        let mut pending: FuturesUnordered<_> = (0..self.upstreams.len())
+            .map(|index| self.send(index, request))
+            .collect();
+        let mut outcomes = vec![None; self.upstreams.len()];
+        while let Some((index, outcome)) = pending.next().await {
+            if let (Strategy::FirstSuccess, Ok(_)) = (&self.strategy, &outcome)
+            {
+                return vec![outcome];
+            }
+            outcomes[index] = Some(outcome);
+        }
+        outcomes.into_iter().flatten().collect()
+    }
+
+    fn failure(&self, message: &str, outcomes: &[Outcome]) -> RpcError {
+        let data = outcomes
+            .iter()
+            .map(|outcome| match outcome {
+                Ok(result) => json!({ "result": result }),
+                Err(error) => json!({ "error": error }),
+            })
+            .collect();
+        RpcError {
+            code: self.error_code,
+            message: message.to_string().into(),
+            data: Some(Value::Array(data)),
+        }
+    }
+
+    fn combine(&self, mut outcomes: Vec<Outcome>) -> Result<Value> {
+        match &self.strategy {
+            Strategy::FirstSuccess => match outcomes.as_slice() {
+                [Ok(_)] => Ok(outcomes.remove(0).unwrap()),
+                _ => Err(crate::Error::Rpc(
+                    self.failure("All upstream requests failed", &outcomes),
+                )),
+            },
+            Strategy::Majority => {
+                let successes: Vec<&Value> =
+                    outcomes.iter().filter_map(|o| o.as_ref().ok()).collect();
+                let majority = successes.iter().find(|result| {
+                    successes.iter().filter(|other| other == result).count() * 2
+                        > outcomes.len()
+                });
+                match majority {
+                    Some(result) => Ok(Value::clone(result)),
+                    None => Err(crate::Error::Rpc(self.failure(
+                        "No majority among upstream responses",
+                        &outcomes,
+                    ))),
+                }
+            }
+            Strategy::All(combine) => combine(outcomes),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync> Service for Aggregate<T> {
+    type Data = T;
+
+    async fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        if request.id().is_none() {
+            join_all(self.upstreams.iter().map(|u| u.send(request))).await;
+            return Ok(Some(request.into()));
+        }
+        let outcomes = self.dispatch(request).await;
+        let result = self.combine(outcomes)?;
+        Ok(Some((request, result).into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::Server;
+
+    /// Upstream replying with a fixed outcome after a delay.
+    struct Fixed(std::result::Result<Value, &'static str>, u64);
+
+    #[async_trait]
+    impl Transport for Fixed {
+        async fn send(&self, request: &Request) -> Result<Option<Response>> {
+            tokio::time::sleep(Duration::from_secs(self.1)).await;
+            match &self.0 {
+                Ok(result) => Ok(Some((request, result.clone()).into())),
+                Err(message) => Err(crate::Error::from(Box::from(*message))),
+            }
+        }
+    }
+
+    fn upstreams(
+        outcomes: Vec<(std::result::Result<Value, &'static str>, u64)>,
+    ) -> Vec<Box<dyn Transport>> {
+        outcomes
+            .into_iter()
+            .map(|(outcome, delay)| {
+                Box::new(Fixed(outcome, delay)) as Box<dyn Transport>
+            })
+            .collect()
+    }
+
+    async fn serve(service: Aggregate<()>) -> Response {
+        let service: Box<dyn Service<Data = ()>> = Box::new(service);
+        let server = Server::new(vec![&service]);
+        let request = Request::new_reply("balance", None);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(request.id(), response.id());
+        response
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aggregate_first_success() {
+        let service = Aggregate::new(
+            upstreams(vec![
+                (Ok(json!("slow")), 5),
+                (Err("Connection refused"), 0),
+                (Ok(json!("fast")), 1),
+            ]),
+            Strategy::FirstSuccess,
+        );
+        let response = serve(service).await;
+        assert_eq!(&Some(json!("fast")), response.result());
+
+        let service = Aggregate::new(
+            upstreams(vec![(Err("Connection refused"), 0), (Ok(json!(1)), 5)]),
+            Strategy::FirstSuccess,
+        )
+        .timeout(Duration::from_secs(1))
+        .error_code(-32099);
+        let error = serve(service).await.error().clone().unwrap();
+        assert_eq!(-32099, error.code);
+        assert_eq!(
+            Some(json!([
+                {"error": {"code": INTERNAL_ERROR, "message": "Connection refused"}},
+                {"error": {
+                    "code": INTERNAL_ERROR,
+                    "message": "Call to balance timed out after 1s"
+                }},
+            ])),
+            error.data
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aggregate_majority() {
+        let service = Aggregate::new(
+            upstreams(vec![
+                (Ok(json!(10)), 0),
+                (Ok(json!(9)), 0),
+                (Ok(json!(10)), 0),
+            ]),
+            Strategy::Majority,
+        );
+        assert_eq!(&Some(json!(10)), serve(service).await.result());
+
+        let service = Aggregate::new(
+            upstreams(vec![
+                (Ok(json!(10)), 0),
+                (Err("Connection refused"), 0),
+                (Ok(json!(9)), 0),
+            ]),
+            Strategy::Majority,
+        );
+        let error = serve(service).await.error().clone().unwrap();
+        assert_eq!("No majority among upstream responses", error.message);
+        assert_eq!(&json!({"result": 9}), &error.data.unwrap()[2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aggregate_all() {
+        let service = Aggregate::new(
+            upstreams(vec![
+                (Ok(json!(1)), 2),
+                (Err("Connection refused"), 0),
+                (Ok(json!(2)), 1),
+            ]),
+            Strategy::All(Box::new(|outcomes| {
+                let total: u64 = outcomes
+                    .iter()
+                    .filter_map(|o| o.as_ref().ok()?.as_u64())
+                    .sum();
+                let failed = outcomes.iter().filter(|o| o.is_err()).count();
+                Ok(json!({"total": total, "failed": failed}))
+            })),
+        );
+        assert_eq!(
+            &Some(json!({"total": 3, "failed": 1})),
+            serve(service).await.result()
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! Per-method authorization by scope.
+//!
+//! An [AuthzPolicy](AuthzPolicy) lists the scopes each method requires.
+//! A server configured with
+//! [with_authz()](crate::Server::with_authz) compares them against the
+//! scopes of the caller before the services are called and answers a
+//! request missing a scope with a `-32001` forbidden error naming the
+//! missing scopes in the data:
+//!
+//! ```
+//! use json_rpc2::{
+//!     authz::{AuthzPolicy, Scopes, FORBIDDEN},
+//!     method::method,
+//!     Request, Result, RpcError, Server, Service,
+//! };
+//! use serde_json::json;
+//!
+//! fn reset(_: (), _: &Scopes) -> Result<bool> {
+//!     Ok(true)
+//! }
+//!
+//! let service: Box<dyn Service<Data = Scopes>> =
+//!     Box::new(method("admin.reset", reset));
+//! let policy = AuthzPolicy::new().require("admin.*", &["admin"]);
+//! let server = Server::new(vec![&service]).with_authz(policy);
+//!
+//! let request = Request::new_reply("admin.reset", None);
+//! let response = server.serve(&request, &Scopes::new(["admin"]));
+//! assert_eq!(Some(json!(true)), response.unwrap().into());
+//!
+//! let response = server.serve(&request, &Scopes::new(["user"]));
+//! let error: Option<RpcError> = response.unwrap().into();
+//! let error = error.unwrap();
+//! assert_eq!(FORBIDDEN, error.code);
+//! assert_eq!(Some(json!({"method": "admin.reset", "missing": ["admin"]})), error.data);
+//! ```
+//!
+//! The scopes of the caller come from the context, which implements
+//! [HasScopes](HasScopes), or from [Scopes](Scopes) a transport placed
+//! in the [extensions](crate::extensions) of the request; a scope held
+//! by either is granted.
+//!
+//! ## Rules
+//!
+//! A rule names a method or, ending in `.*`, every method under a
+//! prefix: `admin.*` matches `admin.reset` and `admin.users.delete`
+//! but not `admin` or `administer`. A single `*` matches every method.
+//! The rule for a method is chosen by precedence:
+//!
+//! 1. A rule for the exact method.
+//! 2. The matching wildcard with the longest prefix, so `admin.users.*`
+//!    applies to `admin.users.delete` before `admin.*`.
+//! 3. The `*` wildcard.
+//!
+//! Requiring a pattern again replaces its scopes. A rule with no scopes
+//! makes methods public. Methods without a rule are allowed unless the
+//! policy is created with [deny_unlisted()](AuthzPolicy::deny_unlisted).
+
+use crate::{extensions::Extensions, Request, RpcError};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+/// Error code for requests missing a required scope.
+pub const FORBIDDEN: isize = -32001;
+
+/// Create the forbidden error for `method` missing the `missing`
+/// scopes.
+pub fn forbidden(method: &str, missing: &[&str]) -> RpcError {
+    RpcError {
+        code: FORBIDDEN,
+        message: "Forbidden".into(),
+        data: Some(json!({"method": method, "missing": missing})),
+    }
+}
+
+/// Extracts the scopes granted to the caller from a context.
+pub trait HasScopes {
+    /// Whether the caller holds `scope`.
+    fn has_scope(&self, scope: &str) -> bool;
+}
+
+impl HasScopes for () {
+    fn has_scope(&self, _scope: &str) -> bool {
+        false
+    }
+}
+
+impl HasScopes for HashSet<String> {
+    fn has_scope(&self, scope: &str) -> bool {
+        self.contains(scope)
+    }
+}
+
+impl HasScopes for Vec<String> {
+    fn has_scope(&self, scope: &str) -> bool {
+        self.iter().any(|held| held == scope)
+    }
+}
+
+/// Set of scopes, usable as the context or placed in the request
+/// extensions by a transport.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Scopes(HashSet<String>);
+
+impl Scopes {
+    /// Create a set of scopes.
+    pub fn new<I, S>(scopes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(scopes.into_iter().map(Into::into).collect())
+    }
+
+    /// The scopes a transport placed in the extensions of a request.
+    pub fn of(extensions: &Extensions) -> Option<&Scopes> {
+        extensions.get::<Scopes>()
+    }
+}
+
+impl HasScopes for Scopes {
+    fn has_scope(&self, scope: &str) -> bool {
+        self.0.contains(scope)
+    }
+}
+
+/// Treatment of methods without a rule.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Unlisted {
+    /// Allow the call.
+    #[default]
+    Allow,
+    /// Answer with a forbidden error.
+    Deny,
+}
+
+/// Scopes required by each method, see the [module](self) for the
+/// precedence of rules.
+#[derive(Debug, Clone, Default)]
+pub struct AuthzPolicy {
+    methods: HashMap<String, Vec<String>>,
+    /// Wildcard prefixes including the trailing dot, longest first.
+    prefixes: Vec<(String, Vec<String>)>,
+    unlisted: Unlisted,
+}
+
+impl AuthzPolicy {
+    /// Create a policy that allows methods without a rule.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create a policy that forbids methods without a rule.
+    pub fn deny_unlisted() -> Self {
+        Self {
+            unlisted: Unlisted::Deny,
+            ..Default::default()
+        }
+    }
+
+    /// Require every one of `scopes` for the methods matching
+    /// `pattern`, a method name, a prefix ending in `.*` or `*`.
+    pub fn require(mut self, pattern: &str, scopes: &[&str]) -> Self {
+        let scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+        let prefix = match pattern {
+            "*" => Some(""),
+            _ => pattern.strip_suffix('*').filter(|p| p.ends_with('.')),
+        };
+        match prefix {
+            Some(prefix) => {
+                self.prefixes.retain(|(existing, _)| existing != prefix);
+                self.prefixes.push((prefix.to_string(), scopes));
+                self.prefixes
+                    .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+            }
+            None => {
+                self.methods.insert(pattern.to_string(), scopes);
+            }
+        }
+        self
+    }
+
+    /// The treatment of methods without a rule.
+    pub fn unlisted(&self) -> Unlisted {
+        self.unlisted
+    }
+
+    /// The scopes required for `method`, `None` when no rule matches.
+    pub fn required(&self, method: &str) -> Option<&[String]> {
+        if let Some(scopes) = self.methods.get(method) {
+            return Some(scopes);
+        }
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| method.starts_with(prefix.as_str()))
+            .map(|(_, scopes)| scopes.as_slice())
+    }
+
+    /// Check that the caller holds the scopes required for a request.
+    ///
+    /// Scopes are taken from the context and from [Scopes](Scopes) in
+    /// the extensions of the request.
+    pub fn check<T: HasScopes>(
+        &self,
+        request: &Request,
+        ctx: &T,
+    ) -> Result<(), RpcError> {
+        let method = request.method();
+        let required = match self.required(method) {
+            Some(required) => required,
+            None if self.unlisted == Unlisted::Allow => return Ok(()),
+            None => return Err(forbidden(method, &[])),
+        };
+        let extensions = Scopes::of(request.extensions());
+        let missing: Vec<&str> = required
+            .iter()
+            .map(String::as_str)
+            .filter(|scope| {
+                !ctx.has_scope(scope)
+                    && !extensions.is_some_and(|held| held.has_scope(scope))
+            })
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(forbidden(method, &missing))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Response, Server, Service};
+
+    fn allowed(policy: &AuthzPolicy, method: &str, scopes: &[&str]) -> bool {
+        let request = Request::new_reply(method, None);
+        policy
+            .check(&request, &Scopes::new(scopes.iter().copied()))
+            .is_ok()
+    }
+
+    #[test]
+    fn authz_wildcard_precedence() {
+        let policy = AuthzPolicy::new()
+            .require("*", &["user"])
+            .require("admin.*", &["admin"])
+            .require("admin.users.*", &["users"])
+            .require("admin.users.list", &[])
+            .require("admin.reset", &["root"]);
+
+        assert_eq!(Some(&["user".to_string()][..]), policy.required("ping"));
+        assert!(allowed(&policy, "ping", &["user"]));
+        assert!(!allowed(&policy, "ping", &[]));
+
+        // Longest prefix before shorter prefixes and the catch all
+        assert!(allowed(&policy, "admin.stats", &["admin"]));
+        assert!(!allowed(&policy, "admin.stats", &["user"]));
+        assert!(allowed(&policy, "admin.users.delete", &["users"]));
+        assert!(!allowed(&policy, "admin.users.delete", &["admin"]));
+
+        // Exact rules before every wildcard
+        assert!(allowed(&policy, "admin.users.list", &[]));
+        assert!(allowed(&policy, "admin.reset", &["root"]));
+        assert!(!allowed(&policy, "admin.reset", &["admin"]));
+
+        // A prefix only matches whole segments
+        assert!(allowed(&policy, "admin", &["user"]));
+        assert!(allowed(&policy, "administer", &["user"]));
+        assert!(!allowed(&policy, "administer", &["admin"]));
+
+        // Requiring a pattern again replaces it
+        let policy = policy.require("admin.*", &["ops"]);
+        assert!(allowed(&policy, "admin.stats", &["ops"]));
+        assert!(!allowed(&policy, "admin.stats", &["admin"]));
+    }
+
+    #[test]
+    fn authz_unlisted() {
+        let policy = AuthzPolicy::new().require("admin.*", &["admin"]);
+        assert_eq!(Unlisted::Allow, policy.unlisted());
+        assert!(allowed(&policy, "ping", &[]));
+
+        let policy = AuthzPolicy::deny_unlisted()
+            .require("admin.*", &["admin"])
+            .require("ping", &[]);
+        assert_eq!(Unlisted::Deny, policy.unlisted());
+        assert!(allowed(&policy, "ping", &[]));
+        assert!(allowed(&policy, "admin.stats", &["admin"]));
+        let request = Request::new_reply("other", None);
+        assert_eq!(
+            Err(forbidden("other", &[])),
+            policy.check(&request, &Scopes::default())
+        );
+    }
+
+    struct Echo;
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> crate::Result<Option<Response>> {
+            Ok(Some((request, json!(request.method())).into()))
+        }
+    }
+
+    #[test]
+    fn authz_server_extensions() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let policy =
+            AuthzPolicy::new().require("admin.reset", &["admin", "ops"]);
+        let server = Server::new(vec![&service]).with_authz(policy);
+
+        let mut request = Request::new_reply("admin.reset", None);
+        request.extensions_mut().insert(Scopes::new(["ops"]));
+        let response = server.serve(&request, &()).unwrap();
+        let error: Option<RpcError> = response.into();
+        assert_eq!(Some(forbidden("admin.reset", &["admin"])), error);
+
+        request
+            .extensions_mut()
+            .insert(Scopes::new(["admin", "ops"]));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(Some(json!("admin.reset")), response.into());
+
+        let notification = Request::new_notification("admin.reset", None);
+        assert!(server.serve(&notification, &()).is_none());
+    }
+
+    #[tokio::test]
+    async fn authz_async_server() {
+        use crate::futures;
+
+        struct AsyncEcho;
+        #[async_trait::async_trait]
+        impl futures::Service for AsyncEcho {
+            type Data = Vec<String>;
+            async fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> crate::Result<Option<Response>> {
+                Ok(Some((request, json!(true)).into()))
+            }
+        }
+
+        let service: Box<dyn futures::Service<Data = Vec<String>>> =
+            Box::new(AsyncEcho);
+        let policy =
+            AuthzPolicy::deny_unlisted().require("admin.*", &["admin"]);
+        let server = futures::Server::new(vec![&service]).with_authz(policy);
+
+        let request = Request::new_reply("admin.reset", None);
+        let ctx = vec!["admin".to_string()];
+        let response = server.serve(&request, &ctx).await.unwrap();
+        assert_eq!(Some(json!(true)), response.into());
+
+        let request = Request::new_reply("other", None);
+        let response = server.serve(&request, &ctx).await.unwrap();
+        let error: Option<RpcError> = response.into();
+        assert_eq!(FORBIDDEN, error.unwrap().code);
+    }
+}
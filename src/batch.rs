@@ -0,0 +1,493 @@
+//! Utilities for batches of requests and responses.
+//!
+//! Servers answer a batch with `serve_batch()`; set a
+//! [DuplicateIds](DuplicateIds) policy with `with_duplicate_ids()` to
+//! detect calls in a batch that share an id and a
+//! [BatchPolicy](BatchPolicy) with `with_batch_policy()` to stop
+//! serving a batch after an error. Limit the size of a batch with
+//! `with_max_batch()`.
+//!
+//! The async server reports the outcome and time taken by every element
+//! of a batch, notifications included, with `serve_batch_report()`.
+
+use crate::{Error, Request, Response, RpcError};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// Error code for calls skipped because an earlier call in the
+/// batch failed.
+pub const BATCH_SKIPPED: isize = -32001;
+
+/// How a server treats the elements of a batch after an error.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum BatchPolicy {
+    /// Every element is served regardless of the others.
+    #[default]
+    Independent,
+    /// After the first error response the remaining calls are answered
+    /// with a `-32001` error without being served and the remaining
+    /// notifications are dropped.
+    ///
+    /// The async server serves the batch sequentially with this policy
+    /// so that no element runs after an earlier one fails.
+    AbortOnError,
+}
+
+/// Outcome of serving an element of a batch.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ItemOutcome {
+    /// The element was answered with a result.
+    Success,
+    /// The element was answered with an error with this code, such as
+    /// method not found. For a notification the error is not sent.
+    Error(isize),
+    /// The element was not served because an earlier one failed.
+    Skipped,
+}
+
+impl ItemOutcome {
+    /// The outcome for the response to an element.
+    pub(crate) fn of(response: &Response) -> Self {
+        match response.error() {
+            Some(error) => ItemOutcome::Error(error.code),
+            None => ItemOutcome::Success,
+        }
+    }
+}
+
+/// How one element of a batch was served.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchItemReport {
+    /// Position of the element in the batch.
+    pub index: usize,
+    /// Method name of the element.
+    pub method: String,
+    /// Whether the element is a notification, which has no response in
+    /// the batch.
+    pub notification: bool,
+    /// Time taken to serve the element.
+    pub elapsed: Duration,
+    /// Outcome of the element.
+    pub outcome: ItemOutcome,
+}
+
+/// Response for an element skipped after an earlier failure,
+/// notifications are not answered.
+pub(crate) fn skipped_response(request: &Request) -> Option<Response> {
+    request.id().as_ref()?;
+    let error = RpcError {
+        code: BATCH_SKIPPED,
+        message: "skipped due to earlier failure".into(),
+        data: None,
+    };
+    Some((request, error).into())
+}
+
+/// Error for a batch with more than `max` requests, which is answered
+/// without serving any of them.
+pub(crate) fn too_large(
+    requests: &[Request],
+    max: Option<usize>,
+) -> Option<Response> {
+    let max = max?;
+    if requests.len() <= max {
+        return None;
+    }
+    let error = Error::InvalidRequest {
+        id: None,
+        data: format!(
+            "batch of {} requests exceeds the limit of {}",
+            requests.len(),
+            max
+        ),
+        line: None,
+        column: None,
+        offset: None,
+    };
+    Some(error.into())
+}
+
+/// Callback invoked with each call that reuses an id in a batch.
+pub type DuplicateHandler = dyn Fn(&Request) + Send + Sync;
+
+/// How a server treats calls in a batch that reuse the id of an
+/// earlier call.
+///
+/// Ids are compared by their JSON representation so `1` and `"1"` are
+/// distinct; notifications and calls with a `null` id never conflict.
+#[derive(Default)]
+pub enum DuplicateIds {
+    /// Serve every call.
+    #[default]
+    Allow,
+    /// Serve every call, logging each duplicate and passing it
+    /// to a callback.
+    Warn(Box<DuplicateHandler>),
+    /// Serve the first call with an id and answer later calls with
+    /// that id with an invalid request error.
+    Reject,
+}
+
+impl DuplicateIds {
+    /// Indices of the requests to answer with an error.
+    pub(crate) fn rejected(&self, requests: &[Request]) -> HashSet<usize> {
+        match self {
+            DuplicateIds::Allow => HashSet::new(),
+            DuplicateIds::Warn(handler) => {
+                for index in duplicate_ids(requests) {
+                    let request = &requests[index];
+                    log::warn!(
+                        "duplicate id {} in batch for {}",
+                        request.id().as_ref().unwrap_or(&Value::Null),
+                        request.method()
+                    );
+                    handler(request);
+                }
+                HashSet::new()
+            }
+            DuplicateIds::Reject => {
+                duplicate_ids(requests).into_iter().collect()
+            }
+        }
+    }
+}
+
+/// Indices of the calls in a batch whose id was used by an earlier
+/// call.
+pub fn duplicate_ids(requests: &[Request]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    requests
+        .iter()
+        .enumerate()
+        .filter_map(|(index, request)| match request.id() {
+            Some(id) if !id.is_null() => {
+                (!seen.insert(id.to_string())).then_some(index)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Error response for a call rejected as a duplicate.
+pub(crate) fn duplicate_response(request: &Request) -> Response {
+    let error = Error::InvalidRequest {
+        id: request.id().clone(),
+        data: "duplicate id in batch".to_string(),
+        line: None,
+        column: None,
+        offset: None,
+    };
+    (request, error).into()
+}
+
+/// Outcome of matching a single request in a batch.
+#[derive(Debug, PartialEq)]
+pub enum BatchMatch {
+    /// Response with the same id as the request.
+    Matched(Response),
+    /// A request expecting a reply that has no response.
+    MissingResponse,
+    /// The request was a notification so no response was expected.
+    Notification,
+}
+
+impl BatchMatch {
+    /// The matched response, if any.
+    pub fn response(&self) -> Option<&Response> {
+        match self {
+            BatchMatch::Matched(response) => Some(response),
+            _ => None,
+        }
+    }
+}
+
+/// Result of pairing batch responses with their requests.
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchOutcome {
+    /// Match for each request in the same order as the requests.
+    pub matches: Vec<BatchMatch>,
+    /// Responses that could not be paired with a request.
+    ///
+    /// Includes responses with a null id (parse and invalid request
+    /// errors), responses for unknown ids and surplus responses for
+    /// an id that was already matched.
+    pub unmatched: Vec<Response>,
+    /// Ids that appear on more than one request.
+    pub duplicate_request_ids: Vec<Value>,
+    /// Ids that appear on more than one response.
+    pub duplicate_response_ids: Vec<Value>,
+}
+
+impl BatchOutcome {
+    /// Determine if every request expecting a reply was matched
+    /// and there are no stray responses or duplicate ids.
+    pub fn is_complete(&self) -> bool {
+        self.unmatched.is_empty()
+            && self.duplicate_request_ids.is_empty()
+            && self.duplicate_response_ids.is_empty()
+            && !self
+                .matches
+                .iter()
+                .any(|m| matches!(m, BatchMatch::MissingResponse))
+    }
+}
+
+/// Pair batch responses with the requests that produced them.
+///
+/// Responses may arrive in any order and are matched by id, ids are
+/// compared by their JSON representation so `1` and `"1"` are distinct.
+/// When several requests share an id they are matched with responses
+/// carrying that id in order.
+///
+/// A request whose id is `null` is treated as expecting a reply but can
+/// never be matched because a `null` response id means the server could
+/// not determine the request id.
+pub fn match_batch(
+    requests: &[Request],
+    responses: Vec<Response>,
+) -> BatchOutcome {
+    let mut outcome = BatchOutcome::default();
+    let mut pending: HashMap<String, VecDeque<usize>> = HashMap::new();
+    let mut seen_requests = HashSet::new();
+    let mut seen_responses = HashSet::new();
+
+    for (index, request) in requests.iter().enumerate() {
+        match request.id() {
+            Some(id) => {
+                outcome.matches.push(BatchMatch::MissingResponse);
+                if id.is_null() {
+                    continue;
+                }
+                let key = id.to_string();
+                if !seen_requests.insert(key.clone())
+                    && !outcome.duplicate_request_ids.contains(id)
+                {
+                    outcome.duplicate_request_ids.push(id.clone());
+                }
+                pending.entry(key).or_default().push_back(index);
+            }
+            None => outcome.matches.push(BatchMatch::Notification),
+        }
+    }
+
+    for response in responses {
+        let id = match response.id() {
+            Some(id) if !id.is_null() => id.clone(),
+            _ => {
+                outcome.unmatched.push(response);
+                continue;
+            }
+        };
+        let key = id.to_string();
+        if !seen_responses.insert(key.clone())
+            && !outcome.duplicate_response_ids.contains(&id)
+        {
+            outcome.duplicate_response_ids.push(id);
+        }
+        match pending.get_mut(&key).and_then(|queue| queue.pop_front()) {
+            Some(index) => {
+                outcome.matches[index] = BatchMatch::Matched(response)
+            }
+            None => outcome.unmatched.push(response),
+        }
+    }
+
+    outcome
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RpcError, Server, Service, INVALID_REQUEST};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn call(id: Value) -> Request {
+        Request::new(Some(id), "call".to_string(), None)
+    }
+
+    fn reply(request: &Request) -> Response {
+        (request, Value::Bool(true)).into()
+    }
+
+    #[test]
+    fn batch_out_of_order() {
+        let requests = vec![
+            call(json!(1)),
+            Request::new_notification("notify", None),
+            call(json!("2")),
+            call(json!(3)),
+        ];
+        let responses = vec![reply(&requests[3]), reply(&requests[0])];
+        let outcome = match_batch(&requests, responses);
+        assert_eq!(
+            vec![
+                BatchMatch::Matched(reply(&requests[0])),
+                BatchMatch::Notification,
+                BatchMatch::MissingResponse,
+                BatchMatch::Matched(reply(&requests[3])),
+            ],
+            outcome.matches
+        );
+        assert!(outcome.unmatched.is_empty());
+        assert!(!outcome.is_complete());
+    }
+
+    #[test]
+    fn batch_strays() {
+        let requests = vec![call(json!(1))];
+        let parse_error: Response = Error::Parse {
+            data: "bad".to_string(),
+            line: None,
+            column: None,
+            offset: None,
+        }
+        .into();
+        let unknown = reply(&call(json!(9)));
+        let typed = reply(&call(json!("1")));
+        let outcome = match_batch(
+            &requests,
+            vec![parse_error, unknown, typed, reply(&requests[0])],
+        );
+        assert_eq!(3, outcome.unmatched.len());
+        assert_eq!(Some(&reply(&requests[0])), outcome.matches[0].response());
+    }
+
+    #[test]
+    fn batch_duplicates() {
+        let requests = vec![call(json!(1)), call(json!(1))];
+        let first = reply(&requests[0]);
+        let second: Response =
+            (&requests[1], RpcError::new("second".to_string(), None)).into();
+        let third = reply(&requests[1]);
+        let outcome =
+            match_batch(&requests, vec![reply(&requests[0]), second, third]);
+        assert_eq!(vec![json!(1)], outcome.duplicate_request_ids);
+        assert_eq!(vec![json!(1)], outcome.duplicate_response_ids);
+        assert_eq!(Some(&first), outcome.matches[0].response());
+        assert!(outcome.matches[1].response().unwrap().error().is_some());
+        assert_eq!(1, outcome.unmatched.len());
+        assert!(!outcome.is_complete());
+    }
+
+    #[test]
+    fn batch_complete() {
+        let requests = vec![call(json!(1)), call(json!(2))];
+        let responses = vec![reply(&requests[1]), reply(&requests[0])];
+        assert!(match_batch(&requests, responses).is_complete());
+    }
+
+    struct Echo;
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> crate::Result<Option<Response>> {
+            Ok(Some((request, json!(request.method())).into()))
+        }
+    }
+
+    fn duplicates() -> Vec<Request> {
+        vec![
+            Request::new(Some(json!(1)), "first".to_string(), None),
+            Request::new(Some(json!("1")), "string".to_string(), None),
+            Request::new_notification("notify", None),
+            Request::new_notification("notify", None),
+            Request::new(Some(json!(1)), "second".to_string(), None),
+        ]
+    }
+
+    #[test]
+    fn batch_duplicates_allow() {
+        let requests = duplicates();
+        assert_eq!(vec![4], duplicate_ids(&requests));
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let server = Server::new(vec![&service]);
+        let results: Vec<_> = server
+            .serve_batch(&requests, &())
+            .into_iter()
+            .map(|response| response.result().clone().unwrap())
+            .collect();
+        assert_eq!(
+            vec![json!("first"), json!("string"), json!("second")],
+            results
+        );
+    }
+
+    #[test]
+    fn batch_duplicates_warn() {
+        let warned = Arc::new(Mutex::new(Vec::new()));
+        let handler = {
+            let warned = Arc::clone(&warned);
+            move |request: &Request| {
+                warned.lock().unwrap().push(request.method().to_string())
+            }
+        };
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let server = Server::new(vec![&service])
+            .with_duplicate_ids(DuplicateIds::Warn(Box::new(handler)));
+        let responses = server.serve_batch(&duplicates(), &());
+        assert_eq!(3, responses.len());
+        assert!(responses.iter().all(|r| r.error().is_none()));
+        assert_eq!(vec!["second".to_string()], *warned.lock().unwrap());
+    }
+
+    #[test]
+    fn batch_duplicates_reject() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let server = Server::new(vec![&service])
+            .with_duplicate_ids(DuplicateIds::Reject);
+        let responses = server.serve_batch(&duplicates(), &());
+        assert_eq!(&Some(json!("first")), responses[0].result());
+        assert_eq!(&Some(json!("string")), responses[1].result());
+        assert_eq!(&Some(json!(1)), responses[2].id());
+        let error = responses[2].error().clone().unwrap();
+        assert_eq!(INVALID_REQUEST, error.code);
+        assert_eq!(Some(json!("duplicate id in batch")), error.data);
+    }
+
+    #[test]
+    fn batch_abort_on_error() {
+        struct Fail;
+        impl Service for Fail {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> crate::Result<Option<Response>> {
+                match request.method() {
+                    "fail" => Err(Error::from(Box::from("Transfer failed"))),
+                    _ => Ok(None),
+                }
+            }
+        }
+
+        let requests = vec![
+            Request::new(Some(json!(1)), "first".to_string(), None),
+            Request::new(Some(json!(2)), "fail".to_string(), None),
+            Request::new_notification("notify", None),
+            Request::new(Some(json!(3)), "third".to_string(), None),
+        ];
+        let fail: Box<dyn Service<Data = ()>> = Box::new(Fail);
+        let echo: Box<dyn Service<Data = ()>> = Box::new(Echo);
+
+        let server = Server::new(vec![&fail, &echo]);
+        let responses = server.serve_batch(&requests, &());
+        assert_eq!(&Some(json!("third")), responses[2].result());
+
+        let server = Server::new(vec![&fail, &echo])
+            .with_batch_policy(BatchPolicy::AbortOnError);
+        let responses = server.serve_batch(&requests, &());
+        assert_eq!(3, responses.len());
+        assert_eq!(&Some(json!("first")), responses[0].result());
+        assert!(responses[1].error().is_some());
+        assert_eq!(&Some(json!(3)), responses[2].id());
+        let error = responses[2].error().clone().unwrap();
+        assert_eq!(BATCH_SKIPPED, error.code);
+        assert_eq!("skipped due to earlier failure", error.message);
+    }
+}
@@ -0,0 +1,254 @@
+//! Coalesce client calls into batches, requires the `async` feature.
+//!
+//! A [BatchingClient](BatchingClient) queues calls for a short window
+//! and sends them to a [BatchTransport](BatchTransport) as a single
+//! batch; the responses are matched to the waiting calls by id.
+
+use crate::{
+    client::convert_result,
+    futures::{Pending, PendingResponse},
+    Error, Request, Response, Result,
+};
+use async_trait::async_trait;
+use futures_util::future::{self, Either};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[async_trait]
+/// Trait for transports that deliver a batch of requests to a server.
+///
+/// Only available with the `async` feature.
+pub trait BatchTransport: Send + Sync {
+    /// Send a batch and return the responses in any order.
+    async fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>>;
+}
+
+#[derive(Default)]
+struct Queue {
+    requests: Vec<Request>,
+    deadline: Option<Instant>,
+}
+
+/// Client that coalesces calls into batches.
+///
+/// A batch is sent when the window since the first queued call has
+/// elapsed or when the maximum number of requests is queued, whichever
+/// happens first. Notifications do not start the window, they are
+/// sent with the next batch or by calling [flush()](Self::flush).
+///
+/// When sending the batch fails every call in the batch fails with
+/// the same error. Calls are flushed by one of the waiting calls so
+/// dropping that call while the batch is being sent fails the others.
+///
+/// Only available with the `async` feature.
+pub struct BatchingClient<T> {
+    transport: T,
+    pending: Pending,
+    queue: Mutex<Queue>,
+    window: Duration,
+    max_requests: usize,
+}
+
+impl<T: BatchTransport> BatchingClient<T> {
+    /// Create a client with a window of 10ms and at most 32 requests
+    /// in a batch.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            pending: Pending::new(),
+            queue: Mutex::new(Default::default()),
+            window: Duration::from_millis(10),
+            max_requests: 32,
+        }
+    }
+
+    /// Set how long to wait for more calls before a batch is sent.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Set the maximum number of requests in a batch.
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.max_requests = max_requests.max(1);
+        self
+    }
+
+    /// The underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Queue a call and convert the result to `R`.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub async fn call<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<R> {
+        let request = Request::new_reply(method, params);
+        let id = request.id().clone().unwrap_or(Value::Null);
+        let response = self.pending.register(&id);
+        let deadline = self.enqueue(request);
+        convert_result(self.wait(response, deadline).await?)
+    }
+
+    /// Queue a notification to send with the next batch.
+    pub fn notify(&self, method: &str, params: Option<Value>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue
+            .requests
+            .push(Request::new_notification(method, params));
+    }
+
+    /// Send the queued requests now.
+    pub async fn flush(&self) {
+        let requests = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.deadline = None;
+            std::mem::take(&mut queue.requests)
+        };
+        if requests.is_empty() {
+            return;
+        }
+        match self.transport.send_batch(&requests).await {
+            Ok(responses) => {
+                for response in responses {
+                    self.pending.complete(response);
+                }
+                for id in requests.iter().filter_map(|r| r.id().as_ref()) {
+                    self.pending.fail(
+                        id,
+                        Error::from(Box::from("No response received in batch")),
+                    );
+                }
+            }
+            Err(e) => {
+                let shared = Arc::new(e);
+                for id in requests.iter().filter_map(|r| r.id().as_ref()) {
+                    self.pending
+                        .fail(id, Error::Boxed(Box::new(Arc::clone(&shared))));
+                }
+            }
+        }
+    }
+
+    /// Queue a request and return the deadline for the batch, `None`
+    /// when the batch is full and must be sent now.
+    fn enqueue(&self, request: Request) -> Option<Instant> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.requests.push(request);
+        let pending = queue.requests.iter().filter(|r| r.id().is_some());
+        if pending.count() >= self.max_requests {
+            return None;
+        }
+        let window = self.window;
+        Some(
+            *queue
+                .deadline
+                .get_or_insert_with(|| Instant::now() + window),
+        )
+    }
+
+    async fn wait(
+        &self,
+        mut response: PendingResponse,
+        deadline: Option<Instant>,
+    ) -> Result<Response> {
+        if let Some(deadline) = deadline {
+            let sleep = pin!(tokio::time::sleep_until(deadline));
+            match future::select(&mut response, sleep).await {
+                Either::Left((result, _)) => return result,
+                Either::Right(_) => {}
+            }
+        }
+        self.flush().await;
+        response.await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct Echo {
+        batches: Mutex<Vec<Vec<String>>>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl BatchTransport for Echo {
+        async fn send_batch(
+            &self,
+            requests: &[Request],
+        ) -> Result<Vec<Response>> {
+            self.batches.lock().unwrap().push(
+                requests.iter().map(|r| r.method().to_string()).collect(),
+            );
+            if self.fail {
+                return Err(Error::from(Box::from("connection reset")));
+            }
+            Ok(requests
+                .iter()
+                .rev()
+                .filter(|r| r.id().is_some())
+                .map(|r| (r, r.params().clone().unwrap_or(Value::Null)).into())
+                .collect())
+        }
+    }
+
+    fn batches(client: &BatchingClient<Echo>) -> Vec<Vec<String>> {
+        client.transport().batches.lock().unwrap().clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_window() {
+        let client = BatchingClient::new(Echo::default());
+        client.notify("log", None);
+        let (a, b, c) = tokio::join!(
+            client.call::<u64>("a", Some(json!(1))),
+            client.call::<u64>("b", Some(json!(2))),
+            client.call::<u64>("c", Some(json!(3))),
+        );
+        assert_eq!((1, 2, 3), (a.unwrap(), b.unwrap(), c.unwrap()));
+        assert_eq!(vec![vec!["log", "a", "b", "c"]], batches(&client));
+        assert!(client.pending.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_max_requests() {
+        let client = BatchingClient::new(Echo::default()).max_requests(2);
+        let (a, b, c) = tokio::join!(
+            client.call::<u64>("a", Some(json!(1))),
+            client.call::<u64>("b", Some(json!(2))),
+            client.call::<u64>("c", Some(json!(3))),
+        );
+        assert_eq!((1, 2, 3), (a.unwrap(), b.unwrap(), c.unwrap()));
+        assert_eq!(vec![vec!["a", "b"], vec!["c"]], batches(&client));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_failure() {
+        let client = BatchingClient::new(Echo {
+            fail: true,
+            ..Default::default()
+        });
+        let (a, b) = tokio::join!(
+            client.call::<u64>("a", None),
+            client.call::<u64>("b", None),
+        );
+        assert_eq!("connection reset", a.unwrap_err().to_string());
+        assert_eq!("connection reset", b.unwrap_err().to_string());
+        assert_eq!(1, batches(&client).len());
+        assert!(client.pending.is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+//! Builders for responses and servers.
+//!
+//! ## Responses
+//!
+//! [Response::builder()](crate::Response::builder) starts a
+//! [ResponseBuilder](ResponseBuilder) which must be given exactly one of
+//! a result or an error before it can be built:
+//!
+//! ```
+//! use json_rpc2::{Response, RpcError};
+//! use serde_json::json;
+//!
+//! let response = Response::builder().id(7).result(json!({"ok": true})).build();
+//! assert_eq!(&Some(json!(7)), response.id());
+//!
+//! let error = RpcError::new("Gateway failed".to_string(), None);
+//! let response = Response::builder().id("a").error(error).build();
+//! assert!(response.error().is_some());
+//! ```
+//!
+//! Setting both or neither does not compile:
+//!
+//! ```compile_fail
+//! use json_rpc2::{Response, RpcError};
+//!
+//! let error = RpcError::new("Gateway failed".to_string(), None);
+//! Response::builder().result(7.into()).error(error).build();
+//! ```
+//!
+//! ```compile_fail
+//! json_rpc2::Response::builder().id(7).build();
+//! ```
+//!
+//! ## Servers
+//!
+//! [Server::builder()](crate::Server::builder) collects the services and
+//! the configuration of a server and checks them when the server is
+//! built; the server owns its services so it can be shared between
+//! threads in an `Arc`:
+//!
+//! ```
+//! use json_rpc2::{conformance::Conformance, method::method, *};
+//!
+//! fn add((a, b): (u64, u64), _: &()) -> Result<u64> {
+//!     Ok(a + b)
+//! }
+//!
+//! let server = Server::builder()
+//!     .service(method("add", add))
+//!     .conformance(Conformance::strict())
+//!     .max_batch(64)
+//!     .build()?;
+//! let response = server.serve(&Request::new_reply("add", Some(serde_json::json!([1, 2]))), &());
+//! assert_eq!(Some(serde_json::json!(3)), response.unwrap().into());
+//!
+//! let duplicate = Server::<()>::builder()
+//!     .service(method("add", add))
+//!     .service(method("add", add))
+//!     .build();
+//! assert!(matches!(duplicate, Err(builder::ConfigError::DuplicateMethod { .. })));
+//! # Ok::<(), builder::ConfigError>(())
+//! ```
+//!
+//! With the `async` feature
+//! [futures::Server::builder()](crate::futures::Server::builder) builds an
+//! async server the same way.
+
+use crate::{
+    batch, conformance::Conformance, log_unreachable, policy::ErrorPolicy,
+    routes, slow, Error, Request, Response, RpcError, Server, Service,
+    ServiceRef, VERSION,
+};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builder state before a result or an error is assigned.
+#[derive(Debug)]
+pub struct NoBody;
+
+/// Builder state once a result or an error is assigned.
+#[derive(Debug)]
+pub struct WithBody(std::result::Result<Value, RpcError>);
+
+/// Builder for a response.
+///
+/// The id defaults to `null`.
+#[derive(Debug)]
+pub struct ResponseBuilder<S> {
+    id: Option<Value>,
+    body: S,
+}
+
+impl ResponseBuilder<NoBody> {
+    pub(crate) fn new() -> Self {
+        Self {
+            id: Some(Value::Null),
+            body: NoBody,
+        }
+    }
+
+    /// Set the result of a successful response.
+    pub fn result(self, result: Value) -> ResponseBuilder<WithBody> {
+        ResponseBuilder {
+            id: self.id,
+            body: WithBody(Ok(result)),
+        }
+    }
+
+    /// Set the error of an error response.
+    pub fn error(self, error: RpcError) -> ResponseBuilder<WithBody> {
+        ResponseBuilder {
+            id: self.id,
+            body: WithBody(Err(error)),
+        }
+    }
+}
+
+impl<S> ResponseBuilder<S> {
+    /// Set the id of the response.
+    pub fn id<I: Into<Value>>(mut self, id: I) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+}
+
+impl ResponseBuilder<WithBody> {
+    /// Create the response.
+    pub fn build(self) -> Response {
+        let (result, error) = match self.body.0 {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Response {
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+            id: self.id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Configuration that cannot be built into a server.
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum ConfigError {
+    /// Two services report the same method so the later service could
+    /// never handle it.
+    #[error("method {method} is provided by services {first} and {second}")]
+    DuplicateMethod {
+        /// The method name.
+        method: String,
+        /// Index of the service that handles the method.
+        first: usize,
+        /// Index of the service that is shadowed.
+        second: usize,
+    },
+    /// A batch limit of zero would reject every batch.
+    #[error("the batch limit must be at least one")]
+    ZeroMaxBatch,
+}
+
+/// Check the services and limits given to a builder.
+///
+/// Services that do not report their methods are not checked.
+pub(crate) fn check(
+    order: &[Vec<String>],
+    allow_duplicates: bool,
+    max_batch: Option<usize>,
+) -> std::result::Result<(), ConfigError> {
+    if max_batch == Some(0) {
+        return Err(ConfigError::ZeroMaxBatch);
+    }
+    if allow_duplicates {
+        return Ok(());
+    }
+    let mut seen = HashMap::new();
+    for (index, methods) in order.iter().enumerate() {
+        for method in methods {
+            if let Some(first) = seen.insert(method.as_str(), index) {
+                if first != index {
+                    return Err(ConfigError::DuplicateMethod {
+                        method: method.to_string(),
+                        first,
+                        second: index,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Builder for a [Server](crate::Server) that owns its services.
+pub struct ServerBuilder<T: 'static> {
+    services: Vec<Arc<dyn Service<Data = T>>>,
+    fallback: Option<Arc<dyn Service<Data = T>>>,
+    allow_duplicates: bool,
+    max_batch: Option<usize>,
+    server: Server<'static, T>,
+}
+
+impl<T: 'static> ServerBuilder<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            services: Vec::new(),
+            fallback: None,
+            allow_duplicates: false,
+            max_batch: None,
+            server: Server::new_shared(Vec::new()),
+        }
+    }
+
+    /// Add a service, services are called in the order they are added.
+    pub fn service<S: Service<Data = T> + 'static>(self, service: S) -> Self {
+        self.shared_service(Arc::new(service))
+    }
+
+    /// Add a service that is shared with other servers.
+    pub fn shared_service(
+        mut self,
+        service: Arc<dyn Service<Data = T>>,
+    ) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    /// Set a service called for requests no other service handles, for
+    /// example to forward them elsewhere.
+    ///
+    /// Its methods are not checked for duplicates and it is asked for
+    /// every method, including those it does not list.
+    pub fn fallback<S: Service<Data = T> + 'static>(
+        mut self,
+        service: S,
+    ) -> Self {
+        self.fallback = Some(Arc::new(routes::Fallback(service)));
+        self
+    }
+
+    /// Allow services to report the same method, the first service
+    /// handles it.
+    pub fn allow_duplicates(mut self) -> Self {
+        self.allow_duplicates = true;
+        self
+    }
+
+    /// See [Server::with_validator()](crate::Server::with_validator).
+    pub fn validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&Request, &T) -> std::result::Result<(), RpcError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.server = self.server.with_validator(validator);
+        self
+    }
+
+    /// See [Server::with_authz()](crate::Server::with_authz).
+    pub fn authz(mut self, policy: crate::authz::AuthzPolicy) -> Self
+    where
+        T: crate::authz::HasScopes,
+    {
+        self.server = self.server.with_authz(policy);
+        self
+    }
+
+    /// See [Server::on_served()](crate::Server::on_served).
+    pub fn hook<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Request, Option<&Response>, Duration) + Send + Sync + 'static,
+    {
+        self.server = self.server.on_served(f);
+        self
+    }
+
+    /// See [Server::with_conformance()](crate::Server::with_conformance).
+    pub fn conformance(mut self, conformance: Conformance) -> Self {
+        self.server = self.server.with_conformance(conformance);
+        self
+    }
+
+    /// See [Server::with_max_batch()](crate::Server::with_max_batch).
+    pub fn max_batch(mut self, max: usize) -> Self {
+        self.max_batch = Some(max);
+        self
+    }
+
+    /// See [Server::with_batch_policy()](crate::Server::with_batch_policy).
+    pub fn batch_policy(mut self, policy: batch::BatchPolicy) -> Self {
+        self.server = self.server.with_batch_policy(policy);
+        self
+    }
+
+    /// See [Server::with_duplicate_ids()](crate::Server::with_duplicate_ids).
+    pub fn duplicate_ids(mut self, policy: batch::DuplicateIds) -> Self {
+        self.server = self.server.with_duplicate_ids(policy);
+        self
+    }
+
+    /// See [Server::with_methods()](crate::Server::with_methods).
+    pub fn list_methods(mut self) -> Self {
+        self.server = self.server.with_methods();
+        self
+    }
+
+    /// See [Server::with_error_mapper()](crate::Server::with_error_mapper).
+    pub fn error_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Error, &Request) -> Option<RpcError> + Send + Sync + 'static,
+    {
+        self.server = self.server.with_error_mapper(mapper);
+        self
+    }
+
+    /// See [Server::with_debug_errors()](crate::Server::with_debug_errors).
+    pub fn debug_errors(mut self, enabled: bool) -> Self {
+        self.server = self.server.with_debug_errors(enabled);
+        self
+    }
+
+    /// See [Server::with_policy()](crate::Server::with_policy).
+    pub fn policy(mut self, policy: ErrorPolicy) -> Self {
+        self.server = self.server.with_policy(policy);
+        self
+    }
+
+    /// See [Server::with_slow_request_log()](crate::Server::with_slow_request_log).
+    pub fn slow_request_log<F>(mut self, threshold: Duration, f: F) -> Self
+    where
+        F: Fn(&slow::SlowRequest<'_>) + Send + Sync + 'static,
+    {
+        self.server = self.server.with_slow_request_log(threshold, f);
+        self
+    }
+
+    /// See [Server::with_stats()](crate::Server::with_stats).
+    pub fn stats(mut self) -> Self {
+        self.server = self.server.with_stats();
+        self
+    }
+
+    /// See [Server::with_timing()](crate::Server::with_timing).
+    #[cfg(feature = "extra-fields")]
+    pub fn timing(mut self) -> Self {
+        self.server = self.server.with_timing();
+        self
+    }
+
+    /// See [Server::with_deprecation_warnings()](crate::Server::with_deprecation_warnings).
+    #[cfg(feature = "extra-fields")]
+    pub fn deprecation_warnings(mut self) -> Self {
+        self.server = self.server.with_deprecation_warnings();
+        self
+    }
+
+    /// Check the configuration and create the server.
+    pub fn build(self) -> std::result::Result<Server<'static, T>, ConfigError> {
+        let order: Vec<Vec<String>> = self
+            .services
+            .iter()
+            .map(|service| service.methods())
+            .collect();
+        check(&order, self.allow_duplicates, self.max_batch)?;
+        let mut server = self.server;
+        if let Some(max) = self.max_batch {
+            server = server.with_max_batch(max);
+        }
+        server.services.extend(
+            self.services
+                .into_iter()
+                .chain(self.fallback)
+                .map(ServiceRef::Shared),
+        );
+        if self.allow_duplicates {
+            log_unreachable(server.order());
+        }
+        Ok(server)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::method::method;
+    use serde_json::json;
+
+    #[test]
+    fn build_responses() {
+        let response = Response::builder().result(json!([1, 2])).build();
+        assert_eq!(
+            json!({"jsonrpc": "2.0", "id": null, "result": [1, 2]}),
+            serde_json::to_value(&response).unwrap()
+        );
+
+        let error = RpcError::new("Gateway failed".to_string(), None);
+        let response = Response::builder().error(error).id(7).build();
+        assert_eq!(
+            json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "error": {"code": -32603, "message": "Gateway failed"},
+            }),
+            serde_json::to_value(&response).unwrap()
+        );
+    }
+
+    fn add((a, b): (u64, u64), _: &()) -> crate::Result<u64> {
+        Ok(a + b)
+    }
+
+    fn echo(params: Value, _: &()) -> crate::Result<Value> {
+        Ok(params)
+    }
+
+    /// Answers every method so it must be called last.
+    struct Fallback;
+
+    impl Service for Fallback {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &(),
+        ) -> crate::Result<Option<Response>> {
+            Ok(Some((request, json!("fallback")).into()))
+        }
+
+        fn methods(&self) -> Vec<String> {
+            vec!["add".to_string()]
+        }
+    }
+
+    #[test]
+    fn build_server() {
+        let server = Server::builder()
+            .fallback(Fallback)
+            .service(method("add", add))
+            .service(method("echo", echo))
+            .max_batch(2)
+            .build()
+            .unwrap();
+        let add = Request::new_reply("add", Some(json!([1, 2])));
+        let other = Request::new_reply("other", None);
+        assert_eq!(Some(json!(3)), server.serve(&add, &()).unwrap().into());
+        assert_eq!(
+            Some(json!("fallback")),
+            server.serve(&other, &()).unwrap().into()
+        );
+
+        let responses =
+            server.serve_batch(&[add.clone(), add.clone(), add], &());
+        assert_eq!(1, responses.len());
+        let error: Option<RpcError> = responses[0].clone().into();
+        assert_eq!(crate::INVALID_REQUEST, error.unwrap().code);
+    }
+
+    #[test]
+    fn build_server_errors() {
+        let duplicate = Server::<()>::builder()
+            .service(method("add", add))
+            .service(method("echo", echo))
+            .service(method("add", add))
+            .build();
+        assert_eq!(
+            Some(ConfigError::DuplicateMethod {
+                method: "add".to_string(),
+                first: 0,
+                second: 2,
+            }),
+            duplicate.err()
+        );
+
+        let allowed = Server::<()>::builder()
+            .service(method("add", add))
+            .service(method("add", add))
+            .allow_duplicates()
+            .build();
+        assert!(allowed.is_ok());
+
+        let zero = Server::<()>::builder().max_batch(0).build();
+        assert_eq!(Some(ConfigError::ZeroMaxBatch), zero.err());
+    }
+}
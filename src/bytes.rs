@@ -0,0 +1,268 @@
+//! Binary data as base64 strings, requires the `bytes` feature.
+//!
+//! Serde writes a `Vec<u8>` as an array of numbers which is several
+//! times larger than the data and slow to parse. Wrap binary fields in
+//! [Base64Bytes](Base64Bytes) to send them as standard base64 strings
+//! instead:
+//!
+//! ```
+//! use json_rpc2::{bytes::Base64Bytes, Request};
+//! use serde::{Deserialize, Serialize};
+//! use serde_json::json;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Upload {
+//!     name: String,
+//!     data: Base64Bytes,
+//! }
+//!
+//! let request = Request::new_reply("upload", Some(json!({
+//!     "name": "hello.txt",
+//!     "data": "aGVsbG8=",
+//! })));
+//! let upload: Upload = request.deserialize()?;
+//! assert_eq!(b"hello", upload.data.as_slice());
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+//!
+//! Formats that are not human readable, such as MessagePack, get the
+//! bytes natively rather than as a string.
+//!
+//! For a method that takes a single blob,
+//! [Request::params_bytes()](crate::Request::params_bytes) and
+//! [Response::result_bytes()](crate::Response::result_bytes) decode a
+//! base64 parameter or result without a wrapper type.
+
+use crate::{Error, Request, Response, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{
+    de::{self, Deserializer, Visitor},
+    Deserialize, Serialize, Serializer,
+};
+use serde_json::Value;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Bytes serialized as a base64 string.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Base64Bytes(pub Vec<u8>);
+
+impl Base64Bytes {
+    /// The bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Take the bytes.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl From<Vec<u8>> for Base64Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<&[u8]> for Base64Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+}
+
+impl From<Base64Bytes> for Vec<u8> {
+    fn from(bytes: Base64Bytes) -> Self {
+        bytes.0
+    }
+}
+
+impl Deref for Base64Bytes {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Base64Bytes {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl AsRef<[u8]> for Base64Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Serialize for Base64Bytes {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64Bytes {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Base64Bytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a base64 string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(
+        self,
+        value: &str,
+    ) -> std::result::Result<Self::Value, E> {
+        STANDARD.decode(value).map(Base64Bytes).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(
+        self,
+        value: &[u8],
+    ) -> std::result::Result<Self::Value, E> {
+        Ok(Base64Bytes(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(
+        self,
+        value: Vec<u8>,
+    ) -> std::result::Result<Self::Value, E> {
+        Ok(Base64Bytes(value))
+    }
+}
+
+impl Request {
+    /// Decode the parameters of a method taking a single base64 string,
+    /// such as `["aGVsbG8="]`.
+    ///
+    /// Other parameters yield `Error::InvalidParams`.
+    pub fn params_bytes(&self) -> Result<Vec<u8>> {
+        let encoded = match self.params() {
+            Some(Value::Array(items)) if items.len() == 1 => items[0].as_str(),
+            _ => None,
+        };
+        let invalid = |data: String| Error::InvalidParams {
+            id: self.id().clone(),
+            data: data.into(),
+        };
+        let encoded = encoded.ok_or_else(|| {
+            invalid("expected a single base64 string parameter".to_string())
+        })?;
+        STANDARD
+            .decode(encoded)
+            .map_err(|e| invalid(format!("parameter is not base64: {}", e)))
+    }
+}
+
+impl Response {
+    /// Decode a result that is a base64 string.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub fn result_bytes(&self) -> Result<Vec<u8>> {
+        if let Some(error) = self.error() {
+            return Err(Error::Rpc(error.clone()));
+        }
+        let encoded =
+            self.result().as_ref().and_then(Value::as_str).ok_or_else(
+                || Error::from(Box::from("result is not a base64 string")),
+            )?;
+        STANDARD
+            .decode(encoded)
+            .map_err(|e| Error::from(Box::from(e)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Server, Service, INVALID_PARAMS};
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize)]
+    struct Chunk {
+        offset: u64,
+        data: Base64Bytes,
+    }
+
+    #[test]
+    fn base64_bytes_in_params() {
+        let chunk = Chunk {
+            offset: 4,
+            data: Base64Bytes::from(&b"\x00\xffbinary"[..]),
+        };
+        let params = serde_json::to_value(&chunk).unwrap();
+        assert_eq!(json!({"offset": 4, "data": "AP9iaW5hcnk="}), params);
+
+        let request = Request::new_reply("write", Some(params));
+        let decoded: Chunk = request.deserialize().unwrap();
+        assert_eq!(chunk.data, decoded.data);
+
+        let positional =
+            Request::new_reply("write", Some(json!([4, "AP9iaW5hcnk="])));
+        let (_, data): (u64, Base64Bytes) = positional.deserialize().unwrap();
+        assert_eq!(b"\x00\xffbinary", data.as_slice());
+
+        let invalid = Request::new_reply(
+            "write",
+            Some(json!({"offset": 0, "data": "!"})),
+        );
+        match invalid.deserialize::<Chunk>() {
+            Err(Error::InvalidParams { data, .. }) => {
+                assert!(data.contains("data"), "{}", data)
+            }
+            _ => panic!("expected invalid params"),
+        }
+    }
+
+    struct Echo;
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let bytes = request.params_bytes()?;
+            let reversed: Vec<u8> = bytes.into_iter().rev().collect();
+            let result = serde_json::to_value(Base64Bytes(reversed)).unwrap();
+            Ok(Some((request, result).into()))
+        }
+    }
+
+    #[test]
+    fn params_and_result_bytes() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let server = Server::new(vec![&service]);
+
+        let request = Request::new_reply("reverse", Some(json!(["AQID"])));
+        assert_eq!(vec![1, 2, 3], request.params_bytes().unwrap());
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(vec![3, 2, 1], response.result_bytes().unwrap());
+
+        let request = Request::new_reply("reverse", Some(json!(["AQID", 1])));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(INVALID_PARAMS, response.error().clone().unwrap().code);
+        assert!(matches!(response.result_bytes(), Err(Error::Rpc(_))));
+    }
+}
@@ -0,0 +1,403 @@
+//! Cache responses for pure methods.
+//!
+//! [Cached](Cached) wraps a service and stores successful responses
+//! keyed by the method name and the canonical parameters, so objects
+//! with the same keys in a different order share an entry. Only the
+//! methods added with [method()](Cached::method) are cached.
+//!
+//! The cache is pluggable using the [Cache](Cache) trait; the `cache`
+//! feature bundles an [LruCache](LruCache) with expiry.
+
+use crate::{describe::MethodMeta, Request, Response, Result, Service};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(any(test, feature = "cache"))]
+pub use lru::LruCache;
+
+/// Storage for cached responses.
+pub trait Cache: Send + Sync {
+    /// Get the response stored for a key.
+    fn get(&self, key: &str) -> Option<Response>;
+
+    /// Store a response for a key.
+    fn put(&self, key: String, response: Response);
+}
+
+/// Service that caches the responses of an inner service.
+pub struct Cached<S> {
+    inner: S,
+    cache: Box<dyn Cache>,
+    methods: HashSet<String>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<S> Cached<S> {
+    /// Create a caching wrapper around a service.
+    pub fn new<C: Cache + 'static>(inner: S, cache: C) -> Self {
+        Self {
+            inner,
+            cache: Box::new(cache),
+            methods: HashSet::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Allow the responses of a method to be cached.
+    ///
+    /// Methods must be free of side effects as a cached response is
+    /// returned without calling the inner service.
+    pub fn method(mut self, name: &str) -> Self {
+        self.methods.insert(name.to_string());
+        self
+    }
+
+    /// The inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Number of requests answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of cacheable requests passed to the inner service.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Cache key for the request, `None` when it must not be cached.
+    fn key(&self, request: &Request) -> Option<String> {
+        if request.id().is_none() || !self.methods.contains(request.method()) {
+            return None;
+        }
+        Some(key(request))
+    }
+
+    fn lookup(&self, request: &Request, key: &str) -> Option<Response> {
+        match self.cache.get(key) {
+            Some(mut response) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                response.id = request.id().clone();
+                Some(response)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn store(&self, key: String, result: &Result<Option<Response>>) {
+        if let Ok(Some(response)) = result {
+            if response.error().is_none() {
+                self.cache.put(key, response.clone());
+            }
+        }
+    }
+}
+
+/// Key for a request from the method name and canonical parameters.
+pub(crate) fn key(request: &Request) -> String {
+    let mut key = request.method().to_string();
+    key.push(':');
+    if let Some(params) = request.params() {
+        canonical(params, &mut key);
+    }
+    key
+}
+
+/// Write a value with object keys in sorted order.
+fn canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (index, key) in keys.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                canonical(&map[key], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                canonical(item, out);
+            }
+            out.push(']');
+        }
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+impl<S: Service> Service for Cached<S> {
+    type Data = S::Data;
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let key = match self.key(request) {
+            Some(key) => key,
+            None => return self.inner.handle(request, ctx),
+        };
+        if let Some(response) = self.lookup(request, &key) {
+            return Ok(Some(response));
+        }
+        let result = self.inner.handle(request, ctx);
+        self.store(key, &result);
+        result
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<S: crate::futures::Service> crate::futures::Service for Cached<S> {
+    type Data = S::Data;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let key = match self.key(request) {
+            Some(key) => key,
+            None => return self.inner.handle(request, ctx).await,
+        };
+        if let Some(response) = self.lookup(request, &key) {
+            return Ok(Some(response));
+        }
+        let result = self.inner.handle(request, ctx).await;
+        self.store(key, &result);
+        result
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(any(test, feature = "cache"))]
+mod lru {
+    use super::Cache;
+    use crate::Response;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    struct Entry {
+        response: Response,
+        expires: Instant,
+        used: u64,
+    }
+
+    #[derive(Default)]
+    struct Entries {
+        map: HashMap<String, Entry>,
+        clock: u64,
+    }
+
+    /// Least recently used cache where entries expire after a time to live.
+    ///
+    /// Only available with the `cache` feature.
+    pub struct LruCache {
+        capacity: usize,
+        ttl: Duration,
+        entries: Mutex<Entries>,
+    }
+
+    impl LruCache {
+        /// Create a cache holding at most `capacity` responses.
+        pub fn new(capacity: usize, ttl: Duration) -> Self {
+            Self {
+                capacity: capacity.max(1),
+                ttl,
+                entries: Mutex::new(Default::default()),
+            }
+        }
+
+        /// Number of entries including any that have expired.
+        pub fn len(&self) -> usize {
+            self.entries.lock().unwrap().map.len()
+        }
+
+        /// Determine if the cache is empty.
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+    }
+
+    impl Cache for LruCache {
+        fn get(&self, key: &str) -> Option<Response> {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let clock = entries.clock;
+            let expired = match entries.map.get_mut(key) {
+                Some(entry) if entry.expires > Instant::now() => {
+                    entry.used = clock;
+                    return Some(entry.response.clone());
+                }
+                Some(_) => true,
+                None => false,
+            };
+            if expired {
+                entries.map.remove(key);
+            }
+            None
+        }
+
+        fn put(&self, key: String, response: Response) {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let used = entries.clock;
+            if !entries.map.contains_key(&key)
+                && entries.map.len() >= self.capacity
+            {
+                let oldest = entries
+                    .map
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.map.remove(&oldest);
+                }
+            }
+            let expires = Instant::now() + self.ttl;
+            entries.map.insert(
+                key,
+                Entry {
+                    response,
+                    expires,
+                    used,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RpcError, Server};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct CountService(AtomicU64);
+    impl Service for CountService {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            match request.method() {
+                "fail" => Ok(Some(
+                    (request, RpcError::new("failed".to_string(), None)).into(),
+                )),
+                _ => Ok(Some((request, json!(count)).into())),
+            }
+        }
+    }
+
+    fn call(id: u64, method: &str, params: Value) -> Request {
+        Request::new(Some(json!(id)), method.to_string(), Some(params))
+    }
+
+    fn server(
+        capacity: usize,
+        ttl: Duration,
+    ) -> (Server<'static, ()>, Arc<Cached<CountService>>) {
+        let cached = Arc::new(
+            Cached::new(
+                CountService(AtomicU64::new(0)),
+                LruCache::new(capacity, ttl),
+            )
+            .method("state")
+            .method("fail"),
+        );
+        let service: Arc<dyn Service<Data = ()>> = cached.clone();
+        (Server::new_shared(vec![service]), cached)
+    }
+
+    #[test]
+    fn cache_hit_rewrites_id() {
+        let (server, cached) = server(8, Duration::from_secs(60));
+        let first = server
+            .serve(&call(1, "state", json!({"a": 1, "b": [2, 3]})), &())
+            .unwrap();
+        let second = server
+            .serve(&call(2, "state", json!({"b": [2, 3], "a": 1})), &())
+            .unwrap();
+        assert_eq!(&Some(json!(2)), second.id());
+        assert_eq!(first.result(), second.result());
+        assert_eq!((1, 1), (cached.hits(), cached.misses()));
+
+        let other = server.serve(&call(3, "state", json!({"a": 2})), &());
+        assert_eq!(Some(json!(2)), other.unwrap().into());
+    }
+
+    #[test]
+    fn cache_bypass() {
+        let (server, cached) = server(8, Duration::from_secs(60));
+        server.serve(&call(1, "fail", json!([])), &());
+        server.serve(&call(2, "fail", json!([])), &());
+        server.serve(&call(3, "other", json!([])), &());
+        server.serve(&call(4, "other", json!([])), &());
+        server.serve(&Request::new_notification("state", None), &());
+        assert_eq!((0, 2), (cached.hits(), cached.misses()));
+        assert_eq!(5, cached.inner().0.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn lru_evicts_and_expires() {
+        let cache = LruCache::new(2, Duration::from_secs(60));
+        let response = |id: u64| -> Response {
+            (&call(id, "a", json!(1)), json!(id)).into()
+        };
+        cache.put("a".to_string(), response(1));
+        cache.put("b".to_string(), response(2));
+        assert!(cache.get("a").is_some());
+        cache.put("c".to_string(), response(3));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert_eq!(2, cache.len());
+
+        let cache = LruCache::new(2, Duration::from_secs(0));
+        cache.put("a".to_string(), response(1));
+        assert!(cache.get("a").is_none());
+        assert!(cache.is_empty());
+    }
+}
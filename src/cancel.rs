@@ -0,0 +1,486 @@
+//! Cancellation of in-flight requests.
+//!
+//! A [CancellationRegistry](CancellationRegistry) maps request ids to
+//! [CancellationToken](CancellationToken)s. When a registry is assigned
+//! to the async server using
+//! [with_cancellation()](crate::futures::Server::with_cancellation) a token
+//! is created for every request with an id and the handler future is
+//! dropped as soon as the token is triggered; the server then replies
+//! with the `-32800` request cancelled error.
+//!
+//! Add a [CancelService](CancelService) sharing the same registry to
+//! handle `$/cancelRequest` notifications.
+//!
+//! Request ids are only unique within a connection, so a registry
+//! [attached](CancellationRegistry::attach) to a request is used
+//! instead of the registry of the server, by the server and the cancel
+//! service alike. [serve_until()](crate::futures::serve_until),
+//! [respond()](crate::futures::respond) and the TCP connections of the
+//! [net](crate::net) module attach a new registry for each stream they
+//! serve; other loops serving several connections should attach one
+//! registry per connection.
+//!
+//! Attaching also inserts the token of the request, so handlers that
+//! want to observe cancellation themselves (for example to clean up)
+//! get it with `request.extensions().get::<CancellationToken>()`. A
+//! request served without a registry attached is still cancelled but
+//! the server does not copy it to insert the token.
+
+use crate::{Request, Response, Result, RpcError, Service};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Error code for a request that was cancelled.
+pub const REQUEST_CANCELLED: isize = -32800;
+
+/// Method name for cancel notifications.
+pub const CANCEL_REQUEST: &str = "$/cancelRequest";
+
+/// Create the error for a cancelled request.
+pub fn cancelled() -> RpcError {
+    RpcError {
+        code: REQUEST_CANCELLED,
+        message: "Request cancelled".into(),
+        data: None,
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// Token that signals a request has been cancelled.
+///
+/// Cloned tokens share the same state.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    /// Create a new token.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Trigger cancellation and wake any tasks waiting on the token.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.inner.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Determine if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Future that resolves when the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+        }
+    }
+
+    /// Run a future to completion unless the token is cancelled first.
+    ///
+    /// Yields `None` if the future was cancelled.
+    pub async fn run<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut future = std::pin::pin!(future);
+        let mut cancelled = self.cancelled();
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready(Some(output));
+            }
+            if Pin::new(&mut cancelled).poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn same(&self, other: &CancellationToken) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+/// Future returned by [cancelled()](CancellationToken::cancelled).
+pub struct Cancelled {
+    token: CancellationToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        {
+            let mut wakers = self.token.inner.wakers.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // Check again in case we raced with a call to cancel().
+        if self.token.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Map of request ids to cancellation tokens.
+///
+/// Cloned registries share the same map.
+#[derive(Clone, Default)]
+pub struct CancellationRegistry {
+    tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+}
+
+impl CancellationRegistry {
+    /// Create a new registry.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create and store a token for a request id.
+    ///
+    /// Ids are compared by their JSON representation so `1` and `"1"`
+    /// are distinct.
+    pub fn register(&self, id: &Value) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.insert(id, token.clone());
+        token
+    }
+
+    /// Store the token attached to a request, or a new token when none
+    /// was attached or it was already cancelled.
+    pub(crate) fn register_request(
+        &self,
+        request: &Request,
+        id: &Value,
+    ) -> CancellationToken {
+        let token = request
+            .extensions()
+            .get::<CancellationToken>()
+            .filter(|token| !token.is_cancelled())
+            .cloned()
+            .unwrap_or_default();
+        self.insert(id, token.clone());
+        token
+    }
+
+    fn insert(&self, id: &Value, token: CancellationToken) {
+        self.tokens.lock().unwrap().insert(id.to_string(), token);
+    }
+
+    /// Get the token for a request id.
+    pub fn token(&self, id: &Value) -> Option<CancellationToken> {
+        self.tokens.lock().unwrap().get(&id.to_string()).cloned()
+    }
+
+    /// Cancel the request with the given id.
+    ///
+    /// Returns whether a pending request was found.
+    pub fn cancel(&self, id: &Value) -> bool {
+        if let Some(token) = self.tokens.lock().unwrap().remove(&id.to_string())
+        {
+            token.cancel();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Insert the registry into the extensions of a request so the
+    /// request is registered and cancelled there, along with a new
+    /// token for a request with an id.
+    pub fn attach(&self, request: &mut Request) {
+        if request.id().is_some() {
+            request.extensions_mut().insert(CancellationToken::new());
+        }
+        request.extensions_mut().insert(self.clone());
+    }
+
+    /// The registry attached to a request, or this registry.
+    pub(crate) fn scope<'r>(&'r self, request: &'r Request) -> &'r Self {
+        request.extensions().get::<Self>().unwrap_or(self)
+    }
+
+    /// Number of pending requests.
+    pub fn len(&self) -> usize {
+        self.tokens.lock().unwrap().len()
+    }
+
+    /// Determine if there are no pending requests.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove the token for a request id if it is still the given token.
+    pub(crate) fn unregister(&self, id: &Value, token: &CancellationToken) {
+        let mut tokens = self.tokens.lock().unwrap();
+        let key = id.to_string();
+        if tokens.get(&key).map(|t| t.same(token)).unwrap_or(false) {
+            tokens.remove(&key);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CancelParams {
+    id: Value,
+}
+
+/// Service that handles `$/cancelRequest` notifications.
+pub struct CancelService<T> {
+    registry: CancellationRegistry,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> CancelService<T> {
+    /// Create a cancel service for a registry.
+    pub fn new(registry: CancellationRegistry) -> Self {
+        Self {
+            registry,
+            marker: PhantomData,
+        }
+    }
+
+    fn reply(&self, request: &Request) -> Result<Option<Response>> {
+        if request.method() != CANCEL_REQUEST {
+            return Ok(None);
+        }
+        let params: CancelParams = request.deserialize()?;
+        let found = self.registry.scope(request).cancel(&params.id);
+        Ok(Some((request, Value::Bool(found)).into()))
+    }
+}
+
+impl<T> Service for CancelService<T> {
+    type Data = T;
+    fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.reply(request)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![CANCEL_REQUEST.to_string()]
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<T: Send + Sync> crate::futures::Service for CancelService<T> {
+    type Data = T;
+    async fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.reply(request)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![CANCEL_REQUEST.to_string()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::{Server, Service};
+    use serde_json::json;
+    use std::time::Duration;
+
+    struct SleepService;
+
+    #[async_trait::async_trait]
+    impl Service for SleepService {
+        type Data = CancellationRegistry;
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "sleep" => {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    Ok(Some((request, Value::Null).into()))
+                }
+                "nap" => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(Some((request, Value::Null).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    struct ObserveService;
+
+    #[async_trait::async_trait]
+    impl Service for ObserveService {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "observe" => {
+                    let token = request.extensions().get::<CancellationToken>();
+                    let registry =
+                        request.extensions().get::<CancellationRegistry>();
+                    let id = request.id().as_ref().unwrap();
+                    let registered = registry.unwrap().token(id).unwrap();
+                    assert!(registered.same(token.unwrap()));
+                    Ok(Some((request, Value::Null).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_in_flight() {
+        let registry = CancellationRegistry::new();
+        let sleep: Box<dyn Service<Data = CancellationRegistry>> =
+            Box::new(SleepService);
+        let cancel: Box<dyn Service<Data = CancellationRegistry>> =
+            Box::new(CancelService::new(registry.clone()));
+        let server = Server::new(vec![&cancel, &sleep])
+            .with_cancellation(registry.clone());
+
+        let request = Request::new(Some(json!(7)), "sleep".to_string(), None);
+        let notification =
+            Request::new_notification(CANCEL_REQUEST, Some(json!({"id": 7})));
+
+        let (response, cancel_response) =
+            tokio::join!(server.serve(&request, &registry), async {
+                while registry.is_empty() {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                server.serve(&notification, &registry).await
+            });
+
+        assert!(cancel_response.is_none());
+        let response = response.unwrap();
+        assert_eq!(&Some(json!(7)), response.id());
+        assert_eq!(Some(cancelled()), response.into());
+        assert!(registry.is_empty());
+    }
+
+    #[tokio::test]
+    async fn token_visible_to_handler() {
+        let registry = CancellationRegistry::new();
+        let observe: Box<dyn Service<Data = ()>> = Box::new(ObserveService);
+        let server =
+            Server::new(vec![&observe]).with_cancellation(registry.clone());
+        let connection = CancellationRegistry::new();
+        let mut request = Request::new_reply("observe", None);
+        connection.attach(&mut request);
+        let response = server.serve(&request, &()).await;
+        assert_eq!(Some(Value::Null), response.unwrap().into());
+        assert!(registry.is_empty() && connection.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancel_scoped_to_connection() {
+        let registry = CancellationRegistry::new();
+        let sleep: Box<dyn Service<Data = CancellationRegistry>> =
+            Box::new(SleepService);
+        let cancel: Box<dyn Service<Data = CancellationRegistry>> =
+            Box::new(CancelService::new(registry.clone()));
+        let server = Server::new(vec![&cancel, &sleep])
+            .with_cancellation(registry.clone());
+
+        // Both connections use the id 7
+        let (first, second) =
+            (CancellationRegistry::new(), CancellationRegistry::new());
+        let mut sleeping =
+            Request::new(Some(json!(7)), "sleep".to_string(), None);
+        first.attach(&mut sleeping);
+        let mut napping = Request::new(Some(json!(7)), "nap".to_string(), None);
+        second.attach(&mut napping);
+        let mut notification =
+            Request::new_notification(CANCEL_REQUEST, Some(json!({"id": 7})));
+        first.attach(&mut notification);
+
+        let served = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                server.serve(&sleeping, &registry),
+                server.serve(&napping, &registry),
+                async {
+                    while first.is_empty() || second.is_empty() {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                    server.serve(&notification, &registry).await
+                }
+            )
+        });
+        let (sleeping, napping, _) = served.await.unwrap();
+
+        assert_eq!(Some(cancelled()), sleeping.unwrap().into());
+        assert_eq!(Some(Value::Null), napping.unwrap().into());
+        assert!(registry.is_empty() && first.is_empty() && second.is_empty());
+    }
+
+    #[tokio::test]
+    async fn serve_until_scopes_registry() {
+        use crate::futures::serve_until;
+        use futures_util::stream;
+
+        let registry = CancellationRegistry::new();
+        let observe: Box<dyn Service<Data = ()>> = Box::new(ScopeService);
+        let server =
+            Server::new(vec![&observe]).with_cancellation(registry.clone());
+        let requests = vec![Request::new_reply("scope", None)];
+        let mut responses = Vec::new();
+        serve_until(
+            &server,
+            &(),
+            stream::iter(requests),
+            |response| responses.push(response),
+            std::future::pending(),
+            Duration::ZERO,
+        )
+        .await;
+        assert_eq!(Some(Value::Bool(true)), responses.remove(0).into());
+    }
+
+    struct ScopeService;
+
+    #[async_trait::async_trait]
+    impl Service for ScopeService {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let registry = request.extensions().get::<CancellationRegistry>();
+            let id = request.id().as_ref().unwrap();
+            let token = request.extensions().get::<CancellationToken>();
+            let scoped =
+                registry.unwrap().token(id).unwrap().same(token.unwrap());
+            Ok(Some((request, Value::Bool(scoped)).into()))
+        }
+    }
+}
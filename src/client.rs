@@ -0,0 +1,599 @@
+//! Send requests and convert the responses.
+//!
+//! The crate is transport agnostic so the client delegates sending
+//! requests to an implementation of [Transport](Transport). For the
+//! non-blocking version see [futures::Client](crate::futures::Client).
+//!
+//! Both clients accept a chain of [ClientLayer](ClientLayer)s that are
+//! applied in order around every call to the transport, for example to
+//! inject credentials or to [Retry](Retry) failed calls.
+
+use crate::{
+    id::{IdGenerator, RandomIds},
+    random_id,
+    typed::{Method, TypedRequest, TypedResponse},
+    Error, Request, Response, Result,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{collections::HashSet, convert::TryFrom, time::Duration};
+
+/// Trait for transports that deliver requests to a server.
+pub trait Transport: Send + Sync {
+    /// Send a request and wait for the response.
+    ///
+    /// Transports should yield `None` for notifications.
+    fn send(&self, request: &Request) -> Result<Option<Response>>;
+}
+
+/// Interceptor applied to outgoing requests.
+///
+/// Layers are called in the order they were added to the client.
+pub trait ClientLayer: Send + Sync {
+    /// Modify a request before it is first sent.
+    fn before(&self, _request: &mut Request) {}
+
+    /// Inspect or modify the result of sending a request.
+    ///
+    /// Called after every attempt, including attempts that are retried;
+    /// error responses from the server are passed as `Ok`.
+    fn after(&self, _request: &Request, _result: &mut Result<Response>) {}
+
+    /// Decide whether to send the request again after `attempt` attempts.
+    ///
+    /// Return the delay before the next attempt or `None` to keep the
+    /// result; the first layer that returns a delay wins. The request
+    /// may be modified, for example to assign a new id.
+    fn retry(
+        &self,
+        _request: &mut Request,
+        _attempt: u32,
+        _result: &Result<Response>,
+    ) -> Option<Duration> {
+        None
+    }
+}
+
+/// Outcome of a single attempt at sending a request.
+pub(crate) enum Attempt {
+    Done(Result<Response>),
+    Retry(Duration),
+}
+
+/// Chain of layers shared by the blocking and async clients.
+#[derive(Default)]
+pub(crate) struct Layers(Vec<Box<dyn ClientLayer>>);
+
+impl Layers {
+    pub(crate) fn push(&mut self, layer: Box<dyn ClientLayer>) {
+        self.0.push(layer);
+    }
+
+    pub(crate) fn prepare(&self, request: &Request) -> Request {
+        let mut request = request.clone();
+        for layer in self.0.iter() {
+            layer.before(&mut request);
+        }
+        request
+    }
+
+    pub(crate) fn complete(
+        &self,
+        request: &mut Request,
+        response: Result<Option<Response>>,
+        attempt: u32,
+    ) -> Attempt {
+        let mut result = response
+            .map_err(|e| transport_timeout(request, e))
+            .and_then(|r| check_response(request, r));
+        for layer in self.0.iter() {
+            layer.after(request, &mut result);
+        }
+        match self
+            .0
+            .iter()
+            .find_map(|layer| layer.retry(request, attempt, &result))
+        {
+            Some(delay) => Attempt::Retry(delay),
+            None => Attempt::Done(result),
+        }
+    }
+}
+
+/// Policy for the id of a retried request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RetryId {
+    /// Send the retry with the same id so a server that already saw
+    /// the request can recognise the duplicate.
+    Same,
+    /// Assign a new random id for every retry so that a late response
+    /// to an earlier attempt is never mistaken for the retry.
+    Fresh,
+}
+
+/// Layer that retries calls which fail with a transport error.
+///
+/// Only methods added with [method()](Retry::method) are retried and
+/// error responses from the server are never retried so the allowed
+/// methods should be idempotent. The delay starts at the initial
+/// backoff and doubles for every attempt up to the maximum.
+pub struct Retry {
+    max_retries: u32,
+    initial: Duration,
+    max_delay: Duration,
+    methods: HashSet<String>,
+    id: RetryId,
+}
+
+impl Retry {
+    /// Create a layer that retries at most `max_retries` times.
+    ///
+    /// The backoff starts at 100ms up to 10s and retries keep the id.
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            initial: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            methods: HashSet::new(),
+            id: RetryId::Same,
+        }
+    }
+
+    /// Set the initial and maximum delay between attempts.
+    pub fn backoff(mut self, initial: Duration, max_delay: Duration) -> Self {
+        self.initial = initial;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Allow calls to a method to be retried.
+    pub fn method(mut self, name: &str) -> Self {
+        self.methods.insert(name.to_string());
+        self
+    }
+
+    /// Set the policy for the id of retried requests.
+    pub fn id(mut self, policy: RetryId) -> Self {
+        self.id = policy;
+        self
+    }
+}
+
+impl ClientLayer for Retry {
+    fn retry(
+        &self,
+        request: &mut Request,
+        attempt: u32,
+        result: &Result<Response>,
+    ) -> Option<Duration> {
+        match result {
+            Err(Error::Rpc(_)) | Ok(_) => return None,
+            Err(_) => {}
+        }
+        if attempt > self.max_retries
+            || !self.methods.contains(request.method())
+        {
+            return None;
+        }
+        if self.id == RetryId::Fresh {
+            *request.id_mut() = Some(random_id());
+        }
+        let factor = 2u32.saturating_pow(attempt - 1);
+        let delay = self.initial.checked_mul(factor).unwrap_or(self.max_delay);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// Client for calling methods on a server.
+pub struct Client<T> {
+    transport: T,
+    layers: Layers,
+    ids: Box<dyn IdGenerator>,
+}
+
+impl<T: Transport> Client<T> {
+    /// Create a new client.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            layers: Default::default(),
+            ids: Box::new(RandomIds),
+        }
+    }
+
+    /// Set the generator for the ids of calls, random by default.
+    ///
+    /// See the [id](crate::id) module.
+    pub fn with_ids<I: IdGenerator + 'static>(mut self, ids: I) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    /// Add a layer to the chain applied around the transport.
+    pub fn with_layer<L: ClientLayer + 'static>(mut self, layer: L) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// The underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Send a request and return the response.
+    ///
+    /// It is an error if the server does not reply or replies with
+    /// a different id.
+    pub fn request(&self, request: &Request) -> Result<Response> {
+        let mut request = self.layers.prepare(request);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let response = self.transport.send(&request);
+            match self.layers.complete(&mut request, response, attempt) {
+                Attempt::Done(result) => return result,
+                Attempt::Retry(delay) => std::thread::sleep(delay),
+            }
+        }
+    }
+
+    /// Call a method and convert the result to `R`.
+    ///
+    /// An error response yields the error from
+    /// [Error::from_response()](crate::Error::from_response).
+    pub fn call<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<R> {
+        let request = Request::new_reply_with(&*self.ids, method, params);
+        let response = self.request(&request)?;
+        convert_result(response)
+    }
+
+    /// Send a typed request and convert the result to `R`.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub fn call_typed<P, R: DeserializeOwned>(
+        &self,
+        request: &TypedRequest<P>,
+    ) -> Result<TypedResponse<R>> {
+        let response = self.request(request.as_request())?;
+        TypedResponse::try_from(response)
+    }
+
+    /// Call a declared method and convert the result to its type.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub fn call_method<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: Method<P, R>,
+        params: P,
+    ) -> Result<R> {
+        let mut request = Request::from(method.request(params)?);
+        *request.id_mut() = Some(self.ids.next_id());
+        let response = self.request(&request)?;
+        convert_result(response)
+    }
+
+    /// Send a notification.
+    pub fn notify(&self, method: &str, params: Option<Value>) -> Result<()> {
+        let request = Request::new_notification(method, params);
+        self.transport.send(&self.layers.prepare(&request))?;
+        Ok(())
+    }
+}
+
+pub(crate) fn check_response(
+    request: &Request,
+    response: Option<Response>,
+) -> Result<Response> {
+    let response = response.ok_or_else(|| {
+        Error::from(Box::from(format!(
+            "No response received for {}",
+            request.method()
+        )))
+    })?;
+    if response.error().is_none() && response.id() != request.id() {
+        return Err(Error::from(Box::from(format!(
+            "Response id does not match the request for {}",
+            request.method()
+        ))));
+    }
+    Ok(response)
+}
+
+pub(crate) fn convert_result<R: DeserializeOwned>(
+    response: Response,
+) -> Result<R> {
+    if let Some(error) = Error::from_response(&response) {
+        return Err(error);
+    }
+    let value = response.into_result()?;
+    serde_json::from_value(value).map_err(|e| Error::from(Box::from(e)))
+}
+
+/// Convert a transport timing out into `Error::Timeout`.
+///
+/// Sockets with a read timeout fail with `WouldBlock` on some platforms
+/// and `TimedOut` on others.
+fn transport_timeout(request: &Request, error: Error) -> Error {
+    use std::io::ErrorKind::{TimedOut, WouldBlock};
+    match error {
+        Error::Io(e) if matches!(e.kind(), TimedOut | WouldBlock) => {
+            Error::Timeout {
+                method: Some(request.method().to_string()),
+                elapsed: None,
+                id: request.id().clone(),
+            }
+        }
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RpcError, Server, Service};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    struct EchoService;
+    impl Service for EchoService {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "echo" => Ok(Some(
+                    (request, request.params().clone().unwrap()).into(),
+                )),
+                "fail" => {
+                    let err = RpcError {
+                        code: -32001,
+                        message: "Failed".into(),
+                        data: Some(Value::String("reason".to_string())),
+                    };
+                    Ok(Some((request, err).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    struct Local(Box<dyn Service<Data = ()>>);
+    impl Transport for Local {
+        fn send(&self, request: &Request) -> Result<Option<Response>> {
+            let server = Server::new(vec![&self.0]);
+            Ok(server.serve(request, &()))
+        }
+    }
+
+    #[test]
+    fn client_call() -> Result<()> {
+        let client = Client::new(Local(Box::new(EchoService)));
+        let value: Vec<u8> = client.call("echo", Some(json!([1, 2])))?;
+        assert_eq!(vec![1, 2], value);
+        client.notify("echo", Some(json!([])))?;
+        Ok(())
+    }
+
+    #[test]
+    fn client_call_typed() -> Result<()> {
+        let client = Client::new(Local(Box::new(EchoService)));
+        let request = TypedRequest::new("echo", vec![1u8, 2])?;
+        let response: TypedResponse<Vec<u8>> = client.call_typed(&request)?;
+        assert_eq!(request.id(), response.id());
+        assert_eq!(&vec![1, 2], response.result());
+        Ok(())
+    }
+
+    #[test]
+    fn client_call_method() -> Result<()> {
+        const SWAP: Method<(u8, String), (String, u8)> = Method::new("swap");
+
+        let service = SWAP.service(|(a, b): (u8, String), _: &()| Ok((b, a)));
+        let client = Client::new(Local(Box::new(service)));
+        assert_eq!(
+            ("a".to_string(), 1),
+            client.call_method(SWAP, (1, "a".to_string()))?
+        );
+
+        const WRONG: Method<u8, u8> = Method::new("swap");
+        assert!(matches!(
+            client.call_method(WRONG, 1),
+            Err(Error::Rpc(RpcError {
+                code: crate::INVALID_PARAMS,
+                ..
+            }))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn client_error() {
+        let client = Client::new(Local(Box::new(EchoService)));
+        let result: Result<Value> = client.call("fail", None);
+        match result {
+            Err(Error::Rpc(error)) => {
+                assert_eq!(-32001, error.code);
+                assert_eq!(
+                    Some(Value::String("reason".to_string())),
+                    error.data
+                );
+            }
+            _ => panic!("expected rpc error"),
+        }
+    }
+
+    #[test]
+    fn client_timeout_and_cancelled() {
+        struct Slow;
+        impl Transport for Slow {
+            fn send(&self, request: &Request) -> Result<Option<Response>> {
+                match request.method() {
+                    "cancel" => Ok(Some(
+                        Error::Cancelled {
+                            id: request.id().clone(),
+                        }
+                        .into(),
+                    )),
+                    "deadline" => {
+                        Ok(Some((request, crate::deadline::exceeded()).into()))
+                    }
+                    _ => {
+                        Err(std::io::Error::from(std::io::ErrorKind::TimedOut)
+                            .into())
+                    }
+                }
+            }
+        }
+
+        let client = Client::new(Slow);
+        let result: Result<Value> = client.call("read", None);
+        match result {
+            Err(Error::Timeout {
+                method,
+                elapsed: None,
+                id: Some(_),
+            }) => assert_eq!(Some("read".to_string()), method),
+            result => panic!("expected timeout, got {:?}", result),
+        }
+        let result: Result<Value> = client.call("deadline", None);
+        assert!(matches!(result, Err(Error::Timeout { id: Some(_), .. })));
+        let result: Result<Value> = client.call("cancel", None);
+        assert!(matches!(result, Err(Error::Cancelled { id: Some(_) })));
+    }
+
+    struct Flaky {
+        local: Local,
+        failures: Mutex<usize>,
+        sent: Mutex<Vec<Request>>,
+    }
+
+    impl Flaky {
+        fn new(failures: usize) -> Self {
+            Self {
+                local: Local(Box::new(EchoService)),
+                failures: Mutex::new(failures),
+                sent: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn ids(&self) -> Vec<Option<Value>> {
+            let sent = self.sent.lock().unwrap();
+            sent.iter().map(|r| r.id().clone()).collect()
+        }
+    }
+
+    impl Transport for Flaky {
+        fn send(&self, request: &Request) -> Result<Option<Response>> {
+            self.sent.lock().unwrap().push(request.clone());
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Error::from(Box::from("connection reset")));
+            }
+            self.local.send(request)
+        }
+    }
+
+    struct Auth;
+    impl ClientLayer for Auth {
+        fn before(&self, request: &mut Request) {
+            if let Some(Value::Array(params)) = request.params_mut() {
+                params.push(json!("token"));
+            }
+        }
+
+        fn after(&self, _request: &Request, result: &mut Result<Response>) {
+            let error = match result {
+                Ok(response) => response.error().clone(),
+                Err(_) => None,
+            };
+            if let Some(mut error) = error {
+                error.message = format!("auth: {}", error.message).into();
+                *result = Err(Error::Rpc(error));
+            }
+        }
+    }
+
+    fn retry(max_retries: u32) -> Retry {
+        Retry::new(max_retries)
+            .backoff(Duration::from_millis(0), Duration::from_millis(0))
+            .method("echo")
+    }
+
+    #[test]
+    fn client_layers() -> Result<()> {
+        let client = Client::new(Local(Box::new(EchoService))).with_layer(Auth);
+        let value: Vec<Value> = client.call("echo", Some(json!([1])))?;
+        assert_eq!(vec![json!(1), json!("token")], value);
+        let result: Result<Value> = client.call("fail", None);
+        match result {
+            Err(Error::Rpc(error)) => assert_eq!("auth: Failed", error.message),
+            _ => panic!("expected rpc error"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn client_retry_same_id() -> Result<()> {
+        let client = Client::new(Flaky::new(2)).with_layer(retry(2));
+        let value: Vec<u8> = client.call("echo", Some(json!([1])))?;
+        assert_eq!(vec![1], value);
+        let ids = client.transport().ids();
+        assert_eq!(3, ids.len());
+        assert!(ids.iter().all(|id| id == &ids[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn client_retry_fresh_id() -> Result<()> {
+        let client =
+            Client::new(Flaky::new(1)).with_layer(retry(1).id(RetryId::Fresh));
+        let value: Vec<u8> = client.call("echo", Some(json!([1])))?;
+        assert_eq!(vec![1], value);
+        let ids = client.transport().ids();
+        assert_eq!(2, ids.len());
+        assert_ne!(ids[0], ids[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn client_retry_gives_up() {
+        let client = Client::new(Flaky::new(3)).with_layer(retry(2));
+        let result: Result<Value> = client.call("echo", Some(json!([])));
+        assert!(matches!(result, Err(Error::Boxed(_))));
+        assert_eq!(3, client.transport().ids().len());
+
+        let client = Client::new(Flaky::new(1)).with_layer(retry(2));
+        let result: Result<Value> = client.call("fail", None);
+        assert!(result.is_err());
+        assert_eq!(1, client.transport().ids().len());
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let layer = Retry::new(10)
+            .backoff(Duration::from_millis(100), Duration::from_millis(500))
+            .method("echo");
+        let mut request = Request::new_reply("echo", None);
+        let failed: Result<Response> = Err(Error::from(Box::from("reset")));
+        let delays: Vec<_> = (1..=5)
+            .map(|attempt| layer.retry(&mut request, attempt, &failed))
+            .collect();
+        assert_eq!(
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(400)),
+                Some(Duration::from_millis(500)),
+                Some(Duration::from_millis(500)),
+            ],
+            delays
+        );
+    }
+}
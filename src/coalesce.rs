@@ -0,0 +1,306 @@
+//! Deduplicate identical in-flight requests, requires the `async` feature.
+//!
+//! [Coalesce](Coalesce) runs the inner service once for concurrent
+//! requests with the same method and parameters; the first request
+//! leads and the others wait for its response which is copied to each
+//! of them with their own id. Only methods added with
+//! [method()](Coalesce::method) are coalesced.
+
+use crate::{
+    cache, describe::MethodMeta, futures::Service, Error, Request, Response,
+    Result, RpcError,
+};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+type Waiters = Vec<oneshot::Sender<Option<Response>>>;
+
+/// Service that shares the response of identical in-flight requests.
+///
+/// An error from the leader is shared as an error response. If the
+/// leader panics or is cancelled the waiting requests fail with an
+/// internal error.
+///
+/// Only available with the `async` feature.
+pub struct Coalesce<S> {
+    inner: S,
+    methods: HashSet<String>,
+    flights: Flights,
+}
+
+impl<S> Coalesce<S> {
+    /// Create a coalescing wrapper around a service.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            methods: HashSet::new(),
+            flights: Default::default(),
+        }
+    }
+
+    /// Allow concurrent calls to a method to be coalesced.
+    pub fn method(mut self, name: &str) -> Self {
+        self.methods.insert(name.to_string());
+        self
+    }
+
+    /// The inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+/// Requests in flight by key and the requests waiting for them.
+#[derive(Default)]
+pub(crate) struct Flights(Mutex<HashMap<String, Waiters>>);
+
+/// Outcome of joining the flight for a key.
+pub(crate) enum Join<'a> {
+    /// The first request for the key which must complete the flight.
+    Lead(Flight<'a>),
+    /// A later request waiting for the response of the leader.
+    Wait(oneshot::Receiver<Option<Response>>),
+}
+
+impl Flights {
+    /// Lead the flight for a key or wait for the leader.
+    pub(crate) fn join(&self, key: String) -> Join<'_> {
+        let mut flights = self.0.lock().unwrap();
+        match flights.get_mut(&key) {
+            Some(waiters) => {
+                let (sender, receiver) = oneshot::channel();
+                waiters.push(sender);
+                Join::Wait(receiver)
+            }
+            None => {
+                flights.insert(key.clone(), Vec::new());
+                Join::Lead(Flight {
+                    key,
+                    flights: self,
+                    done: false,
+                })
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+/// Wait for the leader and copy its response with the id of `request`.
+pub(crate) async fn follow(
+    waiter: oneshot::Receiver<Option<Response>>,
+    request: &Request,
+) -> Result<Option<Response>> {
+    match waiter.await {
+        Ok(response) => Ok(response.map(|mut response| {
+            response.id = request.id().clone();
+            response
+        })),
+        Err(_) => {
+            Err(Error::from(Box::from("Coalesced request was abandoned")))
+        }
+    }
+}
+
+/// Entry for the leader of a key, releases waiting requests on drop.
+pub(crate) struct Flight<'a> {
+    key: String,
+    flights: &'a Flights,
+    done: bool,
+}
+
+impl Flight<'_> {
+    /// Send a response to the waiting requests.
+    pub(crate) fn complete(&mut self, response: Option<Response>) {
+        self.done = true;
+        let waiters = self.flights.0.lock().unwrap().remove(&self.key);
+        for waiter in waiters.into_iter().flatten() {
+            let _ = waiter.send(response.clone());
+        }
+    }
+
+    /// Send the result of the leader to the waiting requests, an error
+    /// is sent as an error response.
+    pub(crate) fn share(
+        &mut self,
+        request: &Request,
+        result: &Result<Option<Response>>,
+    ) {
+        let shared = match result {
+            Ok(response) => response.clone(),
+            Err(e) => Some((request, RpcError::from(e)).into()),
+        };
+        self.complete(shared);
+    }
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            // Dropping the senders wakes the waiting requests.
+            if let Ok(mut flights) = self.flights.0.lock() {
+                flights.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Service> Service for Coalesce<S> {
+    type Data = S::Data;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        if request.id().is_none() || !self.methods.contains(request.method()) {
+            return self.inner.handle(request, ctx).await;
+        }
+        let mut flight = match self.flights.join(cache::key(request)) {
+            Join::Lead(flight) => flight,
+            Join::Wait(waiter) => return follow(waiter, request).await,
+        };
+        let result = self.inner.handle(request, ctx).await;
+        flight.share(request, &result);
+        result
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::Server;
+    use futures_util::future::join_all;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct SlowService(AtomicUsize);
+
+    #[async_trait]
+    impl Service for SlowService {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            match request.method() {
+                "fail" => Err(Error::from(Box::from("backend unavailable"))),
+                "panic" => panic!("handler failed"),
+                _ => Ok(Some((request, json!("state")).into())),
+            }
+        }
+    }
+
+    fn call(id: u64, method: &str) -> Request {
+        Request::new(Some(json!(id)), method.to_string(), Some(json!([1])))
+    }
+
+    fn coalesce() -> Arc<Coalesce<SlowService>> {
+        Arc::new(
+            Coalesce::new(SlowService::default())
+                .method("state")
+                .method("fail")
+                .method("panic"),
+        )
+    }
+
+    #[tokio::test]
+    async fn coalesce_runs_once() {
+        let coalesce = coalesce();
+        let service: Arc<dyn Service<Data = ()>> = coalesce.clone();
+        let server = Server::new_shared(vec![service]);
+        let requests: Vec<Request> =
+            (1..=5).map(|id| call(id, "state")).collect();
+        let responses =
+            join_all(requests.iter().map(|r| server.serve(r, &()))).await;
+        assert_eq!(1, coalesce.inner().0.load(Ordering::SeqCst));
+        for (id, response) in (1..=5).zip(responses) {
+            let response = response.unwrap();
+            assert_eq!(&Some(json!(id)), response.id());
+            assert_eq!(Some(json!("state")), response.into());
+        }
+
+        server.serve(&call(6, "state"), &()).await;
+        assert_eq!(2, coalesce.inner().0.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn coalesce_shares_errors() {
+        let coalesce = coalesce();
+        let service: Arc<dyn Service<Data = ()>> = coalesce.clone();
+        let server = Server::new_shared(vec![service]);
+        let requests: Vec<Request> =
+            (1..=3).map(|id| call(id, "fail")).collect();
+        let responses =
+            join_all(requests.iter().map(|r| server.serve(r, &()))).await;
+        assert_eq!(1, coalesce.inner().0.load(Ordering::SeqCst));
+        for (id, response) in (1..=3).zip(responses) {
+            let response = response.unwrap();
+            assert_eq!(&Some(json!(id)), response.id());
+            let error: Option<RpcError> = response.into();
+            assert_eq!("backend unavailable", error.unwrap().message);
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesce_leader_cancelled() {
+        let coalesce = coalesce();
+        let leader = call(1, "state");
+        let follower = call(2, "state");
+        let (cancelled, result) = tokio::join!(
+            tokio::time::timeout(
+                Duration::from_millis(5),
+                coalesce.handle(&leader, &())
+            ),
+            coalesce.handle(&follower, &()),
+        );
+        assert!(cancelled.is_err());
+        assert!(result.is_err());
+        assert!(coalesce.flights.is_empty());
+    }
+
+    #[tokio::test]
+    async fn coalesce_leader_panics() {
+        let coalesce = coalesce();
+        let leader = {
+            let coalesce = Arc::clone(&coalesce);
+            tokio::spawn(async move {
+                coalesce.handle(&call(1, "panic"), &()).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let result = coalesce.handle(&call(2, "panic"), &()).await;
+        assert!(leader.await.is_err());
+        match result {
+            Err(e) => {
+                assert_eq!("Coalesced request was abandoned", e.to_string())
+            }
+            Ok(_) => panic!("expected error"),
+        }
+    }
+}
@@ -0,0 +1,608 @@
+//! Select which parts of the specification are enforced.
+//!
+//! A [Conformance](Conformance) bundles the individual checks applied
+//! to a request: the `jsonrpc` version, the type of the id, that the
+//! parameters are structured, that methods beginning with `rpc.` are
+//! reserved for extensions and that no unknown top-level fields are
+//! present. Start from a preset and adjust single checks with the
+//! builder methods:
+//!
+//! ```
+//! use json_rpc2::conformance::Conformance;
+//!
+//! let conformance = Conformance::strict().unknown_fields(false);
+//! let payload = r#"{"jsonrpc":"2.0","id":1,"method":"rpc.sum","x":1}"#;
+//! assert!(conformance.parse_str(payload).is_err());
+//! assert!(Conformance::lenient().parse_str(payload).is_ok());
+//! ```
+//!
+//! The default is what the parse functions such as
+//! [from_str()](crate::from_str) enforce: the version, id and
+//! parameter checks.
+//!
+//! Set a conformance on a server with
+//! [with_conformance()](crate::Server::with_conformance) to check
+//! requests that were not parsed by this crate, failures are answered
+//! with an invalid request error. A server can only detect unknown
+//! fields with the `extra-fields` feature.
+//!
+//! To verify a server end to end, [check_server()](check_server) runs
+//! the example exchanges from the specification against it and
+//! reports the examples it answers differently; use
+//! [check_with()](check_with) for other servers and transports.
+
+use crate::{
+    check_envelope, health, invalid_request, map_json_error, map_payload_error,
+    notify, recover_id, validate_with, Error, Request, Response, Result,
+    Server, METHODS,
+};
+use serde::{de::IgnoredAny, Deserialize};
+use serde_json::{json, Value};
+use std::{borrow::Cow, collections::BTreeMap, fmt};
+
+/// Top-level fields of a request.
+const FIELDS: [&str; 5] = ["jsonrpc", "method", "id", "params", "_meta"];
+
+/// Checks enforced on requests.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conformance {
+    pub(crate) version: bool,
+    pub(crate) id_type: bool,
+    pub(crate) structured_params: bool,
+    reserved_prefix: bool,
+    unknown_fields: bool,
+    extensions: Vec<Cow<'static, str>>,
+}
+
+impl Conformance {
+    /// Enforce every check.
+    pub fn strict() -> Self {
+        Self {
+            version: true,
+            id_type: true,
+            structured_params: true,
+            reserved_prefix: true,
+            unknown_fields: true,
+            extensions: Vec::new(),
+        }
+    }
+
+    /// Enforce no checks, any request that can be deserialized is
+    /// accepted.
+    pub fn lenient() -> Self {
+        Self {
+            version: false,
+            id_type: false,
+            structured_params: false,
+            reserved_prefix: false,
+            unknown_fields: false,
+            extensions: Vec::new(),
+        }
+    }
+
+    /// Require the `jsonrpc` version to be `"2.0"`.
+    pub fn version(mut self, enabled: bool) -> Self {
+        self.version = enabled;
+        self
+    }
+
+    /// Require the id to be a string, number or null.
+    pub fn id_type(mut self, enabled: bool) -> Self {
+        self.id_type = enabled;
+        self
+    }
+
+    /// Require the parameters to be an array or object.
+    pub fn structured_params(mut self, enabled: bool) -> Self {
+        self.structured_params = enabled;
+        self
+    }
+
+    /// Reject methods beginning with `rpc.` other than the extensions
+    /// provided by this crate and those added with
+    /// [extension()](Conformance::extension).
+    pub fn reserved_prefix(mut self, enabled: bool) -> Self {
+        self.reserved_prefix = enabled;
+        self
+    }
+
+    /// Reject requests with top-level fields other than those of the
+    /// specification and `_meta`.
+    pub fn unknown_fields(mut self, enabled: bool) -> Self {
+        self.unknown_fields = enabled;
+        self
+    }
+
+    /// Allow an `rpc.` method when reserved methods are rejected.
+    pub fn extension<S: Into<Cow<'static, str>>>(mut self, method: S) -> Self {
+        self.extensions.push(method.into());
+        self
+    }
+
+    /// Check a request.
+    ///
+    /// Unknown fields are only detected with the `extra-fields`
+    /// feature.
+    pub fn check(&self, request: &Request) -> Result<()> {
+        let structured = matches!(
+            request.params,
+            None | Some(Value::Array(_) | Value::Object(_))
+        );
+        check_envelope(&request.jsonrpc, request.id.clone(), structured, self)?;
+        self.check_method(request)?;
+        #[cfg(feature = "extra-fields")]
+        if self.unknown_fields {
+            if let Some(name) = request.extra.keys().next() {
+                return Err(unknown_field(request, name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a request from a string slice with these checks.
+    ///
+    /// See [from_str()](crate::from_str).
+    pub fn parse_str(&self, payload: &str) -> Result<Request> {
+        self.parse_slice(payload.as_bytes())
+    }
+
+    /// Parse a request from a byte slice with these checks.
+    ///
+    /// See [from_slice()](crate::from_slice).
+    pub fn parse_slice(&self, payload: &[u8]) -> Result<Request> {
+        let request = serde_json::from_slice::<Request>(payload)
+            .map_err(|e| map_payload_error(e, payload))?;
+        if self.unknown_fields {
+            let fields: BTreeMap<String, IgnoredAny> =
+                serde_json::from_slice(payload)
+                    .map_err(|e| Error::from(Box::from(e)))?;
+            self.check_fields(&request, fields.keys())?;
+        }
+        self.validate(request)
+    }
+
+    /// Parse a request from a [Value](serde_json::Value) with these
+    /// checks.
+    ///
+    /// See [from_value()](crate::from_value).
+    pub fn parse_value(&self, payload: Value) -> Result<Request> {
+        let id = recover_id(&payload);
+        let fields: Vec<String> = match (&payload, self.unknown_fields) {
+            (Value::Object(map), true) => map.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        let request = serde_json::from_value::<Request>(payload)
+            .map_err(|e| map_json_error(e, None, || id))?;
+        self.check_fields(&request, fields.iter())?;
+        self.validate(request)
+    }
+
+    fn validate(&self, request: Request) -> Result<Request> {
+        let request = validate_with(request, self)?;
+        self.check_method(&request)?;
+        Ok(request)
+    }
+
+    fn check_fields<'a, I>(
+        &self,
+        request: &Request,
+        mut fields: I,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        match fields.find(|name| !FIELDS.contains(&name.as_str())) {
+            Some(name) => Err(unknown_field(request, name)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_method(&self, request: &Request) -> Result<()> {
+        let method = request.method();
+        if !self.reserved_prefix
+            || !method.starts_with("rpc.")
+            || [
+                METHODS,
+                health::PING,
+                health::HEALTH,
+                notify::PROGRESS,
+                notify::STREAM_CHUNK,
+                notify::STREAM_END,
+            ]
+            .contains(&method)
+            || self.extensions.iter().any(|name| name == method)
+        {
+            return Ok(());
+        }
+        Err(invalid_request(
+            request.id.clone(),
+            format!("method {} is reserved for extensions", method),
+        ))
+    }
+}
+
+impl Default for Conformance {
+    fn default() -> Self {
+        Self::lenient()
+            .version(true)
+            .id_type(true)
+            .structured_params(true)
+    }
+}
+
+fn unknown_field(request: &Request, name: &str) -> Error {
+    invalid_request(request.id.clone(), format!("unknown field {}", name))
+}
+
+/// An example exchange from the specification.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Example {
+    /// Short description of the example.
+    pub name: String,
+    /// The payload sent to the server.
+    pub input: String,
+    /// The response from the specification, `None` when no response is
+    /// sent.
+    pub expected: Option<Value>,
+}
+
+/// An example the server did not answer as the specification does.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// The example.
+    pub example: Example,
+    /// The output of the server, `None` when it sent no response.
+    pub actual: Option<String>,
+}
+
+/// Outcome of running the examples against a server.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Number of examples run.
+    pub examples: usize,
+    /// Examples that failed.
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    /// Determine if every example passed.
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Panic listing the failures unless every example passed.
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            panic!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} examples failed",
+            self.failures.len(),
+            self.examples
+        )?;
+        for failure in &self.failures {
+            let expected = match &failure.example.expected {
+                Some(expected) => expected.to_string(),
+                None => "no response".to_string(),
+            };
+            let actual = failure.actual.as_deref().unwrap_or("no response");
+            write!(
+                f,
+                "\n{}:\n  input: {}\n  expected: {}\n  actual: {}",
+                failure.example.name, failure.example.input, expected, actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The examples at the end of the specification.
+///
+/// They call the methods `subtract`, which takes two positional or
+/// named (`minuend` and `subtrahend`) parameters, `sum`, `get_data`
+/// returning `["hello", 5]` and the notifications `update`,
+/// `notify_sum` and `notify_hello`.
+pub fn examples() -> Vec<Example> {
+    serde_json::from_str(include_str!("spec_examples.json"))
+        .expect("specification examples are valid")
+}
+
+/// Run the [examples()](examples) against a server.
+///
+/// Batches are served one request at a time. Responses are compared
+/// on their ids, results and error codes, error messages may differ
+/// and the responses to a batch may be in any order. Set a
+/// conformance with
+/// [with_conformance()](crate::Server::with_conformance) so errors for
+/// notifications are not answered:
+///
+/// ```
+/// use json_rpc2::{conformance::{check_server, Conformance}, *};
+/// use serde_json::{json, Value};
+///
+/// struct Spec;
+/// impl Service for Spec {
+///     type Data = ();
+///     fn handle(&self, request: &Request, _ctx: &()) -> Result<Option<Response>> {
+///         let result = match request.method() {
+///             "subtract" => {
+///                 let (a, b) = match request.params() {
+///                     Some(Value::Object(named)) => (named["minuend"].clone(), named["subtrahend"].clone()),
+///                     _ => request.deserialize::<(Value, Value)>()?,
+///                 };
+///                 json!(a.as_i64().unwrap() - b.as_i64().unwrap())
+///             }
+///             "sum" | "notify_sum" => json!(request.deserialize::<Vec<i64>>()?.iter().sum::<i64>()),
+///             "get_data" => json!(["hello", 5]),
+///             "update" | "notify_hello" => Value::Null,
+///             _ => return Ok(None),
+///         };
+///         Ok(Some((request, result).into()))
+///     }
+/// }
+///
+/// let service: Box<dyn Service<Data = ()>> = Box::new(Spec);
+/// let server = Server::new(vec![&service]).with_conformance(Conformance::strict());
+/// check_server(&server, &()).assert_ok();
+/// ```
+pub fn check_server<T>(server: &Server<'_, T>, ctx: &T) -> Report {
+    let conformance = server.conformance.clone().unwrap_or_default();
+    check_with(|payload| serve_payload(server, &conformance, payload, ctx))
+}
+
+/// Run the [examples()](examples) passing each input to `serve`, which
+/// returns the serialized response.
+///
+/// Use this for the async server or to test a whole stack, such as a
+/// server behind an HTTP endpoint.
+pub fn check_with<F>(mut serve: F) -> Report
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let examples = examples();
+    let mut report = Report {
+        examples: examples.len(),
+        failures: Vec::new(),
+    };
+    for example in examples {
+        let actual = serve(&example.input);
+        let parsed = actual
+            .as_ref()
+            .map(|output| serde_json::from_str::<Value>(output).ok());
+        let passed = match (&example.expected, parsed) {
+            (None, None) => true,
+            (Some(expected), Some(Some(actual))) => {
+                summary(expected) == summary(&actual)
+            }
+            _ => false,
+        };
+        if !passed {
+            report.failures.push(Failure { example, actual });
+        }
+    }
+    report
+}
+
+/// Serve a payload that may be a batch.
+fn serve_payload<T>(
+    server: &Server<'_, T>,
+    conformance: &Conformance,
+    payload: &str,
+    ctx: &T,
+) -> Option<String> {
+    let respond = |parsed: Result<Request>| match parsed {
+        Ok(request) => server.serve(&request, ctx),
+        Err(e) => Some(Response::from(e)),
+    };
+    let output = match serde_json::from_str::<Value>(payload) {
+        Ok(Value::Array(items)) if items.is_empty() => serde_json::to_string(
+            &respond(Err(invalid_request(None, "empty batch".into())))?,
+        ),
+        Ok(Value::Array(items)) => {
+            let responses: Vec<Response> = items
+                .into_iter()
+                .filter_map(|item| respond(conformance.parse_value(item)))
+                .collect();
+            if responses.is_empty() {
+                return None;
+            }
+            serde_json::to_string(&responses)
+        }
+        _ => serde_json::to_string(&respond(conformance.parse_str(payload))?),
+    };
+    output.ok()
+}
+
+/// The fields of a response that do not depend on the wording of
+/// messages, the responses to a batch are sorted.
+fn summary(value: &Value) -> Value {
+    let one = |response: &Value| match response.get("error") {
+        Some(error) => {
+            json!({"id": response.get("id"), "code": error.get("code")})
+        }
+        None => {
+            json!({"id": response.get("id"), "result": response.get("result")})
+        }
+    };
+    match value {
+        Value::Array(items) => {
+            let mut items: Vec<Value> = items.iter().map(one).collect();
+            items.sort_by_cached_key(|item| item.to_string());
+            Value::Array(items)
+        }
+        response => one(response),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Response, Server, Service, INVALID_REQUEST, METHOD_NOT_FOUND};
+
+    /// Methods used by the examples in the specification.
+    struct Examples;
+    impl Service for Examples {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let params = request.params().clone().unwrap_or(Value::Null);
+            let result = match request.method() {
+                "subtract" => match &params {
+                    Value::Array(_) => {
+                        let (a, b): (i64, i64) = request.deserialize()?;
+                        json!(a - b)
+                    }
+                    _ => json!(
+                        params["minuend"].as_i64().unwrap()
+                            - params["subtrahend"].as_i64().unwrap()
+                    ),
+                },
+                "sum" | "notify_sum" => {
+                    let values: Vec<i64> = request.deserialize()?;
+                    json!(values.iter().sum::<i64>())
+                }
+                "get_data" => json!(["hello", 5]),
+                "update" | "notify_hello" => Value::Null,
+                _ => return Ok(None),
+            };
+            Ok(Some((request, result).into()))
+        }
+    }
+
+    /// Serve a payload the way a transport would, including batches.
+    fn serve(conformance: &Conformance, payload: &str) -> Option<Value> {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Examples);
+        let server =
+            Server::new(vec![&service]).with_conformance(conformance.clone());
+        let output = serve_payload(&server, conformance, payload, &())?;
+        Some(summary(&serde_json::from_str(&output).unwrap()))
+    }
+
+    #[test]
+    fn conformance_specification_examples() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Examples);
+        let server =
+            Server::new(vec![&service]).with_conformance(Conformance::strict());
+        let report = check_server(&server, &());
+        report.assert_ok();
+        assert_eq!(15, report.examples);
+    }
+
+    #[test]
+    fn conformance_report_failures() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Examples);
+        // Without a conformance errors for notifications are answered.
+        let server = Server::new(vec![&service]);
+        let report = check_server(&server, &());
+        let names: Vec<&str> = report
+            .failures
+            .iter()
+            .map(|failure| failure.example.name.as_str())
+            .collect();
+        assert_eq!(vec!["notification without parameters"], names);
+        assert!(report.to_string().starts_with("1 of 15 examples failed"));
+
+        // Batch responses may be in any order but must all be present.
+        let strict = Conformance::strict();
+        let server =
+            Server::new(vec![&service]).with_conformance(strict.clone());
+        let report = check_with(|payload| {
+            let output = serve_payload(&server, &strict, payload, &())?;
+            let mut output: Value = serde_json::from_str(&output).unwrap();
+            if let Value::Array(items) = &mut output {
+                items.reverse();
+                if items.len() > 3 {
+                    items.pop();
+                }
+            }
+            Some(output.to_string())
+        });
+        let names: Vec<&str> = report
+            .failures
+            .iter()
+            .map(|failure| failure.example.name.as_str())
+            .collect();
+        assert_eq!(vec!["mixed batch"], names);
+    }
+
+    #[test]
+    fn conformance_presets() {
+        let invalid = Some(json!({"id": 1, "code": INVALID_REQUEST}));
+        let cases = vec![
+            (
+                r#"{"jsonrpc":"1.0","id":1,"method":"update"}"#,
+                invalid.clone(),
+                Some(json!({"id": 1, "result": null})),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"update","params":7}"#,
+                invalid.clone(),
+                Some(json!({"id": 1, "result": null})),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"update","extra":true}"#,
+                invalid.clone(),
+                Some(json!({"id": 1, "result": null})),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"rpc.update"}"#,
+                invalid,
+                Some(json!({"id": 1, "code": METHOD_NOT_FOUND})),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"rpc.ping"}"#,
+                Some(json!({"id": 1, "code": METHOD_NOT_FOUND})),
+                Some(json!({"id": 1, "code": METHOD_NOT_FOUND})),
+            ),
+        ];
+        for (payload, strict, lenient) in cases {
+            assert_eq!(
+                strict,
+                serve(&Conformance::strict(), payload),
+                "{}",
+                payload
+            );
+            assert_eq!(
+                lenient,
+                serve(&Conformance::lenient(), payload),
+                "{}",
+                payload
+            );
+        }
+
+        let payload = r#"{"jsonrpc":"2.0","id":1,"method":"rpc.update"}"#;
+        let conformance = Conformance::strict().extension("rpc.update");
+        assert!(conformance.parse_str(payload).is_ok());
+        assert!(Conformance::default().parse_str(payload).is_ok());
+    }
+
+    #[test]
+    fn conformance_server() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Examples);
+        let server =
+            Server::new(vec![&service]).with_conformance(Conformance::strict());
+
+        let request = Request::new(Some(json!(1)), "update".into(), None);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(&Some(Value::Null), response.result());
+
+        let request =
+            Request::new(Some(json!([1])), "update".into(), Some(json!(1)));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(&Some(Value::Null), response.id());
+        assert_eq!(INVALID_REQUEST, response.error().clone().unwrap().code);
+
+        let request = Request::new_notification("rpc.update", None);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(INVALID_REQUEST, response.error().clone().unwrap().code);
+    }
+}
@@ -0,0 +1,424 @@
+//! Deserialize requests in a single pass.
+//!
+//! The derived implementation allocates the version and method name
+//! before copying them into the request and, with the `extra-fields`
+//! feature, buffers every unknown field before converting it. This
+//! implementation reads the known fields directly into the request,
+//! borrows the version and reuses recently parsed method names while
+//! producing the same values and errors as the derive.
+
+use crate::{Request, VERSION};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+const FIELDS: &[&str] = &["jsonrpc", "method", "id", "params", "_meta"];
+
+/// Known fields of a request.
+enum Field {
+    Jsonrpc,
+    Method,
+    Id,
+    Params,
+    Meta,
+    #[cfg(feature = "extra-fields")]
+    Other(String),
+    #[cfg(not(feature = "extra-fields"))]
+    Other,
+}
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_identifier(FieldVisitor)
+    }
+}
+
+struct FieldVisitor;
+
+impl<'de> Visitor<'de> for FieldVisitor {
+    type Value = Field;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("field identifier")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Field, E>
+    where
+        E: de::Error,
+    {
+        Ok(match value {
+            "jsonrpc" => Field::Jsonrpc,
+            "method" => Field::Method,
+            "id" => Field::Id,
+            "params" => Field::Params,
+            "_meta" => Field::Meta,
+            #[cfg(feature = "extra-fields")]
+            _ => Field::Other(value.to_string()),
+            #[cfg(not(feature = "extra-fields"))]
+            _ => Field::Other,
+        })
+    }
+}
+
+/// Version string that borrows the expected version.
+struct Version(Cow<'static, str>);
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(VersionVisitor)
+    }
+}
+
+struct VersionVisitor;
+
+impl<'de> Visitor<'de> for VersionVisitor {
+    type Value = Version;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Version, E>
+    where
+        E: de::Error,
+    {
+        Ok(Version(if value == VERSION {
+            Cow::Borrowed(VERSION)
+        } else {
+            Cow::Owned(value.to_string())
+        }))
+    }
+}
+
+/// Method name allocated once as shared string.
+struct Method(Arc<str>);
+
+impl<'de> Deserialize<'de> for Method {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(MethodVisitor)
+    }
+}
+
+struct MethodVisitor;
+
+impl<'de> Visitor<'de> for MethodVisitor {
+    type Value = Method;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Method, E>
+    where
+        E: de::Error,
+    {
+        Ok(Method(recent_method(value)))
+    }
+}
+
+/// Number of method names remembered by each thread.
+const RECENT_METHODS: usize = 8;
+
+/// Longest method name that is remembered.
+const RECENT_METHOD_LEN: usize = 64;
+
+thread_local! {
+    static RECENT: RefCell<(usize, [Option<Arc<str>>; RECENT_METHODS])> =
+        RefCell::new(Default::default());
+}
+
+/// Share a method name recently parsed on this thread or allocate it.
+///
+/// Services answer a small set of method names so the last few names
+/// are kept to avoid allocating a copy for every request.
+fn recent_method(value: &str) -> Arc<str> {
+    if value.len() > RECENT_METHOD_LEN {
+        return Arc::from(value);
+    }
+    RECENT
+        .try_with(|recent| {
+            let (next, names) = &mut *recent.borrow_mut();
+            if let Some(name) = names.iter().flatten().find(|n| &***n == value)
+            {
+                return Arc::clone(name);
+            }
+            let name: Arc<str> = Arc::from(value);
+            names[*next] = Some(Arc::clone(&name));
+            *next = (*next + 1) % RECENT_METHODS;
+            name
+        })
+        .unwrap_or_else(|_| Arc::from(value))
+}
+
+impl<'de> Deserialize<'de> for Request {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Like the derive a struct with flattened fields is only
+        // read from a map.
+        #[cfg(feature = "extra-fields")]
+        return deserializer.deserialize_map(RequestVisitor);
+        #[cfg(not(feature = "extra-fields"))]
+        return deserializer.deserialize_struct(
+            "Request",
+            FIELDS,
+            RequestVisitor,
+        );
+    }
+}
+
+struct RequestVisitor;
+
+impl<'de> Visitor<'de> for RequestVisitor {
+    type Value = Request;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("struct Request")
+    }
+
+    #[cfg(not(feature = "extra-fields"))]
+    fn visit_seq<A>(self, mut seq: A) -> Result<Request, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let expected = &"struct Request with 5 elements";
+        let jsonrpc = seq
+            .next_element::<Version>()?
+            .ok_or_else(|| de::Error::invalid_length(0, expected))?;
+        let method = seq
+            .next_element::<Method>()?
+            .ok_or_else(|| de::Error::invalid_length(1, expected))?;
+        let id = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(2, expected))?;
+        let params = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(3, expected))?;
+        let meta = seq.next_element()?.unwrap_or_default();
+        Ok(Request {
+            jsonrpc: jsonrpc.0,
+            method: method.0,
+            id,
+            params,
+            meta,
+            received: None,
+            extensions: Default::default(),
+        })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Request, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut jsonrpc: Option<Version> = None;
+        let mut method: Option<Method> = None;
+        let mut id: Option<Option<Value>> = None;
+        let mut params: Option<Option<Value>> = None;
+        let mut meta: Option<Option<Value>> = None;
+        #[cfg(feature = "extra-fields")]
+        let mut extra = serde_json::Map::new();
+
+        while let Some(field) = map.next_key::<Field>()? {
+            match field {
+                Field::Jsonrpc => {
+                    if jsonrpc.is_some() {
+                        return Err(de::Error::duplicate_field(FIELDS[0]));
+                    }
+                    jsonrpc = Some(map.next_value()?);
+                }
+                Field::Method => {
+                    if method.is_some() {
+                        return Err(de::Error::duplicate_field(FIELDS[1]));
+                    }
+                    method = Some(map.next_value()?);
+                }
+                Field::Id => {
+                    if id.is_some() {
+                        return Err(de::Error::duplicate_field(FIELDS[2]));
+                    }
+                    id = Some(map.next_value()?);
+                }
+                Field::Params => {
+                    if params.is_some() {
+                        return Err(de::Error::duplicate_field(FIELDS[3]));
+                    }
+                    params = Some(map.next_value()?);
+                }
+                Field::Meta => {
+                    if meta.is_some() {
+                        return Err(de::Error::duplicate_field(FIELDS[4]));
+                    }
+                    meta = Some(map.next_value()?);
+                }
+                #[cfg(feature = "extra-fields")]
+                Field::Other(name) => {
+                    extra.insert(name, map.next_value()?);
+                }
+                #[cfg(not(feature = "extra-fields"))]
+                Field::Other => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        let jsonrpc =
+            jsonrpc.ok_or_else(|| de::Error::missing_field(FIELDS[0]))?;
+        let method =
+            method.ok_or_else(|| de::Error::missing_field(FIELDS[1]))?;
+        Ok(Request {
+            jsonrpc: jsonrpc.0,
+            method: method.0,
+            id: id.unwrap_or_default(),
+            params: params.unwrap_or_default(),
+            meta: meta.unwrap_or_default(),
+            received: None,
+            extensions: Default::default(),
+            #[cfg(feature = "extra-fields")]
+            extra,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    mod derived {
+        use serde_json::Value;
+        use std::sync::Arc;
+
+        /// Request as deserialized by the derive.
+        #[derive(serde::Deserialize, Debug)]
+        pub struct Request {
+            pub jsonrpc: String,
+            pub method: Arc<str>,
+            pub id: Option<Value>,
+            pub params: Option<Value>,
+            #[serde(rename = "_meta", default)]
+            pub meta: Option<Value>,
+            #[cfg(feature = "extra-fields")]
+            #[serde(flatten)]
+            pub extra: serde_json::Map<String, Value>,
+        }
+    }
+
+    const PAYLOADS: &[&str] = &[
+        r#"{"jsonrpc":"2.0","method":"ping","id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"ping"}"#,
+        r#"{"method":"sum","params":[1,2],"id":"a","jsonrpc":"2.0"}"#,
+        r#"{"jsonrpc":"1.0","method":"ping","id":null,"params":null}"#,
+        r#"{"jsonrpc":"2.0","method":"ping","_meta":{"trace":"x"}}"#,
+        r#"{"jsonrpc":"2.0","method":"ping","x":1,"y":{"z":[true]},"x":2}"#,
+        r#"{"jsonrpc":"2.0","method":"péng\n"}"#,
+        r#"{}"#,
+        r#"{"jsonrpc":"2.0"}"#,
+        r#"{"method":"ping"}"#,
+        r#"{"jsonrpc":2,"method":"ping"}"#,
+        r#"{"jsonrpc":"2.0","method":null}"#,
+        r#"{"jsonrpc":"2.0","method":["ping"]}"#,
+        r#"{"jsonrpc":"2.0","jsonrpc":"2.0","method":"ping"}"#,
+        r#"{"jsonrpc":"2.0","method":"ping","method":"pong"}"#,
+        r#"{"jsonrpc":"2.0","method":"ping","id":1,"id":2}"#,
+        r#"{"jsonrpc":"2.0","method":"ping","params":[],"params":[]}"#,
+        r#"{"jsonrpc":"2.0","method":"ping","_meta":1,"_meta":2}"#,
+        r#"{"jsonrpc":"2.0","method":"ping""#,
+        r#"{"jsonrpc":"2.0","method":"ping",}"#,
+        r#"{"jsonrpc":"2.0","method":"ping"} x"#,
+        r#"["2.0","ping",1,null]"#,
+        r#"["2.0","ping",1,null,{}]"#,
+        r#"["2.0","ping"]"#,
+        r#"["2.0","ping",1,null,{},5]"#,
+        r#"[]"#,
+        r#""ping""#,
+        r#"null"#,
+        r#"42"#,
+    ];
+
+    #[test]
+    fn de_matches_derive() {
+        for payload in PAYLOADS {
+            let derived = serde_json::from_str::<derived::Request>(payload);
+            let request = serde_json::from_str::<Request>(payload);
+            match (derived, request) {
+                (Ok(derived), Ok(request)) => {
+                    assert_eq!(derived.jsonrpc, &*request.jsonrpc);
+                    assert_eq!(derived.method, request.method);
+                    assert_eq!(derived.id, request.id);
+                    assert_eq!(derived.params, request.params);
+                    assert_eq!(derived.meta, request.meta);
+                    #[cfg(feature = "extra-fields")]
+                    assert_eq!(derived.extra, request.extra);
+                }
+                (Err(derived), Err(request)) => {
+                    assert_eq!(
+                        derived.to_string(),
+                        request.to_string(),
+                        "{}",
+                        payload
+                    );
+                    assert_eq!(derived.classify(), request.classify());
+                }
+                (derived, request) => panic!(
+                    "{}: derive {:?}, request {:?}",
+                    payload, derived, request
+                ),
+            }
+
+            let value = match serde_json::from_str::<Value>(payload) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            let derived =
+                serde_json::from_value::<derived::Request>(value.clone());
+            let request = serde_json::from_value::<Request>(value);
+            assert_eq!(
+                derived.map(|_| ()).map_err(|e| e.to_string()),
+                request.map(|_| ()).map_err(|e| e.to_string()),
+                "{}",
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn de_shares_names() {
+        let parse = |payload: String| -> Request {
+            serde_json::from_str(&payload).unwrap()
+        };
+        let first = parse(r#"{"jsonrpc":"2.0","method":"a"}"#.to_string());
+        let second = parse(r#"{"jsonrpc":"2.0","method":"a"}"#.to_string());
+        assert!(Arc::ptr_eq(&first.method, &second.method));
+
+        let long = "m".repeat(RECENT_METHOD_LEN + 1);
+        let payload = format!(r#"{{"jsonrpc":"2.0","method":"{}"}}"#, long);
+        let first = parse(payload.clone());
+        let second = parse(payload);
+        assert_eq!(first.method, second.method);
+        assert!(!Arc::ptr_eq(&first.method, &second.method));
+    }
+
+    #[test]
+    fn de_borrows_version() {
+        let request: Request =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "method": "a"}))
+                .unwrap();
+        assert!(matches!(request.jsonrpc, Cow::Borrowed(_)));
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc":"1.0","method":"a"}"#).unwrap();
+        assert_eq!("1.0", request.jsonrpc);
+    }
+}
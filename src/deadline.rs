@@ -0,0 +1,186 @@
+//! Deadlines sent by clients in the request metadata.
+//!
+//! Clients put an absolute deadline, in milliseconds since the Unix
+//! epoch, in the `deadline_ms` field of the `_meta` object. Handlers
+//! read it with [Deadline::from_request()](Deadline::from_request) to
+//! pass the remaining budget to downstream calls.
+//!
+//! The async server stops handlers when the deadline passes after
+//! [with_deadlines()](crate::futures::Server::with_deadlines) and replies
+//! with the `-32000` deadline exceeded error; a deadline that has
+//! already passed when the request arrives, for example because the
+//! clocks of the client and server disagree, fails immediately. The
+//! blocking server cannot interrupt handlers but can reject expired
+//! requests with the [reject_expired()](reject_expired) validator.
+
+use crate::{Error, Request, Response, Result, RpcError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Name of the deadline field in the metadata.
+pub const DEADLINE_MS: &str = "deadline_ms";
+
+/// Error code for requests whose deadline has passed.
+pub const DEADLINE_EXCEEDED: isize = -32000;
+
+/// Create the error for a request whose deadline has passed.
+pub fn exceeded() -> RpcError {
+    RpcError {
+        code: DEADLINE_EXCEEDED,
+        message: "Deadline exceeded".into(),
+        data: None,
+    }
+}
+
+/// The instant by which a request should be answered.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    /// Deadline of a request from the `deadline_ms` metadata field.
+    pub fn from_request(request: &Request) -> Option<Self> {
+        let millis = request.meta()?.get(DEADLINE_MS)?.as_u64()?;
+        let deadline = UNIX_EPOCH + Duration::from_millis(millis);
+        let now = Instant::now();
+        Some(match deadline.duration_since(SystemTime::now()) {
+            Ok(remaining) => Deadline(now + remaining),
+            Err(_) => Deadline(now),
+        })
+    }
+
+    /// The time left before the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Determine if the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Duration::ZERO
+    }
+}
+
+/// The result for a request whose deadline has passed, notifications
+/// are not answered.
+pub(crate) fn exceeded_result(request: &Request) -> Result<Response> {
+    match request.id() {
+        Some(id) => Err(Error::Timeout {
+            method: Some(request.method().to_string()),
+            elapsed: None,
+            id: Some(id.clone()),
+        }),
+        None => Ok(request.into()),
+    }
+}
+
+/// Validator that rejects requests whose deadline has passed, use with
+/// [Server::with_validator()](crate::Server::with_validator).
+pub fn reject_expired<T>(
+    request: &Request,
+    _ctx: &T,
+) -> std::result::Result<(), RpcError> {
+    match Deadline::from_request(request) {
+        Some(deadline) if deadline.is_expired() => Err(exceeded()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Result, Server, Service};
+    use serde_json::{json, Value};
+
+    fn with_deadline(millis: u128) -> Request {
+        let mut request = Request::new_reply("sleep", None);
+        request.set_meta(json!({ DEADLINE_MS: millis })).unwrap();
+        request
+    }
+
+    fn now_ms() -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+    }
+
+    struct Echo;
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let remaining = Deadline::from_request(request)
+                .map(|deadline| deadline.remaining().as_secs());
+            Ok(Some((request, json!(remaining)).into()))
+        }
+    }
+
+    #[test]
+    fn deadline_from_meta() {
+        let deadline =
+            Deadline::from_request(&with_deadline(now_ms() + 60_000)).unwrap();
+        assert!(!deadline.is_expired());
+        assert!(deadline.remaining() > Duration::from_secs(50));
+
+        let deadline = Deadline::from_request(&with_deadline(1)).unwrap();
+        assert!(deadline.is_expired());
+        assert!(Deadline::from_request(&Request::new_reply("sleep", None))
+            .is_none());
+    }
+
+    #[test]
+    fn deadline_reject_expired() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let server = Server::new(vec![&service]).with_validator(reject_expired);
+
+        let response = server.serve(&with_deadline(1), &()).unwrap();
+        assert_eq!(DEADLINE_EXCEEDED, response.error().clone().unwrap().code);
+
+        let request = with_deadline(now_ms() + 60_000);
+        let response = server.serve(&request, &()).unwrap();
+        assert!(matches!(response.result(), Some(Value::Number(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_async_server() {
+        use crate::futures::{Server, Service};
+
+        struct Sleep;
+
+        #[async_trait::async_trait]
+        impl Service for Sleep {
+            type Data = ();
+            async fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                tokio::time::sleep(Duration::from_secs(120)).await;
+                Ok(Some((request, Value::Bool(true)).into()))
+            }
+        }
+
+        let service: Box<dyn Service<Data = ()>> = Box::new(Sleep);
+        let server = Server::new(vec![&service]).with_deadlines();
+
+        let request = with_deadline(1);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(request.id(), response.id());
+        assert_eq!(DEADLINE_EXCEEDED, response.error().clone().unwrap().code);
+
+        let response = server
+            .serve(&with_deadline(now_ms() + 60_000), &())
+            .await
+            .unwrap();
+        assert_eq!(DEADLINE_EXCEEDED, response.error().clone().unwrap().code);
+
+        let request = with_deadline(now_ms() + 600_000);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(&Some(Value::Bool(true)), response.result());
+
+        let mut notification = Request::new_notification("sleep", None);
+        notification.set_meta(json!({ DEADLINE_MS: 1 })).unwrap();
+        assert!(server.serve(&notification, &()).await.is_none());
+    }
+}
@@ -0,0 +1,261 @@
+//! Backtraces and error sources in internal error responses.
+//!
+//! Internal errors are answered without any detail by default. A server
+//! built with [with_debug_errors()](crate::Server::with_debug_errors)
+//! answers an `Error::Boxed` from a service with the source chain of the
+//! error and, when it was captured, the backtrace in the data:
+//!
+//! ```json
+//! {"sources": ["payment failed", "connection refused"], "backtrace": "..."}
+//! ```
+//!
+//! Backtraces are captured for the `Error::Boxed` created by converting
+//! a boxed error while a service of that server handles a request, so
+//! errors elsewhere in the process are left untouched. An async server
+//! captures while it polls the handler, errors created in tasks the
+//! handler spawns have no backtrace. Never enable debug errors where
+//! the data must not reach clients.
+
+use crate::Error;
+use serde_json::{Map, Value};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::Cell;
+use std::fmt;
+#[cfg(any(test, feature = "async"))]
+use std::{
+    future::{poll_fn, Future},
+    pin::pin,
+};
+
+/// Key in the error data for the source chain.
+pub const SOURCES: &str = "sources";
+
+/// Key in the error data for the backtrace.
+pub const BACKTRACE: &str = "backtrace";
+
+thread_local! {
+    static CAPTURE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether backtraces are captured for boxed errors created on this
+/// thread.
+pub fn is_capturing() -> bool {
+    CAPTURE.with(Cell::get)
+}
+
+/// Call `f` capturing backtraces for boxed errors when `enabled`.
+pub(crate) fn capturing<R>(enabled: bool, f: impl FnOnce() -> R) -> R {
+    if !enabled {
+        return f();
+    }
+    /// Restores the previous value even if `f` panics.
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CAPTURE.with(|capture| capture.set(self.0));
+        }
+    }
+    let _restore = Restore(CAPTURE.with(|capture| capture.replace(true)));
+    f()
+}
+
+/// Poll `future` capturing backtraces for boxed errors when `enabled`.
+#[cfg(any(test, feature = "async"))]
+pub(crate) async fn capturing_future<F: Future>(
+    enabled: bool,
+    future: F,
+) -> F::Output {
+    let mut future = pin!(future);
+    poll_fn(|cx| capturing(enabled, || future.as_mut().poll(cx))).await
+}
+
+/// Boxed error with the backtrace of where it was converted.
+///
+/// Displays as the inner error and has the same sources.
+pub(crate) struct Traced {
+    pub(crate) error: Box<dyn std::error::Error + Send + Sync>,
+    pub(crate) backtrace: Backtrace,
+}
+
+impl fmt::Debug for Traced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for Traced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Traced {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Attach a backtrace to a boxed error when capturing is enabled.
+pub(crate) fn capture(
+    error: Box<dyn std::error::Error + Send + Sync>,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    if is_capturing() && !error.is::<Traced>() {
+        Box::new(Traced {
+            error,
+            backtrace: Backtrace::force_capture(),
+        })
+    } else {
+        error
+    }
+}
+
+/// The boxed error without a captured backtrace.
+pub(crate) fn inner<'a>(
+    error: &'a (dyn std::error::Error + Send + Sync + 'static),
+) -> &'a (dyn std::error::Error + Send + Sync + 'static) {
+    match error.downcast_ref::<Traced>() {
+        Some(traced) => &*traced.error,
+        None => error,
+    }
+}
+
+/// Data describing an `Error::Boxed`, `None` for other errors.
+pub(crate) fn data(error: &Error) -> Option<Value> {
+    let error = match error {
+        Error::Boxed(error) => error,
+        _ => return None,
+    };
+    let mut sources = Vec::new();
+    let mut source: Option<&(dyn std::error::Error + 'static)> =
+        Some(inner(&**error));
+    while let Some(error) = source {
+        sources.push(Value::String(error.to_string()));
+        source = error.source();
+    }
+
+    let mut data = Map::new();
+    data.insert(SOURCES.to_string(), Value::Array(sources));
+    if let Some(traced) = error.downcast_ref::<Traced>() {
+        if traced.backtrace.status() == BacktraceStatus::Captured {
+            data.insert(
+                BACKTRACE.to_string(),
+                Value::String(traced.backtrace.to_string()),
+            );
+        }
+    }
+    Some(Value::Object(data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Request, Response, Result, Server, Service};
+    use serde_json::json;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("payment failed")]
+    struct Payment(#[source] std::io::Error);
+
+    struct Failing;
+    impl Service for Failing {
+        type Data = ();
+        fn handle(
+            &self,
+            _request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let error = std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                "connection refused",
+            );
+            let error: Box<dyn std::error::Error + Send + Sync> =
+                Box::new(Payment(error));
+            Err(error.into())
+        }
+    }
+
+    #[test]
+    fn debug_errors() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Failing);
+        let request = Request::new_reply("pay", None);
+
+        let server = Server::new(vec![&service]);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(None, response.error().as_ref().unwrap().data);
+
+        let server = Server::new(vec![&service]).with_debug_errors(true);
+        let response = server.serve(&request, &()).unwrap();
+        let error = response.error().as_ref().unwrap();
+        assert_eq!("payment failed", error.message);
+        let data = error.data.as_ref().unwrap();
+        assert_eq!(
+            &json!(["payment failed", "connection refused"]),
+            &data[SOURCES]
+        );
+        assert!(data[BACKTRACE].as_str().unwrap().contains("Failing"));
+    }
+
+    #[test]
+    fn debug_errors_scoped() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Failing);
+        let request = Request::new_reply("pay", None);
+        let debug = Server::new(vec![&service]).with_debug_errors(true);
+        let server = Server::new(vec![&service]);
+        debug.serve(&request, &()).unwrap();
+        assert!(!is_capturing());
+
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(None, response.error().as_ref().unwrap().data);
+        let error: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(std::fmt::Error);
+        match Error::from(error) {
+            Error::Boxed(error) => assert!(error.is::<std::fmt::Error>()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn debug_errors_async() {
+        use crate::futures;
+
+        struct Failing;
+        #[async_trait::async_trait]
+        impl futures::Service for Failing {
+            type Data = ();
+            async fn handle(
+                &self,
+                _request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                tokio::task::yield_now().await;
+                Err(Error::from(Box::from("payment failed")))
+            }
+        }
+
+        let service: Box<dyn futures::Service<Data = ()>> = Box::new(Failing);
+        let request = Request::new_reply("pay", None);
+        let server = futures::Server::new(vec![&service]);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(None, response.error().as_ref().unwrap().data);
+
+        let server = server.with_debug_errors(true);
+        let response = server.serve(&request, &()).await.unwrap();
+        let data = response.error().as_ref().unwrap().data.clone().unwrap();
+        assert!(data[BACKTRACE].as_str().unwrap().contains("Failing"));
+        assert!(!is_capturing());
+    }
+
+    #[test]
+    fn debug_errors_downcast() {
+        let error: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(Payment(std::io::Error::from(
+                std::io::ErrorKind::ConnectionRefused,
+            )));
+        let error = capturing(true, || Error::from(error));
+        assert!(error.is::<Payment>());
+        assert_eq!("payment failed", error.to_string());
+        let error = error.downcast::<std::fmt::Error>().unwrap_err();
+        assert!(data(&error).unwrap().get(BACKTRACE).is_some());
+        assert!(error.downcast::<Payment>().is_ok());
+    }
+}
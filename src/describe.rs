@@ -0,0 +1,335 @@
+//! Human-facing descriptions of methods.
+//!
+//! Services describe their methods with
+//! [Service::method_meta()](crate::Service::method_meta); by default
+//! each method is described by its name only.
+//! [method_with_meta()](crate::method::method_with_meta) registers a
+//! function together with its [MethodMeta](MethodMeta) and
+//! [Server::method_meta()](crate::Server::method_meta) collects the
+//! descriptions of every service, for example to generate
+//! documentation:
+//!
+//! ```
+//! use json_rpc2::{
+//!     describe::{MethodMeta, ParamMeta},
+//!     method::method_with_meta,
+//!     Request, Result, Server, Service,
+//! };
+//! use serde_json::json;
+//!
+//! fn add((a, b): (u64, u64), _: &()) -> Result<u64> {
+//!     Ok(a + b)
+//! }
+//!
+//! let meta = MethodMeta::new("add")
+//!     .summary("Add two numbers")
+//!     .param(ParamMeta::new("a"))
+//!     .param(ParamMeta::new("b"))
+//!     .deprecated("use sum");
+//! let service: Box<dyn Service<Data = ()>> =
+//!     Box::new(method_with_meta(meta, add));
+//! let server = Server::new(vec![&service]);
+//! let described = server.method_meta();
+//! assert_eq!(Some("use sum"), described[0].deprecated.as_deref());
+//!
+//! let request = Request::new_reply("add", Some(json!([1, 2])));
+//! let response = server.serve(&request, &()).unwrap();
+//! assert_eq!(Some(json!(3)), response.into());
+//! ```
+//!
+//! A server logs a warning whenever a deprecated method is called. With
+//! the `extra-fields` feature,
+//! [with_deprecation_warnings()](crate::Server::with_deprecation_warnings)
+//! also attaches the notice to the response in a top-level `_meta`
+//! object, read it back with [warning()](warning):
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "id": 1, "result": 3, "_meta": {"warning": "add is deprecated: use sum"}}
+//! ```
+//!
+//! A server built [with_methods()](crate::Server::with_methods) also
+//! answers `rpc.methods` called with `{"describe": true}` with the
+//! descriptions, so clients can discover the summaries and deprecation
+//! notices of a running server.
+//!
+//! Deprecations are read when services are added to the server, so the
+//! methods of a [Registry](crate::registry::Registry) are not checked.
+//!
+//! Uses the `log` facade by default or `tracing` when the `tracing`
+//! feature is enabled.
+
+use crate::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(not(feature = "tracing"))]
+use log::warn as log_warn;
+#[cfg(feature = "tracing")]
+use tracing::warn as log_warn;
+
+/// Name of the deprecation notice in the response metadata.
+pub const WARNING: &str = "warning";
+
+/// Description of a method.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct MethodMeta {
+    /// The name of the method.
+    pub name: String,
+    /// One line summary of the method.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// The parameters in positional order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<ParamMeta>,
+    /// Note for a deprecated method such as the method to call
+    /// instead, `None` unless the method is deprecated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<String>,
+}
+
+impl MethodMeta {
+    /// Describe the method `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the summary.
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Add a parameter after those already added.
+    pub fn param(mut self, param: ParamMeta) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    /// Mark the method deprecated.
+    pub fn deprecated(mut self, note: impl Into<String>) -> Self {
+        self.deprecated = Some(note.into());
+        self
+    }
+}
+
+/// Description of a method parameter.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct ParamMeta {
+    /// The name of the parameter.
+    pub name: String,
+    /// One line summary of the parameter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Whether the parameter must be given.
+    #[serde(default = "required")]
+    pub required: bool,
+}
+
+fn required() -> bool {
+    true
+}
+
+impl ParamMeta {
+    /// Describe the required parameter `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            summary: None,
+            required: true,
+        }
+    }
+
+    /// Set the summary.
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Mark the parameter optional.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+}
+
+/// Descriptions of the methods of several services sorted by name,
+/// keeping the first description of a method.
+pub(crate) fn list<I>(services: I) -> Vec<MethodMeta>
+where
+    I: IntoIterator<Item = Vec<MethodMeta>>,
+{
+    let mut methods: Vec<MethodMeta> = services.into_iter().flatten().collect();
+    // Stable so the first description of a method is kept
+    methods.sort_by(|a, b| a.name.cmp(&b.name));
+    methods.dedup_by(|later, first| later.name == first.name);
+    methods
+}
+
+/// The deprecation notice for a call to a deprecated method.
+#[cfg(feature = "extra-fields")]
+pub(crate) fn notice(request: &Request, note: &str) -> String {
+    format!("{} is deprecated: {}", request.method(), note)
+}
+
+/// Log a warning for a call to a deprecated method.
+pub(crate) fn warn(request: &Request, note: &str) {
+    let id = request.id().as_ref().unwrap_or(&Value::Null);
+    log_warn!("deprecated method {} id={}: {}", request.method(), id, note);
+}
+
+/// The deprecation notice the server attached to a response.
+///
+/// Only available with the `extra-fields` feature.
+#[cfg(feature = "extra-fields")]
+pub fn warning(response: &crate::Response) -> Option<&str> {
+    response
+        .extra_fields()
+        .get(crate::meta::META)?
+        .get(WARNING)?
+        .as_str()
+}
+
+/// Attach a deprecation notice to the metadata in the top-level fields
+/// of a response.
+///
+/// A `_meta` field that is not an object is left untouched.
+#[cfg(feature = "extra-fields")]
+pub(crate) fn attach(
+    fields: &mut serde_json::Map<String, Value>,
+    notice: String,
+) {
+    let meta = fields
+        .entry(crate::meta::META)
+        .or_insert_with(|| Value::Object(Default::default()));
+    if let Value::Object(meta) = meta {
+        meta.insert(WARNING.to_string(), Value::String(notice));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        method::{method, method_with_meta},
+        namespace::Namespace,
+        Result, Server, Service,
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn add((a, b): (u64, u64), _: &()) -> Result<u64> {
+        Ok(a + b)
+    }
+
+    fn add_meta() -> MethodMeta {
+        MethodMeta::new("add")
+            .summary("Add two numbers")
+            .param(ParamMeta::new("a"))
+            .param(ParamMeta::new("b").optional())
+            .deprecated("use sum")
+    }
+
+    fn services() -> Vec<Arc<dyn Service<Data = ()>>> {
+        vec![
+            Arc::new(method_with_meta(add_meta(), add)),
+            Arc::new(method("sum", add)),
+            Arc::new(Namespace::new("math", method_with_meta(add_meta(), add))),
+            Arc::new(method("add", add)),
+        ]
+    }
+
+    #[test]
+    fn describe_methods() {
+        let server = Server::new_shared(services());
+        let described = server.method_meta();
+        let names: Vec<&str> =
+            described.iter().map(|meta| meta.name.as_str()).collect();
+        assert_eq!(vec!["add", "math.add", "sum"], names);
+        assert_eq!(add_meta(), described[0]);
+        assert_eq!(Some("use sum"), described[1].deprecated.as_deref());
+        assert_eq!(MethodMeta::new("sum"), described[2]);
+
+        let value = serde_json::to_value(&described[0]).unwrap();
+        assert_eq!(
+            json!({
+                "name": "add",
+                "summary": "Add two numbers",
+                "params": [
+                    {"name": "a", "required": true},
+                    {"name": "b", "required": false},
+                ],
+                "deprecated": "use sum",
+            }),
+            value
+        );
+        let meta: MethodMeta =
+            serde_json::from_value(json!({"name": "sum"})).unwrap();
+        assert_eq!(MethodMeta::new("sum"), meta);
+    }
+
+    #[test]
+    fn describe_rpc_methods() {
+        let server = Server::new_shared(services()).with_methods();
+        let listing = |params| {
+            let request = crate::Request::new_reply(crate::METHODS, params);
+            let response = server.serve(&request, &()).unwrap();
+            response.result().clone().unwrap()
+        };
+        assert_eq!(json!(["add", "math.add", "sum"]), listing(None));
+        assert_eq!(
+            json!(["add", "math.add", "sum"]),
+            listing(Some(json!({"describe": false})))
+        );
+        let described = listing(Some(json!({"describe": true})));
+        let described: Vec<MethodMeta> =
+            serde_json::from_value(described).unwrap();
+        assert_eq!(server.method_meta(), described);
+        assert_eq!(Some("use sum"), described[0].deprecated.as_deref());
+    }
+
+    #[cfg(feature = "extra-fields")]
+    #[test]
+    fn deprecation_warnings() {
+        let request = crate::Request::new_reply("add", Some(json!([1, 2])));
+        let server = Server::new_shared(services());
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(None, warning(&response));
+
+        let server = server.with_deprecation_warnings();
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(Some("add is deprecated: use sum"), warning(&response));
+        assert_eq!(Some(json!(3)), response.into());
+
+        let payload = serde_json::to_string(&request).unwrap();
+        let response = server.serve_str(&payload, &()).unwrap();
+        let response: crate::Response =
+            serde_json::from_str(&response).unwrap();
+        assert_eq!(Some("add is deprecated: use sum"), warning(&response));
+
+        let request = crate::Request::new_reply("sum", Some(json!([1, 2])));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(None, warning(&response));
+    }
+
+    #[cfg(feature = "extra-fields")]
+    #[tokio::test]
+    async fn deprecation_warnings_async() {
+        use crate::{futures, method::async_method_with_meta};
+
+        async fn add((a, b): (u64, u64), _: &()) -> Result<u64> {
+            Ok(a + b)
+        }
+
+        let service: Arc<dyn futures::Service<Data = ()>> =
+            Arc::new(async_method_with_meta(add_meta(), add));
+        let server = futures::Server::new_shared(vec![service])
+            .with_deprecation_warnings();
+        assert_eq!(vec![add_meta()], server.method_meta());
+        let request = crate::Request::new_reply("add", Some(json!([1, 2])));
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(Some("add is deprecated: use sum"), warning(&response));
+    }
+}
@@ -0,0 +1,130 @@
+//! Typed values attached to a request by the transport.
+//!
+//! [Extensions](Extensions) holds at most one value of each type and is
+//! never serialized. Transports insert values that are not part of the
+//! message, such as the [session](crate::session) of the connection or
+//! the address of the peer, and handlers read them from
+//! [Request::extensions()](crate::Request::extensions):
+//!
+//! ```
+//! use json_rpc2::Request;
+//!
+//! struct Peer(&'static str);
+//!
+//! let mut request = Request::new_reply("hello", None);
+//! request.extensions_mut().insert(Peer("127.0.0.1"));
+//! assert_eq!("127.0.0.1", request.extensions().get::<Peer>().unwrap().0);
+//! ```
+//!
+//! Values are reference counted so cloning a request shares them.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Map of values keyed by their type.
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Create empty extensions.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Insert a value, replacing any value of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.insert_arc(Arc::new(value));
+    }
+
+    /// Insert a shared value, replacing any value of the same type.
+    pub fn insert_arc<T: Send + Sync + 'static>(&mut self, value: Arc<T>) {
+        self.map.insert(TypeId::of::<T>(), value);
+    }
+
+    /// Get the value of a type.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Get the shared value of a type, for example to move into a task
+    /// that outlives the request.
+    pub fn get_arc<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| Arc::clone(value).downcast().ok())
+    }
+
+    /// Remove the value of a type, returns `false` when there was none.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> bool {
+        self.map.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Determine if there is a value of a type.
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Number of values.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Determine if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Request;
+
+    #[derive(Debug, PartialEq)]
+    struct User(String);
+
+    #[test]
+    fn extensions_by_type() {
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+        extensions.insert(User("alice".to_string()));
+        extensions.insert(7u32);
+        extensions.insert(8u32);
+        assert_eq!(2, extensions.len());
+        assert_eq!(Some(&8), extensions.get::<u32>());
+        assert_eq!(None, extensions.get::<u64>());
+        assert_eq!("alice", extensions.get_arc::<User>().unwrap().0.as_str());
+
+        let copy = extensions.clone();
+        assert!(extensions.remove::<User>());
+        assert!(!extensions.remove::<User>());
+        assert!(!extensions.contains::<User>());
+        assert!(copy.contains::<User>());
+    }
+
+    #[test]
+    fn extensions_not_serialized() {
+        let mut request = Request::new_reply("hello", None);
+        request.extensions_mut().insert(User("alice".to_string()));
+        let clone = request.clone();
+        assert!(clone.extensions().contains::<User>());
+        let text = serde_json::to_string(&request).unwrap();
+        assert!(!text.contains("alice"));
+        let parsed: Request = serde_json::from_str(&text).unwrap();
+        assert!(parsed.extensions().is_empty());
+    }
+}
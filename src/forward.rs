@@ -0,0 +1,210 @@
+//! Forward raw request payloads without re-encoding them.
+//!
+//! A proxy that passes requests through unchanged does not need to
+//! build a [Request](crate::Request). [Forwarder::parse()](Forwarder::parse)
+//! checks only the envelope, the version, method, id and the type of
+//! the parameters, while borrowing from the payload; the parameters are
+//! never decoded. [with_id()](Forwarder::with_id) then replaces the id
+//! by splicing the new id into the original payload:
+//!
+//! ```
+//! use json_rpc2::forward::Forwarder;
+//! use serde_json::json;
+//!
+//! let payload = r#"{"jsonrpc":"2.0","id":1,"method":"sum","params":[1,2]}"#;
+//! let forwarder = Forwarder::parse(payload).unwrap();
+//! assert_eq!("sum", forwarder.method());
+//! assert_eq!(
+//!     r#"{"jsonrpc":"2.0","id":"upstream-7","method":"sum","params":[1,2]}"#,
+//!     forwarder.with_id(&json!("upstream-7")).unwrap()
+//! );
+//! ```
+
+use crate::{
+    check_envelope, conformance::Conformance, map_payload_error, Error, Result,
+};
+use serde::{Deserialize, Deserializer};
+use serde_json::{value::RawValue, Value};
+use std::{borrow::Cow, ops::Range};
+
+#[derive(Deserialize)]
+struct Envelope<'a> {
+    #[serde(borrow)]
+    jsonrpc: Cow<'a, str>,
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    #[serde(default, borrow, deserialize_with = "raw")]
+    id: Option<&'a RawValue>,
+    #[serde(default, borrow, deserialize_with = "raw")]
+    params: Option<&'a RawValue>,
+}
+
+/// Keep an explicit `null` as a raw value rather than `None`.
+fn raw<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<&'de RawValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <&RawValue>::deserialize(deserializer).map(Some)
+}
+
+/// Request payload checked for forwarding.
+#[derive(Debug)]
+pub struct Forwarder<'a> {
+    payload: &'a str,
+    method: Cow<'a, str>,
+    id: Option<Value>,
+    /// Position of the id in the payload.
+    id_span: Option<Range<usize>>,
+}
+
+impl<'a> Forwarder<'a> {
+    /// Check the envelope of a request payload.
+    ///
+    /// Errors are the same as those of [from_str()](crate::from_str)
+    /// except that invalid parameters of an array or object type are
+    /// not detected.
+    pub fn parse(payload: &'a str) -> Result<Self> {
+        let envelope: Envelope<'a> = serde_json::from_str(payload)
+            .map_err(|e| map_payload_error(e, payload.as_bytes()))?;
+        let id = match envelope.id {
+            Some(raw) => Some(
+                serde_json::from_str(raw.get())
+                    .map_err(|e| Error::from(Box::from(e)))?,
+            ),
+            None => None,
+        };
+        let structured = match envelope.params {
+            Some(params) => {
+                matches!(params.get(), "null")
+                    || matches!(params.get().as_bytes()[0], b'[' | b'{')
+            }
+            None => true,
+        };
+        let id = check_envelope(
+            &envelope.jsonrpc,
+            id,
+            structured,
+            &Conformance::default(),
+        )?;
+        let id_span = envelope.id.and_then(|raw| span(payload, raw.get()));
+        Ok(Self {
+            payload,
+            method: envelope.method,
+            id,
+            id_span,
+        })
+    }
+
+    /// The request method.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The request id, `None` for a notification.
+    pub fn id(&self) -> &Option<Value> {
+        &self.id
+    }
+
+    /// The original payload.
+    pub fn payload(&self) -> &'a str {
+        self.payload
+    }
+
+    /// The payload with the id replaced.
+    ///
+    /// The new id is spliced into the original payload, a notification
+    /// has no id to replace so the payload is parsed and serialized
+    /// again with the id added.
+    pub fn with_id(&self, id: &Value) -> Result<String> {
+        let encoded =
+            serde_json::to_string(id).map_err(|e| Error::from(Box::from(e)))?;
+        match &self.id_span {
+            Some(span) => {
+                let mut payload = String::with_capacity(
+                    self.payload.len() - span.len() + encoded.len(),
+                );
+                payload.push_str(&self.payload[..span.start]);
+                payload.push_str(&encoded);
+                payload.push_str(&self.payload[span.end..]);
+                Ok(payload)
+            }
+            None => {
+                let mut request = crate::from_str(self.payload)?;
+                *request.id_mut() = Some(id.clone());
+                serde_json::to_string(&request)
+                    .map_err(|e| Error::from(Box::from(e)))
+            }
+        }
+    }
+}
+
+/// Position of a borrowed slice within the payload.
+fn span(payload: &str, slice: &str) -> Option<Range<usize>> {
+    let start =
+        (slice.as_ptr() as usize).checked_sub(payload.as_ptr() as usize)?;
+    let end = start + slice.len();
+    (end <= payload.len()).then_some(start..end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn forward_splice_id() -> Result<()> {
+        let payload = "{ \"id\" : \"a\\\"b\" , \"jsonrpc\":\"2.0\",\"method\":\"m\",\"params\":{\"id\":1}}";
+        let forwarder = Forwarder::parse(payload)?;
+        assert_eq!(&Some(json!("a\"b")), forwarder.id());
+        let spliced = forwarder.with_id(&json!(42))?;
+        assert_eq!(
+            "{ \"id\" : 42 , \"jsonrpc\":\"2.0\",\"method\":\"m\",\"params\":{\"id\":1}}",
+            spliced
+        );
+
+        let payload = r#"{"jsonrpc":"2.0","id":null,"method":"m"}"#;
+        let forwarder = Forwarder::parse(payload)?;
+        assert_eq!(&Some(Value::Null), forwarder.id());
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","id":7,"method":"m"}"#,
+            forwarder.with_id(&json!(7))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn forward_notification() -> Result<()> {
+        let payload = r#"{"jsonrpc":"2.0","method":"m","params":[1]}"#;
+        let forwarder = Forwarder::parse(payload)?;
+        assert!(forwarder.id().is_none());
+        let request = crate::from_str(&forwarder.with_id(&json!(1))?)?;
+        assert_eq!(&Some(json!(1)), request.id());
+        assert_eq!(&Some(json!([1])), request.params());
+        Ok(())
+    }
+
+    #[test]
+    fn forward_invalid() {
+        for (payload, data) in &[
+            (
+                r#"{"jsonrpc":"1.0","id":1,"method":"m"}"#,
+                "jsonrpc version",
+            ),
+            (r#"{"jsonrpc":"2.0","id":[1],"method":"m"}"#, "id must be"),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"m","params":1}"#,
+                "params must be",
+            ),
+        ] {
+            match Forwarder::parse(payload) {
+                Err(Error::InvalidRequest { data: message, .. }) => {
+                    assert!(message.starts_with(data), "{}", message)
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(Forwarder::parse("{"), Err(Error::Parse { .. })));
+    }
+}
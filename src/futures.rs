@@ -1,7 +1,48 @@
 //! Non-blocking implementation, requires the `async` feature.
 
-use crate::{Error, Request, Response, Result};
+use crate::{
+    batch::{self, BatchItemReport, BatchPolicy, DuplicateIds, ItemOutcome},
+    builder::{self, ConfigError},
+    cancel::CancellationRegistry,
+    check_request,
+    client::{convert_result, Attempt, ClientLayer, Layers},
+    conformance::Conformance,
+    deadline::{self, Deadline},
+    describe::{self, MethodMeta},
+    error_response,
+    id::{IdGenerator, RandomIds},
+    log_unreachable,
+    message::{Call, Message, Notification},
+    method_list, method_listing,
+    namespace::Namespace,
+    policy::ErrorPolicy,
+    routes,
+    session::Session,
+    shutdown::{self, ServedStats},
+    slow::{self, SlowLog, SlowRequest},
+    stats::{Recorder, Stats},
+    truncate,
+    typed::{self, TypedRequest, TypedResponse},
+    Error, ErrorMapper, Request, Response, Result, RpcError, ServedHook,
+    ServiceRef, Validator, METHODS,
+};
 use async_trait::async_trait;
+use futures_util::{
+    future::{self, Either},
+    stream::{FuturesUnordered, Stream, StreamExt},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    future::Future,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
 
 #[async_trait]
 /// Trait for async services that maybe handle a request.
@@ -17,6 +58,21 @@ pub trait Service: Send + Sync {
         request: &Request,
         ctx: &Self::Data,
     ) -> Result<Option<Response>>;
+
+    /// See [Service::methods()](crate::Service::methods).
+    fn methods(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// See [Service::method_meta()](crate::Service::method_meta).
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.methods().into_iter().map(MethodMeta::new).collect()
+    }
+
+    /// See [Service::route_by_methods()](crate::Service::route_by_methods).
+    fn route_by_methods(&self) -> bool {
+        true
+    }
 }
 
 /// Serve requests.
@@ -24,16 +80,658 @@ pub trait Service: Send + Sync {
 /// Requests are passed to each service in turn and the first service
 /// that returns a response wins.
 ///
+/// The server is `Send + Sync` so it can be shared between tasks.
+///
 /// Only available with the `async` feature.
 pub struct Server<'a, T: Send + Sync> {
     /// Services that the server should invoke for every request.
-    services: Vec<&'a Box<dyn Service<Data = T>>>,
+    services: routes::Services<ServiceRef<'a, dyn Service<Data = T>>>,
+    /// Registry for cancellation tokens.
+    cancellation: Option<CancellationRegistry>,
+    /// Whether to answer `rpc.methods` with the method names.
+    list_methods: bool,
+    /// Checks run on every request before the services.
+    validators: Vec<Box<Validator<T>>>,
+    /// Translates errors returned by services into error responses.
+    error_mapper: Option<Box<ErrorMapper>>,
+    /// How calls in a batch that reuse an id are treated.
+    duplicate_ids: DuplicateIds,
+    /// Whether a batch is served after an error.
+    batch_policy: BatchPolicy,
+    /// Largest number of requests served in a batch.
+    max_batch: Option<usize>,
+    /// Largest serialized response in bytes.
+    max_response: Option<usize>,
+    /// Categories and log levels of error codes.
+    policy: ErrorPolicy,
+    /// Called after every request is served.
+    on_served: Option<Box<ServedHook>>,
+    /// Checks applied to every request before the validators.
+    conformance: Option<Conformance>,
+    /// Whether handlers are stopped at the deadline in the metadata.
+    deadlines: bool,
+    /// Whether internal errors include their sources and backtrace.
+    debug_errors: bool,
+    /// Limit for the data of errors other than the default.
+    max_error_data: Option<usize>,
+    /// Whether responses are stamped with the processing time.
+    #[cfg(feature = "extra-fields")]
+    timing: bool,
+    /// Whether responses to deprecated methods carry a warning.
+    #[cfg(feature = "extra-fields")]
+    deprecation_warnings: bool,
+    /// Reports requests the services take too long to handle.
+    slow: Option<SlowLog>,
+    /// Turns away new requests during shutdown.
+    drain: Option<shutdown::DrainGuard>,
+    /// Counters for the requests served.
+    stats: Option<Recorder>,
+}
+
+impl<T: Send + Sync> Server<'static, T> {
+    /// Create a server that owns its services.
+    ///
+    /// The server does not borrow so it can be wrapped in an `Arc`
+    /// and used with [respond()](respond).
+    pub fn new_shared(services: Vec<Arc<dyn Service<Data = T>>>) -> Self {
+        Self {
+            services: services.into_iter().map(ServiceRef::Shared).collect(),
+            cancellation: None,
+            list_methods: false,
+            validators: Vec::new(),
+            error_mapper: None,
+            duplicate_ids: Default::default(),
+            batch_policy: Default::default(),
+            max_batch: None,
+            max_response: None,
+            policy: Default::default(),
+            on_served: None,
+            conformance: None,
+            deadlines: false,
+            debug_errors: false,
+            max_error_data: None,
+            #[cfg(feature = "extra-fields")]
+            timing: false,
+            #[cfg(feature = "extra-fields")]
+            deprecation_warnings: false,
+            slow: None,
+            drain: None,
+            stats: None,
+        }
+    }
+
+    /// Create a server calling services with a higher priority first.
+    ///
+    /// See [Server::new_prioritized()](crate::Server::new_prioritized).
+    pub fn new_prioritized(
+        mut services: Vec<(i32, Arc<dyn Service<Data = T>>)>,
+    ) -> Self {
+        services.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+        let server = Self::new_shared(
+            services.into_iter().map(|(_, service)| service).collect(),
+        );
+        log_unreachable(server.order());
+        server
+    }
+}
+
+impl<T: Send + Sync + 'static> Server<'static, T> {
+    /// Build a server that owns its services and check the
+    /// configuration.
+    ///
+    /// See [Server::builder()](crate::Server::builder).
+    pub fn builder() -> ServerBuilder<T> {
+        ServerBuilder {
+            services: Vec::new(),
+            fallback: None,
+            allow_duplicates: false,
+            max_batch: None,
+            server: Server::new_shared(Vec::new()),
+        }
+    }
+
+    /// Serve the services of another server under a prefix.
+    ///
+    /// See [Server::mount()](crate::Server::mount).
+    pub fn mount(mut self, prefix: &str, other: Server<'static, T>) -> Self {
+        self.services
+            .extend(other.services.into_iter().map(|service| {
+                let service: Arc<dyn Service<Data = T>> =
+                    Arc::new(Namespace::new(prefix, service));
+                ServiceRef::Shared(service)
+            }));
+        self
+    }
+}
+
+/// Builder for a [Server](Server) that owns its services.
+///
+/// See [ServerBuilder](crate::builder::ServerBuilder).
+///
+/// Only available with the `async` feature.
+pub struct ServerBuilder<T: Send + Sync + 'static> {
+    services: Vec<Arc<dyn Service<Data = T>>>,
+    fallback: Option<Arc<dyn Service<Data = T>>>,
+    allow_duplicates: bool,
+    max_batch: Option<usize>,
+    server: Server<'static, T>,
+}
+
+impl<T: Send + Sync + 'static> ServerBuilder<T> {
+    /// Add a service, services are called in the order they are added.
+    pub fn service<S: Service<Data = T> + 'static>(self, service: S) -> Self {
+        self.shared_service(Arc::new(service))
+    }
+
+    /// Add a service that is shared with other servers.
+    pub fn shared_service(
+        mut self,
+        service: Arc<dyn Service<Data = T>>,
+    ) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    /// Set a service called for requests no other service handles.
+    ///
+    /// Its methods are not checked for duplicates and it is asked for
+    /// every method, including those it does not list.
+    pub fn fallback<S: Service<Data = T> + 'static>(
+        mut self,
+        service: S,
+    ) -> Self {
+        self.fallback = Some(Arc::new(routes::Fallback(service)));
+        self
+    }
+
+    /// Allow services to report the same method, the first service
+    /// handles it.
+    pub fn allow_duplicates(mut self) -> Self {
+        self.allow_duplicates = true;
+        self
+    }
+
+    /// See [Server::with_validator()](Server::with_validator).
+    pub fn validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&Request, &T) -> std::result::Result<(), RpcError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.server = self.server.with_validator(validator);
+        self
+    }
+
+    /// See [Server::with_authz()](Server::with_authz).
+    pub fn authz(mut self, policy: crate::authz::AuthzPolicy) -> Self
+    where
+        T: crate::authz::HasScopes,
+    {
+        self.server = self.server.with_authz(policy);
+        self
+    }
+
+    /// See [Server::on_served()](Server::on_served).
+    pub fn hook<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Request, Option<&Response>, Duration) + Send + Sync + 'static,
+    {
+        self.server = self.server.on_served(f);
+        self
+    }
+
+    /// See [Server::with_conformance()](Server::with_conformance).
+    pub fn conformance(mut self, conformance: Conformance) -> Self {
+        self.server = self.server.with_conformance(conformance);
+        self
+    }
+
+    /// See [Server::with_max_batch()](Server::with_max_batch).
+    pub fn max_batch(mut self, max: usize) -> Self {
+        self.max_batch = Some(max);
+        self
+    }
+
+    /// See [Server::with_batch_policy()](Server::with_batch_policy).
+    pub fn batch_policy(mut self, policy: BatchPolicy) -> Self {
+        self.server = self.server.with_batch_policy(policy);
+        self
+    }
+
+    /// See [Server::with_duplicate_ids()](Server::with_duplicate_ids).
+    pub fn duplicate_ids(mut self, policy: DuplicateIds) -> Self {
+        self.server = self.server.with_duplicate_ids(policy);
+        self
+    }
+
+    /// See [Server::with_methods()](Server::with_methods).
+    pub fn list_methods(mut self) -> Self {
+        self.server = self.server.with_methods();
+        self
+    }
+
+    /// See [Server::with_error_mapper()](Server::with_error_mapper).
+    pub fn error_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Error, &Request) -> Option<RpcError> + Send + Sync + 'static,
+    {
+        self.server = self.server.with_error_mapper(mapper);
+        self
+    }
+
+    /// See [Server::with_debug_errors()](Server::with_debug_errors).
+    pub fn debug_errors(mut self, enabled: bool) -> Self {
+        self.server = self.server.with_debug_errors(enabled);
+        self
+    }
+
+    /// See [Server::with_policy()](Server::with_policy).
+    pub fn policy(mut self, policy: ErrorPolicy) -> Self {
+        self.server = self.server.with_policy(policy);
+        self
+    }
+
+    /// See [Server::with_slow_request_log()](Server::with_slow_request_log).
+    pub fn slow_request_log<F>(mut self, threshold: Duration, f: F) -> Self
+    where
+        F: Fn(&SlowRequest<'_>) + Send + Sync + 'static,
+    {
+        self.server = self.server.with_slow_request_log(threshold, f);
+        self
+    }
+
+    /// See [Server::with_stats()](Server::with_stats).
+    pub fn stats(mut self) -> Self {
+        self.server = self.server.with_stats();
+        self
+    }
+
+    /// See [Server::with_timing()](Server::with_timing).
+    #[cfg(feature = "extra-fields")]
+    pub fn timing(mut self) -> Self {
+        self.server = self.server.with_timing();
+        self
+    }
+
+    /// See [Server::with_deprecation_warnings()](Server::with_deprecation_warnings).
+    #[cfg(feature = "extra-fields")]
+    pub fn deprecation_warnings(mut self) -> Self {
+        self.server = self.server.with_deprecation_warnings();
+        self
+    }
+
+    /// See [Server::with_cancellation()](Server::with_cancellation).
+    pub fn cancellation(mut self, registry: CancellationRegistry) -> Self {
+        self.server = self.server.with_cancellation(registry);
+        self
+    }
+
+    /// See [Server::with_deadlines()](Server::with_deadlines).
+    pub fn deadlines(mut self) -> Self {
+        self.server = self.server.with_deadlines();
+        self
+    }
+
+    /// Check the configuration and create the server.
+    pub fn build(self) -> std::result::Result<Server<'static, T>, ConfigError> {
+        let order: Vec<Vec<String>> = self
+            .services
+            .iter()
+            .map(|service| service.methods())
+            .collect();
+        builder::check(&order, self.allow_duplicates, self.max_batch)?;
+        let mut server = self.server;
+        if let Some(max) = self.max_batch {
+            server = server.with_max_batch(max);
+        }
+        server.services.extend(
+            self.services
+                .into_iter()
+                .chain(self.fallback)
+                .map(ServiceRef::Shared),
+        );
+        if self.allow_duplicates {
+            log_unreachable(server.order());
+        }
+        Ok(server)
+    }
+}
+
+#[async_trait]
+impl<'a, T: Send + Sync> Service for ServiceRef<'a, dyn Service<Data = T>> {
+    type Data = T;
+
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        (**self).handle(request, ctx).await
+    }
+
+    fn methods(&self) -> Vec<String> {
+        (**self).methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        (**self).method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        (**self).route_by_methods()
+    }
+}
+
+impl<'a, T: Send + Sync> routes::Routable
+    for ServiceRef<'a, dyn Service<Data = T>>
+{
+    fn route(&self) -> Option<Vec<String>> {
+        routes::route(self.methods(), self.route_by_methods())
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        Service::method_meta(self)
+    }
 }
 
 impl<'a, T: Send + Sync> Server<'a, T> {
     /// Create a new server.
     pub fn new(services: Vec<&'a Box<dyn Service<Data = T>>>) -> Self {
-        Self { services }
+        Self {
+            services: services.into_iter().map(ServiceRef::Borrowed).collect(),
+            cancellation: None,
+            list_methods: false,
+            validators: Vec::new(),
+            error_mapper: None,
+            duplicate_ids: Default::default(),
+            batch_policy: Default::default(),
+            max_batch: None,
+            max_response: None,
+            policy: Default::default(),
+            on_served: None,
+            conformance: None,
+            deadlines: false,
+            debug_errors: false,
+            max_error_data: None,
+            #[cfg(feature = "extra-fields")]
+            timing: false,
+            #[cfg(feature = "extra-fields")]
+            deprecation_warnings: false,
+            slow: None,
+            drain: None,
+            stats: None,
+        }
+    }
+
+    /// Add a service that is called before the existing services.
+    pub fn push_front(&mut self, service: Arc<dyn Service<Data = T>>) {
+        self.services.insert(0, ServiceRef::Shared(service));
+        log_unreachable(self.order());
+    }
+
+    /// Add a service that is called after the existing services.
+    pub fn push_back(&mut self, service: Arc<dyn Service<Data = T>>) {
+        self.services.push(ServiceRef::Shared(service));
+        log_unreachable(self.order());
+    }
+
+    /// The methods of each service in the order the services are
+    /// called.
+    pub fn order(&self) -> Vec<Vec<String>> {
+        self.services
+            .iter()
+            .map(|service| service.methods())
+            .collect()
+    }
+
+    /// Combine the services of another server with this server.
+    ///
+    /// See [Server::merge()](crate::Server::merge); the cancellation
+    /// registry and validators of this server are kept.
+    pub fn merge(mut self, other: Server<'a, T>) -> Self {
+        self.services.extend(other.services);
+        self
+    }
+
+    /// Answer `rpc.methods` with a sorted array of the method names.
+    ///
+    /// See [Server::with_methods()](crate::Server::with_methods).
+    pub fn with_methods(mut self) -> Self {
+        self.list_methods = true;
+        self
+    }
+
+    /// Check every request against a conformance level before the
+    /// validators run.
+    ///
+    /// See [Server::with_conformance()](crate::Server::with_conformance).
+    pub fn with_conformance(mut self, conformance: Conformance) -> Self {
+        self.conformance = Some(conformance);
+        self
+    }
+
+    /// Add a validator that runs before the services.
+    ///
+    /// See [Server::with_validator()](crate::Server::with_validator).
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&Request, &T) -> std::result::Result<(), RpcError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    /// Check the scopes each method requires before the services.
+    ///
+    /// See [Server::with_authz()](crate::Server::with_authz).
+    pub fn with_authz(self, policy: crate::authz::AuthzPolicy) -> Self
+    where
+        T: crate::authz::HasScopes,
+    {
+        self.with_validator(move |request, ctx| policy.check(request, ctx))
+    }
+
+    /// Set a function that translates errors returned by services.
+    ///
+    /// See [Server::with_error_mapper()](crate::Server::with_error_mapper).
+    pub fn with_error_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Error, &Request) -> Option<RpcError> + Send + Sync + 'static,
+    {
+        self.error_mapper = Some(Box::new(mapper));
+        self
+    }
+
+    /// Include the source chain and backtrace of an `Error::Boxed` in
+    /// the data of the internal error response.
+    ///
+    /// See [Server::with_debug_errors()](crate::Server::with_debug_errors).
+    pub fn with_debug_errors(mut self, enabled: bool) -> Self {
+        self.debug_errors = enabled;
+        self
+    }
+
+    /// Set the limit in bytes for the data of converted errors.
+    ///
+    /// See [Server::with_max_error_data()](crate::Server::with_max_error_data).
+    pub fn with_max_error_data(mut self, max: usize) -> Self {
+        self.max_error_data = Some(max);
+        self
+    }
+
+    /// Stamp responses with the time taken to serve the request.
+    ///
+    /// See [Server::with_timing()](crate::Server::with_timing).
+    #[cfg(feature = "extra-fields")]
+    pub fn with_timing(mut self) -> Self {
+        self.timing = true;
+        self
+    }
+
+    /// Attach a warning to responses to deprecated methods.
+    ///
+    /// See [Server::with_deprecation_warnings()](crate::Server::with_deprecation_warnings).
+    #[cfg(feature = "extra-fields")]
+    pub fn with_deprecation_warnings(mut self) -> Self {
+        self.deprecation_warnings = true;
+        self
+    }
+
+    /// Set how calls in a batch that reuse an id are treated.
+    ///
+    /// See [Server::with_duplicate_ids()](crate::Server::with_duplicate_ids).
+    pub fn with_duplicate_ids(mut self, policy: DuplicateIds) -> Self {
+        self.duplicate_ids = policy;
+        self
+    }
+
+    /// Set whether a batch is served after an error.
+    ///
+    /// With [BatchPolicy::AbortOnError](BatchPolicy::AbortOnError) the
+    /// elements of a batch are served one at a time.
+    pub fn with_batch_policy(mut self, policy: BatchPolicy) -> Self {
+        self.batch_policy = policy;
+        self
+    }
+
+    /// Reject batches with more than `max` requests.
+    ///
+    /// See [Server::with_max_batch()](crate::Server::with_max_batch).
+    pub fn with_max_batch(mut self, max: usize) -> Self {
+        self.max_batch = Some(max);
+        self
+    }
+
+    /// Replace responses larger than `max` bytes when serialized with
+    /// an internal error.
+    ///
+    /// Applies to the responses of [serve_until()](serve_until),
+    /// [respond()](respond) and the [net](crate::net) serve loop, not
+    /// to [serve()](Server::serve), see the
+    /// [truncate](crate::truncate#responses) module.
+    pub fn with_max_response_size(mut self, max: usize) -> Self {
+        self.max_response = Some(max);
+        self
+    }
+
+    /// The limit in bytes for serialized responses.
+    pub fn max_response_size(&self) -> Option<usize> {
+        self.max_response
+    }
+
+    /// Set the categories and log levels of error codes.
+    ///
+    /// See [Server::with_policy()](crate::Server::with_policy).
+    pub fn with_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The categories and log levels of error codes.
+    pub fn policy(&self) -> &ErrorPolicy {
+        &self.policy
+    }
+
+    /// Turn away new requests once the shutdown handle of the guard is
+    /// triggered while the requests in flight complete.
+    ///
+    /// See [Server::with_drain()](crate::Server::with_drain).
+    pub fn with_drain(mut self, guard: shutdown::DrainGuard) -> Self {
+        self.drain = Some(guard);
+        self
+    }
+
+    /// Determine if the server is turning away new requests.
+    pub fn is_draining(&self) -> bool {
+        self.drain.as_ref().is_some_and(|drain| drain.is_draining())
+    }
+
+    /// Number of requests being handled, always zero without a
+    /// [DrainGuard](shutdown::DrainGuard).
+    pub fn in_flight(&self) -> usize {
+        self.drain.as_ref().map_or(0, |drain| drain.in_flight())
+    }
+
+    /// Call a function for requests slower than the threshold.
+    ///
+    /// See [Server::with_slow_request_log()](crate::Server::with_slow_request_log).
+    pub fn with_slow_request_log<F>(mut self, threshold: Duration, f: F) -> Self
+    where
+        F: Fn(&SlowRequest<'_>) + Send + Sync + 'static,
+    {
+        self.slow = Some(SlowLog::new(threshold, Box::new(f)));
+        self
+    }
+
+    /// Count the requests served.
+    ///
+    /// See [Server::with_stats()](crate::Server::with_stats).
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(Default::default());
+        self
+    }
+
+    /// A snapshot of the counters, `None` unless counting is enabled.
+    pub fn stats(&self) -> Option<Stats> {
+        self.stats.as_ref().map(Recorder::snapshot)
+    }
+
+    /// Set a function called after every request is served.
+    ///
+    /// See [Server::on_served()](crate::Server::on_served).
+    pub fn on_served<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Request, Option<&Response>, Duration) + Send + Sync + 'static,
+    {
+        self.on_served = Some(Box::new(f));
+        self
+    }
+
+    /// The sorted names of the methods provided by the services.
+    pub fn methods(&self) -> Vec<String> {
+        method_list(self.services.iter().map(|service| service.methods()))
+    }
+
+    /// See [Server::method_meta()](crate::Server::method_meta).
+    pub fn method_meta(&self) -> Vec<MethodMeta> {
+        describe::list(
+            self.services.iter().map(|service| service.method_meta()),
+        )
+    }
+
+    /// Register a cancellation token for every request with an id.
+    ///
+    /// When a token is cancelled the handler future is dropped and the
+    /// request cancelled error is sent as the response.
+    ///
+    /// Requests with a registry attached are registered there instead
+    /// with the token attached to them, see the
+    /// [cancel module](crate::cancel).
+    pub fn with_cancellation(mut self, registry: CancellationRegistry) -> Self {
+        self.cancellation = Some(registry);
+        self
+    }
+
+    /// A new registry for the requests of one stream or connection when
+    /// the server cancels requests.
+    pub(crate) fn stream_registry(&self) -> Option<CancellationRegistry> {
+        self.cancellation
+            .as_ref()
+            .map(|_| CancellationRegistry::new())
+    }
+
+    /// Stop handlers when the deadline sent in the request metadata
+    /// passes.
+    ///
+    /// Requests are answered with the deadline exceeded error, see the
+    /// [deadline module](crate::deadline).
+    pub fn with_deadlines(mut self) -> Self {
+        self.deadlines = true;
+        self
     }
 
     /// Call services in order and return the first response message.
@@ -45,7 +743,80 @@ impl<'a, T: Send + Sync> Server<'a, T> {
         request: &Request,
         ctx: &T,
     ) -> Result<Response> {
-        for service in self.services.iter() {
+        let in_flight = self.drain.as_ref().map(shutdown::DrainGuard::enter);
+        if matches!(in_flight, Some(None)) {
+            return Ok(shutdown::drained(request));
+        }
+        if let Some(Err(e)) =
+            self.conformance.as_ref().map(|c| c.check(request))
+        {
+            return Ok(e.into());
+        }
+        if let Some(response) = check_request(&self.validators, request, ctx) {
+            return Ok(response);
+        }
+        let started = Instant::now();
+        let result = match (&self.cancellation, request.id()) {
+            (Some(registry), Some(id)) => {
+                let registry = registry.scope(request);
+                let token = registry.register_request(request, id);
+                let result = token.run(self.dispatch_by(request, ctx)).await;
+                registry.unregister(id, &token);
+                match result {
+                    Some(result) => result,
+                    None => Err(Error::Cancelled {
+                        id: Some(id.clone()),
+                    }),
+                }
+            }
+            _ => self.dispatch_by(request, ctx).await,
+        };
+        if let Some(slow) = &self.slow {
+            slow.check(request, started, slow::failed(&result));
+        }
+        result
+    }
+
+    /// Dispatch within the deadline of the request when enabled.
+    async fn dispatch_by(
+        &self,
+        request: &Request,
+        ctx: &T,
+    ) -> Result<Response> {
+        let deadline = match self.deadlines {
+            true => Deadline::from_request(request),
+            false => None,
+        };
+        let dispatch = crate::debug::capturing_future(
+            self.debug_errors,
+            self.dispatch(request, ctx),
+        );
+        match deadline {
+            Some(deadline) if deadline.is_expired() => {
+                deadline::exceeded_result(request)
+            }
+            Some(deadline) => {
+                tokio::time::timeout(deadline.remaining(), dispatch)
+                    .await
+                    .unwrap_or_else(|_| deadline::exceeded_result(request))
+            }
+            None => dispatch.await,
+        }
+    }
+
+    async fn dispatch(&self, request: &Request, ctx: &T) -> Result<Response> {
+        if let Some(note) = self.services.deprecation(request.method()) {
+            describe::warn(request, note);
+        }
+        if self.list_methods && request.method() == METHODS {
+            let listing = method_listing(
+                request,
+                || self.methods(),
+                || self.method_meta(),
+            );
+            return Ok((request, listing).into());
+        }
+        for service in self.services.candidates(request.method()) {
             if let Some(result) = service.handle(request, ctx).await? {
                 return Ok(result);
             }
@@ -63,15 +834,1384 @@ impl<'a, T: Send + Sync> Server<'a, T> {
     ///
     /// If a request was a notification (no id field) this will yield `None`.
     pub async fn serve(&self, request: &Request, ctx: &T) -> Option<Response> {
+        self.serve_outcome(request, ctx).await.0
+    }
+
+    /// Serve a request, also yielding the outcome which is known for
+    /// notifications even though they are not answered.
+    async fn serve_outcome(
+        &self,
+        request: &Request,
+        ctx: &T,
+    ) -> (Option<Response>, ItemOutcome) {
+        let started = Instant::now();
+        let response = self.response(request, ctx).await;
+        let outcome = ItemOutcome::of(&response);
+        let response = self.answer(response);
+        #[cfg(feature = "extra-fields")]
+        let response =
+            response.map(|response| self.stamp(request, response, started));
+        self.observe(request, response.as_ref(), started);
+        (response, outcome)
+    }
+
+    /// Attach the processing time when timing is enabled.
+    #[cfg(feature = "extra-fields")]
+    fn stamp(
+        &self,
+        request: &Request,
+        mut response: Response,
+        started: Instant,
+    ) -> Response {
+        if self.timing {
+            crate::timing::stamp(
+                &mut response,
+                request.received().unwrap_or(started),
+            );
+        }
+        if let Some(note) = self.deprecation_warning(request) {
+            describe::attach(response.extra_fields_mut(), note);
+        }
+        response
+    }
+
+    /// The deprecation notice to attach to the response to `request`.
+    #[cfg(feature = "extra-fields")]
+    fn deprecation_warning(&self, request: &Request) -> Option<String> {
+        if !self.deprecation_warnings {
+            return None;
+        }
+        let note = self.services.deprecation(request.method())?;
+        Some(describe::notice(request, note))
+    }
+
+    fn observe(
+        &self,
+        request: &Request,
+        response: Option<&Response>,
+        started: Instant,
+    ) {
+        let elapsed = started.elapsed();
+        if let Some(stats) = &self.stats {
+            let listing = self.services.listing(request.method());
+            stats.record(request, response, elapsed, &self.policy, listing);
+        }
+        if let Some(on_served) = &self.on_served {
+            on_served(request, response, elapsed);
+        }
+    }
+
+    /// The response to a request, including one for a notification.
+    async fn response(&self, request: &Request, ctx: &T) -> Response {
         match self.handle(request, ctx).await {
-            Ok(response) => {
-                if response.error().is_some() || response.id().is_some() {
-                    Some(response)
+            Ok(response) => response,
+            Err(e) => error_response(
+                &self.error_mapper,
+                &self.policy,
+                self.debug_errors,
+                self.max_error_data,
+                request,
+                e,
+            ),
+        }
+    }
+
+    /// The response when it should be sent.
+    fn answer(&self, response: Response) -> Option<Response> {
+        // Errors for notifications are answered unless conforming, an
+        // invalid request always has an id even if it is null
+        let answer = response.id().is_some()
+            || (self.conformance.is_none() && response.error().is_some());
+        answer.then_some(response)
+    }
+
+    /// Serve requests concurrently returning a response per request
+    /// in the same order, `None` for notifications.
+    ///
+    /// See [Server::serve_many()](crate::Server::serve_many).
+    pub async fn serve_many<I, R>(
+        &self,
+        requests: I,
+        ctx: &T,
+    ) -> Vec<Option<Response>>
+    where
+        I: IntoIterator<Item = R>,
+        R: std::borrow::Borrow<Request>,
+    {
+        future::join_all(requests.into_iter().map(|request| async move {
+            self.serve(request.borrow(), ctx).await
+        }))
+        .await
+    }
+
+    /// Serve a batch of requests concurrently, the responses are in
+    /// the order of the requests and notifications yield no response.
+    ///
+    /// The requests are served sequentially when the batch policy is
+    /// [BatchPolicy::AbortOnError](BatchPolicy::AbortOnError).
+    pub async fn serve_batch(
+        &self,
+        requests: &[Request],
+        ctx: &T,
+    ) -> Vec<Response> {
+        self.serve_items(requests, ctx)
+            .await
+            .into_iter()
+            .filter_map(|(response, _, _)| response)
+            .collect()
+    }
+
+    /// Serve a batch as [serve_batch()](Self::serve_batch) and report
+    /// the outcome and time taken by every element in the order of the
+    /// requests, including notifications.
+    ///
+    /// A batch rejected for exceeding the maximum size is not served so
+    /// no elements are reported.
+    pub async fn serve_batch_report(
+        &self,
+        requests: &[Request],
+        ctx: &T,
+    ) -> (Vec<Response>, Vec<BatchItemReport>) {
+        let mut responses = Vec::new();
+        let mut reports = Vec::new();
+        let items = self.serve_items(requests, ctx).await;
+        for (index, (response, outcome, elapsed)) in
+            items.into_iter().enumerate()
+        {
+            let request = &requests[index];
+            responses.extend(response);
+            reports.push(BatchItemReport {
+                index,
+                method: request.method().to_string(),
+                notification: request.id().is_none(),
+                elapsed,
+                outcome,
+            });
+        }
+        (responses, reports)
+    }
+
+    /// Serve the elements of a batch yielding the response, outcome and
+    /// time taken for each; a batch that is too large yields its error
+    /// response alone.
+    async fn serve_items(
+        &self,
+        requests: &[Request],
+        ctx: &T,
+    ) -> Vec<(Option<Response>, ItemOutcome, Duration)> {
+        if let Some(response) = batch::too_large(requests, self.max_batch) {
+            let outcome = ItemOutcome::of(&response);
+            return vec![(Some(response), outcome, Duration::ZERO)];
+        }
+        let rejected = self.duplicate_ids.rejected(requests);
+        if self.batch_policy == BatchPolicy::AbortOnError {
+            let mut failed = false;
+            let mut items = Vec::new();
+            for (index, request) in requests.iter().enumerate() {
+                let item = if failed {
+                    let response = batch::skipped_response(request);
+                    (response, ItemOutcome::Skipped, Duration::ZERO)
                 } else {
-                    None
+                    self.serve_item(request, rejected.contains(&index), ctx)
+                        .await
+                };
+                if let Some(response) = &item.0 {
+                    failed |= response.error().is_some();
                 }
+                items.push(item);
             }
-            Err(e) => Some((request, e).into()),
+            return items;
         }
+        future::join_all(requests.iter().enumerate().map(|(index, request)| {
+            self.serve_item(request, rejected.contains(&index), ctx)
+        }))
+        .await
+    }
+
+    /// Serve an element of a batch, a call `rejected` as a duplicate is
+    /// answered with an error.
+    async fn serve_item(
+        &self,
+        request: &Request,
+        rejected: bool,
+        ctx: &T,
+    ) -> (Option<Response>, ItemOutcome, Duration) {
+        let started = Instant::now();
+        let (response, outcome) = if rejected {
+            let response = batch::duplicate_response(request);
+            let outcome = ItemOutcome::of(&response);
+            (Some(response), outcome)
+        } else {
+            self.serve_outcome(request, ctx).await
+        };
+        (response, outcome, started.elapsed())
+    }
+
+    /// Serve a call, errors are converted to the response.
+    pub async fn serve_call(&self, call: &Call, ctx: &T) -> Response {
+        let request = call.as_request();
+        let started = Instant::now();
+        let response = match self.handle(request, ctx).await {
+            Ok(response) => response,
+            Err(e) => error_response(
+                &self.error_mapper,
+                &self.policy,
+                self.debug_errors,
+                self.max_error_data,
+                request,
+                e,
+            ),
+        };
+        #[cfg(feature = "extra-fields")]
+        let response = self.stamp(request, response, started);
+        self.observe(request, Some(&response), started);
+        response
+    }
+
+    /// Serve a notification, any errors are discarded as notifications
+    /// are never answered.
+    pub async fn serve_notification(
+        &self,
+        notification: &Notification,
+        ctx: &T,
+    ) {
+        let request = notification.as_request();
+        let started = Instant::now();
+        let _ = self.handle(request, ctx).await;
+        self.observe(request, None, started);
+    }
+
+    /// Serve a message, only calls yield a response.
+    pub async fn serve_message(
+        &self,
+        message: &Message,
+        ctx: &T,
+    ) -> Option<Response> {
+        match message {
+            Message::Call(call) => Some(self.serve_call(call, ctx).await),
+            Message::Notification(notification) => {
+                self.serve_notification(notification, ctx).await;
+                None
+            }
+        }
+    }
+}
+
+/// Order that [respond()](respond) emits responses.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Order {
+    /// Responses are emitted in the order the requests arrived.
+    Arrival,
+    /// Responses are emitted as soon as each handler completes.
+    Completion,
+}
+
+/// Serve a stream of requests and return a stream of responses.
+///
+/// Up to `limit` handlers run concurrently, a limit of zero is treated
+/// as one. Notifications are served but yield no response. Requests are
+/// only pulled from the input as the output is consumed so backpressure
+/// propagates from the consumer to the source.
+///
+/// With [cancellation](Server::with_cancellation) the requests of the
+/// stream share a registry of their own.
+///
+/// Only available with the `async` feature.
+pub fn respond<T, S>(
+    server: Arc<Server<'static, T>>,
+    ctx: T,
+    requests: S,
+    limit: usize,
+    order: Order,
+) -> impl Stream<Item = Response>
+where
+    T: Send + Sync + 'static,
+    S: Stream<Item = Request>,
+{
+    let ctx = Arc::new(ctx);
+    let max = server.max_response_size();
+    let requests = scope_cancellation(&server, requests);
+    let futures = requests.map(move |request| {
+        let server = Arc::clone(&server);
+        let ctx = Arc::clone(&ctx);
+        async move { server.serve(&request, &ctx).await }
+    });
+    let limit = limit.max(1);
+    let responses = match order {
+        Order::Arrival => Either::Left(futures.buffered(limit)),
+        Order::Completion => Either::Right(futures.buffer_unordered(limit)),
+    };
+    responses
+        .filter_map(future::ready)
+        .map(move |response| truncate::limit(response, max))
+}
+
+/// Attach a new cancellation registry to the requests of a stream when
+/// the server cancels requests, so ids only need to be unique within
+/// the stream.
+fn scope_cancellation<T, S>(
+    server: &Server<'_, T>,
+    requests: S,
+) -> impl Stream<Item = Request>
+where
+    T: Send + Sync,
+    S: Stream<Item = Request>,
+{
+    let registry = server.stream_registry();
+    requests.map(move |mut request| {
+        if let Some(registry) = &registry {
+            if !request.extensions().contains::<CancellationRegistry>() {
+                registry.attach(&mut request);
+            }
+        }
+        request
+    })
+}
+
+enum Event {
+    Shutdown,
+    Request(Option<Request>),
+    Response(Option<Response>),
+}
+
+/// Serve a stream of requests until it ends or `shutdown` resolves.
+///
+/// Requests are handled concurrently and each response is passed to
+/// `write` as soon as it is ready. When `shutdown` resolves no more
+/// requests are read and the handlers in flight are given `grace` to
+/// finish, any still running after that are dropped. When the stream
+/// ends all the handlers in flight run to completion.
+///
+/// With [cancellation](Server::with_cancellation) the requests of the
+/// stream share a registry of their own.
+///
+/// Only available with the `async` feature.
+pub async fn serve_until<T, S, W, F>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    requests: S,
+    mut write: W,
+    shutdown: F,
+    grace: Duration,
+) -> ServedStats
+where
+    T: Send + Sync,
+    S: Stream<Item = Request>,
+    W: FnMut(Response),
+    F: Future<Output = ()>,
+{
+    let max = server.max_response_size();
+    let mut requests = pin!(scope_cancellation(server, requests));
+    let mut shutdown = pin!(shutdown);
+    let mut pending = FuturesUnordered::new();
+    let mut stats = ServedStats::default();
+
+    let stopped = loop {
+        let event = future::poll_fn(|cx| {
+            if shutdown.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Event::Shutdown);
+            }
+            if let Poll::Ready(request) = requests.as_mut().poll_next(cx) {
+                return Poll::Ready(Event::Request(request));
+            }
+            match pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(response)) => {
+                    Poll::Ready(Event::Response(response))
+                }
+                _ => Poll::Pending,
+            }
+        })
+        .await;
+        match event {
+            Event::Shutdown => break true,
+            Event::Request(None) => break false,
+            Event::Request(Some(request)) => {
+                pending.push(async move { server.serve(&request, ctx).await })
+            }
+            Event::Response(response) => {
+                stats.record(response.as_ref());
+                if let Some(response) = response {
+                    write(truncate::limit(response, max));
+                }
+            }
+        }
+    };
+
+    let drain = async {
+        while let Some(response) = pending.next().await {
+            stats.record(response.as_ref());
+            if let Some(response) = response {
+                write(truncate::limit(response, max));
+            }
+        }
+    };
+    if stopped {
+        let _ = tokio::time::timeout(grace, drain).await;
+    } else {
+        drain.await;
+    }
+    stats
+}
+
+/// Serve a stream of requests for a connection with a
+/// [Session](Session) until it ends or `shutdown` resolves.
+///
+/// Works like [serve_until()](serve_until) and inserts the state of the
+/// session into the extensions of every request; the session is
+/// dropped, calling its cleanup function, when the loop returns.
+///
+/// Only available with the `async` feature.
+pub async fn serve_until_with<T, S, W, F, U>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    requests: S,
+    write: W,
+    shutdown: F,
+    grace: Duration,
+    session: Session<U>,
+) -> ServedStats
+where
+    T: Send + Sync,
+    S: Stream<Item = Request>,
+    W: FnMut(Response),
+    F: Future<Output = ()>,
+    U: Send + Sync + 'static,
+{
+    let requests = requests.map(|mut request| {
+        session.attach(&mut request);
+        request
+    });
+    serve_until(server, ctx, requests, write, shutdown, grace).await
+}
+
+#[async_trait]
+/// Trait for async transports that deliver requests to a server.
+///
+/// Only available with the `async` feature.
+pub trait Transport: Send + Sync {
+    /// See [Transport](crate::client::Transport) for more information.
+    async fn send(&self, request: &Request) -> Result<Option<Response>>;
+}
+
+/// Client for calling methods on a server.
+///
+/// Only available with the `async` feature.
+pub struct Client<T> {
+    transport: T,
+    layers: Layers,
+    ids: Box<dyn IdGenerator>,
+}
+
+impl<T: Transport> Client<T> {
+    /// Create a new client.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            layers: Default::default(),
+            ids: Box::new(RandomIds),
+        }
+    }
+
+    /// Set the generator for the ids of calls, random by default.
+    ///
+    /// See the [id](crate::id) module.
+    pub fn with_ids<I: IdGenerator + 'static>(mut self, ids: I) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    /// See [with_layer()](crate::client::Client::with_layer).
+    pub fn with_layer<L: ClientLayer + 'static>(mut self, layer: L) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// The underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Send a request and return the response.
+    ///
+    /// It is an error if the server does not reply or replies with
+    /// a different id.
+    pub async fn request(&self, request: &Request) -> Result<Response> {
+        let mut request = self.layers.prepare(request);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let response = self.transport.send(&request).await;
+            match self.layers.complete(&mut request, response, attempt) {
+                Attempt::Done(result) => return result,
+                Attempt::Retry(delay) => tokio::time::sleep(delay).await,
+            }
+        }
+    }
+
+    /// Call a method and convert the result to `R`.
+    ///
+    /// An error response yields the error from
+    /// [Error::from_response()](crate::Error::from_response).
+    pub async fn call<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<R> {
+        let request = Request::new_reply_with(&*self.ids, method, params);
+        let response = self.request(&request).await?;
+        convert_result(response)
+    }
+
+    /// Send a typed request and convert the result to `R`.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub async fn call_typed<P, R: DeserializeOwned>(
+        &self,
+        request: &TypedRequest<P>,
+    ) -> Result<TypedResponse<R>> {
+        let response = self.request(request.as_request()).await?;
+        TypedResponse::try_from(response)
+    }
+
+    /// Call a declared method and convert the result to its type.
+    ///
+    /// See [Client::call_method()](crate::client::Client::call_method).
+    pub async fn call_method<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: typed::Method<P, R>,
+        params: P,
+    ) -> Result<R> {
+        let mut request = Request::from(method.request(params)?);
+        *request.id_mut() = Some(self.ids.next_id());
+        let response = self.request(&request).await?;
+        convert_result(response)
+    }
+
+    /// Call a method and give up if no response arrives within `timeout`.
+    ///
+    /// When the timeout elapses the pending request future is dropped,
+    /// transports that track calls in a [Pending](Pending) map release
+    /// the entry so a late response is discarded. Yields
+    /// `Error::Timeout` on expiry.
+    pub async fn call_with_timeout<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Option<Value>,
+        timeout: Duration,
+    ) -> Result<R> {
+        let request = Request::new_reply_with(&*self.ids, method, params);
+        let started = tokio::time::Instant::now();
+        match tokio::time::timeout(timeout, self.request(&request)).await {
+            Ok(response) => convert_result(response?),
+            Err(_) => Err(Error::Timeout {
+                method: Some(method.to_string()),
+                elapsed: Some(started.elapsed()),
+                id: request.id().clone(),
+            }),
+        }
+    }
+
+    /// Send a notification.
+    pub async fn notify(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<()> {
+        let request = Request::new_notification(method, params);
+        self.transport.send(&self.layers.prepare(&request)).await?;
+        Ok(())
+    }
+}
+
+type Calls = Arc<Mutex<HashMap<String, oneshot::Sender<Result<Response>>>>>;
+
+/// Map of request ids to calls waiting for a response.
+///
+/// Transports that read responses from a connection register a call
+/// before writing the request and complete it when the response with
+/// the same id arrives. Dropping the returned future, for example on
+/// timeout, removes the entry.
+///
+/// Cloned maps share the same calls.
+#[derive(Clone, Default)]
+pub struct Pending {
+    calls: Calls,
+}
+
+impl Pending {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Register a call waiting for the response with `id`.
+    ///
+    /// A call registered for an id that is already waiting replaces
+    /// the earlier call which then fails.
+    pub fn register(&self, id: &Value) -> PendingResponse {
+        let (sender, receiver) = oneshot::channel();
+        let key = id.to_string();
+        self.calls.lock().unwrap().insert(key.clone(), sender);
+        PendingResponse {
+            key,
+            receiver,
+            calls: Arc::clone(&self.calls),
+        }
+    }
+
+    /// Deliver a response to the call waiting for it.
+    ///
+    /// Yields the response back when no call is waiting for the id.
+    pub fn complete(&self, response: Response) -> Option<Response> {
+        let key = match response.id() {
+            Some(id) => id.to_string(),
+            None => return Some(response),
+        };
+        let sender = self.calls.lock().unwrap().remove(&key);
+        match sender {
+            Some(sender) => {
+                sender.send(Ok(response)).err().and_then(Result::ok)
+            }
+            None => Some(response),
+        }
+    }
+
+    /// Fail the call waiting for the response with `id`.
+    ///
+    /// Returns `false` when no call is waiting for the id.
+    pub fn fail(&self, id: &Value, error: Error) -> bool {
+        let sender = self.calls.lock().unwrap().remove(&id.to_string());
+        match sender {
+            Some(sender) => sender.send(Err(error)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Fail every waiting call with the error made by `error`.
+    ///
+    /// Returns the number of calls that failed.
+    pub fn fail_all<F: Fn() -> Error>(&self, error: F) -> usize {
+        let calls: Vec<_> = self.calls.lock().unwrap().drain().collect();
+        let mut failed = 0;
+        for (_, sender) in calls {
+            if sender.send(Err(error())).is_ok() {
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    /// Number of calls waiting for a response.
+    pub fn len(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+
+    /// Determine if no calls are waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Future returned by [register()](Pending::register).
+pub struct PendingResponse {
+    key: String,
+    receiver: oneshot::Receiver<Result<Response>>,
+    calls: Calls,
+}
+
+impl Future for PendingResponse {
+    type Output = Result<Response>;
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx).map(|result| {
+            result.unwrap_or_else(|_| {
+                Err(Error::from(Box::from("Pending call was dropped")))
+            })
+        })
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        self.receiver.close();
+        let mut calls = self.calls.lock().unwrap();
+        // Only remove our own entry, the id may have been registered again.
+        if calls.get(&self.key).map(|s| s.is_closed()).unwrap_or(false) {
+            calls.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::stream;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn server_is_send_sync() {
+        fn server<T: Send + Sync + 'static>() {
+            assert_send_sync::<Server<'static, T>>();
+            assert_send_sync::<ServerBuilder<T>>();
+        }
+        server::<()>();
+    }
+
+    struct DelayService;
+
+    #[async_trait]
+    impl Service for DelayService {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let millis: u64 = request.deserialize()?;
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            Ok(Some((request, json!(millis)).into()))
+        }
+    }
+
+    async fn collect(order: Order) -> Vec<Value> {
+        let service: Arc<dyn Service<Data = ()>> = Arc::new(DelayService);
+        let server = Arc::new(Server::new_shared(vec![service]));
+        let requests = vec![
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(60))),
+            Request::new(Some(json!(2)), "delay".to_string(), Some(json!(20))),
+            Request::new_notification("delay", Some(json!(0))),
+            Request::new(Some(json!(3)), "delay".to_string(), Some(json!(0))),
+        ];
+        respond(server, (), stream::iter(requests), 4, order)
+            .map(|response| response.id().clone().unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn respond_arrival_order() {
+        assert_eq!(
+            vec![json!(1), json!(2), json!(3)],
+            collect(Order::Arrival).await
+        );
+    }
+
+    #[tokio::test]
+    async fn respond_completion_order() {
+        assert_eq!(
+            vec![json!(3), json!(2), json!(1)],
+            collect(Order::Completion).await
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_message_notification() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]);
+        let call = Call::new(json!(1), "delay", Some(json!(0)));
+        let response = server.serve_call(&call, &()).await;
+        assert_eq!(&Some(json!(0)), response.result());
+
+        // Invalid params are an error but notifications are not answered
+        let message = Notification::new("delay", None).into_request();
+        let message = message.into_message();
+        assert!(server.serve_message(&message, &()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn server_on_served() {
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]).on_served({
+            let served = Arc::clone(&served);
+            move |request, response, elapsed| {
+                served.lock().unwrap().push((
+                    request.method().to_string(),
+                    response.is_some(),
+                    elapsed >= Duration::from_millis(5),
+                ))
+            }
+        });
+        let request = Request::new_reply("delay", Some(json!(5)));
+        assert!(server.serve(&request, &()).await.is_some());
+        let request = Request::new_notification("delay", Some(json!(0)));
+        assert!(server.serve(&request, &()).await.is_none());
+        assert_eq!(
+            vec![
+                ("delay".to_string(), true, true),
+                ("delay".to_string(), false, false),
+            ],
+            *served.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn server_stats() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]).with_stats();
+        let request = Request::new_reply("delay", Some(json!(5)));
+        server.serve(&request, &()).await;
+        let request = Request::new_notification("delay", Some(json!(0)));
+        server.serve(&request, &()).await;
+        let request = Request::new_reply("missing", None);
+        server.serve(&request, &()).await;
+
+        let stats = server.stats().unwrap();
+        assert_eq!(3, stats.requests);
+        assert_eq!(1, stats.notifications);
+        assert_eq!(Some(&2), stats.methods.get("delay"));
+        assert_eq!(1, stats.errors.client_fault);
+        assert!(stats.max_ms >= 5.0);
+    }
+
+    #[tokio::test]
+    async fn server_slow_request_log() {
+        let slow = Arc::new(Mutex::new(Vec::new()));
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]).with_slow_request_log(
+            Duration::from_millis(5),
+            {
+                let slow = Arc::clone(&slow);
+                move |request: &SlowRequest<'_>| {
+                    slow.lock().unwrap().push((
+                        request.request.method().to_string(),
+                        request.failed,
+                    ))
+                }
+            },
+        );
+        let request = Request::new_reply("delay", Some(json!(0)));
+        server.serve(&request, &()).await;
+        let request = Request::new_reply("delay", Some(json!(10)));
+        server.serve(&request, &()).await;
+        assert_eq!(vec![("delay".to_string(), false)], *slow.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn server_merge_and_mount() {
+        let delay: Arc<dyn Service<Data = ()>> = Arc::new(DelayService);
+        let server = Server::new_shared(vec![])
+            .merge(Server::new_shared(vec![Arc::clone(&delay)]))
+            .mount("slow", Server::new_shared(vec![delay]));
+        let request = Request::new_reply("slow.delay", Some(json!(0)));
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(&Some(json!(0)), response.result());
+    }
+
+    #[tokio::test]
+    async fn server_validator() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]).with_validator(
+            |request: &Request, _: &()| match request.params() {
+                Some(Value::Number(n)) if n.as_u64() > Some(10) => {
+                    Err(RpcError::new("Too slow".to_string(), None))
+                }
+                _ => Ok(()),
+            },
+        );
+        let request = Request::new_reply("delay", Some(json!(60)));
+        let response = server.serve(&request, &()).await.unwrap();
+        assert!(response.error().is_some());
+        let request = Request::new_reply("delay", Some(json!(0)));
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(&Some(json!(0)), response.result());
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("Overloaded")]
+    struct Overloaded;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("Gone")]
+    struct Gone;
+
+    struct FailService;
+
+    #[async_trait]
+    impl Service for FailService {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "busy" => Err(Error::from(Box::from(Overloaded))),
+                "gone" => Err(Error::from(Box::from(Gone))),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn server_error_mapper() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(FailService);
+        let server = Server::new(vec![&service]).with_error_mapper(
+            |error: &Error, _: &Request| {
+                let code = if error.downcast_ref::<Overloaded>().is_some() {
+                    -32005
+                } else if error.downcast_ref::<Gone>().is_some() {
+                    -32010
+                } else {
+                    return None;
+                };
+                Some(RpcError {
+                    code,
+                    message: error.to_string().into(),
+                    data: None,
+                })
+            },
+        );
+        for (method, code) in &[("busy", -32005), ("gone", -32010)] {
+            let request = Request::new_reply(method, None);
+            let response = server.serve(&request, &()).await.unwrap();
+            let error: Option<RpcError> = response.into();
+            assert_eq!(*code, error.unwrap().code);
+        }
+    }
+
+    #[tokio::test]
+    async fn server_priority() {
+        let delay: Arc<dyn Service<Data = ()>> = Arc::new(DelayService);
+        let fail: Arc<dyn Service<Data = ()>> = Arc::new(FailService);
+        let mut server = Server::new_prioritized(vec![(0, delay), (1, fail)]);
+        let request = Request::new_reply("busy", None);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(-32603, response.error().as_ref().unwrap().code);
+
+        server.push_front(Arc::new(crate::health::PingService::new()));
+        assert_eq!(vec!["rpc.ping".to_string()], server.order()[0]);
+    }
+
+    fn delay(id: u64, millis: u64) -> Request {
+        Request::new(Some(json!(id)), "delay".to_string(), Some(json!(millis)))
+    }
+
+    #[tokio::test]
+    async fn serve_until_end_of_stream() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]);
+        let requests = vec![
+            delay(1, 20),
+            Request::new_notification("delay", Some(json!(0))),
+            delay(2, 0),
+        ];
+        let mut ids = Vec::new();
+        let stats = serve_until(
+            &server,
+            &(),
+            stream::iter(requests),
+            |response| ids.push(response.id().clone().unwrap()),
+            future::pending(),
+            Duration::from_millis(0),
+        )
+        .await;
+        assert_eq!(vec![json!(2), json!(1)], ids);
+        assert_eq!(
+            ServedStats {
+                requests: 3,
+                errors: 0
+            },
+            stats
+        );
+    }
+
+    #[tokio::test]
+    async fn max_response_size_streams() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]).with_max_response_size(10);
+        let mut responses = Vec::new();
+        serve_until(
+            &server,
+            &(),
+            stream::iter(vec![delay(1, 0)]),
+            |response| responses.push(response),
+            future::pending(),
+            Duration::from_millis(0),
+        )
+        .await;
+
+        let service: Arc<dyn Service<Data = ()>> = Arc::new(DelayService);
+        let server = Arc::new(
+            Server::new_shared(vec![service]).with_max_response_size(10),
+        );
+        let requests = stream::iter(vec![delay(2, 0)]);
+        responses.extend(
+            respond(server, (), requests, 1, Order::Arrival)
+                .collect::<Vec<_>>()
+                .await,
+        );
+
+        let ids: Vec<_> = responses.iter().map(|r| r.id().clone()).collect();
+        assert_eq!(vec![Some(json!(1)), Some(json!(2))], ids);
+        for response in responses {
+            let error = response.error().as_ref().unwrap();
+            assert_eq!(crate::truncate::too_large(35, 10), *error);
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_until_shutdown_grace() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]);
+        let requests =
+            stream::iter(vec![delay(1, 0), delay(2, 10), delay(3, 5000)])
+                .chain(stream::pending());
+        let mut ids = Vec::new();
+        let stats = serve_until(
+            &server,
+            &(),
+            requests,
+            |response| ids.push(response.id().clone().unwrap()),
+            tokio::time::sleep(Duration::from_millis(5)),
+            Duration::from_millis(50),
+        )
+        .await;
+        assert_eq!(vec![json!(1), json!(2)], ids);
+        assert_eq!(2, stats.requests);
+    }
+
+    struct SessionService;
+
+    #[async_trait]
+    impl Service for SessionService {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let calls = request.extensions().get::<Mutex<u64>>().unwrap();
+            let mut calls = calls.lock().unwrap();
+            *calls += 1;
+            Ok(Some((request, json!(*calls)).into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_until_with_session() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(SessionService);
+        let server = Server::new(vec![&service]);
+        let closed = Arc::new(Mutex::new(0));
+        let session = Session::new(Mutex::new(0u64)).on_close({
+            let closed = Arc::clone(&closed);
+            move |calls: &Mutex<u64>| {
+                *closed.lock().unwrap() = *calls.lock().unwrap()
+            }
+        });
+        let requests = vec![delay(1, 0), delay(2, 0), delay(3, 0)];
+        let mut results = Vec::new();
+        serve_until_with(
+            &server,
+            &(),
+            stream::iter(requests),
+            |response| results.push(response.result().clone().unwrap()),
+            future::pending(),
+            Duration::from_millis(0),
+            session,
+        )
+        .await;
+        results.sort_by_key(|value| value.as_u64());
+        assert_eq!(vec![json!(1), json!(2), json!(3)], results);
+        assert_eq!(3, *closed.lock().unwrap());
+    }
+
+    struct PendingTransport {
+        pending: Pending,
+        sent: tokio::sync::mpsc::UnboundedSender<Request>,
+    }
+
+    #[async_trait]
+    impl Transport for PendingTransport {
+        async fn send(&self, request: &Request) -> Result<Option<Response>> {
+            let response =
+                self.pending.register(request.id().as_ref().unwrap());
+            let _ = self.sent.send(request.clone());
+            response.await.map(Some)
+        }
+    }
+
+    fn pending_client() -> (
+        Client<PendingTransport>,
+        Pending,
+        tokio::sync::mpsc::UnboundedReceiver<Request>,
+    ) {
+        let pending = Pending::new();
+        let (sent, rx) = tokio::sync::mpsc::unbounded_channel();
+        let transport = PendingTransport {
+            pending: pending.clone(),
+            sent,
+        };
+        (Client::new(transport), pending, rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_timeout_releases_pending() {
+        let (client, pending, mut rx) = pending_client();
+        let result: Result<Value> = client
+            .call_with_timeout("slow", None, Duration::from_secs(5))
+            .await;
+        match result {
+            Err(Error::Timeout {
+                method,
+                elapsed,
+                id,
+            }) => {
+                assert_eq!(Some("slow".to_string()), method);
+                assert_eq!(Some(Duration::from_secs(5)), elapsed);
+                assert!(id.is_some());
+            }
+            _ => panic!("expected timeout"),
+        }
+        assert!(pending.is_empty());
+
+        let request = rx.recv().await.unwrap();
+        let late: Response = (&request, json!(1)).into();
+        let unclaimed = pending.complete(late).unwrap();
+        assert_eq!(request.id(), unclaimed.id());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_completes_before_timeout() {
+        let (client, pending, mut rx) = pending_client();
+        let responder = pending.clone();
+        let reply = tokio::spawn(async move {
+            let request = rx.recv().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(4)).await;
+            responder.complete((&request, json!(42)).into())
+        });
+        let result: u64 = client
+            .call_with_timeout("slow", None, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(42, result);
+        assert!(reply.await.unwrap().is_none());
+        assert!(pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn call_method_async_service() {
+        const DOUBLE: typed::Method<u64, u64> = typed::Method::new("double");
+
+        async fn double(value: u64, _: &()) -> Result<u64> {
+            Ok(value * 2)
+        }
+
+        struct Local(Server<'static, ()>);
+
+        #[async_trait]
+        impl Transport for Local {
+            async fn send(
+                &self,
+                request: &Request,
+            ) -> Result<Option<Response>> {
+                Ok(self.0.serve(request, &()).await)
+            }
+        }
+
+        let service = Arc::new(DOUBLE.async_service(double));
+        let client = Client::new(Local(Server::new_shared(vec![service])));
+        assert_eq!(42, client.call_method(DOUBLE, 21).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn client_with_ids() {
+        struct EchoId;
+
+        #[async_trait]
+        impl Transport for EchoId {
+            async fn send(
+                &self,
+                request: &Request,
+            ) -> Result<Option<Response>> {
+                let id = request.id().clone().unwrap_or(Value::Null);
+                Ok(Some((request, id).into()))
+            }
+        }
+
+        let client = Client::new(EchoId).with_ids(crate::id::TestIds::new());
+        let first: Value = client.call("a", None).await.unwrap();
+        let second: Value = client.call("b", None).await.unwrap();
+        assert_eq!((json!(1), json!(2)), (first, second));
+    }
+
+    #[tokio::test]
+    async fn pending_drop_keeps_newer_call() {
+        let pending = Pending::new();
+        let first = pending.register(&json!(1));
+        let second = pending.register(&json!(1));
+        drop(first);
+        assert_eq!(1, pending.len());
+        let request = Request::new(Some(json!(1)), "call".to_string(), None);
+        assert!(pending.complete((&request, json!(true)).into()).is_none());
+        assert_eq!(Some(json!(true)), second.await.unwrap().into());
+    }
+
+    #[tokio::test]
+    async fn serve_batch_concurrent() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service])
+            .with_duplicate_ids(DuplicateIds::Reject);
+        let requests = vec![
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(20))),
+            Request::new_notification("delay", Some(json!(0))),
+            Request::new(Some(json!(2)), "delay".to_string(), Some(json!(0))),
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(0))),
+        ];
+        let responses = server.serve_batch(&requests, &()).await;
+        assert_eq!(3, responses.len());
+        assert_eq!(&Some(json!(20)), responses[0].result());
+        assert_eq!(&Some(json!(0)), responses[1].result());
+        assert!(responses[2].error().is_some());
+    }
+
+    #[tokio::test]
+    async fn serve_batch_abort_on_error() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service])
+            .with_batch_policy(BatchPolicy::AbortOnError);
+        let requests = vec![
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(0))),
+            Request::new(Some(json!(2)), "delay".to_string(), None),
+            Request::new(Some(json!(3)), "delay".to_string(), Some(json!(0))),
+        ];
+        let responses = server.serve_batch(&requests, &()).await;
+        assert_eq!(&Some(json!(0)), responses[0].result());
+        assert!(responses[1].error().is_some());
+        let error = responses[2].error().clone().unwrap();
+        assert_eq!(batch::BATCH_SKIPPED, error.code);
+    }
+
+    #[tokio::test]
+    async fn serve_batch_report() {
+        use crate::{INVALID_PARAMS, METHOD_NOT_FOUND};
+
+        /// Handles only the `delay` method.
+        struct Delay;
+
+        #[async_trait]
+        impl Service for Delay {
+            type Data = ();
+            async fn handle(
+                &self,
+                request: &Request,
+                ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                match request.method() {
+                    "delay" => DelayService.handle(request, ctx).await,
+                    _ => Ok(None),
+                }
+            }
+        }
+
+        let service: Box<dyn Service<Data = ()>> = Box::new(Delay);
+        let server = Server::new(vec![&service]);
+        let requests = vec![
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(0))),
+            Request::new(Some(json!(2)), "delay".to_string(), Some(json!(30))),
+            Request::new_notification("delay", Some(json!(0))),
+            Request::new(Some(json!(3)), "missing".to_string(), None),
+            Request::new(Some(json!(4)), "delay".to_string(), None),
+        ];
+        let (responses, reports) =
+            server.serve_batch_report(&requests, &()).await;
+        assert_eq!(4, responses.len());
+        assert_eq!(5, reports.len());
+        let slowest = reports.iter().max_by_key(|report| report.elapsed);
+        assert_eq!(1, slowest.unwrap().index);
+        assert!(reports[1].elapsed >= Duration::from_millis(30));
+        assert_eq!(ItemOutcome::Success, reports[0].outcome);
+        assert!(reports[2].notification);
+        assert_eq!(ItemOutcome::Success, reports[2].outcome);
+        assert_eq!("missing", reports[3].method);
+        assert_eq!(ItemOutcome::Error(METHOD_NOT_FOUND), reports[3].outcome);
+        assert_eq!(ItemOutcome::Error(INVALID_PARAMS), reports[4].outcome);
+
+        let server = Server::new(vec![&service])
+            .with_batch_policy(BatchPolicy::AbortOnError);
+        let (responses, reports) =
+            server.serve_batch_report(&requests, &()).await;
+        assert_eq!(4, responses.len());
+        let outcomes: Vec<ItemOutcome> =
+            reports.iter().map(|report| report.outcome).collect();
+        assert_eq!(
+            vec![
+                ItemOutcome::Success,
+                ItemOutcome::Success,
+                ItemOutcome::Success,
+                ItemOutcome::Error(METHOD_NOT_FOUND),
+                ItemOutcome::Skipped,
+            ],
+            outcomes
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_many_aligned() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DelayService);
+        let server = Server::new(vec![&service]);
+        let requests = vec![
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(20))),
+            Request::new_notification("delay", Some(json!(0))),
+            Request::new(Some(json!(1)), "delay".to_string(), Some(json!(0))),
+        ];
+        let responses = server.serve_many(&requests, &()).await;
+        assert_eq!(&Some(json!(20)), responses[0].as_ref().unwrap().result());
+        assert!(responses[1].is_none());
+        assert_eq!(&Some(json!(0)), responses[2].as_ref().unwrap().result());
+    }
+
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl Service for Named {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            Ok(Some((request, json!(self.0)).into()))
+        }
+
+        fn methods(&self) -> Vec<String> {
+            vec![self.0.to_string()]
+        }
+    }
+
+    #[tokio::test]
+    async fn build_server() {
+        let server = Server::builder()
+            .service(Named("first"))
+            .fallback(Named("first"))
+            .max_batch(1)
+            .deadlines()
+            .build()
+            .unwrap();
+        let request = Request::new_reply("any", None);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(Some(json!("first")), response.into());
+        let responses =
+            server.serve_batch(&[request.clone(), request], &()).await;
+        assert_eq!(1, responses.len());
+        assert!(responses[0].error().is_some());
+
+        let duplicate = Server::<()>::builder()
+            .service(Named("first"))
+            .service(Named("first"))
+            .build();
+        assert!(matches!(
+            duplicate,
+            Err(ConfigError::DuplicateMethod {
+                first: 0,
+                second: 1,
+                ..
+            })
+        ));
     }
 }
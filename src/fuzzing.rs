@@ -0,0 +1,255 @@
+//! Fuzz targets checking the parsing and serving invariants, requires
+//! the `fuzzing` feature.
+//!
+//! Each function takes arbitrary bytes and panics when an invariant
+//! does not hold, so it can be called from a `cargo fuzz` target of
+//! this crate or of a crate built on it:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     json_rpc2::fuzzing::fuzz_parse(data);
+//! });
+//! ```
+//!
+//! The feature enables the `float_roundtrip` feature of `serde_json` so
+//! floats parse exactly and the round trip checks hold for them. The
+//! tests of this module run with `cargo test --features fuzzing` so the
+//! rest of the suite parses floats as the default build does.
+//!
+//! [fuzz_parse()](fuzz_parse) checks that:
+//!
+//! * Parsing never panics and a string and a byte slice parse alike.
+//! * A parsed request is the request `serde_json` parses, whichever
+//!   backend is enabled.
+//! * A parsed request serializes and parses again to the same bytes.
+//! * A payload that is not JSON is a parse error and valid JSON that
+//!   is not a request an invalid request error, and both convert to a
+//!   response with the matching code.
+//!
+//! [fuzz_dispatch()](fuzz_dispatch) serves the payload with a server
+//! and checks that serving never panics, that every reply is a valid
+//! response and that calls are answered with their id.
+
+use crate::{
+    from_slice, from_str, Error, Request, Response, Server, INVALID_REQUEST,
+    PARSE_ERROR,
+};
+use serde_json::Value;
+
+/// Parse the payload and check the parsing invariants.
+///
+/// # Panics
+///
+/// Panics when an invariant does not hold.
+pub fn fuzz_parse(data: &[u8]) {
+    let parsed = from_slice(data);
+    if let Ok(payload) = std::str::from_utf8(data) {
+        let code = |result: &crate::Result<Request>| {
+            result.as_ref().err().map(|e| <(isize, _)>::from(e).0)
+        };
+        let from_str = from_str(payload);
+        assert_eq!(
+            code(&parsed),
+            code(&from_str),
+            "string and slice parse differently"
+        );
+    }
+    match parsed {
+        Ok(request) => {
+            agree(data, &request);
+            round_trip(&request);
+        }
+        Err(error) => classify(data, error),
+    }
+}
+
+/// Check `serde_json` parses the payload to the same request.
+fn agree(data: &[u8], request: &Request) {
+    let expected = crate::json_from_slice(data).expect("serde_json parses");
+    assert_eq!(
+        serde_json::to_vec(&expected).expect("request serializes"),
+        serde_json::to_vec(request).expect("request serializes"),
+        "backends parse to different requests"
+    );
+}
+
+/// Serialize a parsed request and check it parses to the same bytes.
+fn round_trip(request: &Request) {
+    let bytes = serde_json::to_vec(request).expect("request serializes");
+    let parsed = from_slice(&bytes).expect("serialized request parses");
+    let again = serde_json::to_vec(&parsed).expect("request serializes");
+    assert_eq!(bytes, again, "request is not stable through a round trip");
+    let value = serde_json::to_value(request).expect("request converts");
+    let parsed = crate::from_value(value).expect("request value parses");
+    assert_eq!(request.id(), parsed.id(), "id changed through a value");
+}
+
+/// Check the error for a payload that is not a request.
+fn classify(data: &[u8], error: Error) {
+    let json = serde_json::from_slice::<Value>(data).is_ok();
+    let expected = if json { INVALID_REQUEST } else { PARSE_ERROR };
+    let (code, _) = (&error).into();
+    assert_eq!(expected, code, "{:?} misclassified", error);
+    assert!(!error.is_connection(), "{:?} is a connection error", error);
+    assert_eq!(json, matches!(error, Error::InvalidRequest { .. }));
+
+    let response = Response::from(error);
+    let code = response.error().as_ref().map(|error| error.code);
+    assert_eq!(Some(expected), code, "response code differs");
+    if !json {
+        assert_eq!(&Some(Value::Null), response.id(), "parse error has id");
+    }
+    let bytes = serde_json::to_vec(&response).expect("response serializes");
+    serde_json::from_slice::<Response>(&bytes).expect("response parses");
+}
+
+/// Serve the payload and check the serving invariants.
+///
+/// The payload is served with
+/// [serve_slice()](crate::Server::serve_slice) and, when it is a
+/// request, with [serve()](crate::Server::serve).
+///
+/// # Panics
+///
+/// Panics when an invariant does not hold.
+pub fn fuzz_dispatch(data: &[u8], server: &Server<'_, ()>) {
+    let request = from_slice(data).ok();
+    let reply = server.serve_slice(data, &());
+    let response = reply.map(|bytes| {
+        serde_json::from_slice::<Response>(&bytes).expect("reply is a response")
+    });
+    let request = match request {
+        Some(request) => request,
+        None => {
+            let response = response.expect("invalid payload is answered");
+            assert!(response.error().is_some(), "invalid payload succeeded");
+            return;
+        }
+    };
+    check_answer(&request, response.as_ref());
+    let response = server.serve(&request, &());
+    check_answer(&request, response.as_ref());
+}
+
+/// Check the response, if any, to a parsed request.
+fn check_answer(request: &Request, response: Option<&Response>) {
+    match (request.id(), response) {
+        (Some(id), Some(response)) => {
+            assert_eq!(&Some(id.clone()), response.id(), "id not echoed")
+        }
+        (Some(_), None) => panic!("call was not answered"),
+        (None, Some(response)) => {
+            assert!(response.error().is_some(), "notification answered")
+        }
+        (None, None) => {}
+    }
+    if let Some(response) = response {
+        assert!(
+            response.result().is_some() != response.error().is_some(),
+            "response must have a result or an error"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Result, Service};
+    use serde_json::json;
+
+    struct Echo;
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let params = request.params().clone().unwrap_or(Value::Null);
+            Ok(Some((request, params).into()))
+        }
+    }
+
+    fn deep(depth: usize) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"echo","params":{}1{}}}"#,
+            "[".repeat(depth),
+            "]".repeat(depth)
+        )
+    }
+
+    fn corpus() -> Vec<Vec<u8>> {
+        let mut corpus: Vec<Vec<u8>> = vec![
+            b"".to_vec(),
+            b" ".to_vec(),
+            b"null".to_vec(),
+            b"[]".to_vec(),
+            b"{".to_vec(),
+            b"\xff\xfe".to_vec(),
+            br#"{"jsonrpc":"2.0","method":"echo"}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":"a","method":"echo","params":[1]}"#
+                .to_vec(),
+            br#"{"jsonrpc":"2.0","id":1e400,"method":"echo"}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":-1e308,"method":"echo"}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":18446744073709551616,"method":"echo"}"#
+                .to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[123456789012345678901234567890e-5000]}"#
+                .to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":{"_meta":{"a":1}}}"#
+                .to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":"x"}"#
+                .to_vec(),
+            br#"{"jsonrpc":"1.0","id":[1],"method":7}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"\ud800"}"#.to_vec(),
+            br#"{"jsonrpc":"2.0\ud800","id":"x","method":"echo"}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo"}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[2.54e300]}"#
+                .to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[2.5e310]}"#
+                .to_vec(),
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[-0,1.50]}"#
+                .to_vec(),
+            br#"[{"jsonrpc":"2.0","id"O1,"method":"echo"}]"#.to_vec(),
+            b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"echo\",\"params\":[n\x1bull]}"
+                .to_vec(),
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\n\n  \"method\":\"é\",\"x\":}"
+                .as_bytes()
+                .to_vec(),
+            deep(10).into_bytes(),
+            deep(127).into_bytes(),
+            deep(128).into_bytes(),
+            deep(100_000).into_bytes(),
+            format!(r#"{{"jsonrpc":"2.0","id":{}1{},"method":"echo"}}"#,
+                r#"{"a":"#.repeat(50_000),
+                "}".repeat(50_000)
+            )
+            .into_bytes(),
+        ];
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "echo",
+            "params": {"a": [1, 2.5, null, "s"]},
+        });
+        let valid = serde_json::to_vec(&request).unwrap();
+        for end in 0..valid.len() {
+            corpus.push(valid[..end].to_vec());
+        }
+        corpus
+    }
+
+    #[test]
+    fn fuzz_corpus() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let echo = Server::new(vec![&service]);
+        let empty = Server::new(vec![]);
+        for data in corpus() {
+            fuzz_parse(&data);
+            fuzz_dispatch(&data, &echo);
+            fuzz_dispatch(&data, &empty);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Built-in services for liveness and health probes.
+//!
+//! Both services are generic over the user data type so they can be
+//! added alongside any other services; they implement the blocking
+//! `Service` trait and the async `Service` trait when the `async`
+//! feature is enabled.
+
+use crate::{Request, Response, Result, Service};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+/// Method name handled by [PingService](PingService).
+pub const PING: &str = "rpc.ping";
+
+/// Method name handled by [HealthService](HealthService).
+pub const HEALTH: &str = "rpc.health";
+
+/// Result of a ping when extra information has been requested.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Pong {
+    /// Always the string `pong`.
+    pub message: String,
+    /// The version of this crate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Number of seconds since the service was created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u64>,
+}
+
+/// Service that replies to `rpc.ping` with `"pong"`.
+///
+/// When the version or uptime is enabled the result is a
+/// [Pong](Pong) object instead of a plain string.
+pub struct PingService<T> {
+    started: Instant,
+    version: bool,
+    uptime: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for PingService<T> {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            version: false,
+            uptime: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PingService<T> {
+    /// Create a new ping service.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Include the crate version in the reply.
+    pub fn with_version(mut self) -> Self {
+        self.version = true;
+        self
+    }
+
+    /// Include the number of seconds since creation in the reply.
+    pub fn with_uptime(mut self) -> Self {
+        self.uptime = true;
+        self
+    }
+
+    fn reply(&self, request: &Request) -> Option<Response> {
+        if request.method() != PING {
+            return None;
+        }
+        let result = if self.version || self.uptime {
+            let pong = Pong {
+                message: "pong".to_string(),
+                version: if self.version {
+                    Some(env!("CARGO_PKG_VERSION").to_string())
+                } else {
+                    None
+                },
+                uptime: if self.uptime {
+                    Some(self.started.elapsed().as_secs())
+                } else {
+                    None
+                },
+            };
+            serde_json::to_value(pong).unwrap_or(Value::Null)
+        } else {
+            Value::String("pong".to_string())
+        };
+        Some((request, result).into())
+    }
+}
+
+impl<T> Service for PingService<T> {
+    type Data = T;
+    fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        Ok(self.reply(request))
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![PING.to_string()]
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<T: Send + Sync> crate::futures::Service for PingService<T> {
+    type Data = T;
+    async fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        Ok(self.reply(request))
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![PING.to_string()]
+    }
+}
+
+/// Status of a single health check.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct CheckStatus {
+    /// The name of the check.
+    pub name: String,
+    /// Whether the check passed.
+    pub healthy: bool,
+    /// Error message when the check failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Aggregated result of all health checks.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct HealthReport {
+    /// Whether every check passed.
+    pub healthy: bool,
+    /// Status of each check in registration order.
+    pub checks: Vec<CheckStatus>,
+}
+
+type Check = Box<dyn Fn() -> std::result::Result<(), String> + Send + Sync>;
+
+/// Service that replies to `rpc.health` with a [HealthReport](HealthReport).
+///
+/// A failing check does not produce an error response, the report
+/// is always returned so monitoring can inspect individual checks.
+pub struct HealthService<T> {
+    checks: Vec<(String, Check)>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for HealthService<T> {
+    fn default() -> Self {
+        Self {
+            checks: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> HealthService<T> {
+    /// Create a new health service without any checks.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a named check.
+    pub fn check<F>(mut self, name: &str, check: F) -> Self
+    where
+        F: Fn() -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.checks.push((name.to_string(), Box::new(check)));
+        self
+    }
+
+    /// Run all the checks.
+    pub fn run(&self) -> HealthReport {
+        let checks: Vec<CheckStatus> = self
+            .checks
+            .iter()
+            .map(|(name, check)| {
+                let error = check().err();
+                CheckStatus {
+                    name: name.clone(),
+                    healthy: error.is_none(),
+                    error,
+                }
+            })
+            .collect();
+        HealthReport {
+            healthy: checks.iter().all(|c| c.healthy),
+            checks,
+        }
+    }
+
+    fn reply(&self, request: &Request) -> Option<Response> {
+        if request.method() != HEALTH {
+            return None;
+        }
+        let result = serde_json::to_value(self.run()).unwrap_or(Value::Null);
+        Some((request, result).into())
+    }
+}
+
+impl<T> Service for HealthService<T> {
+    type Data = T;
+    fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        Ok(self.reply(request))
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![HEALTH.to_string()]
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<T: Send + Sync> crate::futures::Service for HealthService<T> {
+    type Data = T;
+    async fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        Ok(self.reply(request))
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![HEALTH.to_string()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Server;
+
+    #[test]
+    fn ping_pong() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(PingService::new());
+        let server = Server::new(vec![&service]);
+        let response = server.serve(&Request::new_reply(PING, None), &());
+        assert_eq!(
+            Some(Value::String("pong".to_string())),
+            response.unwrap().into()
+        );
+    }
+
+    #[test]
+    fn ping_version() {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(PingService::new().with_version().with_uptime());
+        let server = Server::new(vec![&service]);
+        let response = server.serve(&Request::new_reply(PING, None), &());
+        let result: Option<Value> = response.unwrap().into();
+        let pong: Pong = serde_json::from_value(result.unwrap()).unwrap();
+        assert_eq!("pong", pong.message);
+        assert_eq!(Some(env!("CARGO_PKG_VERSION")), pong.version.as_deref());
+        assert_eq!(Some(0), pong.uptime);
+    }
+
+    #[test]
+    fn health_report() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(
+            HealthService::new()
+                .check("database", || Ok(()))
+                .check("cache", || Err("connection refused".to_string())),
+        );
+        let server = Server::new(vec![&service]);
+        let response = server.serve(&Request::new_reply(HEALTH, None), &());
+        let result: Option<Value> = response.unwrap().into();
+        let report: HealthReport =
+            serde_json::from_value(result.unwrap()).unwrap();
+        assert!(!report.healthy);
+        assert_eq!(
+            vec![
+                CheckStatus {
+                    name: "database".to_string(),
+                    healthy: true,
+                    error: None,
+                },
+                CheckStatus {
+                    name: "cache".to_string(),
+                    healthy: false,
+                    error: Some("connection refused".to_string()),
+                },
+            ],
+            report.checks
+        );
+    }
+
+    #[tokio::test]
+    async fn async_ping() {
+        use crate::futures;
+        let service: Box<dyn futures::Service<Data = ()>> =
+            Box::new(PingService::new());
+        let server = futures::Server::new(vec![&service]);
+        let response = server.serve(&Request::new_reply(PING, None), &()).await;
+        assert_eq!(
+            Some(Value::String("pong".to_string())),
+            response.unwrap().into()
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! HTTP status codes for responses.
+//!
+//! HTTP transports agree on the status by calling these functions:
+//!
+//! * [StatusMapping::JsonRpc](StatusMapping::JsonRpc), the default,
+//!   follows the JSON-RPC over HTTP recommendation: `200 OK` for every
+//!   response with a body, including error responses, and
+//!   `204 No Content` for notifications.
+//! * [StatusMapping::Rest](StatusMapping::Rest) is for clients that
+//!   expect error statuses: `404` for a method that is not found,
+//!   `400` for other client faults, `503` for retryable errors and
+//!   `500` for server faults, as categorized by an
+//!   [ErrorPolicy](crate::policy::ErrorPolicy).
+//!
+//! With either mapping [BAD_REQUEST](BAD_REQUEST) and
+//! [INTERNAL_SERVER_ERROR](INTERNAL_SERVER_ERROR) are left for failures
+//! of the transport, such as a body that cannot be read.
+//!
+//! ```
+//! use json_rpc2::{http::{http_status_for, StatusMapping}, policy::ErrorPolicy, *};
+//!
+//! let request = Request::new_reply("missing", None);
+//! let response: Response = (&request, Error::MethodNotFound {
+//!     name: "missing".to_string(),
+//!     id: request.id().clone(),
+//! }).into();
+//! let error = response.error().as_ref();
+//! assert_eq!(200, http_status_for(error));
+//!
+//! let policy = ErrorPolicy::default();
+//! assert_eq!(200, StatusMapping::JsonRpc.status(Some(&response), &policy));
+//! assert_eq!(404, StatusMapping::Rest.status(Some(&response), &policy));
+//! assert_eq!(204, StatusMapping::Rest.status(None, &policy));
+//! ```
+
+use crate::{
+    policy::{Category, ErrorPolicy},
+    Response, RpcError, METHOD_NOT_FOUND,
+};
+
+/// Status for a response with a body.
+pub const OK: u16 = 200;
+/// Status for a notification, which has no response.
+pub const NO_CONTENT: u16 = 204;
+/// Status for a body the transport could not read.
+pub const BAD_REQUEST: u16 = 400;
+/// Status for an unknown method with the REST mapping.
+pub const NOT_FOUND: u16 = 404;
+/// Status for a failure of the transport.
+pub const INTERNAL_SERVER_ERROR: u16 = 500;
+/// Status for a retryable error with the REST mapping.
+pub const SERVICE_UNAVAILABLE: u16 = 503;
+
+/// How error responses map to HTTP status codes.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum StatusMapping {
+    /// Every response with a body is `200 OK`.
+    #[default]
+    JsonRpc,
+    /// Error responses use a `4xx` or `5xx` status.
+    Rest,
+}
+
+impl StatusMapping {
+    /// The status for a response, `None` for a notification.
+    pub fn status(
+        self,
+        response: Option<&Response>,
+        policy: &ErrorPolicy,
+    ) -> u16 {
+        match response {
+            Some(response) => {
+                self.error_status(response.error().as_ref(), policy)
+            }
+            None => NO_CONTENT,
+        }
+    }
+
+    /// The status for a response with a body and an optional error.
+    pub fn error_status(
+        self,
+        error: Option<&RpcError>,
+        policy: &ErrorPolicy,
+    ) -> u16 {
+        match (self, error) {
+            (StatusMapping::JsonRpc, _) | (_, None) => OK,
+            (StatusMapping::Rest, Some(error))
+                if error.code == METHOD_NOT_FOUND =>
+            {
+                NOT_FOUND
+            }
+            (StatusMapping::Rest, Some(error)) => {
+                match policy.categorize(error.code) {
+                    Category::ClientFault => BAD_REQUEST,
+                    Category::Retryable => SERVICE_UNAVAILABLE,
+                    Category::ServerFault => INTERNAL_SERVER_ERROR,
+                }
+            }
+        }
+    }
+
+    /// The status for the responses to a batch.
+    ///
+    /// A batch of notifications has no body; otherwise the status is
+    /// `200 OK` with either mapping as the responses may disagree.
+    pub fn batch_status(self, responses: &[Response]) -> u16 {
+        if responses.is_empty() {
+            NO_CONTENT
+        } else {
+            OK
+        }
+    }
+}
+
+/// The status for a response with a body following the JSON-RPC over
+/// HTTP recommendation, which is always `200 OK`.
+///
+/// Use [StatusMapping](StatusMapping) for notifications and the REST
+/// mapping.
+pub fn http_status_for(error: Option<&RpcError>) -> u16 {
+    StatusMapping::JsonRpc.error_status(error, &ErrorPolicy::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cancel::REQUEST_CANCELLED, shed::SERVER_BUSY, Error, Request};
+
+    #[test]
+    fn http_status_mappings() {
+        let policy = ErrorPolicy::default();
+        let request = Request::new_reply("sum", None);
+        let ok: Response = (&request, serde_json::json!(1)).into();
+        let error = |code: isize| -> Response {
+            let error = RpcError {
+                code,
+                message: "failed".into(),
+                data: None,
+            };
+            (&request, error).into()
+        };
+        let parse: Response = Error::Parse {
+            data: "EOF".to_string(),
+            line: None,
+            column: None,
+            offset: None,
+        }
+        .into();
+
+        let cases = [
+            (Some(ok.clone()), OK, OK),
+            (None, NO_CONTENT, NO_CONTENT),
+            (Some(parse), OK, BAD_REQUEST),
+            (Some(error(METHOD_NOT_FOUND)), OK, NOT_FOUND),
+            (Some(error(crate::INVALID_PARAMS)), OK, BAD_REQUEST),
+            (Some(error(REQUEST_CANCELLED)), OK, BAD_REQUEST),
+            (Some(error(SERVER_BUSY)), OK, SERVICE_UNAVAILABLE),
+            (
+                Some(error(crate::INTERNAL_ERROR)),
+                OK,
+                INTERNAL_SERVER_ERROR,
+            ),
+            (Some(error(7)), OK, INTERNAL_SERVER_ERROR),
+        ];
+        for (response, json_rpc, rest) in cases.iter() {
+            let code = response
+                .as_ref()
+                .and_then(|r| r.error().as_ref().map(|e| e.code));
+            assert_eq!(
+                *json_rpc,
+                StatusMapping::JsonRpc.status(response.as_ref(), &policy),
+                "{:?}",
+                code
+            );
+            assert_eq!(
+                *rest,
+                StatusMapping::Rest.status(response.as_ref(), &policy),
+                "{:?}",
+                code
+            );
+        }
+
+        assert_eq!(OK, http_status_for(error(-32700).error().as_ref()));
+        assert_eq!(NO_CONTENT, StatusMapping::Rest.batch_status(&[]));
+        assert_eq!(OK, StatusMapping::Rest.batch_status(&[ok]));
+    }
+}
@@ -0,0 +1,227 @@
+//! Generate ids for requests.
+//!
+//! Requests created with [Request::new_reply()](crate::Request::new_reply)
+//! and by the clients get a random id by default. Snapshot tests of
+//! serialized requests need the same ids on every run so give the
+//! client a [TestIds](TestIds) generator, which counts up from `1`:
+//!
+//! ```
+//! use json_rpc2::{client::{Client, Transport}, id::TestIds, *};
+//! use std::sync::Mutex;
+//!
+//! #[derive(Default)]
+//! struct Recorder(Mutex<Vec<String>>);
+//! impl Transport for Recorder {
+//!     fn send(&self, request: &Request) -> Result<Option<Response>> {
+//!         self.0.lock().unwrap().push(serde_json::to_string(request).unwrap());
+//!         Ok(Some((request, serde_json::Value::Null).into()))
+//!     }
+//! }
+//!
+//! let client = Client::new(Recorder::default()).with_ids(TestIds::new());
+//! client.call::<()>("ping", None)?;
+//! client.call::<()>("ping", None)?;
+//! assert_eq!(
+//!     vec![
+//!         r#"{"jsonrpc":"2.0","method":"ping","id":1}"#,
+//!         r#"{"jsonrpc":"2.0","method":"ping","id":2}"#,
+//!     ],
+//!     *client.transport().0.lock().unwrap(),
+//! );
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! Requests created directly take a generator with
+//! [Request::new_reply_with()](crate::Request::new_reply_with).
+//!
+//! ## Passthrough
+//!
+//! Ids are never normalized: [Request::id()](crate::Request::id) is the
+//! value that was parsed and responses echo it as is, so a proxy passes
+//! ids such as negative numbers, empty strings or 128-bit numbers sent
+//! as strings of digits through untouched. The typed [Id](Id) view from
+//! [typed_id()](crate::Request::typed_id) is lossy and only meant for
+//! inspecting an id, never for building the response.
+//!
+//! Numbers that do not fit in 64 bits are parsed by `serde_json` as
+//! floats unless the `arbitrary-precision` feature is enabled, which
+//! turns on the `arbitrary_precision` feature of `serde_json` so such
+//! numbers keep their digits. Note that it also changes the wording of
+//! some parameter errors. The `simd` parser leaves payloads with such
+//! numbers to `serde_json` so they keep their digits as well.
+
+use crate::{Request, Response};
+use serde_json::{Number, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of ids for requests that expect a reply.
+pub trait IdGenerator: Send + Sync {
+    /// The id for the next request.
+    fn next_id(&self) -> Value;
+}
+
+/// Random numeric ids, the default for requests and clients.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_id(&self) -> Value {
+        crate::random_id()
+    }
+}
+
+/// Sequential numeric ids starting at `1` for reproducible tests.
+#[derive(Debug)]
+pub struct TestIds {
+    next: AtomicU64,
+}
+
+impl TestIds {
+    /// Create a generator yielding `1`, `2`, `3`, ...
+    pub fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    /// Create a generator that counts up from `first`.
+    pub fn starting_at(first: u64) -> Self {
+        Self {
+            next: AtomicU64::new(first),
+        }
+    }
+}
+
+impl Default for TestIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdGenerator for TestIds {
+    fn next_id(&self) -> Value {
+        Value::Number(Number::from(self.next.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+/// Lossy typed view of an id.
+///
+/// Echo the original value from [Request::id()](crate::Request::id)
+/// rather than rebuilding an id from this view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Id<'a> {
+    /// The id is `null`.
+    Null,
+    /// Integer id that fits in 64 bits.
+    Integer(i128),
+    /// Any other number, which may have lost precision.
+    Float(f64),
+    /// String id.
+    String(&'a str),
+}
+
+impl<'a> Id<'a> {
+    /// View an id value, `None` for an array or object which the
+    /// specification does not allow.
+    pub fn from_value(value: &'a Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(Id::Null),
+            Value::Number(number) => Some(
+                number
+                    .as_i64()
+                    .map(i128::from)
+                    .or_else(|| number.as_u64().map(i128::from))
+                    .map(Id::Integer)
+                    .unwrap_or_else(|| {
+                        Id::Float(number.as_f64().unwrap_or(f64::NAN))
+                    }),
+            ),
+            Value::String(value) => Some(Id::String(value)),
+            _ => None,
+        }
+    }
+}
+
+impl Request {
+    /// Lossy typed view of the id, see [Id](Id).
+    pub fn typed_id(&self) -> Option<Id<'_>> {
+        self.id().as_ref().and_then(Id::from_value)
+    }
+}
+
+impl Response {
+    /// Lossy typed view of the id, see [Id](Id).
+    pub fn typed_id(&self) -> Option<Id<'_>> {
+        self.id().as_ref().and_then(Id::from_value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Server;
+    use serde_json::json;
+
+    /// Parse a request, serve it with no services and serialize the
+    /// method not found response.
+    fn proxy(id: &str) -> String {
+        let payload =
+            format!(r#"{{"jsonrpc":"2.0","method":"nope","id":{}}}"#, id);
+        let request = crate::from_str(&payload).unwrap();
+        let server = Server::<()>::new(vec![]);
+        let response = server.serve(&request, &()).unwrap();
+        assert!(response.error().is_some());
+        serde_json::to_string(&response).unwrap()
+    }
+
+    #[test]
+    fn id_passthrough() {
+        let ids = [
+            r#""123456789012345678901234567890123456789""#,
+            "-7",
+            r#""""#,
+        ];
+        for id in ids {
+            let response = proxy(id);
+            assert!(response.contains("-32601"), "{}", response);
+            let echoed = format!(r#""id":{},"#, id);
+            assert!(response.contains(&echoed), "{}", response);
+        }
+    }
+
+    #[cfg(feature = "arbitrary-precision")]
+    #[test]
+    fn id_passthrough_arbitrary_precision() {
+        let id = "123456789012345678901234567890123456789";
+        let response = proxy(id);
+        let echoed = format!(r#""id":{},"#, id);
+        assert!(response.contains(&echoed), "{}", response);
+    }
+
+    #[test]
+    fn id_typed_view() {
+        let request = |id: Value| Request::new(Some(id), "a".to_string(), None);
+        assert_eq!(Some(Id::Integer(-7)), request(json!(-7)).typed_id());
+        assert_eq!(
+            Some(Id::Integer(u64::MAX as i128)),
+            request(json!(u64::MAX)).typed_id()
+        );
+        assert_eq!(Some(Id::Float(1.5)), request(json!(1.5)).typed_id());
+        assert_eq!(Some(Id::String("")), request(json!("")).typed_id());
+        assert_eq!(Some(Id::Null), request(Value::Null).typed_id());
+        assert_eq!(None, request(json!([1])).typed_id());
+        assert_eq!(None, Request::new_notification("a", None).typed_id());
+
+        let response: Response = (&request(json!("x")), json!(1)).into();
+        assert_eq!(Some(Id::String("x")), response.typed_id());
+    }
+
+    #[test]
+    fn id_test_ids() {
+        let ids = TestIds::new();
+        let first = Request::new_reply_with(&ids, "a", None);
+        let second = Request::new_reply_with(&ids, "b", None);
+        assert_eq!(&Some(json!(1)), first.id());
+        assert_eq!(&Some(json!(2)), second.id());
+        assert_eq!(json!(10), TestIds::starting_at(10).next_id());
+        assert!(RandomIds.next_id().is_u64());
+    }
+}
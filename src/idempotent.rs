@@ -0,0 +1,401 @@
+//! Replay responses for retried requests, requires the `async` feature.
+//!
+//! Clients that retry after a timeout cannot tell whether the first
+//! attempt ran. [Idempotent](Idempotent) lets them send an idempotency
+//! key, by default in the `idempotency_key` field of the
+//! [metadata](crate::meta), and stores the response for the key; a
+//! request with a key that was already answered gets the stored
+//! response, with its own id, without calling the inner service.
+//! Concurrent requests with the same key are
+//! [coalesced](crate::coalesce) so the inner service runs once.
+//!
+//! Keys are scoped to the method. Requests without a key and
+//! notifications are passed to the inner service. Errors and error
+//! responses are not stored so a failed call can be retried.
+//!
+//! Responses live in an [IdempotencyStore](IdempotencyStore), such as
+//! the bundled [MemoryStore](MemoryStore); implement the trait to share
+//! the responses between servers, for example in Redis.
+
+use crate::{
+    coalesce::{follow, Flights, Join},
+    describe::MethodMeta,
+    futures::Service,
+    Request, Response, Result,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "tracing"))]
+use log::warn as log_warn;
+#[cfg(feature = "tracing")]
+use tracing::warn as log_warn;
+
+/// Name of the idempotency key field in the metadata.
+pub const IDEMPOTENCY_KEY: &str = "idempotency_key";
+
+/// Storage for the responses to requests with an idempotency key.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Get the response stored for a key.
+    ///
+    /// An error fails the request rather than risk running it twice.
+    async fn get(&self, key: &str) -> Result<Option<Response>>;
+
+    /// Store the response for a key.
+    ///
+    /// An error is logged and the response is still returned.
+    async fn put(&self, key: String, response: Response) -> Result<()>;
+}
+
+type Extractor = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+/// Service that replays the stored response for an idempotency key.
+///
+/// Only available with the `async` feature.
+pub struct Idempotent<S> {
+    inner: S,
+    store: Box<dyn IdempotencyStore>,
+    extractor: Box<Extractor>,
+    flights: Flights,
+}
+
+impl<S> Idempotent<S> {
+    /// Create a wrapper around a service that stores responses in
+    /// `store`.
+    pub fn new<T: IdempotencyStore + 'static>(inner: S, store: T) -> Self {
+        Self {
+            inner,
+            store: Box::new(store),
+            extractor: Box::new(meta_key),
+            flights: Default::default(),
+        }
+    }
+
+    /// Read the idempotency key of a request with a function instead of
+    /// from the metadata.
+    pub fn key_with<F>(mut self, extractor: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.extractor = Box::new(extractor);
+        self
+    }
+
+    /// The inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Store key for the request, `None` when it has no idempotency key.
+    fn key(&self, request: &Request) -> Option<String> {
+        request.id().as_ref()?;
+        let key = (self.extractor)(request)?;
+        Some(format!("{}:{}", request.method(), key))
+    }
+
+    async fn lookup(
+        &self,
+        request: &Request,
+        key: &str,
+    ) -> Result<Option<Response>> {
+        Ok(self.store.get(key).await?.map(|mut response| {
+            response.id = request.id().clone();
+            response
+        }))
+    }
+
+    async fn save(&self, request: &Request, key: String, response: &Response) {
+        if response.error().is_some() {
+            return;
+        }
+        if let Err(e) = self.store.put(key, response.clone()).await {
+            log_warn!(
+                "failed to store idempotent response for {}: {}",
+                request.method(),
+                e
+            );
+        }
+    }
+}
+
+/// The idempotency key from the metadata of a request.
+fn meta_key(request: &Request) -> Option<String> {
+    let key = request.meta()?.get(IDEMPOTENCY_KEY)?.as_str()?;
+    if key.is_empty() {
+        None
+    } else {
+        Some(key.to_string())
+    }
+}
+
+#[async_trait]
+impl<S: Service> Service for Idempotent<S> {
+    type Data = S::Data;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let key = match self.key(request) {
+            Some(key) => key,
+            None => return self.inner.handle(request, ctx).await,
+        };
+        if let Some(response) = self.lookup(request, &key).await? {
+            return Ok(Some(response));
+        }
+        let mut flight = match self.flights.join(key.clone()) {
+            Join::Lead(flight) => flight,
+            Join::Wait(waiter) => return follow(waiter, request).await,
+        };
+        // Another leader may have stored the response since the lookup.
+        let result = match self.lookup(request, &key).await {
+            Ok(None) => {
+                let result = self.inner.handle(request, ctx).await;
+                if let Ok(Some(response)) = &result {
+                    self.save(request, key, response).await;
+                }
+                result
+            }
+            stored => stored,
+        };
+        flight.share(request, &result);
+        result
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+struct Entry {
+    response: Response,
+    expires: Instant,
+}
+
+/// In-memory store holding responses for a time to live.
+///
+/// When full the entry closest to expiry is replaced.
+pub struct MemoryStore {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryStore {
+    /// Create a store holding at most `capacity` responses.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of entries including any that have expired.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Determine if the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Response>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.expires > Instant::now() => {
+                Ok(Some(entry.response.clone()))
+            }
+            Some(_) => {
+                entries.remove(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, key: String, response: Response) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if !entries.contains_key(&key) && entries.len() >= self.capacity {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        let expires = now + self.ttl;
+        entries.insert(key, Entry { response, expires });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{futures::Server, Error};
+    use futures_util::future::join_all;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct Transfer(AtomicUsize);
+
+    #[async_trait]
+    impl Service for Transfer {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            match request.method() {
+                "fail" => Err(Error::from(Box::from("declined"))),
+                _ => Ok(Some((request, json!(count)).into())),
+            }
+        }
+    }
+
+    fn call(id: u64, method: &str, key: Option<&str>) -> Request {
+        let mut request =
+            Request::new(Some(json!(id)), method.to_string(), None);
+        if let Some(key) = key {
+            request.set_meta(json!({IDEMPOTENCY_KEY: key})).unwrap();
+        }
+        request
+    }
+
+    fn idempotent() -> Arc<Idempotent<Transfer>> {
+        Arc::new(Idempotent::new(
+            Transfer::default(),
+            MemoryStore::new(8, Duration::from_secs(60)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn idempotent_replays() {
+        let idempotent = idempotent();
+        let service: Arc<dyn Service<Data = ()>> = idempotent.clone();
+        let server = Server::new_shared(vec![service]);
+
+        let first = server.serve(&call(1, "transfer", Some("a")), &()).await;
+        let retry = server.serve(&call(2, "transfer", Some("a")), &()).await;
+        assert_eq!(1, idempotent.inner().0.load(Ordering::SeqCst));
+        let (first, retry) = (first.unwrap(), retry.unwrap());
+        assert_eq!(first.result(), retry.result());
+        assert_eq!(&Some(json!(2)), retry.id());
+
+        // Other keys, methods and requests without a key run again.
+        for request in [
+            call(3, "transfer", Some("b")),
+            call(4, "refund", Some("a")),
+            call(5, "transfer", None),
+            call(6, "transfer", None),
+        ]
+        .iter()
+        {
+            server.serve(request, &()).await;
+        }
+        assert_eq!(5, idempotent.inner().0.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn idempotent_coalesces() {
+        let idempotent = idempotent();
+        let service: Arc<dyn Service<Data = ()>> = idempotent.clone();
+        let server = Server::new_shared(vec![service]);
+        let requests: Vec<Request> = (1..=4)
+            .map(|id| call(id, "transfer", Some("same")))
+            .collect();
+        let responses =
+            join_all(requests.iter().map(|r| server.serve(r, &()))).await;
+        assert_eq!(1, idempotent.inner().0.load(Ordering::SeqCst));
+        for (id, response) in (1..=4).zip(responses) {
+            let response = response.unwrap();
+            assert_eq!(&Some(json!(id)), response.id());
+            assert_eq!(&Some(json!(1)), response.result());
+        }
+        assert!(idempotent.flights.is_empty());
+    }
+
+    #[tokio::test]
+    async fn idempotent_errors_not_stored() {
+        let idempotent = idempotent();
+        for id in 1..=2 {
+            let result =
+                idempotent.handle(&call(id, "fail", Some("a")), &()).await;
+            assert!(result.is_err());
+        }
+        assert_eq!(2, idempotent.inner().0.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn idempotent_key_with() {
+        let idempotent = Idempotent::new(
+            Transfer::default(),
+            MemoryStore::new(8, Duration::from_secs(60)),
+        )
+        .key_with(|request| {
+            request
+                .params()
+                .as_ref()?
+                .get("nonce")?
+                .as_str()
+                .map(String::from)
+        });
+        let request = |id: u64| {
+            Request::new(
+                Some(json!(id)),
+                "transfer".to_string(),
+                Some(json!({"nonce": "n1"})),
+            )
+        };
+        idempotent.handle(&request(1), &()).await.unwrap();
+        idempotent.handle(&request(2), &()).await.unwrap();
+        assert_eq!(1, idempotent.inner().0.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn memory_store_expiry_and_capacity() -> Result<()> {
+        let store = MemoryStore::new(2, Duration::from_millis(20));
+        let response: Response =
+            (&Request::new_reply("m", None), json!(1)).into();
+        store.put("a".to_string(), response.clone()).await?;
+        store.put("b".to_string(), response.clone()).await?;
+        store.put("c".to_string(), response.clone()).await?;
+        assert_eq!(2, store.len());
+        assert!(store.get("a").await?.is_none());
+        assert!(store.get("c").await?.is_some());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        store.put("d".to_string(), response).await?;
+        assert_eq!(1, store.len());
+        assert!(store.get("c").await?.is_none());
+        Ok(())
+    }
+}
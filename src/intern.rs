@@ -0,0 +1,141 @@
+//! Reuse known method names when parsing requests.
+//!
+//! Most services answer a small set of method names so a
+//! [MethodTable](MethodTable) holds a shared copy of each name; requests
+//! parsed with the table point at the shared name instead of allocating
+//! their own. Names that are not in the table are allocated as usual.
+
+use crate::{map_payload_error, validate, Request, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Request with a method name borrowed from the payload.
+#[derive(Deserialize)]
+struct RawRequest<'a> {
+    jsonrpc: String,
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    id: Option<Value>,
+    params: Option<Value>,
+    #[serde(rename = "_meta")]
+    meta: Option<Value>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+/// Table of known method names.
+#[derive(Debug, Clone, Default)]
+pub struct MethodTable {
+    names: HashSet<Arc<str>>,
+}
+
+impl MethodTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a method name to the table.
+    pub fn method(mut self, name: &str) -> Self {
+        self.names.insert(Arc::from(name));
+        self
+    }
+
+    /// Shared copy of a name, allocated when it is not in the table.
+    pub fn intern(&self, name: &str) -> Arc<str> {
+        match self.names.get(name) {
+            Some(name) => Arc::clone(name),
+            None => Arc::from(name),
+        }
+    }
+
+    /// Parse a JSON payload from a string slice into a request.
+    pub fn from_str(&self, payload: &str) -> Result<Request> {
+        serde_json::from_str::<RawRequest>(payload)
+            .map(|raw| self.request(raw))
+            .map_err(|e| map_payload_error(e, payload.as_bytes()))
+            .and_then(validate)
+    }
+
+    /// Parse a JSON payload from a byte slice into a request.
+    pub fn from_slice(&self, payload: &[u8]) -> Result<Request> {
+        serde_json::from_slice::<RawRequest>(payload)
+            .map(|raw| self.request(raw))
+            .map_err(|e| map_payload_error(e, payload))
+            .and_then(validate)
+    }
+
+    /// Parse a JSON payload from a [Value](serde_json::Value) into a
+    /// request.
+    pub fn from_value(&self, payload: Value) -> Result<Request> {
+        let mut request = crate::from_value(payload)?;
+        request.method = self.intern(&request.method);
+        Ok(request)
+    }
+
+    fn request(&self, raw: RawRequest<'_>) -> Request {
+        Request {
+            jsonrpc: raw.jsonrpc.into(),
+            #[cfg(feature = "extra-fields")]
+            extra: raw.extra,
+            method: self.intern(&raw.method),
+            id: raw.id,
+            params: raw.params,
+            meta: raw.meta,
+            received: None,
+            extensions: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+    use serde_json::json;
+
+    fn table() -> MethodTable {
+        MethodTable::new()
+            .method("eth_call")
+            .method("eth_getBalance")
+    }
+
+    #[test]
+    fn intern_known_methods() -> Result<()> {
+        let table = table();
+        let payload = r#"{"jsonrpc":"2.0","id":1,"method":"eth_call"}"#;
+        let first = table.from_str(payload)?;
+        let second = table.from_slice(payload.as_bytes())?;
+        let third = table.from_value(json!({
+            "jsonrpc": "2.0",
+            "method": "eth_call",
+        }))?;
+        assert!(Arc::ptr_eq(&first.method, &second.method));
+        assert!(Arc::ptr_eq(&first.method, &third.method));
+        assert_eq!("eth_call", first.method());
+        assert_eq!(&Some(json!(1)), first.id());
+
+        let other =
+            table.from_str(r#"{"jsonrpc":"2.0","method":"eth_chainId"}"#)?;
+        assert_eq!("eth_chainId", other.method());
+        assert!(other.id().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn intern_errors() {
+        let table = table();
+        assert!(matches!(
+            table.from_str(r#"{"jsonrpc":"2.0","#),
+            Err(Error::Parse { .. })
+        ));
+        assert!(matches!(
+            table.from_str(r#"{"jsonrpc":"2.0","id":1}"#),
+            Err(Error::InvalidRequest { .. })
+        ));
+    }
+}
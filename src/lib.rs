@@ -35,10 +35,119 @@
 //! }
 //! ```
 //!
+//! ## Macros
+//!
+//! The [rpc_service!](rpc_service) macro removes the boilerplate of
+//! matching method names and converting parameters and results; without
+//! macros a plain function becomes a service using
+//! [method()](method::method).
+//!
+//! The `macros` feature adds the `#[rpc]` attribute which generates a
+//! service and a typed client from a trait definition:
+//!
+//! ```
+//! # #[cfg(feature = "macros")]
+//! # {
+//! use json_rpc2::{rpc, Request, Result, Server, Service};
+//!
+//! #[rpc]
+//! pub trait Wallet {
+//!     #[rpc(name = "wallet.balance")]
+//!     fn balance(&self, account: String) -> Result<u64>;
+//! }
+//!
+//! struct MemoryWallet;
+//! impl Wallet for MemoryWallet {
+//!     fn balance(&self, _account: String) -> Result<u64> {
+//!         Ok(42)
+//!     }
+//! }
+//!
+//! let service: Box<dyn Service<Data = ()>> =
+//!     Box::new(WalletServer::new(MemoryWallet));
+//! let server = Server::new(vec![&service]);
+//! let request = Request::new_reply(
+//!     "wallet.balance", Some(serde_json::json!(["alice"])));
+//! let response = server.serve(&request, &());
+//! assert_eq!(Some(serde_json::json!(42)), response.unwrap().into());
+//! # }
+//! ```
+//!
 //! ## Parsing
 //!
 //! When converting from incoming payloads use the `from_*` functions
 //! to convert JSON to a [Request](Request) so that errors are mapped correctly.
+//! A [MethodTable](intern::MethodTable) offers the same functions and
+//! reuses the known method names rather than allocating one per request.
+//! With the `simd` feature [from_str()](from_str) and
+//! [from_slice()](from_slice) parse with simd-json, see the `simd`
+//! module.
+//!
+//! Use [into_message()](Request::into_message) to tell calls and
+//! notifications apart, see the [message](message) module. The
+//! [typed](typed) module wraps requests and responses so parameters
+//! and results are checked against Rust types. Methods that accept
+//! both positional and named parameters read them with
+//! [deserialize_either()](Request::deserialize_either), see the
+//! [params](params) module.
+//!
+//! On a bidirectional connection parse each payload with the
+//! [peer](peer) module to tell requests, responses and batches apart.
+//!
+//! Unknown top-level fields are discarded unless the `extra-fields`
+//! feature is enabled; they are then kept in
+//! [extra_fields()](Request::extra_fields) of requests and responses and
+//! serialized again so proxies pass vendor extensions through.
+//!
+//! Ids are echoed exactly as parsed so proxies pass them through
+//! untouched; enable the `arbitrary-precision` feature to keep numeric
+//! ids wider than 64 bits, see the [id](id) module.
+//!
+//! To choose which parts of the specification are enforced, such as
+//! rejecting unknown fields and reserved `rpc.` methods, parse with a
+//! [Conformance](conformance::Conformance) preset.
+//!
+//! The `fuzzing` feature exposes the parsing and serving invariants as
+//! fuzz targets, see the `fuzzing` module.
+//!
+//! ## Batches
+//!
+//! Batch responses may arrive in any order, use
+//! [match_batch()](batch::match_batch) to pair them with the requests.
+//! Servers answer a batch with [serve_batch()](Server::serve_batch).
+//! With the `async` feature a [BatchingClient](batching::BatchingClient)
+//! coalesces calls made within a short window into a single batch, and
+//! [serve_batch_report()](futures::Server::serve_batch_report) reports
+//! the outcome and time taken by every element of a batch.
+//!
+//! ## Responses
+//!
+//! Services reply by converting from the request, for example
+//! `(request, value).into()`; use [Response::builder()](Response::builder)
+//! when there is no request at hand. For large results
+//! [serve_str()](Server::serve_str) writes the result of services that
+//! support it without building a `Value`, see [raw]. With the
+//! `schemars` feature the envelope types implement `JsonSchema`, see
+//! the `schema` module.
+//!
+//! With the `bytes` feature the `bytes` module sends binary data as
+//! base64 strings rather than arrays of numbers.
+//!
+//! ## Client
+//!
+//! The [client](client) module sends requests using a
+//! [Transport](client::Transport) and converts the responses. A
+//! [ProxyService](proxy::ProxyService) forwards requests that no other
+//! service handles to an upstream server using a client; a
+//! [Forwarder](forward::Forwarder) passes raw payloads through
+//! replacing only the id. With the
+//! `async` feature an [Aggregate](aggregate::Aggregate) sends each
+//! request to several upstream servers and combines the answers, and a
+//! [MultiplexClient](multiplex::MultiplexClient) shares one
+//! bidirectional connection between concurrent calls and yields the
+//! notifications pushed by the server as typed streams. A
+//! [Reconnecting](reconnect::Reconnecting) transport connects again
+//! with backoff after its connection fails.
 //!
 //! ## Context
 //!
@@ -48,6 +157,48 @@
 //! methods. Use `Data = T` with a custom type to expose user data to your handlers
 //! that is not available when the services are created.
 //!
+//! State that belongs to a connection rather than to the server, such as
+//! the authenticated user, travels in the [extensions](extensions) of
+//! each request so one server can be shared by every connection; see the
+//! [session](session) module.
+//!
+//! ## Composition
+//!
+//! Services can be added and removed while the server is running using
+//! a [Registry](registry::Registry).
+//!
+//! [Server::builder()](Server::builder) collects services and
+//! configuration and reports mistakes such as two services providing
+//! the same method when the server is built.
+//!
+//! An [AuthzPolicy](authz::AuthzPolicy) given to
+//! [with_authz()](Server::with_authz) forbids methods to callers
+//! without the scopes they require.
+//!
+//! Servers built separately can be combined with
+//! [merge()](Server::merge) or served under a prefix using
+//! [mount()](Server::mount). Enable
+//! [with_methods()](Server::with_methods) to list the methods of the
+//! services in reply to `rpc.methods`.
+//! Services may describe their methods with summaries, parameters and
+//! deprecations for documentation, see the [describe](describe)
+//! module.
+//!
+//! To test middleware and servers, a
+//! [MockService](testing::MockService) answers methods with closures
+//! and records the requests that reached it.
+//!
+//! ## Threads
+//!
+//! Share a server created with [new_shared()](Server::new_shared) between
+//! worker threads using a [ThreadPoolServer](pool::ThreadPoolServer).
+//!
+//! ## Shutdown
+//!
+//! Serving loops share a graceful shutdown story that finishes in-flight
+//! requests and reports [ServedStats](shutdown::ServedStats), see the
+//! [shutdown](shutdown) module.
+//!
 //! ## Async
 //!
 //! For nonblocking support enable the `async` feature and use the `Service`
@@ -57,13 +208,242 @@
 //!
 //! See the `async` example for usage.
 //!
+//! [Error](Error), [Request](Request), [Response](Response), both
+//! `Server` types and their builders are `Send` and `Sync` so they can
+//! cross `tokio::spawn` and be shared in an `Arc`; tests assert this so
+//! it is not lost by accident.
+//!
+//! To serve a stream of requests, for example from a framed codec or a
+//! channel receiver, share a server in an `Arc` and pass the stream to
+//! [respond()](futures::respond). To serve a backlog with the most
+//! important requests first use a
+//! [PriorityQueueServer](priority::PriorityQueueServer).
+//!
+//! With the `net` feature [serve_tcp()](net::serve_tcp) serves newline
+//! delimited requests over TCP and a [TcpTransport](net::TcpTransport)
+//! connects a client to it; run the `server` and `client` examples with
+//! `--features net` to try it. When a connection also carries requests
+//! and notifications sent to the peer, an
+//! [OutboundWriter](outbound::OutboundWriter) funnels every message
+//! through a single writer task.
+//!
+//! ## Concurrency
+//!
+//! Wrap an async service in [ConcurrencyLimit](limit::ConcurrencyLimit)
+//! to bound the number of handlers running at once. To reject requests
+//! with a server busy error rather than queuing them use
+//! [LoadShed](shed::LoadShed).
+//!
+//! ## Caching
+//!
+//! Wrap a service in [Cached](cache::Cached) to answer repeated calls to
+//! pure methods from a cache.
+//!
+//! With the `async` feature [Coalesce](coalesce::Coalesce) runs the handler
+//! once for identical requests that are in flight at the same time and
+//! [Idempotent](idempotent::Idempotent) replays the response to a
+//! retried request with the same idempotency key.
+//!
+//! ## Cancellation
+//!
+//! The async server can drop in-flight handlers when a request is
+//! cancelled, see the [cancel](cancel) module. It can also stop
+//! handlers at a deadline sent by the client, see the
+//! [deadline](deadline) module.
+//!
+//! ## Notifications
+//!
+//! Handlers can send notifications and report progress for long running
+//! requests using a [Notifier](notify::Notifier), see the `progress`
+//! example for usage. With the `sse` feature the `sse` module streams
+//! the notifications to browsers as Server-Sent Events.
+//!
+//! [Subscriptions](subscription::Subscriptions) keeps track of the
+//! subscriptions created by subscribe methods and tags their
+//! notifications with the subscription id, see the `subscribe` example.
+//! With the `async` feature the `stream` module sends a large result as
+//! a stream of chunk notifications and reassembles it on the client.
+//!
+//! ## Metadata
+//!
+//! Trace context and other metadata travel in a `_meta` field of the
+//! parameters or of the request, see the [meta](meta) module. With the
+//! `signing` feature the `signing` module signs requests with HMAC in
+//! the `_meta` field of the request.
+//!
+//! ## Health
+//!
+//! The [health](health) module provides services for `rpc.ping` and
+//! `rpc.health` so liveness probes can be answered consistently.
+//! Servers built [with_stats()](Server::with_stats) count the requests
+//! they serve, see [stats].
+//!
+//! ## Logging
+//!
+//! Wrap a service in [Logged](logged::Logged) to log every request it
+//! handles using the `log` facade, or `tracing` when the `tracing`
+//! feature is enabled. Share [RedactionRules](redact::RedactionRules)
+//! between the logs and anywhere else messages are written to keep
+//! secrets out of them.
+//!
+//! To observe every request served, for example to record latency,
+//! set a function with [on_served()](Server::on_served). The
+//! [tape](tape) module uses it to record requests and responses so
+//! they can be replayed against another build.
+//!
+//! ## Errors
+//!
+//! Services may return any error by converting it to `Error::Boxed`,
+//! which is answered with an internal error unless an error mapper
+//! translates it, see [with_error_mapper()](Server::with_error_mapper).
+//!
+//! Invalid parameters are reported with
+//! [invalid_params()](Error::invalid_params), which borrows constant
+//! messages, or [invalid_params_with()](Error::invalid_params_with),
+//! which formats the message only when the error is answered.
+//! Converting a request and a `&str` into an error copies the message.
+//!
+//! Which codes are retryable or the fault of the client, and the level
+//! they are logged at, is configured with an
+//! [ErrorPolicy](policy::ErrorPolicy) on the server, see the
+//! [policy](policy) module.
+//!
+//! With the `anyhow` feature an `anyhow::Error` converts directly so
+//! `?` works in handlers; the response message includes the context
+//! chain and [downcast_ref()](Error::downcast_ref) sees the underlying
+//! error:
+//!
+//! ```
+//! # #[cfg(feature = "anyhow")]
+//! # {
+//! use anyhow::Context;
+//! use json_rpc2::{Request, Response, Result, Server, Service};
+//!
+//! fn load(name: &str) -> anyhow::Result<String> {
+//!     std::fs::read_to_string(name).context("Failed to load config")
+//! }
+//!
+//! struct ConfigService;
+//! impl Service for ConfigService {
+//!     type Data = ();
+//!     fn handle(
+//!         &self,
+//!         request: &Request,
+//!         _ctx: &Self::Data,
+//!     ) -> Result<Option<Response>> {
+//!         let contents = load("/does/not/exist")?;
+//!         Ok(Some((request, serde_json::Value::String(contents)).into()))
+//!     }
+//! }
+//!
+//! let service: Box<dyn Service<Data = ()>> = Box::new(ConfigService);
+//! let server = Server::new(vec![&service]);
+//! let request = Request::new_reply("config", None);
+//! let response = server.serve(&request, &()).unwrap();
+//! let error: Option<json_rpc2::RpcError> = response.into();
+//! assert!(error.unwrap().message.starts_with("Failed to load config: "));
+//! # }
+//! ```
 
+#[cfg(any(test, feature = "async"))]
+pub mod aggregate;
+pub mod authz;
+pub mod batch;
+#[cfg(any(test, feature = "async"))]
+pub mod batching;
+pub mod builder;
+#[cfg(feature = "bytes")]
+pub mod bytes;
+pub mod cache;
+pub mod cancel;
+pub mod client;
+#[cfg(any(test, feature = "async"))]
+pub mod coalesce;
+pub mod conformance;
+mod de;
+pub mod deadline;
+pub mod debug;
+pub mod describe;
+pub mod extensions;
+pub mod forward;
 #[cfg(any(test, feature = "async"))]
 pub mod futures;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod health;
+pub mod http;
+pub mod id;
+#[cfg(any(test, feature = "async"))]
+pub mod idempotent;
+pub mod intern;
+#[cfg(any(test, feature = "async"))]
+pub mod limit;
+pub mod logged;
+#[doc(hidden)]
+pub mod macros;
+pub mod message;
+pub mod meta;
+pub mod method;
+#[cfg(any(test, feature = "async"))]
+pub mod multiplex;
+pub mod namespace;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod notify;
+#[cfg(feature = "net")]
+pub mod outbound;
+pub mod params;
+pub mod peer;
+pub mod policy;
+pub mod pool;
+#[cfg(any(test, feature = "async"))]
+pub mod priority;
+pub mod proxy;
+#[cfg(feature = "query")]
+pub mod query;
+pub mod raw;
+#[cfg(any(test, feature = "async"))]
+pub mod reconnect;
+pub mod redact;
+pub mod registry;
+mod routes;
+#[cfg(feature = "schemars")]
+pub mod schema;
+pub mod session;
+pub mod shed;
+pub mod shutdown;
+#[cfg(feature = "signing")]
+pub mod signing;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod slow;
+#[cfg(feature = "sse")]
+pub mod sse;
+pub mod stats;
+#[cfg(any(test, feature = "async"))]
+pub mod stream;
+pub mod subscription;
+pub mod tape;
+pub mod testing;
+#[cfg(feature = "extra-fields")]
+pub mod timing;
+pub mod truncate;
+pub mod typed;
+
+#[cfg(any(test, feature = "async"))]
+#[doc(hidden)]
+pub use async_trait::async_trait as __async_trait;
+#[cfg(feature = "macros")]
+pub use json_rpc2_macros::rpc;
 
 use rand::Rng;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::{Number, Value};
+use serde_json::{error::Category, Number, Value};
+use slow::SlowLog;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Instant;
 
 const VERSION: &str = "2.0";
 const INVALID_REQUEST: isize = -32600;
@@ -72,10 +452,24 @@ const INVALID_PARAMS: isize = -32602;
 const INTERNAL_ERROR: isize = -32603;
 const PARSE_ERROR: isize = -32700;
 
+/// Method name of the built-in method listing.
+///
+/// See [Server::with_methods()](Server::with_methods).
+pub const METHODS: &str = "rpc.methods";
+
+/// Key in the error data that holds the cause of an error.
+///
+/// See [RpcError::with_cause()](RpcError::with_cause).
+pub const CAUSE: &str = "cause";
+
 /// Result type for service handler functions and internal library errors.
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Enumeration of errors.
+///
+/// The error is `Send + Sync + 'static`: sources are stored as strings
+/// or in `Error::Boxed` which requires `Send + Sync`, so it can be
+/// returned from spawned tasks.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     /// Error generated when a JSON payload cannot be parsed.
@@ -83,13 +477,30 @@ pub enum Error {
     Parse {
         /// The underlying JSON error message.
         data: String,
+        /// The line of the payload where the error was detected.
+        line: Option<usize>,
+        /// The column of the payload where the error was detected.
+        column: Option<usize>,
+        /// The byte offset of the error when parsing from a string
+        /// or byte slice.
+        offset: Option<usize>,
     },
     /// Error generated when the contents of a JSON payload do not
     /// match the request type semantics.
     #[error("Invalid JSON-RPC request")]
     InvalidRequest {
+        /// The id of the request message when it could be recovered
+        /// from the payload.
+        id: Option<Value>,
         /// The underlying JSON error message.
         data: String,
+        /// The line of the payload where the error was detected.
+        line: Option<usize>,
+        /// The column of the payload where the error was detected.
+        column: Option<usize>,
+        /// The byte offset of the error when parsing from a string
+        /// or byte slice.
+        offset: Option<usize>,
     },
 
     /// Error generated when the request method name did not
@@ -109,169 +520,1810 @@ pub enum Error {
         /// The id of the request message.
         id: Option<Value>,
         /// The underlying JSON error message.
-        data: String,
+        data: ErrorDetail,
+    },
+
+    /// Error with an explicit code, message and data.
+    ///
+    /// Used for error responses received by a client and passed
+    /// through untouched when converted to a response.
+    #[error("{}", .0.message)]
+    Rpc(RpcError),
+
+    /// Error generated when a call or handler runs out of time.
+    ///
+    /// Converted to the `-32000` deadline exceeded error, the code can
+    /// be changed with
+    /// [ErrorPolicy::timeout_code()](policy::ErrorPolicy::timeout_code).
+    /// Clients yield it for their own timeouts, a transport timing out
+    /// and a deadline exceeded response, see
+    /// [from_response()](Error::from_response).
+    #[error("{}", timeout_message(.method, .elapsed))]
+    Timeout {
+        /// The name of the request method when known.
+        method: Option<String>,
+        /// How long the caller waited when known.
+        elapsed: Option<std::time::Duration>,
+        /// The id of the request message.
+        id: Option<Value>,
+    },
+
+    /// Error generated when a request was cancelled before it was
+    /// answered.
+    ///
+    /// Converted to the `-32800` request cancelled error, the code can
+    /// be changed with
+    /// [ErrorPolicy::cancelled_code()](policy::ErrorPolicy::cancelled_code).
+    #[error("Request cancelled")]
+    Cancelled {
+        /// The id of the request message.
+        id: Option<Value>,
     },
 
+    /// Error generated when reading or writing a transport fails.
+    ///
+    /// Converted to an internal error response; see
+    /// [is_connection()](Error::is_connection) for when no response
+    /// should be sent.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Error generated when a connection was closed before any data
+    /// of a request was read.
+    ///
+    /// This is not a protocol error, a serve loop should treat it as
+    /// the end of the stream. A connection closed part way through a
+    /// message is a parse error.
+    #[error("Connection closed")]
+    Closed,
+
+    /// Error generated when the connection of a client was lost while
+    /// a call was waiting for its response.
+    ///
+    /// Returned by the client in the `multiplex` module.
+    #[error("Connection lost")]
+    ConnectionLost,
+
     /// Generic error type converted to an internal error response.
+    ///
+    /// See the [debug](debug) module to include the source chain and a
+    /// backtrace in the response.
     #[error(transparent)]
-    Boxed(#[from] Box<dyn std::error::Error + Send + Sync>),
+    Boxed(Box<dyn std::error::Error + Send + Sync>),
+}
+
+fn timeout_message(
+    method: &Option<String>,
+    elapsed: &Option<std::time::Duration>,
+) -> String {
+    let call = match method {
+        Some(method) => format!("Call to {} timed out", method),
+        None => "Request timed out".to_string(),
+    };
+    match elapsed {
+        Some(elapsed) => format!("{} after {:?}", call, elapsed),
+        None => call,
+    }
 }
 
-impl<'a> From<&'a Error> for (isize, Option<String>) {
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Boxed(debug::capture(error))
+    }
+}
+
+impl<'a> From<&'a Error> for (isize, Option<Value>) {
     fn from(error: &'a Error) -> Self {
         match error {
             Error::MethodNotFound { .. } => (METHOD_NOT_FOUND, None),
             Error::InvalidParams { data, .. } => {
-                (INVALID_PARAMS, Some(data.to_string()))
+                (INVALID_PARAMS, Some(truncate::data(data)))
+            }
+            Error::Parse { data, .. } => {
+                (PARSE_ERROR, Some(truncate::data(data)))
+            }
+            Error::InvalidRequest { data, .. } => {
+                (INVALID_REQUEST, Some(truncate::data(data)))
+            }
+            Error::Rpc(error) => (error.code, error.data.clone()),
+            Error::Timeout { .. } => (deadline::DEADLINE_EXCEEDED, None),
+            Error::Cancelled { .. } => (cancel::REQUEST_CANCELLED, None),
+            _ => (INTERNAL_ERROR, None),
+        }
+    }
+}
+
+impl Error {
+    /// Borrow the inner error of `Error::Boxed` as type `E`.
+    ///
+    /// Yields `None` for other variants or when the inner error is
+    /// not an `E`.
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        match self {
+            Error::Boxed(error) => {
+                let error = debug::inner(&**error);
+                #[cfg(feature = "anyhow")]
+                if let Some(error) = error.downcast_ref::<AnyhowError>() {
+                    return error.0.downcast_ref::<E>();
+                }
+                error.downcast_ref::<E>()
+            }
+            _ => None,
+        }
+    }
+
+    /// Take the inner error of `Error::Boxed` as type `E`.
+    ///
+    /// The error is given back unchanged for other variants or when
+    /// the inner error is not an `E`.
+    pub fn downcast<E>(self) -> std::result::Result<E, Error>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        match self {
+            Error::Boxed(error) if error.is::<debug::Traced>() => {
+                let traced = *error
+                    .downcast::<debug::Traced>()
+                    .expect("error is traced");
+                match Error::Boxed(traced.error).downcast::<E>() {
+                    Err(Error::Boxed(error)) => {
+                        Err(Error::Boxed(Box::new(debug::Traced {
+                            error,
+                            backtrace: traced.backtrace,
+                        })))
+                    }
+                    result => result,
+                }
+            }
+            #[cfg(feature = "anyhow")]
+            Error::Boxed(error) if error.is::<AnyhowError>() => {
+                match error.downcast::<AnyhowError>() {
+                    Ok(error) => error.0.downcast::<E>().map_err(Error::from),
+                    Err(error) => Err(Error::Boxed(error)),
+                }
+            }
+            Error::Boxed(error) => match error.downcast::<E>() {
+                Ok(error) => Ok(*error),
+                Err(error) => Err(Error::Boxed(error)),
+            },
+            _ => Err(self),
+        }
+    }
+
+    /// Determine if this is an `Error::Boxed` holding an `E`.
+    pub fn is<E>(&self) -> bool
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.downcast_ref::<E>().is_some()
+    }
+
+    /// Determine if this is a connection level failure that cannot
+    /// be answered.
+    ///
+    /// True for `Error::Closed`, `Error::ConnectionLost` and for
+    /// `Error::Io` when the peer has gone away; a serve loop should
+    /// stop reading rather than send a response. Other IO errors are
+    /// answered with an internal error.
+    pub fn is_connection(&self) -> bool {
+        use std::io::ErrorKind;
+        match self {
+            Error::Closed | Error::ConnectionLost => true,
+            Error::Io(error) => matches!(
+                error.kind(),
+                ErrorKind::UnexpectedEof
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+            ),
+            _ => false,
+        }
+    }
+
+    /// The message for an error response.
+    ///
+    /// Variants with a fixed message borrow it rather than formatting
+    /// the error so building an error response does not allocate.
+    pub fn message(&self) -> Cow<'static, str> {
+        match self {
+            Error::Parse { .. } => {
+                Cow::Borrowed("Parsing failed, invalid JSON data")
+            }
+            Error::InvalidRequest { .. } => {
+                Cow::Borrowed("Invalid JSON-RPC request")
+            }
+            Error::InvalidParams { .. } => {
+                Cow::Borrowed("Message parameters are invalid")
+            }
+            Error::Rpc(error) => error.message.clone(),
+            Error::Timeout { .. } => deadline::exceeded().message,
+            Error::Cancelled { .. } => cancel::cancelled().message,
+            _ => Cow::Owned(self.to_string()),
+        }
+    }
+
+    /// The line of the payload where a parse or invalid request
+    /// error was detected.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Parse { line, .. } | Error::InvalidRequest { line, .. } => {
+                *line
+            }
+            _ => None,
+        }
+    }
+
+    /// The column of the payload where a parse or invalid request
+    /// error was detected.
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            Error::Parse { column, .. }
+            | Error::InvalidRequest { column, .. } => *column,
+            _ => None,
+        }
+    }
+
+    /// The byte offset into the payload where a parse or invalid
+    /// request error was detected.
+    ///
+    /// Only available when the request was parsed from a string or
+    /// byte slice.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Parse { offset, .. }
+            | Error::InvalidRequest { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// Create an error with a custom code and structured data.
+    ///
+    /// The data is serialized immediately so the error remains
+    /// `Send + Sync`; if the data cannot be serialized an internal
+    /// error is returned instead.
+    pub fn with_data<S: Serialize>(
+        code: isize,
+        message: &str,
+        data: S,
+    ) -> Error {
+        match serde_json::to_value(data) {
+            Ok(data) => Error::Rpc(RpcError {
+                code,
+                message: Cow::Owned(message.to_string()),
+                data: Some(data),
+            }),
+            Err(e) => Error::from(Box::from(e)),
+        }
+    }
+
+    /// Create an invalid params error for a request.
+    ///
+    /// A constant message is borrowed so creating the error does not
+    /// allocate beyond copying the id.
+    pub fn invalid_params(
+        request: &Request,
+        data: impl Into<Cow<'static, str>>,
+    ) -> Error {
+        Error::InvalidParams {
+            id: request.id().clone(),
+            data: ErrorDetail::from(data.into()),
+        }
+    }
+
+    /// Create an invalid params error that formats its data when it is
+    /// first needed.
+    ///
+    /// The id is copied from the request now so the error does not
+    /// borrow it; `data` runs at most once, when the error is converted
+    /// into a response or its data is read, and never if the error is
+    /// discarded.
+    ///
+    /// ```
+    /// use json_rpc2::{Error, Request, Response};
+    ///
+    /// let request = Request::new_reply("import", None);
+    /// let row = 7;
+    /// let error = Error::invalid_params_with(&request, move || {
+    ///     format!("bad row {}", row)
+    /// });
+    /// let response: Response = error.into();
+    /// let error = response.error().clone().unwrap();
+    /// assert_eq!(Some(serde_json::json!("bad row 7")), error.data);
+    /// ```
+    pub fn invalid_params_with<F>(request: &Request, data: F) -> Error
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        Error::InvalidParams {
+            id: request.id().clone(),
+            data: ErrorDetail::lazy(data),
+        }
+    }
+
+    /// Create an error from a type that declares its own code,
+    /// message and data.
+    ///
+    /// The error is sent to the client as given by
+    /// [to_rpc_error()](ToRpcError::to_rpc_error).
+    pub fn from_rpc<E: ToRpcError>(error: E) -> Error {
+        Error::Rpc(error.to_rpc_error())
+    }
+
+    /// The error of a response received by a client, `None` when the
+    /// response is not an error.
+    ///
+    /// The request cancelled code yields `Error::Cancelled` and the
+    /// deadline exceeded error `Error::Timeout`, recognized by its
+    /// default code and message as the code is shared with other
+    /// server errors. Any other error yields `Error::Rpc`.
+    pub fn from_response(response: &Response) -> Option<Error> {
+        let error = response.error().as_ref()?;
+        let id = response.id().clone();
+        Some(match error.code {
+            cancel::REQUEST_CANCELLED => Error::Cancelled { id },
+            deadline::DEADLINE_EXCEEDED
+                if error.message == deadline::exceeded().message =>
+            {
+                Error::Timeout {
+                    method: None,
+                    elapsed: None,
+                    id,
+                }
+            }
+            _ => Error::Rpc(error.clone()),
+        })
+    }
+}
+
+/// The message is copied, create the error with
+/// [Error::invalid_params()](Error::invalid_params) so a constant
+/// message is borrowed instead.
+impl<'a> From<(&'a mut Request, &'a str)> for Error {
+    fn from(value: (&'a mut Request, &'a str)) -> Error {
+        Error::from((value.0, value.1.to_string()))
+    }
+}
+
+impl<'a> From<(&'a mut Request, String)> for Error {
+    fn from(value: (&'a mut Request, String)) -> Error {
+        Error::invalid_params(value.0, value.1)
+    }
+}
+
+impl<'a> From<(&'a mut Request, Cow<'static, str>)> for Error {
+    fn from(value: (&'a mut Request, Cow<'static, str>)) -> Error {
+        Error::invalid_params(value.0, value.1)
+    }
+}
+
+/// The message is copied, create the error with
+/// [Error::invalid_params()](Error::invalid_params) so a constant
+/// message is borrowed instead.
+impl<'a> From<(&'a Request, &'a str)> for Error {
+    fn from(value: (&'a Request, &'a str)) -> Error {
+        Error::invalid_params(value.0, value.1.to_string())
+    }
+}
+
+impl<'a> From<(&'a Request, String)> for Error {
+    fn from(value: (&'a Request, String)) -> Error {
+        Error::invalid_params(value.0, value.1)
+    }
+}
+
+impl<'a> From<(&'a Request, Cow<'static, str>)> for Error {
+    fn from(value: (&'a Request, Cow<'static, str>)) -> Error {
+        Error::invalid_params(value.0, value.1)
+    }
+}
+
+/// The data of an invalid params error.
+///
+/// Holds a message or a function that formats the message the first
+/// time it is read, see
+/// [Error::invalid_params_with()](Error::invalid_params_with). Reads
+/// as a `str` and compares equal to strings.
+pub struct ErrorDetail(Detail);
+
+enum Detail {
+    Text(Cow<'static, str>),
+    Lazy(
+        Box<dyn Fn() -> String + Send + Sync>,
+        std::sync::OnceLock<String>,
+    ),
+}
+
+impl ErrorDetail {
+    /// Create a detail that calls `format` when it is first read.
+    pub fn lazy<F>(format: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        Self(Detail::Lazy(Box::new(format), Default::default()))
+    }
+
+    /// Determine if the message has been formatted, always true for a
+    /// detail created from a string.
+    pub fn is_formatted(&self) -> bool {
+        match &self.0 {
+            Detail::Text(_) => true,
+            Detail::Lazy(_, text) => text.get().is_some(),
+        }
+    }
+
+    /// The message, formatting it if needed.
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Detail::Text(text) => text,
+            Detail::Lazy(format, text) => text.get_or_init(format),
+        }
+    }
+}
+
+impl std::ops::Deref for ErrorDetail {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::fmt::Debug for ErrorDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl std::fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<Cow<'static, str>> for ErrorDetail {
+    fn from(text: Cow<'static, str>) -> Self {
+        Self(Detail::Text(text))
+    }
+}
+
+impl From<&'static str> for ErrorDetail {
+    fn from(text: &'static str) -> Self {
+        Cow::Borrowed(text).into()
+    }
+}
+
+impl From<String> for ErrorDetail {
+    fn from(text: String) -> Self {
+        Cow::<'static, str>::Owned(text).into()
+    }
+}
+
+impl From<ErrorDetail> for String {
+    fn from(detail: ErrorDetail) -> String {
+        match detail.0 {
+            Detail::Text(text) => text.into_owned(),
+            Detail::Lazy(format, text) => {
+                text.into_inner().unwrap_or_else(format)
             }
-            Error::Parse { data } => (PARSE_ERROR, Some(data.to_string())),
-            Error::InvalidRequest { data } => {
-                (INVALID_REQUEST, Some(data.to_string()))
+        }
+    }
+}
+
+impl PartialEq for ErrorDetail {
+    fn eq(&self, other: &ErrorDetail) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<str> for ErrorDetail {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for ErrorDetail {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for ErrorDetail {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<ErrorDetail> for str {
+    fn eq(&self, other: &ErrorDetail) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PartialEq<ErrorDetail> for &str {
+    fn eq(&self, other: &ErrorDetail) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl PartialEq<ErrorDetail> for String {
+    fn eq(&self, other: &ErrorDetail) -> bool {
+        self == other.as_str()
+    }
+}
+
+/// Error information for response messages.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct RpcError {
+    /// The error code.
+    pub code: isize,
+    /// The error message.
+    pub message: Cow<'static, str>,
+    /// Additional data for the error, typically an underlying
+    /// cause for the error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    /// Create a new JSON-RPC internal error.
+    pub fn new(message: String, data: Option<Value>) -> Self {
+        Self {
+            code: INTERNAL_ERROR,
+            message: message.into(),
+            data,
+        }
+    }
+
+    /// Create an error caused by another error.
+    ///
+    /// The cause is nested in the data as `{"cause": <error>}` so the
+    /// chain survives being sent to another service, read it back with
+    /// [cause()](RpcError::cause).
+    pub fn with_cause(code: isize, message: String, cause: RpcError) -> Self {
+        let mut data = serde_json::Map::new();
+        data.insert(
+            CAUSE.to_string(),
+            serde_json::to_value(cause).expect("error is always serializable"),
+        );
+        Self {
+            code,
+            message: message.into(),
+            data: Some(Value::Object(data)),
+        }
+    }
+
+    /// The error that caused this error, if the data has a `cause`.
+    pub fn cause(&self) -> Option<RpcError> {
+        let cause = self.data.as_ref()?.get(CAUSE)?;
+        RpcError::deserialize(cause).ok()
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)?;
+        let mut cause = self.cause();
+        while let Some(error) = cause {
+            write!(f, ": caused by {} (code {})", error.message, error.code)?;
+            cause = error.cause();
+        }
+        Ok(())
+    }
+}
+
+/// Trait for error types that choose their response representation.
+///
+/// Convert with [Error::from_rpc()](Error::from_rpc) to return the
+/// error from a handler.
+pub trait ToRpcError {
+    /// The error sent in the response.
+    fn to_rpc_error(&self) -> RpcError;
+}
+
+impl ToRpcError for RpcError {
+    fn to_rpc_error(&self) -> RpcError {
+        self.clone()
+    }
+}
+
+/// Trait for services that maybe handle a request.
+pub trait Service: Send + Sync {
+    /// Type of the user data for this service.
+    type Data;
+
+    /// Service implementations are invoked with a request
+    /// and should reply with a response if the method name
+    /// is one handled by the service.
+    ///
+    /// If the method name for the request is not handled by the service
+    /// it should return `None`.
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>>;
+
+    /// Reply to a request, possibly with a serialized result.
+    ///
+    /// Called instead of [handle()](Service::handle) by
+    /// [Server::serve_str()](Server::serve_str) and
+    /// [Server::serve_slice()](Server::serve_slice); the default wraps
+    /// the response of [handle()](Service::handle).
+    fn handle_raw(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<raw::Reply>> {
+        Ok(self.handle(request, ctx)?.map(raw::Reply::Value))
+    }
+
+    /// The names of the methods handled by this service.
+    ///
+    /// Used to list the methods a server provides; services that
+    /// cannot enumerate their methods return an empty list.
+    ///
+    /// A server only asks a service that lists its methods for those
+    /// methods, see [route_by_methods()](Service::route_by_methods).
+    fn methods(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Descriptions of the methods handled by this service.
+    ///
+    /// The default describes each of [methods()](Service::methods) by
+    /// its name only, see the [describe](describe) module.
+    fn method_meta(&self) -> Vec<describe::MethodMeta> {
+        self.methods()
+            .into_iter()
+            .map(describe::MethodMeta::new)
+            .collect()
+    }
+
+    /// Whether the server may skip this service for methods missing
+    /// from [methods()](Service::methods).
+    ///
+    /// Return `false` when the list may change after the service is
+    /// added to a server, as for a [Registry](registry::Registry), or
+    /// when the service answers methods it does not list.
+    fn route_by_methods(&self) -> bool {
+        true
+    }
+}
+
+/// Service held by a server, either borrowed or shared.
+pub(crate) enum ServiceRef<'a, S: ?Sized> {
+    Borrowed(&'a Box<S>),
+    Shared(std::sync::Arc<S>),
+}
+
+impl<'a, S: ?Sized> std::ops::Deref for ServiceRef<'a, S> {
+    type Target = S;
+    fn deref(&self) -> &S {
+        match self {
+            ServiceRef::Borrowed(service) => service,
+            ServiceRef::Shared(service) => service,
+        }
+    }
+}
+
+impl<'a, T> Service for ServiceRef<'a, dyn Service<Data = T>> {
+    type Data = T;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        (**self).handle(request, ctx)
+    }
+
+    fn handle_raw(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<raw::Reply>> {
+        (**self).handle_raw(request, ctx)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        (**self).methods()
+    }
+
+    fn method_meta(&self) -> Vec<describe::MethodMeta> {
+        (**self).method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        (**self).route_by_methods()
+    }
+}
+
+impl<'a, T> routes::Routable for ServiceRef<'a, dyn Service<Data = T>> {
+    fn route(&self) -> Option<Vec<String>> {
+        routes::route(self.methods(), self.route_by_methods())
+    }
+
+    fn method_meta(&self) -> Vec<describe::MethodMeta> {
+        Service::method_meta(self)
+    }
+}
+
+/// Check applied to every request before it is passed to the services.
+pub type Validator<T> =
+    dyn Fn(&Request, &T) -> std::result::Result<(), RpcError> + Send + Sync;
+
+/// Validator that rejects requests with unknown top-level fields, use
+/// with [Server::with_validator()](Server::with_validator).
+///
+/// Only available with the `extra-fields` feature.
+#[cfg(feature = "extra-fields")]
+pub fn reject_extra_fields<T>(
+    request: &Request,
+    _ctx: &T,
+) -> std::result::Result<(), RpcError> {
+    match request.extra.keys().next() {
+        Some(name) => Err(RpcError {
+            code: INVALID_REQUEST,
+            message: "Invalid request".into(),
+            data: Some(Value::String(format!("unknown field {}", name))),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Error from `anyhow` displayed with its context chain.
+#[cfg(feature = "anyhow")]
+#[derive(Debug)]
+struct AnyhowError(anyhow::Error);
+
+#[cfg(feature = "anyhow")]
+impl std::fmt::Display for AnyhowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl std::error::Error for AnyhowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        Error::Boxed(Box::new(AnyhowError(error)))
+    }
+}
+
+/// Translate an error into the error for the response.
+///
+/// Returning `None` uses the default conversion of the error.
+pub type ErrorMapper =
+    dyn Fn(&Error, &Request) -> Option<RpcError> + Send + Sync;
+
+/// Observe a request after it was served with the response, `None`
+/// for a notification, and the time taken.
+pub type ServedHook =
+    dyn Fn(&Request, Option<&Response>, std::time::Duration) + Send + Sync;
+
+/// Serve requests.
+///
+/// Requests are passed to each service in turn and the first service
+/// that returns a response wins.
+///
+/// The server is `Send + Sync` whatever the type of the user data.
+pub struct Server<'a, T> {
+    /// Services that the server should invoke for every request.
+    services: routes::Services<ServiceRef<'a, dyn Service<Data = T>>>,
+    /// Whether to answer `rpc.methods` with the method names.
+    list_methods: bool,
+    /// Checks run on every request before the services.
+    validators: Vec<Box<Validator<T>>>,
+    /// Translates errors returned by services into error responses.
+    error_mapper: Option<Box<ErrorMapper>>,
+    /// How calls in a batch that reuse an id are treated.
+    duplicate_ids: batch::DuplicateIds,
+    /// Whether a batch is served after an error.
+    batch_policy: batch::BatchPolicy,
+    /// Largest number of requests served in a batch.
+    max_batch: Option<usize>,
+    /// Largest serialized response in bytes.
+    max_response: Option<usize>,
+    /// Categories and log levels of error codes.
+    policy: policy::ErrorPolicy,
+    /// Called after every request is served.
+    on_served: Option<Box<ServedHook>>,
+    /// Checks applied to every request before the validators.
+    conformance: Option<conformance::Conformance>,
+    /// Whether internal errors include their sources and backtrace.
+    debug_errors: bool,
+    /// Limit for the data of errors other than the default.
+    max_error_data: Option<usize>,
+    /// Whether responses are stamped with the processing time.
+    #[cfg(feature = "extra-fields")]
+    timing: bool,
+    /// Whether responses to deprecated methods carry a warning.
+    #[cfg(feature = "extra-fields")]
+    deprecation_warnings: bool,
+    /// Reports requests the services take too long to handle.
+    slow: Option<SlowLog>,
+    /// Turns away new requests during shutdown.
+    drain: Option<shutdown::DrainGuard>,
+    /// Counters for the requests served.
+    stats: Option<stats::Recorder>,
+}
+
+impl<'a, T> Server<'a, T> {
+    /// Create a new server.
+    pub fn new(services: Vec<&'a Box<dyn Service<Data = T>>>) -> Self {
+        Self {
+            services: services.into_iter().map(ServiceRef::Borrowed).collect(),
+            list_methods: false,
+            validators: Vec::new(),
+            error_mapper: None,
+            duplicate_ids: Default::default(),
+            batch_policy: Default::default(),
+            max_batch: None,
+            max_response: None,
+            policy: Default::default(),
+            on_served: None,
+            conformance: None,
+            debug_errors: false,
+            max_error_data: None,
+            #[cfg(feature = "extra-fields")]
+            timing: false,
+            #[cfg(feature = "extra-fields")]
+            deprecation_warnings: false,
+            slow: None,
+            drain: None,
+            stats: None,
+        }
+    }
+}
+
+impl<T> Server<'static, T> {
+    /// Create a server that owns its services.
+    ///
+    /// The server does not borrow so it can be wrapped in an `Arc`
+    /// and shared between threads.
+    pub fn new_shared(
+        services: Vec<std::sync::Arc<dyn Service<Data = T>>>,
+    ) -> Self {
+        Self {
+            services: services.into_iter().map(ServiceRef::Shared).collect(),
+            list_methods: false,
+            validators: Vec::new(),
+            error_mapper: None,
+            duplicate_ids: Default::default(),
+            batch_policy: Default::default(),
+            max_batch: None,
+            max_response: None,
+            policy: Default::default(),
+            on_served: None,
+            conformance: None,
+            debug_errors: false,
+            max_error_data: None,
+            #[cfg(feature = "extra-fields")]
+            timing: false,
+            #[cfg(feature = "extra-fields")]
+            deprecation_warnings: false,
+            slow: None,
+            drain: None,
+            stats: None,
+        }
+    }
+
+    /// Create a server calling services with a higher priority first.
+    ///
+    /// Services with the same priority are called in the order given.
+    pub fn new_prioritized(
+        mut services: Vec<(i32, std::sync::Arc<dyn Service<Data = T>>)>,
+    ) -> Self {
+        services.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+        let server = Self::new_shared(
+            services.into_iter().map(|(_, service)| service).collect(),
+        );
+        log_unreachable(server.order());
+        server
+    }
+}
+
+impl<T: 'static> Server<'static, T> {
+    /// Build a server that owns its services and check the
+    /// configuration, see the [builder](builder) module.
+    pub fn builder() -> builder::ServerBuilder<T> {
+        builder::ServerBuilder::new()
+    }
+
+    /// Serve the services of another server under a prefix.
+    ///
+    /// Each service is wrapped in a [Namespace](namespace::Namespace)
+    /// so `prefix.method` calls `method` on the mounted services.
+    pub fn mount(mut self, prefix: &str, other: Server<'static, T>) -> Self {
+        self.services
+            .extend(other.services.into_iter().map(|service| {
+                let service: std::sync::Arc<dyn Service<Data = T>> =
+                    std::sync::Arc::new(namespace::Namespace::new(
+                        prefix, service,
+                    ));
+                ServiceRef::Shared(service)
+            }));
+        self
+    }
+}
+
+impl<'a, T> Server<'a, T> {
+    /// Add a service that is called before the existing services.
+    pub fn push_front(
+        &mut self,
+        service: std::sync::Arc<dyn Service<Data = T>>,
+    ) {
+        self.services.insert(0, ServiceRef::Shared(service));
+        log_unreachable(self.order());
+    }
+
+    /// Add a service that is called after the existing services.
+    pub fn push_back(
+        &mut self,
+        service: std::sync::Arc<dyn Service<Data = T>>,
+    ) {
+        self.services.push(ServiceRef::Shared(service));
+        log_unreachable(self.order());
+    }
+
+    /// The methods of each service in the order the services are
+    /// called.
+    pub fn order(&self) -> Vec<Vec<String>> {
+        self.services
+            .iter()
+            .map(|service| service.methods())
+            .collect()
+    }
+
+    /// Combine the services of another server with this server.
+    ///
+    /// The services of `other` are called after the services of this
+    /// server so this server wins when both handle a method. Only the
+    /// validators of this server are kept.
+    pub fn merge(mut self, other: Server<'a, T>) -> Self {
+        self.services.extend(other.services);
+        self
+    }
+
+    /// Answer `rpc.methods` with a sorted array of the method names
+    /// provided by the services.
+    ///
+    /// Called with the parameters `{"describe": true}` it answers with
+    /// the [descriptions](describe::MethodMeta) of the methods instead,
+    /// including their summaries and deprecation notices, see
+    /// [method_meta()](Server::method_meta).
+    ///
+    /// The listing is built for every call so it reflects services
+    /// added to a [Registry](registry::Registry) at runtime; `rpc.*`
+    /// methods are not listed.
+    pub fn with_methods(mut self) -> Self {
+        self.list_methods = true;
+        self
+    }
+
+    /// Check every request against a conformance level before the
+    /// validators run.
+    ///
+    /// Requests that fail are answered with an invalid request error,
+    /// see the [conformance](conformance) module. As the specification
+    /// requires, other errors for notifications are no longer answered.
+    pub fn with_conformance(
+        mut self,
+        conformance: conformance::Conformance,
+    ) -> Self {
+        self.conformance = Some(conformance);
+        self
+    }
+
+    /// Add a validator that runs before the services.
+    ///
+    /// Validators run in the order they were added and the first error
+    /// is the response; a notification that fails validation is
+    /// dropped without a response.
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&Request, &T) -> std::result::Result<(), RpcError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    /// Check the scopes each method requires before the services.
+    ///
+    /// The policy runs as a validator in the order validators are
+    /// added, see the [authz](authz) module.
+    pub fn with_authz(self, policy: authz::AuthzPolicy) -> Self
+    where
+        T: authz::HasScopes,
+    {
+        self.with_validator(move |request, ctx| policy.check(request, ctx))
+    }
+
+    /// Set a function that translates errors returned by services.
+    ///
+    /// Use [Error::downcast_ref()](Error::downcast_ref) to choose an
+    /// error code for domain errors that would otherwise be reported
+    /// as internal errors.
+    pub fn with_error_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Error, &Request) -> Option<RpcError> + Send + Sync + 'static,
+    {
+        self.error_mapper = Some(Box::new(mapper));
+        self
+    }
+
+    /// Include the source chain and backtrace of an `Error::Boxed` in
+    /// the data of the internal error response.
+    ///
+    /// Backtraces are captured for boxed errors created while the
+    /// services of this server handle a request, see the [debug](debug)
+    /// module. Pass `false` to answer internal errors without data as
+    /// usual.
+    pub fn with_debug_errors(mut self, enabled: bool) -> Self {
+        self.debug_errors = enabled;
+        self
+    }
+
+    /// Set the limit in bytes for the data of the errors this server
+    /// answers, see the [truncate](truncate) module.
+    pub fn with_max_error_data(mut self, max: usize) -> Self {
+        self.max_error_data = Some(max);
+        self
+    }
+
+    /// Stamp responses with the time taken to serve the request.
+    ///
+    /// See the [timing](timing) module for the convention used. Only
+    /// available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn with_timing(mut self) -> Self {
+        self.timing = true;
+        self
+    }
+
+    /// Attach a warning to responses to deprecated methods.
+    ///
+    /// Calls to deprecated methods are always logged, see the
+    /// [describe](describe) module. Only available with the
+    /// `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn with_deprecation_warnings(mut self) -> Self {
+        self.deprecation_warnings = true;
+        self
+    }
+
+    /// Set how calls in a batch that reuse an id are treated by
+    /// [serve_batch()](Server::serve_batch).
+    pub fn with_duplicate_ids(mut self, policy: batch::DuplicateIds) -> Self {
+        self.duplicate_ids = policy;
+        self
+    }
+
+    /// Set whether [serve_batch()](Server::serve_batch) serves the
+    /// remaining elements of a batch after an error.
+    pub fn with_batch_policy(mut self, policy: batch::BatchPolicy) -> Self {
+        self.batch_policy = policy;
+        self
+    }
+
+    /// Reject batches with more than `max` requests.
+    ///
+    /// [serve_batch()](Server::serve_batch) answers a larger batch with
+    /// a single invalid request error without serving any of it.
+    pub fn with_max_batch(mut self, max: usize) -> Self {
+        self.max_batch = Some(max);
+        self
+    }
+
+    /// Replace responses larger than `max` bytes when serialized with
+    /// an internal error.
+    ///
+    /// Applies where the server serializes responses, see the
+    /// [truncate](truncate#responses) module.
+    pub fn with_max_response_size(mut self, max: usize) -> Self {
+        self.max_response = Some(max);
+        self
+    }
+
+    /// The limit in bytes for serialized responses.
+    pub fn max_response_size(&self) -> Option<usize> {
+        self.max_response
+    }
+
+    /// Set the categories and log levels of error codes.
+    ///
+    /// The server uses the policy to count errors for
+    /// [stats()](Server::stats); it is kept so transports and hooks
+    /// serving from the server make the same decisions.
+    pub fn with_policy(mut self, policy: policy::ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The categories and log levels of error codes.
+    pub fn policy(&self) -> &policy::ErrorPolicy {
+        &self.policy
+    }
+
+    /// Turn away new requests once the shutdown handle of the guard is
+    /// triggered while the requests in flight complete.
+    ///
+    /// See the [shutdown](shutdown#draining) module.
+    pub fn with_drain(mut self, guard: shutdown::DrainGuard) -> Self {
+        self.drain = Some(guard);
+        self
+    }
+
+    /// Determine if the server is turning away new requests.
+    pub fn is_draining(&self) -> bool {
+        self.drain.as_ref().is_some_and(|drain| drain.is_draining())
+    }
+
+    /// Number of requests being handled, always zero without a
+    /// [DrainGuard](shutdown::DrainGuard).
+    pub fn in_flight(&self) -> usize {
+        self.drain.as_ref().map_or(0, |drain| drain.in_flight())
+    }
+
+    /// Call a function for requests the services take longer than the
+    /// threshold to handle, whether they succeed or fail.
+    ///
+    /// Pass [slow::warn](slow::warn) to log a warning; see the
+    /// [slow](slow) module.
+    pub fn with_slow_request_log<F>(
+        mut self,
+        threshold: std::time::Duration,
+        f: F,
+    ) -> Self
+    where
+        F: Fn(&slow::SlowRequest<'_>) + Send + Sync + 'static,
+    {
+        self.slow = Some(SlowLog::new(threshold, Box::new(f)));
+        self
+    }
+
+    /// Count the requests served, see the [stats](stats) module.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(Default::default());
+        self
+    }
+
+    /// A snapshot of the counters, `None` unless counting is enabled
+    /// with [with_stats()](Server::with_stats).
+    pub fn stats(&self) -> Option<stats::Stats> {
+        self.stats.as_ref().map(stats::Recorder::snapshot)
+    }
+
+    /// Set a function called after every request is served.
+    ///
+    /// The function receives the request, the response or `None` for
+    /// a notification and the time taken to serve it. It is called
+    /// for error responses and for requests rejected by validators.
+    pub fn on_served<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Request, Option<&Response>, std::time::Duration)
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.on_served = Some(Box::new(f));
+        self
+    }
+
+    /// The sorted names of the methods provided by the services.
+    ///
+    /// Services that do not report their methods are not included.
+    pub fn methods(&self) -> Vec<String> {
+        method_list(self.services.iter().map(|service| service.methods()))
+    }
+
+    /// The descriptions of the methods provided by the services sorted
+    /// by name, see the [describe](describe) module.
+    ///
+    /// A method described by several services is described by the
+    /// first.
+    pub fn method_meta(&self) -> Vec<describe::MethodMeta> {
+        describe::list(
+            self.services.iter().map(|service| service.method_meta()),
+        )
+    }
+
+    /// Call services in order and return the first response message.
+    ///
+    /// If no services match the incoming request this will
+    /// return `Error::MethodNotFound`.
+    pub(crate) fn handle(
+        &self,
+        request: &Request,
+        ctx: &T,
+    ) -> Result<Response> {
+        let in_flight = self.drain.as_ref().map(shutdown::DrainGuard::enter);
+        if matches!(in_flight, Some(None)) {
+            return Ok(shutdown::drained(request));
+        }
+        if let Some(response) = self.check(request, ctx) {
+            return Ok(response);
+        }
+        let started = Instant::now();
+        let result =
+            debug::capturing(self.debug_errors, || self.dispatch(request, ctx));
+        if let Some(slow) = &self.slow {
+            slow.check(request, started, slow::failed(&result));
+        }
+        result
+    }
+
+    fn dispatch(&self, request: &Request, ctx: &T) -> Result<Response> {
+        if let Some(note) = self.services.deprecation(request.method()) {
+            describe::warn(request, note);
+        }
+        for service in self.services.candidates(request.method()) {
+            if let Some(result) = service.handle(request, ctx)? {
+                return Ok(result);
+            }
+        }
+        Ok(not_found(request))
+    }
+
+    /// Like [handle()](Server::handle) but services may reply with a
+    /// serialized result.
+    fn handle_raw(&self, request: &Request, ctx: &T) -> Result<raw::Reply> {
+        let in_flight = self.drain.as_ref().map(shutdown::DrainGuard::enter);
+        if matches!(in_flight, Some(None)) {
+            return Ok(shutdown::drained(request).into());
+        }
+        if let Some(response) = self.check(request, ctx) {
+            return Ok(response.into());
+        }
+        let started = Instant::now();
+        let result = debug::capturing(self.debug_errors, || {
+            self.dispatch_raw(request, ctx)
+        });
+        if let Some(slow) = &self.slow {
+            let failed = result.as_ref().map_or(true, raw::Reply::is_error);
+            slow.check(request, started, failed);
+        }
+        result
+    }
+
+    fn dispatch_raw(&self, request: &Request, ctx: &T) -> Result<raw::Reply> {
+        if let Some(note) = self.services.deprecation(request.method()) {
+            describe::warn(request, note);
+        }
+        for service in self.services.candidates(request.method()) {
+            if let Some(reply) = service.handle_raw(request, ctx)? {
+                return Ok(reply);
+            }
+        }
+        Ok(not_found(request).into())
+    }
+
+    /// Apply the conformance checks and validators and answer the
+    /// built-in method listing.
+    fn check(&self, request: &Request, ctx: &T) -> Option<Response> {
+        if let Some(Err(e)) =
+            self.conformance.as_ref().map(|c| c.check(request))
+        {
+            return Some(e.into());
+        }
+        if let Some(response) = check_request(&self.validators, request, ctx) {
+            return Some(response);
+        }
+        if self.list_methods && request.method() == METHODS {
+            let listing = method_listing(
+                request,
+                || self.methods(),
+                || self.method_meta(),
+            );
+            return Some((request, listing).into());
+        }
+        None
+    }
+
+    /// Infallible service handler, errors are automatically converted to responses.
+    pub fn serve(&self, request: &Request, ctx: &T) -> Option<Response> {
+        let started = Instant::now();
+        let response = self.respond(request, ctx);
+        #[cfg(feature = "extra-fields")]
+        let response =
+            response.map(|response| self.stamp(request, response, started));
+        self.observe(request, response.as_ref(), started);
+        response
+    }
+
+    /// Attach the processing time when timing is enabled.
+    #[cfg(feature = "extra-fields")]
+    fn stamp(
+        &self,
+        request: &Request,
+        mut response: Response,
+        started: Instant,
+    ) -> Response {
+        if self.timing {
+            timing::stamp(&mut response, request.received().unwrap_or(started));
+        }
+        if let Some(note) = self.deprecation_warning(request) {
+            describe::attach(response.extra_fields_mut(), note);
+        }
+        response
+    }
+
+    /// The deprecation notice to attach to the response to `request`.
+    #[cfg(feature = "extra-fields")]
+    fn deprecation_warning(&self, request: &Request) -> Option<String> {
+        if !self.deprecation_warnings {
+            return None;
+        }
+        let note = self.services.deprecation(request.method())?;
+        Some(describe::notice(request, note))
+    }
+
+    fn observe(
+        &self,
+        request: &Request,
+        response: Option<&Response>,
+        started: Instant,
+    ) {
+        let elapsed = started.elapsed();
+        if let Some(stats) = &self.stats {
+            let listing = self.services.listing(request.method());
+            stats.record(request, response, elapsed, &self.policy, listing);
+        }
+        if let Some(on_served) = &self.on_served {
+            on_served(request, response, elapsed);
+        }
+    }
+
+    fn respond(&self, request: &Request, ctx: &T) -> Option<Response> {
+        let response = match self.handle(request, ctx) {
+            Ok(response) => response,
+            Err(e) => error_response(
+                &self.error_mapper,
+                &self.policy,
+                self.debug_errors,
+                self.max_error_data,
+                request,
+                e,
+            ),
+        };
+        // Errors for notifications are answered unless conforming, an
+        // invalid request always has an id even if it is null
+        let answer = response.id().is_some()
+            || (self.conformance.is_none() && response.error().is_some());
+        answer.then_some(response)
+    }
+
+    /// Parse a payload and serve the request, returning the serialized
+    /// response.
+    ///
+    /// Services reply through [Service::handle_raw()](Service::handle_raw)
+    /// so results they serialize directly never become a `Value`, see
+    /// [raw]. Payloads that do not parse are answered with an error;
+    /// batches are not supported.
+    pub fn serve_str(&self, payload: &str, ctx: &T) -> Option<String> {
+        self.serve_raw(from_str(payload), ctx).and_then(|reply| {
+            truncate::to_string(&reply, reply.id(), self.max_response).ok()
+        })
+    }
+
+    /// Parse a payload and serve the request, returning the serialized
+    /// response.
+    ///
+    /// See [serve_str()](Server::serve_str).
+    pub fn serve_slice(&self, payload: &[u8], ctx: &T) -> Option<Vec<u8>> {
+        self.serve_raw(from_slice(payload), ctx).and_then(|reply| {
+            truncate::to_vec(&reply, reply.id(), self.max_response).ok()
+        })
+    }
+
+    fn serve_raw(
+        &self,
+        request: Result<Request>,
+        ctx: &T,
+    ) -> Option<raw::Reply> {
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                let data = self
+                    .max_error_data
+                    .and_then(|max| truncate::error_data(&e, max));
+                let mut response = Response::from(e);
+                if let (Some(error), Some(data)) = (&mut response.error, data) {
+                    error.data = Some(data);
+                }
+                return Some(response.into());
+            }
+        };
+        let started = Instant::now();
+        let reply = match self.handle_raw(&request, ctx) {
+            Ok(reply) => reply,
+            Err(e) => error_response(
+                &self.error_mapper,
+                &self.policy,
+                self.debug_errors,
+                self.max_error_data,
+                &request,
+                e,
+            )
+            .into(),
+        };
+        let answer = reply.id().is_some()
+            || (self.conformance.is_none() && reply.is_error());
+        #[cfg(feature = "extra-fields")]
+        let reply = {
+            let mut reply = reply;
+            if self.timing {
+                timing::stamp_fields(
+                    reply.extra_fields_mut(),
+                    request.received().unwrap_or(started),
+                );
+            }
+            if let Some(note) = self.deprecation_warning(&request) {
+                describe::attach(reply.extra_fields_mut(), note);
+            }
+            reply
+        };
+        let reply = answer.then_some(reply);
+        self.observe(
+            &request,
+            reply.as_ref().and_then(raw::Reply::response),
+            started,
+        );
+        reply
+    }
+
+    /// Serve each request in turn returning a response per request,
+    /// `None` for notifications.
+    ///
+    /// Unlike [serve_batch()](Server::serve_batch) no batch policies
+    /// apply; every request is served independently.
+    pub fn serve_many<I, R>(
+        &self,
+        requests: I,
+        ctx: &T,
+    ) -> Vec<Option<Response>>
+    where
+        I: IntoIterator<Item = R>,
+        R: std::borrow::Borrow<Request>,
+    {
+        requests
+            .into_iter()
+            .map(|request| self.serve(request.borrow(), ctx))
+            .collect()
+    }
+
+    /// Serve a batch of requests in order, notifications yield no
+    /// response.
+    pub fn serve_batch(&self, requests: &[Request], ctx: &T) -> Vec<Response> {
+        if let Some(response) = batch::too_large(requests, self.max_batch) {
+            return vec![response];
+        }
+        let abort = self.batch_policy == batch::BatchPolicy::AbortOnError;
+        let rejected = self.duplicate_ids.rejected(requests);
+        let mut failed = false;
+        let mut responses = Vec::new();
+        for (index, request) in requests.iter().enumerate() {
+            let response = if abort && failed {
+                batch::skipped_response(request)
+            } else if rejected.contains(&index) {
+                Some(batch::duplicate_response(request))
+            } else {
+                self.serve(request, ctx)
+            };
+            if let Some(response) = response {
+                failed |= response.error().is_some();
+                responses.push(response);
             }
-            _ => (INTERNAL_ERROR, None),
         }
+        responses
     }
-}
 
-impl<'a> From<(&'a mut Request, &'a str)> for Error {
-    fn from(value: (&'a mut Request, &'a str)) -> Error {
-        Error::from((value.0, value.1.to_string()))
+    /// Serve a call, errors are converted to the response.
+    pub fn serve_call(&self, call: &message::Call, ctx: &T) -> Response {
+        let request = call.as_request();
+        let started = Instant::now();
+        let response = match self.handle(request, ctx) {
+            Ok(response) => response,
+            Err(e) => error_response(
+                &self.error_mapper,
+                &self.policy,
+                self.debug_errors,
+                self.max_error_data,
+                request,
+                e,
+            ),
+        };
+        #[cfg(feature = "extra-fields")]
+        let response = self.stamp(request, response, started);
+        self.observe(request, Some(&response), started);
+        response
     }
-}
 
-impl<'a> From<(&'a mut Request, String)> for Error {
-    fn from(value: (&'a mut Request, String)) -> Error {
-        Error::InvalidParams {
-            id: value.0.id().clone(),
-            data: value.1,
-        }
+    /// Serve a notification, any errors are discarded as notifications
+    /// are never answered.
+    pub fn serve_notification(
+        &self,
+        notification: &message::Notification,
+        ctx: &T,
+    ) {
+        let request = notification.as_request();
+        let started = Instant::now();
+        let _ = self.handle(request, ctx);
+        self.observe(request, None, started);
     }
-}
 
-/// Error information for response messages.
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
-pub struct RpcError {
-    /// The error code.
-    pub code: isize,
-    /// The error message.
-    pub message: String,
-    /// Additional data for the error, typically an underlying
-    /// cause for the error.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub data: Option<String>,
-}
-
-impl RpcError {
-    /// Create a new JSON-RPC internal error.
-    pub fn new(message: String, data: Option<String>) -> Self {
-        Self {
-            code: INTERNAL_ERROR,
-            message,
-            data,
+    /// Serve a message, only calls yield a response.
+    pub fn serve_message(
+        &self,
+        message: &message::Message,
+        ctx: &T,
+    ) -> Option<Response> {
+        match message {
+            message::Message::Call(call) => Some(self.serve_call(call, ctx)),
+            message::Message::Notification(notification) => {
+                self.serve_notification(notification, ctx);
+                None
+            }
         }
     }
 }
 
-/// Trait for services that maybe handle a request.
-pub trait Service: Send + Sync {
-    /// Type of the user data for this service.
-    type Data;
-
-    /// Service implementations are invoked with a request
-    /// and should reply with a response if the method name
-    /// is one handled by the service.
-    ///
-    /// If the method name for the request is not handled by the service
-    /// it should return `None`.
-    fn handle(
-        &self,
-        request: &Request,
-        ctx: &Self::Data,
-    ) -> Result<Option<Response>>;
+/// Convert an error from a service to a response using the mapper
+/// when it translates the error.
+///
+/// With `debug` an `Error::Boxed` that is not translated carries its
+/// sources and backtrace in the data; with `max_data` the message of
+/// the parser is truncated to that limit instead of the default.
+pub(crate) fn error_response(
+    mapper: &Option<Box<ErrorMapper>>,
+    policy: &policy::ErrorPolicy,
+    debug: bool,
+    max_data: Option<usize>,
+    request: &Request,
+    error: Error,
+) -> Response {
+    if let Some(error) =
+        mapper.as_ref().and_then(|mapper| mapper(&error, request))
+    {
+        return (request, error).into();
+    }
+    let data = match debug {
+        true => debug::data(&error),
+        false => None,
+    }
+    .or_else(|| max_data.and_then(|max| truncate::error_data(&error, max)));
+    let mut error = policy.rpc_error(error);
+    if data.is_some() {
+        error.data = data;
+    }
+    (request, error).into()
 }
 
-/// Serve requests.
-///
-/// Requests are passed to each service in turn and the first service
-/// that returns a response wins.
-pub struct Server<'a, T> {
-    /// Services that the server should invoke for every request.
-    services: Vec<&'a Box<dyn Service<Data = T>>>,
+/// Response for a method that no service handles.
+fn not_found(request: &Request) -> Response {
+    let err = Error::MethodNotFound {
+        name: request.method().to_string(),
+        id: request.id.clone(),
+    };
+    (request, err).into()
 }
 
-impl<'a, T> Server<'a, T> {
-    /// Create a new server.
-    pub fn new(services: Vec<&'a Box<dyn Service<Data = T>>>) -> Self {
-        Self { services }
+/// Run validators in order and convert the first error to a response.
+pub(crate) fn check_request<T>(
+    validators: &[Box<Validator<T>>],
+    request: &Request,
+    ctx: &T,
+) -> Option<Response> {
+    for validator in validators {
+        if let Err(error) = validator(request, ctx) {
+            return Some(if request.id().is_some() {
+                (request, error).into()
+            } else {
+                request.into()
+            });
+        }
     }
+    None
+}
 
-    /// Call services in order and return the first response message.
-    ///
-    /// If no services match the incoming request this will
-    /// return `Error::MethodNotFound`.
-    pub(crate) fn handle(
-        &self,
-        request: &Request,
-        ctx: &T,
-    ) -> Result<Response> {
-        for service in self.services.iter() {
-            if let Some(result) = service.handle(request, ctx)? {
-                return Ok(result);
+/// Log methods that can never be reached because a service called
+/// earlier also reports the method.
+pub(crate) fn log_unreachable(order: Vec<Vec<String>>) {
+    let mut seen = std::collections::HashSet::new();
+    for (index, methods) in order.into_iter().enumerate() {
+        for method in methods {
+            if seen.contains(&method) {
+                log::debug!(
+                    "Method {} of service {} is handled by an earlier service",
+                    method,
+                    index
+                );
+            } else {
+                seen.insert(method);
             }
         }
-
-        let err = Error::MethodNotFound {
-            name: request.method().to_string(),
-            id: request.id.clone(),
-        };
-
-        Ok((request, err).into())
     }
+}
 
-    /// Infallible service handler, errors are automatically converted to responses.
-    pub fn serve(&self, request: &Request, ctx: &T) -> Option<Response> {
-        match self.handle(request, ctx) {
-            Ok(response) => {
-                if response.error().is_some() || response.id().is_some() {
-                    Some(response)
-                } else {
-                    None
-                }
-            }
-            Err(e) => Some((request, e).into()),
-        }
+/// Merge, sort and deduplicate method names omitting `rpc.*` methods.
+pub(crate) fn method_list<I>(methods: I) -> Vec<String>
+where
+    I: Iterator<Item = Vec<String>>,
+{
+    let mut methods: Vec<String> = methods
+        .flatten()
+        .filter(|method| !method.starts_with("rpc."))
+        .collect();
+    methods.sort();
+    methods.dedup();
+    methods
+}
+
+/// The answer to `rpc.methods`: the method names, or the descriptions
+/// of the methods when the parameters are `{"describe": true}`.
+pub(crate) fn method_listing<N, M>(
+    request: &Request,
+    names: N,
+    meta: M,
+) -> Value
+where
+    N: FnOnce() -> Vec<String>,
+    M: FnOnce() -> Vec<describe::MethodMeta>,
+{
+    let params = request.params().as_ref();
+    let describe = params.and_then(|params| params.get("describe"));
+    if describe != Some(&Value::Bool(true)) {
+        return Value::Array(names().into_iter().map(Value::String).collect());
     }
+    let methods: Vec<describe::MethodMeta> = meta()
+        .into_iter()
+        .filter(|method| !method.name.starts_with("rpc."))
+        .collect();
+    serde_json::to_value(methods).unwrap_or(Value::Null)
 }
 
 /// Parse a JSON payload from a string slice into a request.
+///
+/// When the payload is valid JSON but not a valid request the id is
+/// recovered if possible so the error response can echo it.
+///
+/// With the `simd` feature the payload is copied and parsed by
+/// [simd::from_str()](simd::from_str).
 pub fn from_str(payload: &str) -> Result<Request> {
-    serde_json::from_str::<Request>(payload).map_err(map_json_error)
+    #[cfg(feature = "simd")]
+    return simd::from_str(payload);
+    #[cfg(not(feature = "simd"))]
+    serde_json::from_str::<Request>(payload)
+        .map_err(|e| map_payload_error(e, payload.as_bytes()))
+        .and_then(validate)
 }
 
 /// Parse a JSON payload from a [Value](serde_json::Value) into a request.
+///
+/// When the payload is not a valid request the id is recovered if
+/// possible so the error response can echo it.
 pub fn from_value(payload: Value) -> Result<Request> {
-    serde_json::from_value::<Request>(payload).map_err(map_json_error)
+    let id = recover_id(&payload);
+    serde_json::from_value::<Request>(payload)
+        .map_err(|e| map_json_error(e, None, || id))
+        .and_then(validate)
+}
+
+/// Parse a JSON payload from a borrowed [Value](serde_json::Value)
+/// into a request.
+///
+/// Only the fields stored in the request are cloned so a caller that
+/// needs to keep the payload does not have to clone all of it.
+pub fn from_value_ref(payload: &Value) -> Result<Request> {
+    <Request as Deserialize>::deserialize(payload)
+        .map_err(|e| map_json_error(e, None, || recover_id(payload)))
+        .and_then(validate)
 }
 
 /// Parse a JSON payload from a byte slice into a request.
+///
+/// When the payload is valid JSON but not a valid request the id is
+/// recovered if possible so the error response can echo it.
+///
+/// With the `simd` feature the payload is copied and parsed by
+/// [simd::from_slice()](simd::from_slice).
 pub fn from_slice(payload: &[u8]) -> Result<Request> {
-    serde_json::from_slice::<Request>(payload).map_err(map_json_error)
+    #[cfg(feature = "simd")]
+    return simd::from_slice(payload);
+    #[cfg(not(feature = "simd"))]
+    json_from_slice(payload)
+}
+
+/// Parse a byte slice with `serde_json`.
+pub(crate) fn json_from_slice(payload: &[u8]) -> Result<Request> {
+    serde_json::from_slice::<Request>(payload)
+        .map_err(|e| map_payload_error(e, payload))
+        .and_then(validate)
 }
 
 /// Parse a JSON payload from an IO reader into a request.
+///
+/// The payload cannot be read again so the id is only recovered when
+/// the payload is a valid request object that fails validation.
+///
+/// Failing to read yields `Error::Io`, and reaching the end of the
+/// reader before anything but whitespace yields `Error::Closed`; the
+/// end of the reader part way through a message is a parse error.
 pub fn from_reader<R: std::io::Read>(payload: R) -> Result<Request> {
-    serde_json::from_reader::<R, Request>(payload).map_err(map_json_error)
+    let mut reader = Tracked {
+        inner: payload,
+        data: false,
+    };
+    serde_json::from_reader::<_, Request>(&mut reader)
+        .map_err(|e| match e.classify() {
+            Category::Io => Error::Io(e.into()),
+            Category::Eof if !reader.data => Error::Closed,
+            _ => map_json_error(e, None, || None),
+        })
+        .and_then(validate)
+}
+
+/// Reader that records whether any data other than whitespace was
+/// read.
+struct Tracked<R> {
+    inner: R,
+    data: bool,
+}
+
+impl<R: std::io::Read> std::io::Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if !self.data {
+            self.data = !buf[..read].iter().all(u8::is_ascii_whitespace);
+        }
+        Ok(read)
+    }
+}
+
+/// Generate a random message id.
+pub(crate) fn random_id() -> Value {
+    Value::Number(Number::from(rand::thread_rng().gen_range(1..u32::MAX)))
 }
 
 /// JSON-RPC request.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone)]
 pub struct Request {
-    jsonrpc: String,
-    method: String,
+    jsonrpc: Cow<'static, str>,
+    method: Arc<str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     id: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     params: Option<Value>,
+    /// Metadata in the `_meta` field of the request.
+    #[serde(
+        rename = "_meta",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    meta: Option<Value>,
+    /// When the transport received the request.
+    #[serde(skip)]
+    received: Option<Instant>,
+    /// Values attached by the transport.
+    #[serde(skip)]
+    extensions: extensions::Extensions,
+    /// Unknown top-level fields.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
 }
 
 impl Request {
@@ -282,10 +2334,15 @@ impl Request {
         params: Option<Value>,
     ) -> Self {
         Self {
-            jsonrpc: VERSION.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
             id,
-            method,
+            method: method.into(),
             params,
+            meta: None,
+            received: None,
+            extensions: Default::default(),
         }
     }
 
@@ -293,13 +2350,28 @@ impl Request {
     ///
     /// A random number is generated for the message id.
     pub fn new_reply(method: &str, params: Option<Value>) -> Self {
+        Self::new_reply_with(&id::RandomIds, method, params)
+    }
+
+    /// Create a new request that expects a reply with an id from
+    /// `ids`.
+    ///
+    /// See the [id](id) module to make ids reproducible.
+    pub fn new_reply_with(
+        ids: &dyn id::IdGenerator,
+        method: &str,
+        params: Option<Value>,
+    ) -> Self {
         Self {
-            jsonrpc: VERSION.to_string(),
-            method: method.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+            method: Arc::from(method),
             params,
-            id: Some(Value::Number(Number::from(
-                rand::thread_rng().gen_range(1..std::u32::MAX),
-            ))),
+            id: Some(ids.next_id()),
+            meta: None,
+            received: None,
+            extensions: Default::default(),
         }
     }
 
@@ -308,10 +2380,15 @@ impl Request {
     /// The id field is `None`.
     pub fn new_notification(method: &str, params: Option<Value>) -> Self {
         Self {
-            jsonrpc: VERSION.to_string(),
-            method: method.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+            method: Arc::from(method),
             params,
             id: None,
+            meta: None,
+            received: None,
+            extensions: Default::default(),
         }
     }
 
@@ -336,59 +2413,428 @@ impl Request {
         &self.params
     }
 
+    /// The mutable parameters for the request.
+    pub fn params_mut(&mut self) -> &mut Option<Value> {
+        &mut self.params
+    }
+
+    /// Take the parameters leaving the request without parameters.
+    ///
+    /// A later call to [deserialize()](Self::deserialize) yields the
+    /// missing parameters error.
+    pub fn take_params(&mut self) -> Option<Value> {
+        self.params.take()
+    }
+
+    /// Determine if the request has parameters.
+    pub fn has_params(&self) -> bool {
+        self.params.is_some()
+    }
+
+    /// The number of positional parameters or named parameters.
+    pub fn params_len(&self) -> Option<usize> {
+        match &self.params {
+            Some(Value::Array(params)) => Some(params.len()),
+            Some(Value::Object(params)) => Some(params.len()),
+            _ => None,
+        }
+    }
+
+    /// The metadata for the request.
+    ///
+    /// Yields the `_meta` field of the request, which includes metadata
+    /// extracted by [take_meta()](Self::take_meta), or otherwise the
+    /// `_meta` field of object parameters.
+    pub fn meta(&self) -> Option<&Value> {
+        match &self.meta {
+            Some(meta) => Some(meta),
+            None => match &self.params {
+                Some(Value::Object(map)) => map.get(meta::META),
+                _ => None,
+            },
+        }
+    }
+
+    /// Deserialize the metadata for the request.
+    pub fn meta_as<M: DeserializeOwned>(&self) -> Result<Option<M>> {
+        self.meta()
+            .map(|meta| M::deserialize(meta))
+            .transpose()
+            .map_err(|e| Error::from(Box::from(e)))
+    }
+
+    /// Assign metadata to send in the `_meta` field of the request.
+    pub fn set_meta<M: Serialize>(&mut self, meta: M) -> Result<()> {
+        self.meta = Some(crate::macros::result(meta)?);
+        Ok(())
+    }
+
+    /// Assign metadata to send in the `_meta` field of the parameters.
+    ///
+    /// Requests without parameters are given object parameters. Positional
+    /// parameters have nowhere to carry the metadata so they are left
+    /// untouched and this returns `false`.
+    pub fn set_params_meta(&mut self, meta: Value) -> bool {
+        let params = self
+            .params
+            .get_or_insert_with(|| Value::Object(Default::default()));
+        match params {
+            Value::Object(map) => {
+                map.insert(meta::META.to_string(), meta);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove the `_meta` field from the parameters and move it to
+    /// the `_meta` field of the request so that it is still available
+    /// from [meta()](Self::meta).
+    ///
+    /// Parameters that are left empty are removed.
+    pub fn take_meta(&mut self) -> Option<&Value> {
+        if let Some(Value::Object(map)) = &mut self.params {
+            if let Some(meta) = map.remove(meta::META) {
+                if map.is_empty() {
+                    self.params = None;
+                }
+                self.meta = Some(meta);
+            }
+        }
+        self.meta.as_ref()
+    }
+
+    /// When the transport received the request, if it was recorded.
+    pub fn received(&self) -> Option<Instant> {
+        self.received
+    }
+
+    /// Record when the transport received the request.
+    ///
+    /// Servers that stamp responses with the processing time measure
+    /// from this instant rather than from when serving started.
+    pub fn set_received(&mut self, received: Instant) {
+        self.received = Some(received);
+    }
+
+    /// Values attached to the request by the transport.
+    ///
+    /// See the [extensions](extensions) module.
+    pub fn extensions(&self) -> &extensions::Extensions {
+        &self.extensions
+    }
+
+    /// Mutable values attached to the request by the transport.
+    pub fn extensions_mut(&mut self) -> &mut extensions::Extensions {
+        &mut self.extensions
+    }
+
+    /// Unknown top-level fields of the request.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields(&self) -> &serde_json::Map<String, Value> {
+        &self.extra
+    }
+
+    /// The mutable unknown top-level fields of the request.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields_mut(&mut self) -> &mut serde_json::Map<String, Value> {
+        &mut self.extra
+    }
+
     #[deprecated(note = "Use match expression on method() instead")]
     /// Determine if the given name matches the request method.
     pub fn matches(&self, name: &str) -> bool {
-        *name == self.method
+        name == &*self.method
     }
 
-    /// Deserialize and consume the message parameters into type `T`.
+    /// Deserialize the message parameters into type `T`, leaving them
+    /// on the request.
     ///
     /// If this request message has no parameters or the `params`
     /// payload cannot be converted to `T` this will return
     /// `Error::InvalidParams`.
+    ///
+    /// When the error is caused by a nested field the error data is
+    /// prefixed with the path to the field, for example
+    /// `params.options.timeout: invalid type: string "5s", expected u64`.
     pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
-        if let Some(params) = &self.params {
-            Ok(serde_json::from_value::<T>(params.clone()).map_err(|e| {
-                Error::InvalidParams {
-                    id: self.id.clone(),
-                    data: e.to_string(),
-                }
-            })?)
+        match &self.params {
+            Some(params) => deserialize_params(self, params, None),
+            None => Err(self.missing_params()),
+        }
+    }
+
+    /// Take the message parameters and deserialize them into type `T`.
+    ///
+    /// Built on [take_params()](Self::take_params) so the parameters
+    /// are moved rather than copied into `T`; afterwards the request
+    /// has no parameters. Errors are the same as for
+    /// [deserialize()](Self::deserialize).
+    pub fn take_deserialize<T: DeserializeOwned>(&mut self) -> Result<T> {
+        match self.take_params() {
+            Some(params) => deserialize_params(self, params, None),
+            None => Err(self.missing_params()),
+        }
+    }
+
+    /// The error for a request without parameters.
+    fn missing_params(&self) -> Error {
+        Error::InvalidParams {
+            id: self.id.clone(),
+            data: "No parameters given".into(),
+        }
+    }
+
+    /// Convert into a [Message](message::Message) that distinguishes
+    /// calls from notifications.
+    pub fn into_message(self) -> message::Message {
+        self.into()
+    }
+
+    /// Serialize the request with sensitive parameters redacted.
+    pub fn redacted(&self, rules: &redact::RedactionRules) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+        if let Some(params) = value.get_mut("params") {
+            *params = rules.apply(params);
+        }
+        value
+    }
+}
+
+impl<'a> TryFrom<&'a Value> for Request {
+    type Error = Error;
+
+    fn try_from(payload: &'a Value) -> Result<Self> {
+        from_value_ref(payload)
+    }
+}
+
+/// Deserialize a parameters value reporting the path to an invalid field.
+///
+/// When a `name` is given it is included in the path, otherwise errors
+/// for the top-level value are reported without a path.
+pub(crate) fn deserialize_params<'de, T, D>(
+    request: &Request,
+    value: D,
+    name: Option<&str>,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+    D: serde::Deserializer<'de, Error = serde_json::Error>,
+{
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        let base = match name {
+            Some(name) => format!("params.{}", name),
+            None => "params".to_string(),
+        };
+        let location = if path == "." {
+            name.map(|_| base)
+        } else if path.starts_with('[') {
+            Some(format!("{}{}", base, path))
         } else {
-            Err(Error::InvalidParams {
-                id: self.id.clone(),
-                data: "No parameters given".to_string(),
-            })
+            Some(format!("{}.{}", base, path))
+        };
+        let data = match location {
+            Some(location) => format!("{}: {}", location, e.inner()),
+            None => e.inner().to_string(),
+        };
+        Error::InvalidParams {
+            id: request.id.clone(),
+            data: data.into(),
         }
+    })
+}
+
+/// Check the semantics that deserializing a request does not enforce.
+fn validate(request: Request) -> Result<Request> {
+    validate_with(request, &conformance::Conformance::default())
+}
+
+/// Check the envelope of a request with the checks enabled by a
+/// conformance level.
+fn validate_with(
+    mut request: Request,
+    conformance: &conformance::Conformance,
+) -> Result<Request> {
+    let structured = matches!(
+        request.params,
+        None | Some(Value::Array(_) | Value::Object(_))
+    );
+    request.id =
+        check_envelope(&request.jsonrpc, request.id, structured, conformance)?;
+    Ok(request)
+}
+
+/// Check the version, id and parameters of a request, the id is given
+/// back when valid.
+pub(crate) fn check_envelope(
+    jsonrpc: &str,
+    id: Option<Value>,
+    structured_params: bool,
+    conformance: &conformance::Conformance,
+) -> Result<Option<Value>> {
+    let data = if conformance.version && jsonrpc != VERSION {
+        "jsonrpc version must be \"2.0\""
+    } else if conformance.id_type
+        && !matches!(
+            id,
+            None | Some(Value::Null | Value::Number(_) | Value::String(_))
+        )
+    {
+        "id must be a string, number or null"
+    } else if conformance.structured_params && !structured_params {
+        "params must be an array or object"
+    } else {
+        return Ok(id);
+    };
+    Err(invalid_request(id, data.to_string()))
+}
+
+/// Invalid request error echoing the id when it is a string or number.
+pub(crate) fn invalid_request(id: Option<Value>, data: String) -> Error {
+    let id = match id {
+        Some(Value::Number(_) | Value::String(_)) => id,
+        _ => None,
+    };
+    Error::InvalidRequest {
+        id,
+        data,
+        line: None,
+        column: None,
+        offset: None,
+    }
+}
+
+/// Lenient parse of the id from a payload that is not a valid request.
+fn recover_id(payload: &Value) -> Option<Value> {
+    match payload.get("id") {
+        Some(id @ (Value::Number(_) | Value::String(_))) => Some(id.clone()),
+        _ => None,
     }
 }
 
-fn map_json_error(e: serde_json::Error) -> Error {
+/// Map a JSON error, `recover` is called to find the id for a payload
+/// that was valid JSON.
+///
+/// When the `payload` is available the byte offset of the error is
+/// computed from the line and column.
+fn map_json_error<F>(
+    e: serde_json::Error,
+    payload: Option<&[u8]>,
+    recover: F,
+) -> Error
+where
+    F: FnOnce() -> Option<Value>,
+{
+    // Errors from a `Value` have no position and report line zero
+    let (line, column) = if e.line() > 0 {
+        (Some(e.line()), Some(e.column()))
+    } else {
+        (None, None)
+    };
+    let offset = match (payload, line, column) {
+        (Some(payload), Some(line), Some(column)) => {
+            byte_offset(payload, line, column)
+        }
+        _ => None,
+    };
     if e.is_data() {
         Error::InvalidRequest {
+            id: recover(),
             data: e.to_string(),
+            line,
+            column,
+            offset,
         }
     } else {
         Error::Parse {
             data: e.to_string(),
+            line,
+            column,
+            offset,
         }
     }
 }
 
+/// Map an error parsing a payload, recovering the id from the payload.
+///
+/// A type error may be found before a syntax error later in the
+/// payload, which is a parse error, so only data errors parse the
+/// payload again.
+fn map_payload_error(e: serde_json::Error, payload: &[u8]) -> Error {
+    if !e.is_data() {
+        return map_json_error(e, Some(payload), || None);
+    }
+    match serde_json::from_slice::<Value>(payload) {
+        Ok(value) => map_json_error(e, Some(payload), || recover_id(&value)),
+        Err(syntax) => map_json_error(syntax, Some(payload), || None),
+    }
+}
+
+/// Convert a one-based line and column into a byte offset.
+fn byte_offset(payload: &[u8], line: usize, column: usize) -> Option<usize> {
+    let start = if line == 1 {
+        0
+    } else {
+        payload
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .nth(line - 2)
+            .map(|(index, _)| index + 1)?
+    };
+    Some(std::cmp::min(
+        start + column.saturating_sub(1),
+        payload.len(),
+    ))
+}
+
 /// JSON-RPC response.
-#[derive(Deserialize, Serialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 pub struct Response {
-    jsonrpc: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    jsonrpc: Cow<'static, str>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
     id: Option<Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
     result: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<RpcError>,
+    /// Unknown top-level fields.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+/// Deserialize a field that is present as `Some` even when it is
+/// `null`, so a `null` result or id survives a round trip.
+fn present<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
 }
 
 impl Response {
+    /// Start building a response without a request.
+    pub fn builder() -> builder::ResponseBuilder<builder::NoBody> {
+        builder::ResponseBuilder::new()
+    }
+
     /// The id for the response.
     pub fn id(&self) -> &Option<Value> {
         &self.id
@@ -403,6 +2849,57 @@ impl Response {
     pub fn error(&self) -> &Option<RpcError> {
         &self.error
     }
+
+    /// Unknown top-level fields of the response.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields(&self) -> &serde_json::Map<String, Value> {
+        &self.extra
+    }
+
+    /// The mutable unknown top-level fields of the response.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields_mut(&mut self) -> &mut serde_json::Map<String, Value> {
+        &mut self.extra
+    }
+
+    /// Convert the response into the result value.
+    ///
+    /// An error response yields `Error::Rpc`; a response without a
+    /// result or an error yields `Value::Null`.
+    pub fn into_result(self) -> Result<Value> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(Error::Rpc(error)),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+
+    /// Write a response to the request with the result serialized
+    /// straight into the writer, without building a `Value`.
+    pub fn write_result<R: Serialize + ?Sized, W: std::io::Write>(
+        request: &Request,
+        result: &R,
+        writer: W,
+    ) -> Result<()> {
+        raw::write_result(request, result, writer)
+    }
+
+    /// Serialize the response with sensitive values in the result
+    /// and the error data redacted.
+    pub fn redacted(&self, rules: &redact::RedactionRules) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+        if let Some(result) = value.get_mut("result") {
+            *result = rules.apply(result);
+        }
+        if let Some(data) = value.pointer_mut("/error/data") {
+            *data = rules.apply(data);
+        }
+        value
+    }
 }
 
 impl From<Response> for (Option<Value>, Option<RpcError>, Option<Value>) {
@@ -423,34 +2920,82 @@ impl From<Response> for Option<RpcError> {
     }
 }
 
+impl<'a> From<&'a Error> for RpcError {
+    fn from(error: &'a Error) -> Self {
+        let (code, data): (isize, Option<Value>) = error.into();
+        RpcError {
+            code,
+            message: error.message(),
+            data,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(error: Error) -> Self {
+        let message = error.message();
+        match error {
+            Error::MethodNotFound { .. } => RpcError {
+                code: METHOD_NOT_FOUND,
+                message,
+                data: None,
+            },
+            Error::InvalidParams { data, .. } => RpcError {
+                code: INVALID_PARAMS,
+                message,
+                data: Some(truncate::data_owned(data.into())),
+            },
+            Error::Parse { data, .. } => RpcError {
+                code: PARSE_ERROR,
+                message,
+                data: Some(truncate::data_owned(data)),
+            },
+            Error::InvalidRequest { data, .. } => RpcError {
+                code: INVALID_REQUEST,
+                message,
+                data: Some(truncate::data_owned(data)),
+            },
+            Error::Rpc(error) => error,
+            Error::Timeout { .. } => deadline::exceeded(),
+            Error::Cancelled { .. } => cancel::cancelled(),
+            _ => RpcError {
+                code: INTERNAL_ERROR,
+                message,
+                data: None,
+            },
+        }
+    }
+}
+
 impl From<Error> for Response {
     fn from(error: Error) -> Self {
-        let (code, data): (isize, Option<String>) = (&error).into();
+        let id = match &error {
+            Error::InvalidRequest { id: Some(id), .. }
+            | Error::InvalidParams { id: Some(id), .. }
+            | Error::Timeout { id: Some(id), .. }
+            | Error::Cancelled { id: Some(id) } => id.clone(),
+            _ => Value::Null,
+        };
         Response {
-            jsonrpc: VERSION.to_string(),
-            id: Some(Value::Null),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+            id: Some(id),
             result: None,
-            error: Some(RpcError {
-                code,
-                message: error.to_string(),
-                data,
-            }),
+            error: Some(error.into()),
         }
     }
 }
 
 impl<'a> From<(&'a Request, Error)> for Response {
-    fn from(result: (&'a Request, Error)) -> Self {
-        let (code, data): (isize, Option<String>) = (&result.1).into();
+    fn from((request, error): (&'a Request, Error)) -> Self {
         Response {
-            jsonrpc: VERSION.to_string(),
-            id: result.0.id.clone(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+            id: request.id.clone(),
             result: None,
-            error: Some(RpcError {
-                code,
-                message: result.1.to_string(),
-                data,
-            }),
+            error: Some(error.into()),
         }
     }
 }
@@ -458,7 +3003,9 @@ impl<'a> From<(&'a Request, Error)> for Response {
 impl<'a> From<(&'a Request, RpcError)> for Response {
     fn from(result: (&'a Request, RpcError)) -> Self {
         Response {
-            jsonrpc: VERSION.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
             id: result.0.id.clone(),
             result: None,
             error: Some(result.1),
@@ -469,7 +3016,9 @@ impl<'a> From<(&'a Request, RpcError)> for Response {
 impl<'a> From<(&'a Request, Value)> for Response {
     fn from(req: (&'a Request, Value)) -> Self {
         Self {
-            jsonrpc: VERSION.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
             id: req.0.id.clone(),
             result: Some(req.1),
             error: None,
@@ -480,7 +3029,9 @@ impl<'a> From<(&'a Request, Value)> for Response {
 impl<'a> From<&'a Request> for Response {
     fn from(req: &'a Request) -> Self {
         Self {
-            jsonrpc: VERSION.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
             result: None,
             error: None,
             id: req.id.clone(),
@@ -491,7 +3042,9 @@ impl<'a> From<&'a Request> for Response {
 impl From<Value> for Response {
     fn from(result: Value) -> Self {
         Self {
-            jsonrpc: VERSION.to_string(),
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
             result: Some(result),
             error: None,
             id: Some(Value::from(Number::from(0))),
@@ -499,8 +3052,67 @@ impl From<Value> for Response {
     }
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
+    use serde_json::json;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn types_are_send_sync() {
+        fn server<T: 'static>() {
+            assert_send_sync::<Server<'static, T>>();
+            assert_send_sync::<builder::ServerBuilder<T>>();
+        }
+        assert_send_sync::<Error>();
+        assert_send_sync::<Request>();
+        assert_send_sync::<Response>();
+        assert_send_sync::<RpcError>();
+        server::<std::rc::Rc<()>>();
+    }
+
+    #[test]
+    fn timeout_cancelled_codes() {
+        let timeout = Error::Timeout {
+            method: Some("slow".to_string()),
+            elapsed: Some(std::time::Duration::from_secs(1)),
+            id: Some(json!(7)),
+        };
+        assert_eq!("Call to slow timed out after 1s", timeout.to_string());
+        let response = Response::from(timeout);
+        assert_eq!(&Some(json!(7)), response.id());
+        let error = response.error().clone().unwrap();
+        assert_eq!(deadline::DEADLINE_EXCEEDED, error.code);
+        assert!(matches!(
+            Error::from_response(&response),
+            Some(Error::Timeout { id: Some(_), .. })
+        ));
+
+        let response = Response::from(Error::Cancelled { id: Some(json!(8)) });
+        assert_eq!(&Some(json!(8)), response.id());
+        let error = response.error().clone().unwrap();
+        assert_eq!(cancel::REQUEST_CANCELLED, error.code);
+        assert!(matches!(
+            Error::from_response(&response),
+            Some(Error::Cancelled { id: Some(_) })
+        ));
+
+        // Other errors sharing the deadline code are left alone
+        let request = Request::new_reply("busy", None);
+        let busy = RpcError {
+            code: shed::SERVER_BUSY,
+            message: "Server busy".into(),
+            data: None,
+        };
+        let busy: Response = (&request, busy).into();
+        assert!(matches!(
+            Error::from_response(&busy),
+            Some(Error::Rpc(RpcError { code: -32000, .. }))
+        ));
+        let ok: Response = (&request, json!(1)).into();
+        assert!(Error::from_response(&ok).is_none());
+    }
 
     #[derive(Debug, thiserror::Error)]
     enum MockError {
@@ -551,22 +3163,41 @@ mod test {
             request: &Request,
             _context: &Self::Data,
         ) -> Result<Option<Response>> {
-            let err = RpcError::new("Mock RPC error".to_string(), Some("close-connection".to_string()));
+            let err = RpcError::new(
+                "Mock RPC error".to_string(),
+                Some(Value::String("close-connection".to_string())),
+            );
             let res = Some((request, err).into());
             Ok(res)
         }
     }
 
+    struct DataErrorService;
+    impl Service for DataErrorService {
+        type Data = ();
+        fn handle(
+            &self,
+            _request: &Request,
+            _context: &Self::Data,
+        ) -> Result<Option<Response>> {
+            Err(Error::with_data(
+                -32004,
+                "Resource not found",
+                serde_json::json!({"resource": "user", "id": 7}),
+            ))
+        }
+    }
+
     #[test]
     fn jsonrpc_service_ok() -> Result<()> {
         let service: Box<dyn Service<Data = ()>> =
             Box::new(HelloServiceHandler {});
-        let mut request = Request::new_reply(
+        let request = Request::new_reply(
             "hello",
             Some(Value::String("world".to_string())),
         );
         let server = Server::new(vec![&service]);
-        let response = server.serve(&mut request, &());
+        let response = server.serve(&request, &());
         assert_eq!(
             Some(Value::String("Hello, world!".to_string())),
             response.unwrap().into()
@@ -578,12 +3209,12 @@ mod test {
     fn jsonrpc_service_notification() -> Result<()> {
         let service: Box<dyn Service<Data = ()>> =
             Box::new(HelloServiceHandler {});
-        let mut request = Request::new_notification(
+        let request = Request::new_notification(
             "hello",
             Some(Value::String("world".to_string())),
         );
         let server = Server::new(vec![&service]);
-        let response = server.serve(&mut request, &());
+        let response = server.serve(&request, &());
         assert_eq!(None, response);
         Ok(())
     }
@@ -598,27 +3229,135 @@ mod test {
         assert_eq!(
             Some(RpcError {
                 code: -32600,
-                message: "Invalid JSON-RPC request".to_string(),
-                data: Some(
+                message: "Invalid JSON-RPC request".into(),
+                data: Some(Value::String(
                     "missing field `jsonrpc` at line 1 column 2".to_string()
-                )
+                ))
             }),
             response.into()
         );
         Ok(())
     }
 
+    #[test]
+    fn jsonrpc_invalid_request_recovers_id() {
+        let response: Response =
+            from_str(r#"{"jsonrpc":"1.0","id":42,"method":"foo"}"#)
+                .unwrap_err()
+                .into();
+        assert_eq!(&Some(json!(42)), response.id());
+        assert_eq!(
+            Some(json!("jsonrpc version must be \"2.0\"")),
+            response.error().as_ref().unwrap().data
+        );
+
+        let response: Response =
+            from_slice(br#"{"jsonrpc":2,"id":"a","method":"foo"}"#)
+                .unwrap_err()
+                .into();
+        assert_eq!(&Some(json!("a")), response.id());
+
+        let response: Response =
+            from_value(json!({"jsonrpc":"2.0","id":7,"method":"f","params":1}))
+                .unwrap_err()
+                .into();
+        assert_eq!(&Some(json!(7)), response.id());
+
+        let response: Response =
+            from_str(r#"{"jsonrpc":"2.0","id":true,"method":"foo"}"#)
+                .unwrap_err()
+                .into();
+        assert_eq!(&Some(Value::Null), response.id());
+
+        let response: Response =
+            from_str(r#"{"jsonrpc":"1.0","id":42"#).unwrap_err().into();
+        assert_eq!(&Some(Value::Null), response.id());
+    }
+
+    #[test]
+    fn jsonrpc_from_reader_closed() {
+        struct Failing;
+        impl std::io::Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::ConnectionReset.into())
+            }
+        }
+
+        let request =
+            from_reader(&b"{\"jsonrpc\":\"2.0\",\"method\":\"foo\"}"[..]);
+        assert_eq!("foo", request.unwrap().method());
+
+        for payload in [&b""[..], &b" \n"[..]] {
+            let error = from_reader(payload).unwrap_err();
+            assert!(matches!(error, Error::Closed));
+            assert!(error.is_connection());
+        }
+
+        let error = from_reader(&b"{\"jsonrpc\":"[..]).unwrap_err();
+        assert!(matches!(error, Error::Parse { .. }));
+        assert!(!error.is_connection());
+
+        let error = from_reader(Failing).unwrap_err();
+        assert!(matches!(error, Error::Io(_)));
+        assert!(error.is_connection());
+        let response: Response = error.into();
+        assert_eq!(INTERNAL_ERROR, response.error().clone().unwrap().code);
+    }
+
+    #[test]
+    fn jsonrpc_from_value_ref() -> Result<()> {
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "foo",
+            "params": {"bar": [1, 2, 3]},
+        });
+        let request = from_value_ref(&payload)?;
+        assert_eq!("foo", request.method());
+        assert_eq!(&Some(json!(1)), request.id());
+        assert_eq!(&Some(json!({"bar": [1, 2, 3]})), request.params());
+
+        let request = Request::try_from(&payload)?;
+        assert_eq!("foo", request.method());
+
+        let response: Response =
+            from_value_ref(&json!({"jsonrpc": "1.0", "id": 42}))
+                .unwrap_err()
+                .into();
+        assert_eq!(&Some(json!(42)), response.id());
+        Ok(())
+    }
+
+    #[test]
+    fn jsonrpc_error_location() {
+        let error = from_str("{\n  \"jsonrpc\": \"2.0\",\n  x").unwrap_err();
+        assert!(matches!(error, Error::Parse { .. }));
+        assert_eq!(Some(3), error.line());
+        assert_eq!(Some(3), error.column());
+        assert_eq!(Some(24), error.offset());
+
+        let error = from_slice(br#"{"jsonrpc":"2.0","id":1}"#).unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }));
+        assert_eq!(Some(1), error.line());
+        assert!(error.offset().is_some());
+
+        let error = from_value(json!({"jsonrpc":"2.0"})).unwrap_err();
+        assert_eq!(None, error.line());
+        assert_eq!(None, error.column());
+        assert_eq!(None, error.offset());
+    }
+
     #[test]
     fn jsonrpc_service_method_not_found() -> Result<()> {
         let service: Box<dyn Service<Data = ()>> =
             Box::new(HelloServiceHandler {});
-        let mut request = Request::new_reply("non-existent", None);
+        let request = Request::new_reply("non-existent", None);
         let server = Server::new(vec![&service]);
-        let response = server.serve(&mut request, &());
+        let response = server.serve(&request, &());
         assert_eq!(
             Some(RpcError {
                 code: -32601,
-                message: "Service method not found: non-existent".to_string(),
+                message: "Service method not found: non-existent".into(),
                 data: None
             }),
             response.unwrap().into()
@@ -626,27 +3365,460 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn jsonrpc_server_validators() {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(HelloServiceHandler {});
+        let server = Server::new(vec![&service])
+            .with_validator(|request: &Request, _: &()| {
+                if request.method().len() > 8 {
+                    Err(RpcError {
+                        code: -32001,
+                        message: "Method name too long".into(),
+                        data: None,
+                    })
+                } else {
+                    Ok(())
+                }
+            })
+            .with_validator(|request: &Request, _: &()| {
+                if request.params().is_none() {
+                    Err(RpcError::new("Missing params".to_string(), None))
+                } else {
+                    Ok(())
+                }
+            });
+
+        let request = Request::new_reply("hello", Some(json!("world")));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(&Some(json!("Hello, world!")), response.result());
+
+        let request = Request::new_reply("hello.world", None);
+        let error: Option<RpcError> =
+            server.serve(&request, &()).unwrap().into();
+        assert_eq!("Method name too long", error.unwrap().message);
+
+        let request = Request::new_reply("hello", None);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(request.id(), response.id());
+        let error: Option<RpcError> = response.into();
+        assert_eq!("Missing params", error.unwrap().message);
+
+        let request = Request::new_notification("hello", None);
+        assert!(server.serve(&request, &()).is_none());
+    }
+
+    #[test]
+    fn jsonrpc_server_on_served() {
+        use std::sync::Mutex;
+
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(HelloServiceHandler {});
+        let server = Server::new(vec![&service]).on_served({
+            let served = Arc::clone(&served);
+            move |request, response, _elapsed| {
+                served.lock().unwrap().push((
+                    request.method().to_string(),
+                    response.map(|response| response.error().is_some()),
+                ))
+            }
+        });
+
+        let request = Request::new_reply("hello", Some(json!("world")));
+        assert!(server.serve(&request, &()).is_some());
+        let request = Request::new_reply("goodbye", None);
+        assert!(server.serve(&request, &()).is_some());
+        let request = Request::new_notification("hello", Some(json!("world")));
+        assert!(server.serve(&request, &()).is_none());
+        let notification = message::Notification::new("hello", None);
+        server.serve_notification(&notification, &());
+
+        assert_eq!(
+            vec![
+                ("hello".to_string(), Some(false)),
+                ("goodbye".to_string(), Some(true)),
+                ("hello".to_string(), None),
+                ("hello".to_string(), None),
+            ],
+            *served.lock().unwrap()
+        );
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("Not found: {0}")]
+    struct NotFoundError(String);
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("Conflict")]
+    struct ConflictError;
+
+    struct DomainErrorService;
+
+    impl Service for DomainErrorService {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _context: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "find" => Err(Error::from(Box::from(NotFoundError(
+                    "alice".to_string(),
+                )))),
+                "create" => Err(Error::from(Box::from(ConflictError))),
+                "fail" => Err(Error::from(Box::from("failed"))),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    fn map_domain_error(error: &Error, _request: &Request) -> Option<RpcError> {
+        if let Some(error) = error.downcast_ref::<NotFoundError>() {
+            Some(RpcError {
+                code: -32004,
+                message: error.to_string().into(),
+                data: None,
+            })
+        } else if error.downcast_ref::<ConflictError>().is_some() {
+            Some(RpcError {
+                code: -32009,
+                message: "Conflict".into(),
+                data: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn jsonrpc_error_mapper() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DomainErrorService);
+        let server =
+            Server::new(vec![&service]).with_error_mapper(map_domain_error);
+
+        let error = |method: &str| -> RpcError {
+            let request = Request::new_reply(method, None);
+            let response = server.serve(&request, &()).unwrap();
+            assert_eq!(request.id(), response.id());
+            let error: Option<RpcError> = response.into();
+            error.unwrap()
+        };
+        let not_found = error("find");
+        assert_eq!(-32004, not_found.code);
+        assert_eq!("Not found: alice", not_found.message);
+        assert_eq!(-32009, error("create").code);
+        assert_eq!(INTERNAL_ERROR, error("fail").code);
+
+        let call = message::Call::new(json!(1), "find", None);
+        let response = server.serve_call(&call, &());
+        let error: Option<RpcError> = response.into();
+        assert_eq!(-32004, error.unwrap().code);
+    }
+
+    #[test]
+    fn jsonrpc_error_downcast() {
+        let error = Error::from(Box::from(NotFoundError("bob".to_string())));
+        assert!(error.is::<NotFoundError>());
+        assert!(!error.is::<ConflictError>());
+        let error = error.downcast::<ConflictError>().unwrap_err();
+        let error = error.downcast::<NotFoundError>().unwrap();
+        assert_eq!("bob", error.0);
+
+        let error = Error::MethodNotFound {
+            id: None,
+            name: "foo".to_string(),
+        };
+        assert!(!error.is::<NotFoundError>());
+        assert!(matches!(
+            error.downcast::<NotFoundError>(),
+            Err(Error::MethodNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn jsonrpc_error_anyhow() {
+        use anyhow::Context;
+        let result: anyhow::Result<()> =
+            Err(ConflictError).context("Saving account");
+        let error = Error::from(result.unwrap_err());
+        assert_eq!("Saving account: Conflict", error.to_string());
+        assert!(error.is::<ConflictError>());
+        let rpc: RpcError = Error::from(anyhow::anyhow!("Boom")).into();
+        assert_eq!("Boom", rpc.message);
+        assert!(error.downcast::<ConflictError>().is_ok());
+    }
+
+    #[derive(Debug)]
+    enum QuotaError {
+        Exceeded { limit: u64 },
+        Suspended,
+    }
+
+    impl ToRpcError for QuotaError {
+        fn to_rpc_error(&self) -> RpcError {
+            match self {
+                QuotaError::Exceeded { limit } => RpcError {
+                    code: -32029,
+                    message: "Quota exceeded".into(),
+                    data: Some(json!({ "limit": limit })),
+                },
+                QuotaError::Suspended => RpcError {
+                    code: -32030,
+                    message: "Account suspended".into(),
+                    data: None,
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn jsonrpc_to_rpc_error() {
+        let request = Request::new_reply("upload", None);
+        let error = Error::from_rpc(QuotaError::Exceeded { limit: 10 });
+        let response: Response = (&request, error).into();
+        assert_eq!(
+            json!({
+                "jsonrpc": "2.0",
+                "id": request.id(),
+                "error": {
+                    "code": -32029,
+                    "message": "Quota exceeded",
+                    "data": {"limit": 10},
+                },
+            }),
+            serde_json::to_value(&response).unwrap()
+        );
+
+        let error: RpcError = Error::from_rpc(QuotaError::Suspended).into();
+        assert_eq!(-32030, error.code);
+        assert_eq!("Account suspended", error.message);
+        assert_eq!(None, error.data);
+    }
+
+    #[test]
+    fn jsonrpc_error_downcast_ref() {
+        let error = Error::from(Box::from(ConflictError));
+        assert!(error.downcast_ref::<ConflictError>().is_some());
+        assert!(error.downcast_ref::<NotFoundError>().is_none());
+        let error = Error::InvalidParams {
+            id: None,
+            data: "bad".into(),
+        };
+        assert!(error.downcast_ref::<ConflictError>().is_none());
+    }
+
+    #[test]
+    fn invalid_params_from_shared_request() {
+        let request = Request::new(Some(json!(3)), "m".to_string(), None);
+        let errors = [
+            Error::from((&request, "bad")),
+            Error::from((&request, "bad".to_string())),
+            Error::from((&request, Cow::Borrowed("bad"))),
+        ];
+        for error in errors {
+            let response: Response = error.into();
+            assert_eq!(&Some(json!(3)), response.id());
+            let error = response.error().clone().unwrap();
+            assert_eq!(INVALID_PARAMS, error.code);
+            assert_eq!(Some(json!("bad")), error.data);
+        }
+    }
+
+    #[test]
+    fn invalid_params_lazy() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut request = Request::new_reply("import", None);
+        let format = |calls: &Arc<AtomicUsize>, row: usize| {
+            let calls = Arc::clone(calls);
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                format!("bad row {}", row)
+            }
+        };
+
+        let error = Error::invalid_params_with(&request, format(&calls, 3));
+        drop(error);
+        assert_eq!(0, calls.load(Ordering::SeqCst));
+
+        let id = request.id().clone();
+        let error = Error::invalid_params_with(&request, format(&calls, 7));
+        *request.id_mut() = Some(json!("changed"));
+        let (code, data) = (&error).into();
+        assert_eq!(INVALID_PARAMS, code);
+        assert_eq!(Some(json!("bad row 7")), data);
+        let response: Response = error.into();
+        assert_eq!(&id, response.id());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        let error = Error::invalid_params(&request, "bad row");
+        match &error {
+            Error::InvalidParams { data, .. } => {
+                assert!(data.is_formatted());
+                assert_eq!("bad row", data);
+            }
+            _ => panic!("expected invalid params"),
+        }
+        let error: RpcError = error.into();
+        assert_eq!(Some(json!("bad row")), error.data);
+    }
+
+    fn named(name: &'static str) -> Arc<dyn Service<Data = ()>> {
+        Arc::new(method::method("who", move |_: (), _: &()| Ok(name)))
+    }
+
+    fn who(server: &Server<'_, ()>) -> Option<Value> {
+        let request = Request::new_reply("who", None);
+        server.serve(&request, &()).unwrap().into()
+    }
+
+    #[test]
+    fn jsonrpc_server_priority() {
+        let mut server = Server::new_prioritized(vec![
+            (5, named("low")),
+            (10, named("high")),
+            (5, named("low-later")),
+        ]);
+        assert_eq!(Some(json!("high")), who(&server));
+
+        server.push_front(named("front"));
+        server.push_back(named("back"));
+        assert_eq!(Some(json!("front")), who(&server));
+        assert_eq!(5, server.order().len());
+        assert!(server.order().iter().all(|methods| methods == &["who"]));
+
+        let server =
+            Server::new_prioritized(vec![(5, named("a")), (5, named("b"))]);
+        assert_eq!(Some(json!("a")), who(&server));
+
+        let server = Server::new_prioritized(vec![
+            (-1, Arc::new(HelloServiceHandler {})),
+            (0, named("who")),
+        ]);
+        assert_eq!(vec![vec!["who".to_string()], vec![]], server.order());
+    }
+
+    struct Greedy(bool);
+
+    impl Service for Greedy {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            Ok(Some((request, json!("greedy")).into()))
+        }
+
+        fn methods(&self) -> Vec<String> {
+            vec!["greedy".to_string()]
+        }
+
+        fn route_by_methods(&self) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn jsonrpc_server_routes() {
+        let mut server = Server::new_shared(vec![
+            Arc::new(Greedy(true)),
+            Arc::new(HelloServiceHandler {}),
+        ]);
+        let request = Request::new_reply("hello", Some(json!("routes")));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(Some(json!("Hello, routes!")), response.into());
+        assert_eq!(None, who(&server));
+
+        server.push_back(named("back"));
+        assert_eq!(Some(json!("back")), who(&server));
+        server.push_front(Arc::new(Greedy(false)));
+        assert_eq!(Some(json!("greedy")), who(&server));
+        let request = Request::new_reply("greedy", None);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(Some(json!("greedy")), response.into());
+    }
+
     #[test]
     fn jsonrpc_invalid_params() -> Result<()> {
         let service: Box<dyn Service<Data = ()>> =
             Box::new(HelloServiceHandler {});
-        let mut request = Request::new_reply("hello", Some(Value::Bool(true)));
+        let request = Request::new_reply("hello", Some(Value::Bool(true)));
         let server = Server::new(vec![&service]);
-        let response = server.serve(&mut request, &());
+        let response = server.serve(&request, &());
         assert_eq!(
             Some(RpcError {
                 code: -32602,
-                message: "Message parameters are invalid".to_string(),
-                data: Some(
+                message: "Message parameters are invalid".into(),
+                data: Some(Value::String(
                     "invalid type: boolean `true`, expected a string"
                         .to_string()
-                )
+                ))
             }),
             response.unwrap().into()
         );
         Ok(())
     }
 
+    #[test]
+    fn jsonrpc_invalid_params_path() {
+        #[derive(Debug, Deserialize)]
+        struct Options {
+            #[allow(dead_code)]
+            timeout: u64,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Params {
+            #[allow(dead_code)]
+            options: Options,
+            #[allow(dead_code)]
+            items: Vec<Options>,
+        }
+
+        let request = Request::new_reply(
+            "nested",
+            Some(serde_json::json!({
+                "options": {"timeout": "5s"},
+                "items": [],
+            })),
+        );
+        match request.deserialize::<Params>() {
+            Err(Error::InvalidParams { data, .. }) => assert_eq!(
+                r#"params.options.timeout: invalid type: string "5s", expected u64"#,
+                data
+            ),
+            _ => panic!("expected invalid params"),
+        }
+
+        let request = Request::new_reply(
+            "nested",
+            Some(serde_json::json!({
+                "options": {"timeout": 5},
+                "items": [{"timeout": 1}, {"timeout": true}],
+            })),
+        );
+        match request.deserialize::<Params>() {
+            Err(Error::InvalidParams { data, .. }) => assert_eq!(
+                "params.items[1].timeout: invalid type: boolean `true`, expected u64",
+                data
+            ),
+            _ => panic!("expected invalid params"),
+        }
+
+        let request =
+            Request::new_reply("tuple", Some(serde_json::json!([1, "x"])));
+        match request.deserialize::<(u8, u8)>() {
+            Err(Error::InvalidParams { data, .. }) => assert_eq!(
+                r#"params[1]: invalid type: string "x", expected u8"#,
+                data
+            ),
+            _ => panic!("expected invalid params"),
+        }
+    }
+
     #[test]
     fn jsonrpc_internal_error() -> Result<()> {
         let service: Box<dyn Service<Data = ()>> =
@@ -657,7 +3829,7 @@ mod test {
         assert_eq!(
             Some(RpcError {
                 code: -32603,
-                message: "Mock error".to_string(),
+                message: "Mock error".into(),
                 data: None
             }),
             response.unwrap().into()
@@ -675,20 +3847,51 @@ mod test {
         assert_eq!(
             Some(RpcError {
                 code: -32700,
-                message: "Parsing failed, invalid JSON data".to_string(),
-                data: Some(
+                message: "Parsing failed, invalid JSON data".into(),
+                data: Some(Value::String(
                     "EOF while parsing a string at line 1 column 18"
                         .to_string()
-                )
+                ))
             }),
             response.into()
         );
         Ok(())
     }
 
+    #[test]
+    fn jsonrpc_parse_error_after_type_error() {
+        // The array is rejected before the syntax error is reached
+        let bad_json = r#"[{"jsonrpc":"2.0","id"O1,"method":"echo"}]"#;
+        match from_str(bad_json) {
+            Err(Error::Parse { offset, .. }) => assert_eq!(Some(22), offset),
+            other => panic!("unexpected {:?}", other),
+        }
+        let bad_json = r#"{"jsonrpc":"2.0","method":7,"id":1,}"#;
+        assert!(matches!(
+            from_slice(bad_json.as_bytes()),
+            Err(Error::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn jsonrpc_response_null_result() {
+        let request = Request::new_reply("foo", None);
+        let response: Response = (&request, Value::Null).into();
+        let payload = serde_json::to_string(&response).unwrap();
+        let parsed: Response = serde_json::from_str(&payload).unwrap();
+        assert_eq!(&Some(Value::Null), parsed.result());
+        assert_eq!(response, parsed);
+
+        let parsed: Response = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"x"}}"#,
+        )
+        .unwrap();
+        assert_eq!(&Some(Value::Null), parsed.id());
+        assert_eq!(&None, parsed.result());
+    }
+
     #[test]
     fn jsonrpc_internal_rpc_error() -> Result<()> {
-    
         let service: Box<dyn Service<Data = ()>> =
             Box::new(InternalRpcErrorService {});
         let request = Request::new_reply("foo", None);
@@ -697,11 +3900,195 @@ mod test {
         assert_eq!(
             Some(RpcError {
                 code: -32603,
-                message: "Mock RPC error".to_string(),
-                data: Some("close-connection".to_string())
+                message: "Mock RPC error".into(),
+                data: Some(Value::String("close-connection".to_string()))
             }),
             response.unwrap().into()
         );
         Ok(())
     }
+
+    #[test]
+    fn jsonrpc_error_message_borrowed() {
+        let error = Error::Parse {
+            data: "EOF".to_string(),
+            line: None,
+            column: None,
+            offset: None,
+        };
+        assert!(matches!(error.message(), Cow::Borrowed(_)));
+        let error: RpcError = error.into();
+        assert_eq!(Some(Value::String("EOF".to_string())), error.data);
+
+        let error = Error::MethodNotFound {
+            id: None,
+            name: "foo".to_string(),
+        };
+        assert_eq!("Service method not found: foo", error.message());
+    }
+
+    #[test]
+    fn jsonrpc_error_with_data() -> Result<()> {
+        let service: Box<dyn Service<Data = ()>> = Box::new(DataErrorService);
+        let request =
+            Request::new(Some(Value::from(1)), "foo".to_string(), None);
+        let server = Server::new(vec![&service]);
+        let response = server.serve(&request, &());
+        let payload = serde_json::to_value(response.unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "error": {
+                    "code": -32004,
+                    "message": "Resource not found",
+                    "data": {"resource": "user", "id": 7}
+                }
+            }),
+            payload
+        );
+        Ok(())
+    }
+    #[test]
+    fn extra_fields_round_trip() -> Result<()> {
+        let payload = r#"{"jsonrpc":"2.0","traceId":"abc","id":1,"method":"foo","params":[1],"x-vendor":{"n":[1,2]}}"#;
+        let mut request = from_str(payload)?;
+        assert_eq!(Some(&json!("abc")), request.extra_fields().get("traceId"));
+        let value: Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(value, serde_json::to_value(&request).unwrap());
+
+        *request.params_mut() = Some(json!([2]));
+        let table = intern::MethodTable::new();
+        let reparsed =
+            table.from_str(&serde_json::to_string(&request).unwrap())?;
+        assert_eq!(request.extra_fields(), reparsed.extra_fields());
+        assert_eq!(&Some(json!([2])), reparsed.params());
+
+        let payload =
+            r#"{"jsonrpc":"2.0","id":1,"result":true,"traceId":"abc"}"#;
+        let mut response: Response = serde_json::from_str(payload).unwrap();
+        let value: Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(value, serde_json::to_value(&response).unwrap());
+        response.extra_fields_mut().remove("traceId");
+        assert!(serde_json::to_value(&response)
+            .unwrap()
+            .get("traceId")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn take_params() -> Result<()> {
+        let mut request = Request::new_reply("add", Some(json!([1, 2])));
+        assert!(request.has_params());
+        assert_eq!(Some(2), request.params_len());
+        let params: Vec<u64> = request.deserialize()?;
+        assert_eq!(vec![1, 2], params);
+
+        assert_eq!(Some(json!([1, 2])), request.take_params());
+        assert!(!request.has_params());
+        assert_eq!(None, request.params_len());
+        let result: Result<Vec<u64>> = request.deserialize();
+        assert!(matches!(
+            result,
+            Err(Error::InvalidParams { data, .. }) if data == "No parameters given"
+        ));
+
+        let mut request = Request::new_reply("add", Some(json!({"a": 1})));
+        assert_eq!(Some(1), request.params_len());
+        let params: std::collections::HashMap<String, u64> =
+            request.take_deserialize()?;
+        assert_eq!(Some(&1), params.get("a"));
+        assert!(!request.has_params());
+        let result: Result<std::collections::HashMap<String, u64>> =
+            request.take_deserialize();
+        assert!(matches!(
+            result,
+            Err(Error::InvalidParams { data, .. }) if data == "No parameters given"
+        ));
+
+        let mut request = Request::new_reply("add", Some(json!(["x"])));
+        let result: Result<Vec<u64>> = request.take_deserialize();
+        assert!(matches!(
+            result,
+            Err(Error::InvalidParams { data, .. })
+                if data.starts_with("params[0]: invalid type")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn serve_many_aligned() {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(HelloServiceHandler);
+        let server = Server::new(vec![&service]);
+        let mut requests = [
+            Request::new_reply("hello", Some(json!("world"))),
+            Request::new_notification("hello", Some(json!("world"))),
+            Request::new_reply("missing", None),
+        ];
+        let responses = server.serve_many(requests.iter_mut(), &());
+        assert_eq!(3, responses.len());
+        assert_eq!(
+            &Some(json!("Hello, world!")),
+            responses[0].as_ref().unwrap().result()
+        );
+        assert!(responses[1].is_none());
+        assert!(responses[2].as_ref().unwrap().error().is_some());
+        assert!(server.serve_many(Vec::<Request>::new(), &()).is_empty());
+    }
+
+    #[test]
+    fn extra_fields_rejected() -> Result<()> {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(HelloServiceHandler);
+        let server =
+            Server::new(vec![&service]).with_validator(reject_extra_fields);
+        let request = from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"hello","traceId":"abc"}"#,
+        )?;
+        let response = server.serve(&request, &()).unwrap();
+        let error = response.error().clone().unwrap();
+        assert_eq!(INVALID_REQUEST, error.code);
+        assert_eq!(Some(json!("unknown field traceId")), error.data);
+        Ok(())
+    }
+
+    #[test]
+    fn jsonrpc_error_cause() {
+        let upstream = RpcError {
+            code: -32007,
+            message: "bank-api: insufficient funds".into(),
+            data: Some(json!({"balance": 0})),
+        };
+        let error = RpcError::with_cause(
+            -32000,
+            "payment failed".to_string(),
+            upstream.clone(),
+        );
+        let request = Request::new_reply("pay", None);
+        let response: Response = (&request, error).into();
+        let payload = serde_json::to_string(&response).unwrap();
+        let response: Response = serde_json::from_str(&payload).unwrap();
+        let error = response.error().clone().unwrap();
+        assert_eq!(Some(upstream.clone()), error.cause());
+        assert_eq!(None, upstream.cause());
+        assert_eq!(
+            "payment failed (code -32000): caused by \
+             bank-api: insufficient funds (code -32007)",
+            error.to_string()
+        );
+
+        let outer = RpcError::with_cause(
+            INTERNAL_ERROR,
+            "checkout failed".to_string(),
+            error,
+        );
+        assert_eq!(
+            "checkout failed (code -32603): caused by payment failed \
+             (code -32000): caused by bank-api: insufficient funds \
+             (code -32007)",
+            outer.to_string()
+        );
+    }
 }
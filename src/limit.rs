@@ -0,0 +1,217 @@
+//! Concurrency limiting for async services.
+//!
+//! Only available with the `async` feature.
+
+use crate::{
+    describe::MethodMeta, futures::Service, Request, Response, Result, RpcError,
+};
+use async_trait::async_trait;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Error code for requests rejected because the server is overloaded.
+pub const SERVER_OVERLOADED: isize = -32000;
+
+/// Handle for reading the number of in-flight requests.
+#[derive(Clone)]
+pub struct InFlight {
+    semaphore: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl InFlight {
+    /// Number of requests currently being handled.
+    pub fn get(&self) -> usize {
+        self.limit - self.semaphore.available_permits()
+    }
+}
+
+/// Service that limits the number of concurrent requests handled
+/// by an inner service.
+///
+/// By default excess requests wait for a handler to finish; when
+/// [fail_fast()](ConcurrencyLimit::fail_fast) is set they receive a
+/// `-32000` server overloaded error instead. Notifications count
+/// against the limit and are dropped silently when failing fast.
+///
+/// While saturated in fail fast mode every request is rejected,
+/// including methods the inner service would not handle, so wrap
+/// only the services that need limiting.
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    in_flight: InFlight,
+    fail_fast: bool,
+}
+
+impl<S> ConcurrencyLimit<S> {
+    /// Limit an inner service to `limit` concurrent requests.
+    pub fn new(inner: S, limit: usize) -> Self {
+        Self {
+            inner,
+            in_flight: InFlight {
+                semaphore: Arc::new(Semaphore::new(limit)),
+                limit,
+            },
+            fail_fast: false,
+        }
+    }
+
+    /// Reject requests immediately when the limit is reached.
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    /// Number of requests currently being handled.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.get()
+    }
+
+    /// Handle for reading the in-flight count after the service
+    /// has been boxed.
+    pub fn counter(&self) -> InFlight {
+        self.in_flight.clone()
+    }
+}
+
+#[async_trait]
+impl<S: Service> Service for ConcurrencyLimit<S> {
+    type Data = S::Data;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let semaphore = &self.in_flight.semaphore;
+        let _permit = if self.fail_fast {
+            match semaphore.try_acquire() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    if request.id().is_none() {
+                        return Ok(Some(request.into()));
+                    }
+                    let err = RpcError {
+                        code: SERVER_OVERLOADED,
+                        message: "Server overloaded".into(),
+                        data: Some(json!({"limit": self.in_flight.limit})),
+                    };
+                    return Ok(Some((request, err).into()));
+                }
+            }
+        } else {
+            // The semaphore is never closed.
+            semaphore
+                .acquire()
+                .await
+                .map_err(|e| crate::Error::from(Box::from(e)))?
+        };
+        self.inner.handle(request, ctx).await
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::Server;
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    struct Gate {
+        entered: Arc<AtomicUsize>,
+        gate: Arc<Semaphore>,
+    }
+
+    #[async_trait]
+    impl Service for Gate {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            self.entered.fetch_add(1, Ordering::SeqCst);
+            self.gate.acquire().await.unwrap().forget();
+            Ok(Some((request, Value::Bool(true)).into()))
+        }
+    }
+
+    fn gate() -> (Gate, Arc<AtomicUsize>, Arc<Semaphore>) {
+        let entered = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Semaphore::new(0));
+        (
+            Gate {
+                entered: Arc::clone(&entered),
+                gate: Arc::clone(&gate),
+            },
+            entered,
+            gate,
+        )
+    }
+
+    #[tokio::test]
+    async fn limit_queues() {
+        let (inner, entered, gate) = gate();
+        let limited = ConcurrencyLimit::new(inner, 1);
+        let in_flight = limited.counter();
+        let service: Box<dyn Service<Data = ()>> = Box::new(limited);
+        let server = Server::new(vec![&service]);
+        let first = Request::new_reply("first", None);
+        let second = Request::new_reply("second", None);
+
+        let (a, b, _) = tokio::join!(
+            server.serve(&first, &()),
+            server.serve(&second, &()),
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                assert_eq!(1, entered.load(Ordering::SeqCst));
+                assert_eq!(1, in_flight.get());
+                gate.add_permits(2);
+            }
+        );
+        assert_eq!(Some(Value::Bool(true)), a.unwrap().into());
+        assert_eq!(Some(Value::Bool(true)), b.unwrap().into());
+        assert_eq!(2, entered.load(Ordering::SeqCst));
+        assert_eq!(0, in_flight.get());
+    }
+
+    #[tokio::test]
+    async fn limit_fail_fast() {
+        let (inner, entered, gate) = gate();
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(ConcurrencyLimit::new(inner, 1).fail_fast());
+        let server = Server::new(vec![&service]);
+        let first = Request::new_reply("first", None);
+        let second = Request::new_reply("second", None);
+        let notification = Request::new_notification("third", None);
+
+        let (a, (b, c)) = tokio::join!(server.serve(&first, &()), async {
+            while entered.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            let b = server.serve(&second, &()).await;
+            let c = server.serve(&notification, &()).await;
+            gate.add_permits(1);
+            (b, c)
+        });
+        assert!(c.is_none());
+        assert_eq!(Some(Value::Bool(true)), a.unwrap().into());
+        let error: Option<RpcError> = b.unwrap().into();
+        let error = error.unwrap();
+        assert_eq!(SERVER_OVERLOADED, error.code);
+        assert_eq!(Some(json!({"limit": 1})), error.data);
+    }
+}
@@ -0,0 +1,321 @@
+//! Logging decorator for services.
+//!
+//! Wrap a service in [Logged](Logged) to log the method, id, latency
+//! and outcome of every request it handles. Matched requests are logged
+//! at debug level, requests the inner service does not handle at trace
+//! level and error responses at warn level, or at the level set for
+//! the error code by an [ErrorPolicy](crate::policy::ErrorPolicy).
+//!
+//! Uses the `log` facade by default or `tracing` when the `tracing`
+//! feature is enabled. With `tracing` each request is handled inside
+//! an `rpc` span recording the method, id and the `traceparent` from
+//! the [request metadata](crate::meta).
+
+use crate::{
+    describe::MethodMeta, policy::ErrorPolicy, redact::RedactionRules, Request,
+    Response, Result, Service,
+};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+use log::Level;
+#[cfg(not(feature = "tracing"))]
+use log::{debug, error, info, trace, warn};
+#[cfg(feature = "tracing")]
+use tracing::{debug, error, info, trace, warn};
+
+type Redact = dyn Fn(&str, &Value) -> Value + Send + Sync;
+
+/// Service that logs requests handled by an inner service.
+pub struct Logged<S> {
+    inner: S,
+    redact: Option<Box<Redact>>,
+    policy: Option<ErrorPolicy>,
+}
+
+impl<S> Logged<S> {
+    /// Create a logging wrapper around a service.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            redact: None,
+            policy: None,
+        }
+    }
+
+    /// Set a callback that receives the method name and parameters
+    /// and returns the parameters that should be logged.
+    ///
+    /// Use this to replace sensitive values before they are written
+    /// to the logs.
+    pub fn redact<F>(mut self, redact: F) -> Self
+    where
+        F: Fn(&str, &Value) -> Value + Send + Sync + 'static,
+    {
+        self.redact = Some(Box::new(redact));
+        self
+    }
+
+    /// Redact the parameters that are logged using rules.
+    pub fn redaction(self, rules: RedactionRules) -> Self {
+        self.redact(move |_, params| rules.apply(params))
+    }
+
+    /// Log error responses at the level the policy sets for the code.
+    pub fn policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// The inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn params(&self, request: &Request) -> String {
+        match (request.params(), &self.redact) {
+            (Some(params), Some(redact)) => {
+                redact(request.method(), params).to_string()
+            }
+            (Some(params), None) => params.to_string(),
+            (None, _) => "null".to_string(),
+        }
+    }
+
+    fn log(
+        &self,
+        request: &Request,
+        result: &Result<Option<Response>>,
+        elapsed: Duration,
+    ) {
+        let method = request.method();
+        let id = request.id().as_ref().unwrap_or(&Value::Null);
+        match result {
+            Ok(Some(response)) => {
+                if let Some(error) = response.error() {
+                    let level = match &self.policy {
+                        Some(policy) => policy.level(error.code),
+                        None => Level::Warn,
+                    };
+                    let line = format!(
+                        "{} id={} {:?} error={} {}",
+                        method, id, elapsed, error.code, error.message
+                    );
+                    match level {
+                        Level::Error => error!("{}", line),
+                        Level::Warn => warn!("{}", line),
+                        Level::Info => info!("{}", line),
+                        Level::Debug => debug!("{}", line),
+                        Level::Trace => trace!("{}", line),
+                    }
+                } else {
+                    debug!(
+                        "{} id={} {:?} ok params={}",
+                        method,
+                        id,
+                        elapsed,
+                        self.params(request)
+                    );
+                }
+            }
+            Ok(None) => trace!("{} id={} pass", method, id),
+            Err(e) => warn!("{} id={} {:?} error={}", method, id, elapsed, e),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn span(request: &Request) -> tracing::Span {
+    tracing::debug_span!(
+        "rpc",
+        method = request.method(),
+        id = %request.id().as_ref().unwrap_or(&serde_json::Value::Null),
+        traceparent = crate::meta::traceparent(request),
+    )
+}
+
+impl<S: Service> Service for Logged<S> {
+    type Data = S::Data;
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        #[cfg(feature = "tracing")]
+        let _span = span(request).entered();
+        let started = Instant::now();
+        let result = self.inner.handle(request, ctx);
+        self.log(request, &result, started.elapsed());
+        result
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<S: crate::futures::Service> crate::futures::Service for Logged<S> {
+    type Data = S::Data;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let started = Instant::now();
+        #[cfg(feature = "tracing")]
+        let result = {
+            use tracing::Instrument;
+            let span = span(request);
+            self.inner.handle(request, ctx).instrument(span).await
+        };
+        #[cfg(not(feature = "tracing"))]
+        let result = self.inner.handle(request, ctx).await;
+        self.log(request, &result, started.elapsed());
+        result
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(all(test, not(feature = "tracing")))]
+mod test {
+    use super::*;
+    use crate::Server;
+    use std::sync::Mutex;
+
+    static LINES: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+
+    struct Capture;
+    impl log::Log for Capture {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            LINES
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture;
+
+    fn lines(method: &str) -> Vec<(log::Level, String)> {
+        LINES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, line)| line.starts_with(method))
+            .cloned()
+            .collect()
+    }
+
+    struct LoginService;
+    impl Service for LoginService {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "login" => Ok(Some((request, Value::Bool(true)).into())),
+                "fail" | "reject" => {
+                    let err = crate::RpcError::new("denied".to_string(), None);
+                    Ok(Some((request, err).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[test]
+    fn logged_redaction() {
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(Logged::new(LoginService).redact(|_, params| {
+                let mut params = params.clone();
+                if let Some(password) = params.get_mut("password") {
+                    *password = Value::String("***".to_string());
+                }
+                params
+            }));
+        let server = Server::new(vec![&service]);
+
+        let params = serde_json::json!({"user": "muji", "password": "secret"});
+        server.serve(&Request::new_reply("login", Some(params)), &());
+        server.serve(&Request::new_reply("fail", None), &());
+        server.serve(&Request::new_reply("unknown", None), &());
+
+        let login = lines("login");
+        assert_eq!(1, login.len());
+        assert_eq!(log::Level::Debug, login[0].0);
+        assert!(login[0].1.contains(r#""password":"***""#));
+        assert!(!login[0].1.contains("secret"));
+
+        let fail = lines("fail");
+        assert_eq!(1, fail.len());
+        assert_eq!(log::Level::Warn, fail[0].0);
+
+        let unknown = lines("unknown");
+        assert_eq!(1, unknown.len());
+        assert_eq!(log::Level::Trace, unknown[0].0);
+    }
+
+    #[test]
+    fn logged_policy_level() {
+        use crate::policy::Category;
+
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let policy = ErrorPolicy::default().code(
+            crate::INTERNAL_ERROR,
+            Category::ServerFault,
+            Level::Info,
+        );
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(Logged::new(LoginService).policy(policy));
+        let server = Server::new(vec![&service]);
+        server.serve(&Request::new_reply("reject", None), &());
+
+        let reject = lines("reject");
+        assert_eq!(1, reject.len());
+        assert_eq!(Level::Info, reject[0].0);
+    }
+
+    #[test]
+    fn logged_redaction_rules() {
+        let logged = Logged::new(LoginService)
+            .redaction(RedactionRules::new().key("password"));
+        let params = serde_json::json!({"user": "muji", "password": "secret"});
+        let request = Request::new_reply("login", Some(params));
+        assert_eq!(
+            r#"{"password":"***","user":"muji"}"#,
+            logged.params(&request)
+        );
+    }
+}
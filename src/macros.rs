@@ -0,0 +1,256 @@
+//! Support functions for the [rpc_service!](crate::rpc_service) and
+//! `#[rpc]` macros.
+
+use crate::{Error, Request, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Declare a service that dispatches to a block for each method.
+///
+/// The macro expands to a unit struct implementing
+/// [Service](crate::Service) with the given `Data` type. Parameters
+/// may be passed positionally (an array) or by name (an object);
+/// a parameter that cannot be converted yields `Error::InvalidParams`
+/// with the path to the parameter prefixed to the error message.
+/// Methods not listed fall through so other services may handle them.
+///
+/// Each method body must evaluate to `Result<T>` where `T` is the
+/// declared return type.
+///
+/// ```
+/// use json_rpc2::*;
+/// use serde_json::json;
+///
+/// pub struct AppState {
+///     pub greeting: String,
+/// }
+///
+/// rpc_service! {
+///     pub MathService(ctx: AppState) {
+///         "sum"(a: i64, b: i64) -> i64 { Ok(a + b) }
+///         "greet"(name: String) -> String {
+///             Ok(format!("{}, {}!", ctx.greeting, name))
+///         }
+///     }
+/// }
+///
+/// let service: Box<dyn Service<Data = AppState>> = Box::new(MathService);
+/// let server = Server::new(vec![&service]);
+/// let state = AppState { greeting: "Hello".to_string() };
+/// let request = Request::new_reply("sum", Some(json!([1, 2])));
+/// let response = server.serve(&request, &state);
+/// assert_eq!(Some(json!(3)), response.unwrap().into());
+/// ```
+#[macro_export]
+macro_rules! rpc_service {
+    (
+        $(#[$meta:meta])*
+        $vis:vis $name:ident ( $ctx:ident : $data:ty ) {
+            $(
+                $method:literal ( $( $arg:ident : $ty:ty ),* $(,)? )
+                    -> $ret:ty $body:block
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $crate::Service for $name {
+            type Data = $data;
+            fn handle(
+                &self,
+                request: &$crate::Request,
+                $ctx: &Self::Data,
+            ) -> $crate::Result<Option<$crate::Response>> {
+                let _ = $ctx;
+                match request.method() {
+                    $(
+                        $method => {
+                            #[allow(unused_mut, unused_variables)]
+                            let mut index = 0usize;
+                            $(
+                                let $arg: $ty = $crate::macros::param(
+                                    request,
+                                    &mut index,
+                                    stringify!($arg),
+                                )?;
+                            )*
+                            $crate::macros::arity(request, index)?;
+                            let call = || -> $crate::Result<$ret> { $body };
+                            let value = $crate::macros::result(call()?)?;
+                            Ok(Some((request, value).into()))
+                        }
+                    )*
+                    _ => Ok(None),
+                }
+            }
+
+            fn methods(&self) -> Vec<String> {
+                vec![$($method.to_string()),*]
+            }
+        }
+    };
+}
+
+fn invalid(request: &Request, data: String) -> Error {
+    Error::InvalidParams {
+        id: request.id().clone(),
+        data: data.into(),
+    }
+}
+
+/// Extract the next parameter by position or by name.
+#[doc(hidden)]
+pub fn param<T: DeserializeOwned>(
+    request: &Request,
+    index: &mut usize,
+    name: &str,
+) -> Result<T> {
+    let position = *index;
+    *index += 1;
+    let value = match request.params() {
+        Some(Value::Array(items)) => items.get(position),
+        Some(Value::Object(map)) => map.get(name),
+        None => None,
+        Some(_) => {
+            return Err(invalid(
+                request,
+                "Parameters must be an array or object".to_string(),
+            ))
+        }
+    };
+    crate::deserialize_params(
+        request,
+        value.unwrap_or(&Value::Null),
+        Some(name),
+    )
+}
+
+/// Reject positional parameters that were not consumed.
+#[doc(hidden)]
+pub fn arity(request: &Request, expected: usize) -> Result<()> {
+    match request.params() {
+        Some(Value::Array(items)) if items.len() > expected => Err(invalid(
+            request,
+            format!("Expected {} parameters but got {}", expected, items.len()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Convert a client argument to a value.
+#[doc(hidden)]
+pub fn arg<T: Serialize>(value: &T) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| Error::from(Box::from(e)))
+}
+
+/// Positional parameters for a client call, `None` when empty.
+#[doc(hidden)]
+pub fn positional(values: Vec<Value>) -> Option<Value> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(Value::Array(values))
+    }
+}
+
+/// Convert a method result to a value.
+#[doc(hidden)]
+pub fn result<T: Serialize>(value: T) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| Error::from(Box::from(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Request, RpcError, Server, Service};
+    use serde_json::json;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("Division by zero")]
+    struct DivideByZero;
+
+    struct Counter {
+        base: i64,
+    }
+
+    rpc_service! {
+        CounterService(ctx: Counter) {
+            "add"(a: i64, b: i64) -> i64 { Ok(ctx.base + a + b) }
+            "divide"(a: i64, b: i64) -> i64 {
+                if b == 0 {
+                    return Err(crate::Error::from(Box::from(DivideByZero)));
+                }
+                Ok(a / b)
+            }
+            "label"(prefix: Option<String>) -> String {
+                Ok(prefix.unwrap_or_else(|| "none".to_string()))
+            }
+            "version"() -> &'static str { Ok("1") }
+        }
+    }
+
+    fn serve(request: Request) -> crate::Response {
+        let service: Box<dyn Service<Data = Counter>> =
+            Box::new(CounterService);
+        let server = Server::new(vec![&service]);
+        server.serve(&request, &Counter { base: 10 }).unwrap()
+    }
+
+    #[test]
+    fn macro_positional_and_named() {
+        let positional = serve(Request::new_reply("add", Some(json!([1, 2]))));
+        assert_eq!(Some(json!(13)), positional.into());
+        let named =
+            serve(Request::new_reply("add", Some(json!({"a": 1, "b": 2}))));
+        assert_eq!(Some(json!(13)), named.into());
+        let optional = serve(Request::new_reply("label", None));
+        assert_eq!(Some(json!("none")), optional.into());
+        let version = serve(Request::new_reply("version", None));
+        assert_eq!(Some(json!("1")), version.into());
+    }
+
+    #[test]
+    fn macro_invalid_params() {
+        let response =
+            serve(Request::new_reply("add", Some(json!({"a": 1, "b": "2"}))));
+        let error: Option<RpcError> = response.into();
+        let error = error.unwrap();
+        assert_eq!(-32602, error.code);
+        assert_eq!(
+            Some(json!("params.b: invalid type: string \"2\", expected i64")),
+            error.data
+        );
+
+        let response = serve(Request::new_reply("add", Some(json!([1]))));
+        let error: Option<RpcError> = response.into();
+        assert_eq!(
+            Some(json!("params.b: invalid type: null, expected i64")),
+            error.unwrap().data
+        );
+
+        let response = serve(Request::new_reply("add", Some(json!([1, 2, 3]))));
+        let error: Option<RpcError> = response.into();
+        assert_eq!(-32602, error.unwrap().code);
+    }
+
+    #[test]
+    fn macro_methods() {
+        assert_eq!(
+            vec!["add", "divide", "label", "version"],
+            CounterService.methods()
+        );
+    }
+
+    #[test]
+    fn macro_errors_and_fallthrough() {
+        let response = serve(Request::new_reply("divide", Some(json!([1, 0]))));
+        let error: Option<RpcError> = response.into();
+        let error = error.unwrap();
+        assert_eq!(-32603, error.code);
+        assert_eq!("Division by zero", error.message);
+
+        let response = serve(Request::new_reply("missing", None));
+        let error: Option<RpcError> = response.into();
+        assert_eq!(-32601, error.unwrap().code);
+    }
+}
@@ -0,0 +1,220 @@
+//! Distinct types for calls and notifications.
+//!
+//! A [Request](crate::Request) is the wire-level message and may or may
+//! not have an id. Convert it with
+//! [into_message()](crate::Request::into_message) to get a
+//! [Message](Message) where a [Call](Call) always has an id and a
+//! [Notification](Notification) has none, so code that builds
+//! responses cannot do so for a notification by mistake.
+//!
+//! The servers accept these types with `serve_call()`, which always
+//! yields a response, and `serve_notification()`, which never does.
+
+use crate::{Request, Result};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Request message classified by whether it expects a reply.
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// Request that expects a response.
+    Call(Call),
+    /// Request that must not be answered.
+    Notification(Notification),
+}
+
+impl Message {
+    /// The service method name.
+    pub fn method(&self) -> &str {
+        self.as_request().method()
+    }
+
+    /// The underlying request.
+    pub fn as_request(&self) -> &Request {
+        match self {
+            Message::Call(call) => call.as_request(),
+            Message::Notification(notification) => notification.as_request(),
+        }
+    }
+
+    /// Convert back into the underlying request.
+    pub fn into_request(self) -> Request {
+        match self {
+            Message::Call(call) => call.into_request(),
+            Message::Notification(notification) => notification.into_request(),
+        }
+    }
+}
+
+impl From<Request> for Message {
+    fn from(request: Request) -> Self {
+        if request.id().is_some() {
+            Message::Call(Call(request))
+        } else {
+            Message::Notification(Notification(request))
+        }
+    }
+}
+
+impl From<Message> for Request {
+    fn from(message: Message) -> Self {
+        message.into_request()
+    }
+}
+
+/// Request that has an id and expects a response.
+#[derive(Debug, Clone)]
+pub struct Call(Request);
+
+impl Call {
+    /// Create a new call.
+    pub fn new(id: Value, method: &str, params: Option<Value>) -> Self {
+        Self(Request::new(Some(id), method.to_string(), params))
+    }
+
+    /// The id for the call.
+    pub fn id(&self) -> &Value {
+        self.0.id().as_ref().expect("call to have an id")
+    }
+
+    /// The service method name.
+    pub fn method(&self) -> &str {
+        self.0.method()
+    }
+
+    /// The call parameters.
+    pub fn params(&self) -> &Option<Value> {
+        self.0.params()
+    }
+
+    /// Deserialize the call parameters into type `T`.
+    ///
+    /// See [Request::deserialize()](crate::Request::deserialize).
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
+        self.0.deserialize()
+    }
+
+    /// The underlying request.
+    pub fn as_request(&self) -> &Request {
+        &self.0
+    }
+
+    /// Convert back into the underlying request.
+    pub fn into_request(self) -> Request {
+        self.0
+    }
+}
+
+impl From<Call> for Request {
+    fn from(call: Call) -> Self {
+        call.0
+    }
+}
+
+/// Request without an id that must not be answered.
+#[derive(Debug, Clone)]
+pub struct Notification(Request);
+
+impl Notification {
+    /// Create a new notification.
+    pub fn new(method: &str, params: Option<Value>) -> Self {
+        Self(Request::new_notification(method, params))
+    }
+
+    /// The service method name.
+    pub fn method(&self) -> &str {
+        self.0.method()
+    }
+
+    /// The notification parameters.
+    pub fn params(&self) -> &Option<Value> {
+        self.0.params()
+    }
+
+    /// Deserialize the notification parameters into type `T`.
+    ///
+    /// See [Request::deserialize()](crate::Request::deserialize).
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
+        self.0.deserialize()
+    }
+
+    /// The underlying request.
+    pub fn as_request(&self) -> &Request {
+        &self.0
+    }
+
+    /// Convert back into the underlying request.
+    pub fn into_request(self) -> Request {
+        self.0
+    }
+}
+
+impl From<Notification> for Request {
+    fn from(notification: Notification) -> Self {
+        notification.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Response, Server, Service};
+    use serde_json::json;
+
+    struct Echo;
+
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "echo" => Ok(Some(
+                    (request, request.params().clone().unwrap()).into(),
+                )),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[test]
+    fn message_classify() {
+        let call = Request::new_reply("echo", None).into_message();
+        assert!(matches!(call, Message::Call(_)));
+        let notification =
+            Request::new_notification("echo", None).into_message();
+        assert!(matches!(notification, Message::Notification(_)));
+        assert_eq!("echo", notification.method());
+
+        let call = Call::new(json!(7), "echo", None);
+        assert_eq!(&json!(7), call.id());
+        let request: Request = call.into();
+        assert_eq!(&Some(json!(7)), request.id());
+    }
+
+    #[test]
+    fn message_serve() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let server = Server::new(vec![&service]);
+
+        let call = Call::new(json!(1), "echo", Some(json!([1])));
+        let response = server.serve_call(&call, &());
+        assert_eq!(&Some(json!(1)), response.id());
+        assert_eq!(&Some(json!([1])), response.result());
+
+        let call = Call::new(json!(2), "missing", None);
+        let response = server.serve_call(&call, &());
+        assert_eq!(&Some(json!(2)), response.id());
+        assert!(response.error().is_some());
+
+        // Errors for notifications are not answered either
+        let notification = Notification::new("missing", None);
+        server.serve_notification(&notification, &());
+
+        let message =
+            Request::new_notification("echo", Some(json!([]))).into_message();
+        assert!(server.serve_message(&message, &()).is_none());
+    }
+}
@@ -0,0 +1,290 @@
+//! Metadata carried in a `_meta` field of the parameters.
+//!
+//! Clients add metadata such as the W3C `traceparent` to the object
+//! parameters of a request, for example using the
+//! [TraceContext](TraceContext) layer. On the server wrap services in
+//! [ExtractMeta](ExtractMeta) so handlers see the parameters without
+//! the `_meta` field while the metadata remains available from
+//! [Request::meta()](crate::Request::meta).
+//!
+//! Positional parameters cannot carry metadata so it is not sent for
+//! requests with array parameters.
+//!
+//! Metadata may also be sent in a `_meta` field of the request itself
+//! using [Request::set_meta()](crate::Request::set_meta); servers that
+//! do not accept it can add the [reject_meta()](reject_meta) validator.
+
+use crate::{
+    client::ClientLayer, describe::MethodMeta, Request, Response, Result,
+    RpcError, Service, INVALID_REQUEST,
+};
+use serde_json::Value;
+
+/// Name of the metadata field in the parameters.
+pub const META: &str = "_meta";
+
+/// Validator that rejects requests with a `_meta` field, use with
+/// [Server::with_validator()](crate::Server::with_validator).
+///
+/// Metadata in the parameters is not affected.
+pub fn reject_meta<T>(
+    request: &Request,
+    _ctx: &T,
+) -> std::result::Result<(), RpcError> {
+    match request.meta {
+        Some(_) => Err(RpcError {
+            code: INVALID_REQUEST,
+            message: "Invalid request".into(),
+            data: Some(Value::String("_meta is not allowed".to_string())),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Name of the trace context field in the metadata.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// The trace context of a request, if any.
+pub fn traceparent(request: &Request) -> Option<&str> {
+    request.meta()?.get(TRACEPARENT)?.as_str()
+}
+
+type Provider = dyn Fn() -> Option<String> + Send + Sync;
+
+/// Client layer that adds the current trace context to requests.
+pub struct TraceContext {
+    provider: Box<Provider>,
+}
+
+impl TraceContext {
+    /// Create a layer that calls `provider` for the `traceparent`
+    /// of every request.
+    pub fn new<F>(provider: F) -> Self
+    where
+        F: Fn() -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            provider: Box::new(provider),
+        }
+    }
+}
+
+impl ClientLayer for TraceContext {
+    fn before(&self, request: &mut Request) {
+        if let Some(Value::Array(_)) = request.params() {
+            return;
+        }
+        if let Some(traceparent) = (self.provider)() {
+            let mut meta = match request.meta() {
+                Some(Value::Object(map)) => map.clone(),
+                _ => Default::default(),
+            };
+            meta.insert(TRACEPARENT.to_string(), Value::String(traceparent));
+            request.set_params_meta(Value::Object(meta));
+        }
+    }
+}
+
+/// Service that strips the `_meta` field before calling the inner
+/// service.
+pub struct ExtractMeta<S> {
+    inner: S,
+}
+
+impl<S> ExtractMeta<S> {
+    /// Create a wrapper that extracts metadata for a service.
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// The inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+/// Copy of the request without metadata in the parameters, `None`
+/// when there is no metadata to extract.
+fn extract(request: &Request) -> Option<Request> {
+    match request.params() {
+        Some(Value::Object(map)) if map.contains_key(META) => {
+            let mut request = request.clone();
+            request.take_meta();
+            Some(request)
+        }
+        _ => None,
+    }
+}
+
+impl<S: Service> Service for ExtractMeta<S> {
+    type Data = S::Data;
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        match extract(request) {
+            Some(request) => self.inner.handle(&request, ctx),
+            None => self.inner.handle(request, ctx),
+        }
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<S: crate::futures::Service> crate::futures::Service for ExtractMeta<S> {
+    type Data = S::Data;
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        match extract(request) {
+            Some(request) => self.inner.handle(&request, ctx).await,
+            None => self.inner.handle(request, ctx).await,
+        }
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        client::{Client, Transport},
+        Server,
+    };
+    use serde_json::json;
+
+    struct TraceService;
+    impl Service for TraceService {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let value = json!({
+                "params": request.params(),
+                "traceparent": traceparent(request),
+            });
+            Ok(Some((request, value).into()))
+        }
+    }
+
+    struct Local(Box<dyn Service<Data = ()>>);
+    impl Transport for Local {
+        fn send(&self, request: &Request) -> Result<Option<Response>> {
+            let server = Server::new(vec![&self.0]);
+            Ok(server.serve(request, &()))
+        }
+    }
+
+    fn client() -> Client<Local> {
+        Client::new(Local(Box::new(ExtractMeta::new(TraceService))))
+            .with_layer(TraceContext::new(|| Some("00-abc-def-01".to_string())))
+    }
+
+    #[test]
+    fn meta_round_trip() -> Result<()> {
+        let value: Value = client().call("trace", Some(json!({"a": 1})))?;
+        assert_eq!(
+            json!({"params": {"a": 1}, "traceparent": "00-abc-def-01"}),
+            value
+        );
+        let value: Value = client().call("trace", None)?;
+        assert_eq!(
+            json!({"params": null, "traceparent": "00-abc-def-01"}),
+            value
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn meta_skips_positional() -> Result<()> {
+        let value: Value = client().call("trace", Some(json!([1])))?;
+        assert_eq!(json!({"params": [1], "traceparent": null}), value);
+        Ok(())
+    }
+
+    #[test]
+    fn meta_accessors() {
+        let mut request = Request::new_reply("trace", Some(json!({"a": 1})));
+        assert!(request.meta().is_none());
+        assert!(request.set_params_meta(json!({"user": "muji"})));
+        assert_eq!(Some(&json!({"user": "muji"})), request.meta());
+        assert_eq!(Some(&json!({"user": "muji"})), request.take_meta());
+        assert_eq!(&Some(json!({"a": 1})), request.params());
+        assert_eq!(Some(&json!({"user": "muji"})), request.meta());
+
+        let mut positional = Request::new_reply("trace", Some(json!([1])));
+        assert!(!positional.set_params_meta(json!({})));
+        assert!(positional.meta().is_none());
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+    struct Tenant {
+        tenant: String,
+    }
+
+    #[test]
+    fn meta_top_level() -> Result<()> {
+        let payload = r#"{"jsonrpc":"2.0","method":"trace","id":1,"params":[1],"_meta":{"tenant":"acme"}}"#;
+        let request = crate::from_str(payload)?;
+        assert_eq!(&Some(json!([1])), request.params());
+        let tenant = Tenant {
+            tenant: "acme".to_string(),
+        };
+        assert_eq!(Some(&tenant), request.meta_as::<Tenant>()?.as_ref());
+        assert_eq!(payload, serde_json::to_string(&request).unwrap());
+
+        let table = crate::intern::MethodTable::new();
+        assert_eq!(
+            Some(&json!({"tenant": "acme"})),
+            table.from_str(payload)?.meta()
+        );
+
+        let mut request = Request::new_reply("trace", None);
+        assert!(request.meta_as::<Tenant>()?.is_none());
+        request.set_meta(&tenant)?;
+        assert_eq!(Some(&json!({"tenant": "acme"})), request.meta());
+        assert_eq!(&None, request.params());
+        Ok(())
+    }
+
+    #[test]
+    fn meta_rejected() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(TraceService);
+        let server = Server::new(vec![&service]).with_validator(reject_meta);
+        let mut request = Request::new_reply("trace", None);
+        request.set_params_meta(json!({}));
+        assert!(server.serve(&request, &()).unwrap().error().is_none());
+
+        request.set_meta(json!({})).unwrap();
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(INVALID_REQUEST, response.error().as_ref().unwrap().code);
+    }
+}
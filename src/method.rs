@@ -0,0 +1,475 @@
+//! Services from plain functions.
+//!
+//! [method()](method) turns a function taking the parameters and the
+//! user data into a [Service](crate::Service) for a single method. The
+//! parameters are deserialized before the function is called, yielding
+//! `Error::InvalidParams` when they do not match, and the return value
+//! is serialized as the result. Combine several methods into one
+//! service with the [methods!](crate::methods) macro:
+//!
+//! ```
+//! use json_rpc2::{method::method, methods, Request, Result, Server, Service};
+//! use serde::Deserialize;
+//! use serde_json::json;
+//!
+//! struct AppState {
+//!     base: u64,
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct SumParams {
+//!     a: u64,
+//!     b: u64,
+//! }
+//!
+//! fn sum(params: SumParams, ctx: &AppState) -> Result<u64> {
+//!     Ok(ctx.base + params.a + params.b)
+//! }
+//!
+//! fn version(_: (), _: &AppState) -> Result<&'static str> {
+//!     Ok("1.0")
+//! }
+//!
+//! let service: Box<dyn Service<Data = AppState>> =
+//!     Box::new(methods![method("sum", sum), method("version", version)]);
+//! let server = Server::new(vec![&service]);
+//! let request = Request::new_reply("sum", Some(json!({"a": 1, "b": 2})));
+//! let response = server.serve(&request, &AppState { base: 10 });
+//! assert_eq!(Some(json!(13)), response.unwrap().into());
+//! ```
+//!
+//! With the `async` feature [async_method()](async_method) accepts an
+//! `async fn` and [async_methods!](crate::async_methods) combines them.
+//!
+//! [method_with_meta()](method_with_meta) also registers a description
+//! of the method, see the [describe](crate::describe) module.
+
+use crate::{
+    describe::MethodMeta,
+    deserialize_params,
+    raw::{RawResponse, Reply},
+    Request, Response, Result, Service,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::marker::PhantomData;
+
+/// Service that calls a function for one method.
+pub struct Method<F, P, R, T> {
+    meta: MethodMeta,
+    handler: F,
+    marker: PhantomData<fn(P, &T) -> R>,
+}
+
+/// Create a service that calls `handler` for the `name` method.
+pub fn method<F, P, R, T>(name: &str, handler: F) -> Method<F, P, R, T>
+where
+    F: Fn(P, &T) -> Result<R> + Send + Sync,
+    P: DeserializeOwned,
+    R: Serialize,
+{
+    method_with_meta(MethodMeta::new(name), handler)
+}
+
+/// Create a service that calls `handler` for the method described by
+/// `meta`, see the [describe](crate::describe) module.
+pub fn method_with_meta<F, P, R, T>(
+    meta: MethodMeta,
+    handler: F,
+) -> Method<F, P, R, T>
+where
+    F: Fn(P, &T) -> Result<R> + Send + Sync,
+    P: DeserializeOwned,
+    R: Serialize,
+{
+    Method {
+        meta,
+        handler,
+        marker: PhantomData,
+    }
+}
+
+/// Deserialize the parameters treating missing parameters as `null`
+/// so handlers may take `()` or an `Option`.
+fn params<P: DeserializeOwned>(request: &Request) -> Result<P> {
+    let params = request.params().as_ref().unwrap_or(&Value::Null);
+    deserialize_params(request, params, None)
+}
+
+fn reply<R: Serialize>(request: &Request, result: R) -> Result<Response> {
+    Ok((request, crate::macros::result(result)?).into())
+}
+
+impl<F, P, R, T> Service for Method<F, P, R, T>
+where
+    F: Fn(P, &T) -> Result<R> + Send + Sync,
+    P: DeserializeOwned,
+    R: Serialize,
+{
+    type Data = T;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        if request.method() != self.meta.name {
+            return Ok(None);
+        }
+        let result = (self.handler)(params(request)?, ctx)?;
+        reply(request, result).map(Some)
+    }
+
+    fn handle_raw(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Reply>> {
+        if request.method() != self.meta.name {
+            return Ok(None);
+        }
+        let result = (self.handler)(params(request)?, ctx)?;
+        RawResponse::new(request, &result).map(|response| Some(response.into()))
+    }
+
+    fn methods(&self) -> Vec<String> {
+        vec![self.meta.name.clone()]
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        vec![self.meta.clone()]
+    }
+}
+
+/// Service combining several services, usually created with the
+/// [methods!](crate::methods) macro.
+///
+/// Services are called in the order they were added.
+pub struct Methods<T> {
+    services: Vec<Box<dyn Service<Data = T>>>,
+}
+
+impl<T> Default for Methods<T> {
+    fn default() -> Self {
+        Self {
+            services: Vec::new(),
+        }
+    }
+}
+
+impl<T> Methods<T> {
+    /// Create an empty collection of methods.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a service.
+    pub fn with<S: Service<Data = T> + 'static>(mut self, service: S) -> Self {
+        self.services.push(Box::new(service));
+        self
+    }
+}
+
+impl<T> Service for Methods<T> {
+    type Data = T;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        for service in self.services.iter() {
+            if let Some(response) = service.handle(request, ctx)? {
+                return Ok(Some(response));
+            }
+        }
+        Ok(None)
+    }
+
+    fn handle_raw(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Reply>> {
+        for service in self.services.iter() {
+            if let Some(reply) = service.handle_raw(request, ctx)? {
+                return Ok(Some(reply));
+            }
+        }
+        Ok(None)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.services.iter().flat_map(|s| s.methods()).collect()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.services.iter().flat_map(|s| s.method_meta()).collect()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.services.iter().all(|s| s.route_by_methods())
+    }
+}
+
+/// Combine services into one [Methods](crate::method::Methods) service.
+#[macro_export]
+macro_rules! methods {
+    ($($service:expr),* $(,)?) => {
+        $crate::method::Methods::new()$(.with($service))*
+    };
+}
+
+#[cfg(any(test, feature = "async"))]
+pub use self::asynchronous::*;
+
+#[cfg(any(test, feature = "async"))]
+mod asynchronous {
+    use super::MethodMeta;
+    use super::{params, reply};
+    use crate::{futures::Service, Request, Response, Result};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::{future::Future, marker::PhantomData};
+
+    /// Function returning a future that borrows the user data.
+    ///
+    /// Implemented for `async fn(P, &T) -> Result<R>` so it can be
+    /// passed to [async_method()](async_method).
+    pub trait AsyncHandler<'a, P, T: 'a, R>: Send + Sync {
+        /// The future returned by the handler.
+        type Future: Future<Output = Result<R>> + Send + 'a;
+
+        /// Call the handler.
+        fn call(&self, params: P, ctx: &'a T) -> Self::Future;
+    }
+
+    impl<'a, P, T: 'a, R, F, Fut> AsyncHandler<'a, P, T, R> for F
+    where
+        F: Fn(P, &'a T) -> Fut + Send + Sync,
+        Fut: Future<Output = Result<R>> + Send + 'a,
+    {
+        type Future = Fut;
+
+        fn call(&self, params: P, ctx: &'a T) -> Fut {
+            self(params, ctx)
+        }
+    }
+
+    /// Async service that calls a function for one method.
+    ///
+    /// Only available with the `async` feature.
+    pub struct AsyncMethod<F, P, R, T> {
+        meta: MethodMeta,
+        handler: F,
+        marker: PhantomData<fn(P, &T) -> R>,
+    }
+
+    /// Create an async service that calls `handler` for the `name`
+    /// method.
+    ///
+    /// Only available with the `async` feature.
+    pub fn async_method<F, P, R, T>(
+        name: &str,
+        handler: F,
+    ) -> AsyncMethod<F, P, R, T>
+    where
+        F: for<'a> AsyncHandler<'a, P, T, R>,
+    {
+        async_method_with_meta(MethodMeta::new(name), handler)
+    }
+
+    /// Create an async service that calls `handler` for the method
+    /// described by `meta`, see the [describe](crate::describe) module.
+    ///
+    /// Only available with the `async` feature.
+    pub fn async_method_with_meta<F, P, R, T>(
+        meta: MethodMeta,
+        handler: F,
+    ) -> AsyncMethod<F, P, R, T>
+    where
+        F: for<'a> AsyncHandler<'a, P, T, R>,
+    {
+        AsyncMethod {
+            meta,
+            handler,
+            marker: PhantomData,
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<F, P, R, T> Service for AsyncMethod<F, P, R, T>
+    where
+        F: for<'a> AsyncHandler<'a, P, T, R>,
+        P: DeserializeOwned + Send,
+        R: Serialize + Send,
+        T: Send + Sync,
+    {
+        type Data = T;
+
+        async fn handle(
+            &self,
+            request: &Request,
+            ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            if request.method() != self.meta.name {
+                return Ok(None);
+            }
+            let result = self.handler.call(params(request)?, ctx).await?;
+            reply(request, result).map(Some)
+        }
+
+        fn methods(&self) -> Vec<String> {
+            vec![self.meta.name.clone()]
+        }
+
+        fn method_meta(&self) -> Vec<MethodMeta> {
+            vec![self.meta.clone()]
+        }
+    }
+
+    /// Async service combining several services, usually created
+    /// with the [async_methods!](crate::async_methods) macro.
+    ///
+    /// Only available with the `async` feature.
+    pub struct AsyncMethods<T> {
+        services: Vec<Box<dyn Service<Data = T>>>,
+    }
+
+    impl<T: Send + Sync> Default for AsyncMethods<T> {
+        fn default() -> Self {
+            Self {
+                services: Vec::new(),
+            }
+        }
+    }
+
+    impl<T: Send + Sync> AsyncMethods<T> {
+        /// Create an empty collection of methods.
+        pub fn new() -> Self {
+            Default::default()
+        }
+
+        /// Add a service.
+        pub fn with<S: Service<Data = T> + 'static>(
+            mut self,
+            service: S,
+        ) -> Self {
+            self.services.push(Box::new(service));
+            self
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<T: Send + Sync> Service for AsyncMethods<T> {
+        type Data = T;
+
+        async fn handle(
+            &self,
+            request: &Request,
+            ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            for service in self.services.iter() {
+                if let Some(response) = service.handle(request, ctx).await? {
+                    return Ok(Some(response));
+                }
+            }
+            Ok(None)
+        }
+
+        fn methods(&self) -> Vec<String> {
+            self.services.iter().flat_map(|s| s.methods()).collect()
+        }
+
+        fn method_meta(&self) -> Vec<MethodMeta> {
+            self.services.iter().flat_map(|s| s.method_meta()).collect()
+        }
+
+        fn route_by_methods(&self) -> bool {
+            self.services.iter().all(|s| s.route_by_methods())
+        }
+    }
+}
+
+/// Combine async services into one
+/// [AsyncMethods](crate::method::AsyncMethods) service.
+///
+/// Only available with the `async` feature.
+#[cfg(any(test, feature = "async"))]
+#[macro_export]
+macro_rules! async_methods {
+    ($($service:expr),* $(,)?) => {
+        $crate::method::AsyncMethods::new()$(.with($service))*
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Error, Server};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    struct AppState {
+        base: u64,
+    }
+
+    #[derive(Deserialize)]
+    struct SumParams {
+        a: u64,
+        b: u64,
+    }
+
+    fn sum(params: SumParams, ctx: &AppState) -> Result<u64> {
+        Ok(ctx.base + params.a + params.b)
+    }
+
+    fn label(name: Option<String>, _: &AppState) -> Result<String> {
+        Ok(name.unwrap_or_else(|| "none".to_string()))
+    }
+
+    async fn slow_sum(params: SumParams, ctx: &AppState) -> Result<u64> {
+        tokio::task::yield_now().await;
+        Ok(ctx.base + params.a + params.b)
+    }
+
+    #[test]
+    fn method_service() -> Result<()> {
+        let service: Box<dyn Service<Data = AppState>> =
+            Box::new(methods![method("sum", sum), method("label", label)]);
+        assert_eq!(vec!["sum", "label"], service.methods());
+        let server = Server::new(vec![&service]);
+        let state = AppState { base: 10 };
+
+        let request = Request::new_reply("sum", Some(json!({"a": 1, "b": 2})));
+        let response = server.serve(&request, &state).unwrap();
+        assert_eq!(request.id(), response.id());
+        assert_eq!(Some(json!(13)), response.into());
+
+        let request = Request::new_reply("label", None);
+        let response = server.serve(&request, &state).unwrap();
+        assert_eq!(Some(json!("none")), response.into());
+
+        let request = Request::new_reply("sum", Some(json!({"a": 1})));
+        assert!(matches!(
+            service.handle(&request, &state),
+            Err(Error::InvalidParams { .. })
+        ));
+
+        let request = Request::new_reply("missing", None);
+        assert!(service.handle(&request, &state)?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_method_service() -> Result<()> {
+        use crate::futures::{Server, Service};
+        let service: Box<dyn Service<Data = AppState>> =
+            Box::new(async_methods![async_method("sum", slow_sum)]);
+        assert_eq!(vec!["sum"], service.methods());
+        let server = Server::new(vec![&service]);
+        let request = Request::new_reply("sum", Some(json!({"a": 1, "b": 2})));
+        let response = server.serve(&request, &AppState { base: 1 }).await;
+        assert_eq!(Some(json!(4)), response.unwrap().into());
+        Ok(())
+    }
+}
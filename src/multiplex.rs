@@ -0,0 +1,614 @@
+//! Share one connection between concurrent calls, requires the `async`
+//! feature.
+//!
+//! A [MultiplexClient](MultiplexClient) writes every request to a
+//! single outbound channel and a [Reader](Reader) task completes the
+//! call waiting for each response by its id, so callers never hold the
+//! connection while they wait. The transport only moves
+//! [messages](crate::peer::Message): drain the outbound channel into the
+//! connection and turn what the connection receives into the stream
+//! given to the client, for example with
+//! [parse_message_str()](crate::peer::parse_message_str).
+//!
+//! Messages that are not the response to a call, such as requests and
+//! notifications sent by the server or a response that arrived after
+//! its call timed out, are passed on as [events](Event); answer
+//! requests with [respond()](MultiplexClient::respond).
+//!
+//! Notifications can instead be read as a typed stream with
+//! [subscribe_notifications()](MultiplexClient::subscribe_notifications),
+//! every subscriber to a method gets each notification and a
+//! notification that does not match the type is yielded as an error.
+//! Notifications to methods without subscribers are still passed on as
+//! events and counted by
+//! [unsubscribed_notifications()](MultiplexClient::unsubscribed_notifications)
+//! so a misspelled method name is noticed.
+//!
+//! When the incoming stream ends or yields a
+//! [connection error](crate::Error::is_connection) the calls in flight,
+//! and any made afterwards, fail with `Error::ConnectionLost` and the
+//! notification streams end.
+//!
+//! ```
+//! use futures_util::stream;
+//! use json_rpc2::{multiplex::MultiplexClient, peer::Message, Response};
+//! use serde_json::json;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let (incoming_tx, mut incoming_rx) = tokio::sync::mpsc::unbounded_channel();
+//! let incoming = stream::poll_fn(move |cx| incoming_rx.poll_recv(cx));
+//! let (outgoing, mut outbound) = tokio::sync::mpsc::channel(16);
+//! let (client, _events, reader) = MultiplexClient::new(incoming, outgoing);
+//! tokio::spawn(reader.run());
+//!
+//! // The other end of the connection answers every request with 7.
+//! tokio::spawn(async move {
+//!     while let Some(Message::Request(request)) = outbound.recv().await {
+//!         let response: Response = (&request, json!(7)).into();
+//!         let _ = incoming_tx.send(Ok::<_, json_rpc2::Error>(Message::Response(response)));
+//!     }
+//! });
+//! let result: u64 = client.call("seven", None).await?;
+//! assert_eq!(7, result);
+//! # Ok::<(), json_rpc2::Error>(())
+//! # }).unwrap();
+//! ```
+
+use crate::{
+    client::convert_result,
+    futures::Pending,
+    id::{IdGenerator, RandomIds},
+    peer::Message,
+    Error, Request, Response, Result,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::task::{Context, Poll};
+use tokio::sync::{broadcast, mpsc};
+
+/// Notifications buffered for each subscriber, a subscriber that falls
+/// further behind misses the oldest ones.
+const NOTIFICATION_BUFFER: usize = 256;
+
+/// Subscribers to notifications by method name.
+#[derive(Default)]
+struct Registry {
+    channels: HashMap<String, broadcast::Sender<Request>>,
+    unsubscribed: HashMap<String, u64>,
+}
+
+impl Registry {
+    /// Send a notification to the subscribers of its method, a
+    /// notification without subscribers is counted and returned.
+    fn publish(&mut self, request: Request) -> Option<Request> {
+        let request = match self.channels.get(request.method()) {
+            Some(channel) => match channel.send(request) {
+                Ok(_) => return None,
+                Err(broadcast::error::SendError(request)) => request,
+            },
+            None => request,
+        };
+        *self
+            .unsubscribed
+            .entry(request.method().to_string())
+            .or_insert(0) += 1;
+        Some(request)
+    }
+}
+
+/// Message received that is not the response to a call.
+#[derive(Debug)]
+pub enum Event {
+    /// Request or notification sent by the other end of the
+    /// connection.
+    Request(Request),
+    /// Response with an id that no call is waiting for.
+    Response(Response),
+    /// Error from the incoming stream that did not close the
+    /// connection, such as a payload that failed to parse.
+    Error(Error),
+}
+
+/// Client sharing one connection between concurrent calls.
+///
+/// Cloned clients share the connection.
+///
+/// Only available with the `async` feature.
+#[derive(Clone)]
+pub struct MultiplexClient {
+    outgoing: mpsc::Sender<Message>,
+    pending: Pending,
+    closed: Arc<AtomicBool>,
+    ids: Arc<dyn IdGenerator>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl MultiplexClient {
+    /// Create a client that writes to `outgoing` and reads `incoming`.
+    ///
+    /// Returns the client, the receiver for [events](Event) and the
+    /// [Reader](Reader) which must be run, usually in a task of its
+    /// own, for calls to complete.
+    pub fn new<S>(
+        incoming: S,
+        outgoing: mpsc::Sender<Message>,
+    ) -> (Self, mpsc::UnboundedReceiver<Event>, Reader<S>)
+    where
+        S: Stream<Item = Result<Message>> + Send + Unpin,
+    {
+        let (events, receiver) = mpsc::unbounded_channel();
+        let client = Self {
+            outgoing,
+            pending: Pending::new(),
+            closed: Arc::new(AtomicBool::new(false)),
+            ids: Arc::new(RandomIds),
+            registry: Arc::new(Mutex::new(Registry::default())),
+        };
+        let reader = Reader {
+            incoming,
+            events,
+            pending: client.pending.clone(),
+            closed: Arc::clone(&client.closed),
+            registry: Arc::clone(&client.registry),
+        };
+        (client, receiver, reader)
+    }
+
+    /// Set the generator for the ids of calls, random by default.
+    ///
+    /// See the [id](crate::id) module.
+    pub fn with_ids<I: IdGenerator + 'static>(mut self, ids: I) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
+    /// Send a request and wait for the response with the same id.
+    ///
+    /// A notification is sent without waiting and yields a `null`
+    /// result.
+    pub async fn request(&self, request: &Request) -> Result<Response> {
+        let id = match request.id() {
+            Some(id) => id.clone(),
+            None => {
+                self.send(Message::Request(request.clone())).await?;
+                return Ok(Response::builder().result(Value::Null).build());
+            }
+        };
+        let response = self.pending.register(&id);
+        // Register before checking so the reader either sees this call
+        // when it fails the pending calls or the call sees it closed.
+        if self.is_closed() {
+            self.pending.fail(&id, Error::ConnectionLost);
+        } else {
+            self.send(Message::Request(request.clone())).await?;
+        }
+        response.await
+    }
+
+    /// Call a method and convert the result to `R`.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub async fn call<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<R> {
+        let request = Request::new_reply_with(&*self.ids, method, params);
+        convert_result(self.request(&request).await?)
+    }
+
+    /// Send a notification.
+    pub async fn notify(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<()> {
+        let request = Request::new_notification(method, params);
+        self.send(Message::Request(request)).await
+    }
+
+    /// Send the response to a request received as an
+    /// [event](Event::Request).
+    pub async fn respond(&self, response: Response) -> Result<()> {
+        self.send(Message::Response(response)).await
+    }
+
+    /// Subscribe to the notifications sent to `method` with params
+    /// converted to `T`.
+    ///
+    /// Notifications whose params do not convert, and notifications
+    /// missed because the stream was not read fast enough, are yielded
+    /// as errors and the stream continues. Dropping the stream
+    /// unsubscribes.
+    pub fn subscribe_notifications<T>(&self, method: &str) -> Notifications<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let receiver = {
+            let mut registry = self.registry.lock().unwrap();
+            match registry.channels.get(method) {
+                Some(channel) => channel.subscribe(),
+                None => {
+                    let (channel, receiver) =
+                        broadcast::channel(NOTIFICATION_BUFFER);
+                    // After the connection is lost the stream ends
+                    // straight away.
+                    if !self.is_closed() {
+                        registry.channels.insert(method.to_string(), channel);
+                    }
+                    receiver
+                }
+            }
+        };
+        let stream = stream::unfold(receiver, |mut receiver| async move {
+            let item = match receiver.recv().await {
+                Ok(request) => request.deserialize::<T>(),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    Err(Error::from(Box::from(format!(
+                        "missed {} notifications",
+                        missed
+                    ))))
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((item, receiver))
+        });
+        Notifications {
+            method: method.to_string(),
+            stream: Some(Box::pin(stream)),
+            registry: Arc::clone(&self.registry),
+        }
+    }
+
+    /// Number of subscribers to the notifications sent to `method`.
+    pub fn subscribers(&self, method: &str) -> usize {
+        self.registry
+            .lock()
+            .unwrap()
+            .channels
+            .get(method)
+            .map_or(0, |channel| channel.receiver_count())
+    }
+
+    /// Number of notifications received for each method that had no
+    /// subscribers.
+    pub fn unsubscribed_notifications(&self) -> HashMap<String, u64> {
+        self.registry.lock().unwrap().unsubscribed.clone()
+    }
+
+    /// Number of calls waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Determine if the connection was lost.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    async fn send(&self, message: Message) -> Result<()> {
+        if self.is_closed() {
+            return Err(Error::ConnectionLost);
+        }
+        self.outgoing
+            .send(message)
+            .await
+            .map_err(|_| Error::ConnectionLost)
+    }
+}
+
+/// Typed stream of the notifications sent to one method, see
+/// [subscribe_notifications()](MultiplexClient::subscribe_notifications).
+pub struct Notifications<T> {
+    method: String,
+    stream: Option<Pin<Box<dyn Stream<Item = Result<T>> + Send>>>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl<T> Notifications<T> {
+    /// Method of the notifications.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+}
+
+impl<T> Stream for Notifications<T> {
+    type Item = Result<T>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.as_mut().poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl<T> Drop for Notifications<T> {
+    fn drop(&mut self) {
+        // Drop the receiver first so the last subscriber removes the
+        // channel.
+        self.stream.take();
+        let mut registry = self.registry.lock().unwrap();
+        let unused = registry
+            .channels
+            .get(&self.method)
+            .is_some_and(|channel| channel.receiver_count() == 0);
+        if unused {
+            registry.channels.remove(&self.method);
+        }
+    }
+}
+
+/// Reads the incoming messages of a [MultiplexClient](MultiplexClient).
+pub struct Reader<S> {
+    incoming: S,
+    events: mpsc::UnboundedSender<Event>,
+    pending: Pending,
+    closed: Arc<AtomicBool>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl<S> Reader<S>
+where
+    S: Stream<Item = Result<Message>> + Send + Unpin,
+{
+    /// Read until the incoming stream ends or fails with a connection
+    /// error, then fail the calls in flight.
+    pub async fn run(mut self) {
+        while let Some(message) = self.incoming.next().await {
+            match message {
+                Ok(message) => self.dispatch(message),
+                Err(e) if e.is_connection() => break,
+                Err(e) => {
+                    let _ = self.events.send(Event::Error(e));
+                }
+            }
+        }
+        self.closed.store(true, Ordering::SeqCst);
+        self.pending.fail_all(|| Error::ConnectionLost);
+        // Dropping the channels ends the notification streams.
+        self.registry.lock().unwrap().channels.clear();
+    }
+
+    fn dispatch(&self, message: Message) {
+        match message {
+            Message::Request(request) if request.id().is_none() => {
+                let request = self.registry.lock().unwrap().publish(request);
+                if let Some(request) = request {
+                    let _ = self.events.send(Event::Request(request));
+                }
+            }
+            Message::Request(request) => {
+                let _ = self.events.send(Event::Request(request));
+            }
+            Message::Response(response) => {
+                if let Some(response) = self.pending.complete(response) {
+                    let _ = self.events.send(Event::Response(response));
+                }
+            }
+            Message::Batch(messages) => {
+                for message in messages {
+                    self.dispatch(message);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::{Server, Service};
+    use async_trait::async_trait;
+    use futures_util::{future::join_all, stream};
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// Answers `echo` after the number of milliseconds in the params so
+    /// responses arrive out of order.
+    struct Echo;
+
+    #[async_trait]
+    impl Service for Echo {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let (millis,): (u64,) = request.deserialize()?;
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            Ok(Some((request, json!(millis)).into()))
+        }
+    }
+
+    type Incoming = mpsc::UnboundedSender<Result<Message>>;
+
+    /// In-memory connection, the server end runs in a task and can send
+    /// its own messages with the returned sender.
+    fn connect() -> (
+        MultiplexClient,
+        mpsc::UnboundedReceiver<Event>,
+        Incoming,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let (incoming_tx, mut incoming_rx) = mpsc::unbounded_channel();
+        let incoming = stream::poll_fn(move |cx| incoming_rx.poll_recv(cx));
+        let (outgoing, mut outbound) = mpsc::channel(4);
+        let (client, events, reader) = MultiplexClient::new(incoming, outgoing);
+        tokio::spawn(reader.run());
+
+        let server_tx = incoming_tx.clone();
+        let server = tokio::spawn(async move {
+            let server = Arc::new(Server::new_shared(vec![Arc::new(Echo)]));
+            while let Some(message) = outbound.recv().await {
+                if let Message::Request(request) = message {
+                    let server = Arc::clone(&server);
+                    let tx = server_tx.clone();
+                    tokio::spawn(async move {
+                        if let Some(response) =
+                            server.serve(&request, &()).await
+                        {
+                            let _ = tx.send(Ok(Message::Response(response)));
+                        }
+                    });
+                }
+            }
+        });
+        (client, events, incoming_tx, server)
+    }
+
+    #[tokio::test]
+    async fn multiplex_concurrent_calls() {
+        let (client, _events, _incoming, _server) = connect();
+        let calls = [40u64, 10, 30, 0, 20]
+            .iter()
+            .map(|millis| client.call::<u64>("echo", Some(json!([millis]))))
+            .collect::<Vec<_>>();
+        let results: Vec<u64> = join_all(calls)
+            .await
+            .into_iter()
+            .map(|result| result.unwrap())
+            .collect();
+        assert_eq!(vec![40, 10, 30, 0, 20], results);
+        assert_eq!(0, client.pending());
+        client.notify("echo", Some(json!([0]))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn multiplex_events() {
+        let (client, mut events, incoming, _server) = connect();
+        let request =
+            Request::new(Some(json!("server-1")), "ping".to_string(), None);
+        let unknown: Response = (
+            &Request::new(Some(json!("lost")), "late".to_string(), None),
+            json!(1),
+        )
+            .into();
+        let batch = Message::Batch(vec![
+            Message::Request(request),
+            Message::Response(unknown),
+        ]);
+        incoming.send(Ok(batch)).unwrap();
+        incoming
+            .send(Err(Error::from(Box::from("bad payload"))))
+            .unwrap();
+
+        match events.recv().await.unwrap() {
+            Event::Request(request) => {
+                assert_eq!("ping", request.method());
+                let response: Response = (&request, json!("pong")).into();
+                client.respond(response).await.unwrap();
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(matches!(events.recv().await, Some(Event::Response(_))));
+        assert!(matches!(events.recv().await, Some(Event::Error(_))));
+        // The connection is still usable.
+        let result: u64 = client.call("echo", Some(json!([1]))).await.unwrap();
+        assert_eq!(1, result);
+    }
+
+    #[derive(Debug, serde::Deserialize, PartialEq)]
+    struct Head {
+        number: u64,
+    }
+
+    #[tokio::test]
+    async fn multiplex_subscribe_notifications() {
+        let (client, mut events, incoming, _server) = connect();
+        let mut heads = client.subscribe_notifications::<Head>("chain.newHead");
+        let mut raw = client.subscribe_notifications::<Value>("chain.newHead");
+        assert_eq!(2, client.subscribers("chain.newHead"));
+
+        let notify = |method: &str, params: Value| {
+            let request = Request::new_notification(method, Some(params));
+            incoming.send(Ok(Message::Request(request))).unwrap();
+        };
+        notify("chain.newHead", json!({"number": 1}));
+        notify("chain.newHead", json!({"number": "two"}));
+        notify("chain.newhead", json!({"number": 3}));
+        notify("chain.newHead", json!({"number": 4}));
+
+        assert_eq!(Head { number: 1 }, heads.next().await.unwrap().unwrap());
+        assert!(matches!(
+            heads.next().await,
+            Some(Err(Error::InvalidParams { .. }))
+        ));
+        assert_eq!(Head { number: 4 }, heads.next().await.unwrap().unwrap());
+        assert_eq!(json!({"number": 1}), raw.next().await.unwrap().unwrap());
+
+        // The misspelled method reaches the events and is counted.
+        match events.recv().await.unwrap() {
+            Event::Request(request) => {
+                assert_eq!("chain.newhead", request.method())
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        let unsubscribed = client.unsubscribed_notifications();
+        assert_eq!(Some(&1), unsubscribed.get("chain.newhead"));
+
+        drop(raw);
+        assert_eq!(1, client.subscribers("chain.newHead"));
+        drop(heads);
+        assert_eq!(0, client.subscribers("chain.newHead"));
+        notify("chain.newHead", json!({"number": 5}));
+        assert!(matches!(events.recv().await, Some(Event::Request(_))));
+        let unsubscribed = client.unsubscribed_notifications();
+        assert_eq!(Some(&1), unsubscribed.get("chain.newHead"));
+
+        // Streams end when the connection is lost.
+        let mut heads = client.subscribe_notifications::<Head>("chain.newHead");
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        incoming.send(Err(Error::from(reset))).unwrap();
+        assert!(heads.next().await.is_none());
+        let mut late = client.subscribe_notifications::<Head>("chain.newHead");
+        assert!(late.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn multiplex_connection_lost() {
+        let (incoming_tx, mut incoming_rx) = mpsc::unbounded_channel();
+        let incoming = stream::poll_fn(move |cx| incoming_rx.poll_recv(cx));
+        // The other end reads the requests but never answers.
+        let (outgoing, mut outbound) = mpsc::channel(4);
+        let (client, _events, reader) =
+            MultiplexClient::new(incoming, outgoing);
+        tokio::spawn(reader.run());
+        let calls = (0..3)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    client.call::<u64>("echo", Some(json!([0]))).await
+                })
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..3 {
+            outbound.recv().await.unwrap();
+        }
+        assert_eq!(3, client.pending());
+
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        incoming_tx.send(Err(Error::from(reset))).unwrap();
+        for call in calls {
+            let result = call.await.unwrap();
+            assert!(matches!(result, Err(Error::ConnectionLost)));
+        }
+        assert!(client.is_closed());
+        assert_eq!(0, client.pending());
+        assert!(matches!(
+            client.call::<u64>("echo", Some(json!([0]))).await,
+            Err(Error::ConnectionLost)
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+//! Serve a group of methods under a common prefix.
+//!
+//! Wrap a service in [Namespace](Namespace) to expose its methods as
+//! `prefix.method`; the prefix is removed before the request is passed
+//! to the inner service so it can be written without knowing where it
+//! is mounted. [Server::mount()](crate::Server::mount) wraps every
+//! service of another server this way.
+
+use crate::{describe::MethodMeta, Request, Response, Result, Service};
+use std::sync::Arc;
+
+/// Service that handles methods under a prefix.
+pub struct Namespace<S> {
+    prefix: String,
+    inner: S,
+}
+
+impl<S> Namespace<S> {
+    /// Create a namespace so that `prefix.method` calls `method`
+    /// on the inner service.
+    pub fn new(prefix: &str, inner: S) -> Self {
+        Self {
+            prefix: format!("{}.", prefix),
+            inner,
+        }
+    }
+
+    /// The inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn prefixed(&self, methods: Vec<String>) -> Vec<String> {
+        methods
+            .into_iter()
+            .map(|method| format!("{}{}", self.prefix, method))
+            .collect()
+    }
+
+    /// The request to pass to the inner service when the method
+    /// is in this namespace.
+    fn strip(&self, request: &Request) -> Option<Request> {
+        let method = request.method().strip_prefix(self.prefix.as_str())?;
+        let mut request = request.clone();
+        request.method = Arc::from(method);
+        Some(request)
+    }
+}
+
+impl<S: Service> Service for Namespace<S> {
+    type Data = S::Data;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        match self.strip(request) {
+            Some(request) => self.inner.handle(&request, ctx),
+            None => Ok(None),
+        }
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.prefixed(self.inner.methods())
+    }
+
+    fn method_meta(&self) -> Vec<crate::describe::MethodMeta> {
+        self.inner
+            .method_meta()
+            .into_iter()
+            .map(|meta| MethodMeta {
+                name: format!("{}{}", self.prefix, meta.name),
+
+                ..meta
+            })
+            .collect()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<S> crate::futures::Service for Namespace<S>
+where
+    S: crate::futures::Service,
+    S::Data: Send + Sync,
+{
+    type Data = S::Data;
+
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        match self.strip(request) {
+            Some(request) => self.inner.handle(&request, ctx).await,
+            None => Ok(None),
+        }
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.prefixed(self.inner.methods())
+    }
+
+    fn method_meta(&self) -> Vec<crate::describe::MethodMeta> {
+        self.inner
+            .method_meta()
+            .into_iter()
+            .map(|meta| MethodMeta {
+                name: format!("{}{}", self.prefix, meta.name),
+
+                ..meta
+            })
+            .collect()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Server;
+    use serde_json::json;
+
+    struct Named(&'static str);
+
+    impl Service for Named {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            if request.method() == self.0 {
+                Ok(Some((request, json!(self.0)).into()))
+            } else {
+                Ok(None)
+            }
+        }
+
+        fn methods(&self) -> Vec<String> {
+            vec![self.0.to_string()]
+        }
+    }
+
+    fn shared(name: &'static str) -> Arc<dyn Service<Data = ()>> {
+        Arc::new(Named(name))
+    }
+
+    #[test]
+    fn namespace_strip() -> Result<()> {
+        let service = Namespace::new("wallet", Named("balance"));
+        let request = Request::new_reply("wallet.balance", None);
+        let response = service.handle(&request, &())?.unwrap();
+        assert_eq!(request.id(), response.id());
+        assert_eq!(&Some(json!("balance")), response.result());
+
+        let request = Request::new_reply("balance", None);
+        assert!(service.handle(&request, &())?.is_none());
+        let request = Request::new_reply("walletbalance", None);
+        assert!(service.handle(&request, &())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn server_merge_and_mount() {
+        let wallet = Server::new_shared(vec![shared("balance")]);
+        let admin = Server::new_shared(vec![shared("shutdown")]);
+        let server = Server::new_shared(vec![shared("status")])
+            .merge(admin)
+            .mount("wallet", wallet);
+
+        for (method, result) in &[
+            ("status", "status"),
+            ("shutdown", "shutdown"),
+            ("wallet.balance", "balance"),
+        ] {
+            let request = Request::new_reply(method, None);
+            let response = server.serve(&request, &()).unwrap();
+            assert_eq!(&Some(json!(result)), response.result());
+        }
+
+        let request = Request::new_reply("balance", None);
+        let response = server.serve(&request, &()).unwrap();
+        assert!(response.error().is_some());
+    }
+
+    #[test]
+    fn server_methods_mounted() {
+        let server = Server::new_shared(vec![
+            shared("status"),
+            Arc::new(crate::health::PingService::new()),
+        ])
+        .with_methods();
+        let request = Request::new_reply(crate::METHODS, None);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(&Some(json!(["status"])), response.result());
+
+        let wallet =
+            Server::new_shared(vec![shared("send"), shared("balance")]);
+        let server = server.mount("wallet", wallet);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(
+            &Some(json!(["status", "wallet.balance", "wallet.send"])),
+            response.result()
+        );
+    }
+}
@@ -0,0 +1,688 @@
+//! Serve and call over TCP, requires the `net` feature.
+//!
+//! Messages are framed as newline delimited JSON: every request,
+//! response or batch is serialized on a single line.
+//! [serve_tcp()](serve_tcp) accepts connections and serves the lines
+//! read from each one with an async [Server](crate::futures::Server)
+//! until a shutdown future resolves; a [TcpTransport](TcpTransport)
+//! connects a [Client](crate::futures::Client) to such a server.
+//!
+//! ```
+//! use json_rpc2::{futures::{Client, Server, Service}, net::*, *};
+//! use async_trait::async_trait;
+//! use serde_json::{json, Value};
+//! use std::sync::Arc;
+//!
+//! struct Echo;
+//!
+//! #[async_trait]
+//! impl Service for Echo {
+//!     type Data = ();
+//!     async fn handle(
+//!         &self,
+//!         request: &Request,
+//!         _ctx: &Self::Data,
+//!     ) -> Result<Option<Response>> {
+//!         let params = request.params().clone().unwrap_or(Value::Null);
+//!         Ok(Some((request, params).into()))
+//!     }
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+//! let address = listener.local_addr()?;
+//! let server = Arc::new(Server::new_shared(vec![Arc::new(Echo)]));
+//! let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+//! let serving = tokio::spawn(serve_tcp(server, Arc::new(()), listener, async {
+//!     let _ = stopped.await;
+//! }));
+//!
+//! let client = Client::new(TcpTransport::connect(address).await?);
+//! let echoed: Value = client.call("echo", Some(json!(["hello"]))).await?;
+//! assert_eq!(json!(["hello"]), echoed);
+//!
+//! let _ = stop.send(());
+//! let stats = serving.await.unwrap()?;
+//! assert_eq!(1, stats.requests);
+//! # Ok::<(), Error>(())
+//! # }).unwrap();
+//! ```
+//!
+//! Lines are served concurrently and each reply is written as soon as
+//! it is ready, so a long running call does not hold up the lines
+//! after it. When the server [cancels](crate::cancel) requests every
+//! connection gets a registry of its own; use
+//! [serve_tcp_with()](serve_tcp_with) to give every connection a
+//! [Session](Session) as well.
+//!
+//! Shutting down stops accepting connections and reading lines; the
+//! lines being served on each connection are answered first.
+
+use crate::{
+    from_str, from_value,
+    futures::Server,
+    futures::Transport,
+    session::Session,
+    shutdown::{ServedStats, ShutdownHandle},
+    truncate, Error, Request, Response, Result,
+};
+use async_trait::async_trait;
+use futures_util::{
+    future::{self, Either},
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde_json::Value;
+use std::future::Future;
+use std::io;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::{
+    tcp::{OwnedReadHalf, OwnedWriteHalf},
+    TcpListener, TcpStream, ToSocketAddrs,
+};
+
+#[cfg(not(feature = "tracing"))]
+use log::warn as log_warn;
+#[cfg(feature = "tracing")]
+use tracing::warn as log_warn;
+
+/// Accept connections on `listener` and serve newline delimited
+/// requests until `shutdown` resolves.
+///
+/// Each connection is served in a task of its own, see
+/// [serve_stream()](serve_stream). After `shutdown` resolves the
+/// connections stop reading, finish the lines being served and the
+/// combined stats of every connection are returned.
+///
+/// Only available with the `net` feature.
+pub async fn serve_tcp<T, F>(
+    server: Arc<Server<'static, T>>,
+    ctx: Arc<T>,
+    listener: TcpListener,
+    shutdown: F,
+) -> io::Result<ServedStats>
+where
+    T: Send + Sync + 'static,
+    F: Future<Output = ()>,
+{
+    accept(server, ctx, listener, shutdown, || None::<Session<()>>).await
+}
+
+/// Accept connections on `listener` and serve them with a
+/// [Session](Session) each until `shutdown` resolves.
+///
+/// Works like [serve_tcp()](serve_tcp) and calls `session` for every
+/// connection accepted; the session is dropped, calling its cleanup
+/// function, when the connection is done.
+///
+/// Only available with the `net` feature.
+pub async fn serve_tcp_with<T, F, B, S>(
+    server: Arc<Server<'static, T>>,
+    ctx: Arc<T>,
+    listener: TcpListener,
+    shutdown: F,
+    mut session: B,
+) -> io::Result<ServedStats>
+where
+    T: Send + Sync + 'static,
+    F: Future<Output = ()>,
+    B: FnMut() -> Session<S>,
+    S: Send + Sync + 'static,
+{
+    accept(server, ctx, listener, shutdown, || Some(session())).await
+}
+
+async fn accept<T, F, S>(
+    server: Arc<Server<'static, T>>,
+    ctx: Arc<T>,
+    listener: TcpListener,
+    shutdown: F,
+    mut session: impl FnMut() -> Option<Session<S>>,
+) -> io::Result<ServedStats>
+where
+    T: Send + Sync + 'static,
+    F: Future<Output = ()>,
+    S: Send + Sync + 'static,
+{
+    let stop = ShutdownHandle::new();
+    let stats = Arc::new(Mutex::new(ServedStats::default()));
+    let mut connections = Vec::new();
+    let mut shutdown = pin!(shutdown);
+    loop {
+        let accept = pin!(listener.accept());
+        let stream = match future::select(accept, shutdown.as_mut()).await {
+            Either::Left((Ok((stream, _)), _)) => stream,
+            Either::Left((Err(e), _)) => {
+                log_warn!("failed to accept connection: {}", e);
+                continue;
+            }
+            Either::Right(_) => break,
+        };
+        connections.retain(|connection: &tokio::task::JoinHandle<()>| {
+            !connection.is_finished()
+        });
+        let (server, ctx) = (Arc::clone(&server), Arc::clone(&ctx));
+        let (stop, stats) = (stop.clone(), Arc::clone(&stats));
+        let session = session();
+        connections.push(tokio::spawn(async move {
+            match connection(&server, &ctx, stream, &stop, session).await {
+                Ok(served) => stats.lock().unwrap().merge(served),
+                Err(e) => log_warn!("connection failed: {}", e),
+            }
+        }));
+    }
+    stop.shutdown();
+    for connection in connections {
+        let _ = connection.await;
+    }
+    let stats = *stats.lock().unwrap();
+    Ok(stats)
+}
+
+/// Serve the newline delimited requests of one connection until it is
+/// closed or `shutdown` is triggered.
+///
+/// Lines are served concurrently and the replies written in the order
+/// they complete. With [cancellation](Server::with_cancellation) the
+/// requests of the connection share a registry of their own.
+///
+/// Only available with the `net` feature.
+pub async fn serve_stream<T: Send + Sync>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    stream: TcpStream,
+    shutdown: &ShutdownHandle,
+) -> io::Result<ServedStats> {
+    connection(server, ctx, stream, shutdown, None::<Session<()>>).await
+}
+
+/// Serve the newline delimited requests of one connection with a
+/// [Session](Session).
+///
+/// Works like [serve_stream()](serve_stream) and inserts the state of
+/// the session into the extensions of every request; the session is
+/// dropped, calling its cleanup function, when the connection is done.
+///
+/// Only available with the `net` feature.
+pub async fn serve_stream_with<T, S>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    stream: TcpStream,
+    shutdown: &ShutdownHandle,
+    session: Session<S>,
+) -> io::Result<ServedStats>
+where
+    T: Send + Sync,
+    S: Send + Sync + 'static,
+{
+    connection(server, ctx, stream, shutdown, Some(session)).await
+}
+
+enum Event {
+    Shutdown,
+    Line(io::Result<Option<String>>),
+    Reply(ServedStats, Option<String>),
+}
+
+async fn connection<T, S>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    stream: TcpStream,
+    shutdown: &ShutdownHandle,
+    session: Option<Session<S>>,
+) -> io::Result<ServedStats>
+where
+    T: Send + Sync,
+    S: Send + Sync + 'static,
+{
+    // The session is not shared with the requests, only its state, and
+    // it is dropped when the connection is done.
+    let state = session.as_ref().map(|session| Arc::clone(session.state()));
+    let registry = server.stream_registry();
+    let attach = |request: &mut Request| {
+        if let Some(registry) = &registry {
+            registry.attach(request);
+        }
+        if let Some(state) = &state {
+            request.extensions_mut().insert_arc(Arc::clone(state));
+        }
+    };
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut stopped = pin!(shutdown.wait());
+    let mut pending = FuturesUnordered::new();
+    let mut stats = ServedStats::default();
+    loop {
+        let event = future::poll_fn(|cx| {
+            if stopped.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Event::Shutdown);
+            }
+            if let Poll::Ready(line) = Pin::new(&mut lines).poll_next_line(cx) {
+                return Poll::Ready(Event::Line(line));
+            }
+            match pending.poll_next_unpin(cx) {
+                Poll::Ready(Some((served, reply))) => {
+                    Poll::Ready(Event::Reply(served, reply))
+                }
+                _ => Poll::Pending,
+            }
+        })
+        .await;
+        match event {
+            Event::Shutdown => break,
+            Event::Line(line) => match line? {
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => {
+                    pending.push(serve_line(server, ctx, line, &attach))
+                }
+                None => break,
+            },
+            Event::Reply(served, reply) => {
+                stats.merge(served);
+                write(&mut writer, reply).await?;
+            }
+        }
+    }
+    while let Some((served, reply)) = pending.next().await {
+        stats.merge(served);
+        write(&mut writer, reply).await?;
+    }
+    writer.flush().await?;
+    Ok(stats)
+}
+
+async fn write(
+    writer: &mut OwnedWriteHalf,
+    reply: Option<String>,
+) -> io::Result<()> {
+    if let Some(reply) = reply {
+        writer.write_all(reply.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+/// Serve a request or batch, returning the stats and serialized reply.
+async fn serve_line<T: Send + Sync>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    line: String,
+    attach: &impl Fn(&mut Request),
+) -> (ServedStats, Option<String>) {
+    let mut stats = ServedStats::default();
+    let max = server.max_response_size();
+    let items = match serde_json::from_str::<Value>(&line) {
+        Ok(Value::Array(items)) if !items.is_empty() => items,
+        Ok(Value::Array(_)) => {
+            let error = Error::InvalidRequest {
+                id: None,
+                data: "empty batch".to_string(),
+                line: None,
+                column: None,
+                offset: None,
+            };
+            let reply = reply(max, &mut stats, Some(error.into()));
+            return (stats, reply);
+        }
+        Ok(payload) => {
+            let response = match from_value(payload) {
+                Ok(mut request) => {
+                    attach(&mut request);
+                    server.serve(&request, ctx).await
+                }
+                Err(e) => Some(e.into()),
+            };
+            let reply = reply(max, &mut stats, response);
+            return (stats, reply);
+        }
+        // Parse again for the error with its position.
+        Err(_) => {
+            let error = from_str(&line).err().map(Into::into);
+            let reply = reply(max, &mut stats, error);
+            return (stats, reply);
+        }
+    };
+    let mut responses = Vec::new();
+    let mut requests = Vec::new();
+    for item in items {
+        match from_value(item) {
+            Ok(mut request) => {
+                attach(&mut request);
+                requests.push(request)
+            }
+            Err(e) => responses.push(Response::from(e)),
+        }
+    }
+    stats.requests += responses.len() + requests.len();
+    responses.extend(server.serve_batch(&requests, ctx).await);
+    stats.errors += responses.iter().filter(|r| r.error().is_some()).count();
+    if responses.is_empty() {
+        return (stats, None);
+    }
+    // Every response of the batch is checked against the limit, then
+    // the whole batch.
+    let responses: Vec<Response> = responses
+        .into_iter()
+        .map(|response| truncate::limit(response, max))
+        .collect();
+    (stats, truncate::to_string(&responses, &None, max).ok())
+}
+
+fn reply(
+    max: Option<usize>,
+    stats: &mut ServedStats,
+    response: Option<Response>,
+) -> Option<String> {
+    stats.record(response.as_ref());
+    let response = response?;
+    truncate::to_string(&response, response.id(), max).ok()
+}
+
+/// Transport for an async client sending newline delimited requests
+/// over a TCP connection.
+///
+/// Calls are sent one at a time; responses that do not match the id of
+/// the call, such as errors answering notifications, are skipped.
+///
+/// Only available with the `net` feature.
+pub struct TcpTransport {
+    connection:
+        tokio::sync::Mutex<(Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf)>,
+}
+
+impl TcpTransport {
+    /// Connect to a server.
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(address).await?.into_split();
+        let lines = BufReader::new(reader).lines();
+        Ok(Self {
+            connection: tokio::sync::Mutex::new((lines, writer)),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn send(&self, request: &Request) -> Result<Option<Response>> {
+        let mut line = serde_json::to_string(request)
+            .map_err(|e| Error::from(Box::from(e)))?;
+        line.push('\n');
+        let mut connection = self.connection.lock().await;
+        let (lines, writer) = &mut *connection;
+        writer.write_all(line.as_bytes()).await?;
+        let id = match request.id() {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        loop {
+            let line = lines.next_line().await?.ok_or(Error::ConnectionLost)?;
+            let response: Response = serde_json::from_str(&line)
+                .map_err(|e| Error::from(Box::from(e)))?;
+            if response.id().as_ref() == Some(id) {
+                return Ok(Some(response));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cancel::{
+            cancelled, CancelService, CancellationRegistry, CANCEL_REQUEST,
+        },
+        futures::Client,
+        futures::Service,
+        METHOD_NOT_FOUND,
+    };
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    struct Sum;
+
+    #[async_trait]
+    impl Service for Sum {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "sum" => {
+                    let values: Vec<i64> = request.deserialize()?;
+                    Ok(Some(
+                        (request, json!(values.iter().sum::<i64>())).into(),
+                    ))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    struct Sleep;
+
+    #[async_trait]
+    impl Service for Sleep {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "sleep" => {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    Ok(Some((request, Value::Null).into()))
+                }
+                "visits" => {
+                    let visits = request.extensions().get::<AtomicUsize>();
+                    let visits = visits.unwrap().fetch_add(1, Ordering::SeqCst);
+                    Ok(Some((request, json!(visits + 1)).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    type Connection = (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf);
+
+    async fn send(connection: &mut Connection, message: Value) {
+        let line = format!("{}\n", message);
+        connection.1.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    async fn receive(connection: &mut Connection) -> Response {
+        let line = connection.0.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn call(connection: &mut Connection, message: Value) -> Response {
+        send(connection, message).await;
+        receive(connection).await
+    }
+
+    async fn start() -> (
+        SocketAddr,
+        oneshot::Sender<()>,
+        tokio::task::JoinHandle<io::Result<ServedStats>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Arc::new(Server::new_shared(vec![Arc::new(Sum)]));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let serving =
+            tokio::spawn(serve_tcp(server, Arc::new(()), listener, async {
+                let _ = stopped.await;
+            }));
+        (address, stop, serving)
+    }
+
+    #[tokio::test]
+    async fn tcp_client_and_server() {
+        let (address, stop, serving) = start().await;
+        let client = Client::new(TcpTransport::connect(address).await.unwrap());
+        let sum: i64 =
+            client.call("sum", Some(json!([1, 2, 3]))).await.unwrap();
+        assert_eq!(6, sum);
+        // The error answering the notification is skipped.
+        client.notify("missing", None).await.unwrap();
+        let sum: i64 = client.call("sum", Some(json!([4]))).await.unwrap();
+        assert_eq!(4, sum);
+        match client.call::<i64>("missing", None).await {
+            Err(Error::Rpc(error)) => assert_eq!(METHOD_NOT_FOUND, error.code),
+            result => panic!("expected method not found, got {:?}", result),
+        }
+
+        let _ = stop.send(());
+        let stats = serving.await.unwrap().unwrap();
+        assert_eq!(4, stats.requests);
+        assert_eq!(2, stats.errors);
+    }
+
+    #[tokio::test]
+    async fn tcp_batch_and_invalid_lines() {
+        let (address, stop, serving) = start().await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let input = concat!(
+            r#"[{"jsonrpc":"2.0","id":1,"method":"sum","params":[1]},"#,
+            r#"{"jsonrpc":"2.0","method":"sum","params":[2]},1]"#,
+            "\n\n{bad json\n[]\n"
+        );
+        writer.write_all(input.as_bytes()).await.unwrap();
+
+        let batch: Vec<Response> =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap())
+                .unwrap();
+        assert_eq!(2, batch.len());
+        assert!(batch.iter().any(|r| r.result() == &Some(json!(1))));
+        for _ in 0..2 {
+            let line = lines.next_line().await.unwrap().unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(response.error().is_some());
+        }
+
+        let _ = stop.send(());
+        let stats = serving.await.unwrap().unwrap();
+        assert_eq!(5, stats.requests);
+        assert_eq!(3, stats.errors);
+    }
+
+    #[tokio::test]
+    async fn tcp_max_response_size_batch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new_shared(vec![Arc::new(Sum)]);
+        let server = Arc::new(server.with_max_response_size(48));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let serving =
+            tokio::spawn(serve_tcp(server, Arc::new(()), listener, async {
+                let _ = stopped.await;
+            }));
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (reader, writer) = stream.into_split();
+        let mut connection = (BufReader::new(reader).lines(), writer);
+        let sum = |id: i64| json!({"jsonrpc": "2.0", "id": id, "method": "sum", "params": [id]});
+        // Each response fits, the batch does not
+        let reply = call(&mut connection, sum(1)).await;
+        assert_eq!(Some(json!(1)), reply.into());
+        send(&mut connection, json!([sum(1), sum(2)])).await;
+        let reply = receive(&mut connection).await;
+        assert_eq!(&None, reply.id());
+        let error = reply.error().as_ref().unwrap();
+        assert_eq!(Some(json!({"size": 73, "limit": 48})), error.data);
+
+        let _ = stop.send(());
+        let stats = serving.await.unwrap().unwrap();
+        assert_eq!(3, stats.requests);
+    }
+
+    #[tokio::test]
+    async fn tcp_cancel_sleep_with_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let registry = CancellationRegistry::new();
+        let server = Arc::new(
+            Server::new_shared(vec![
+                Arc::new(Sleep),
+                Arc::new(CancelService::new(registry.clone())),
+            ])
+            .with_cancellation(registry),
+        );
+        let closed = Arc::new(AtomicUsize::new(0));
+        let session = {
+            let closed = Arc::clone(&closed);
+            move || {
+                let closed = Arc::clone(&closed);
+                Session::new(AtomicUsize::new(0)).on_close(move |_| {
+                    closed.fetch_add(1, Ordering::SeqCst);
+                })
+            }
+        };
+        let (stop, stopped) = oneshot::channel::<()>();
+        let serving = tokio::spawn(serve_tcp_with(
+            server,
+            Arc::new(()),
+            listener,
+            async {
+                let _ = stopped.await;
+            },
+            session,
+        ));
+
+        let mut connections = Vec::new();
+        for _ in 0..2 {
+            let stream = TcpStream::connect(address).await.unwrap();
+            let (reader, writer) = stream.into_split();
+            connections.push((BufReader::new(reader).lines(), writer));
+        }
+        let cancel = |id: i64| {
+            json!({"jsonrpc": "2.0", "id": id, "method": CANCEL_REQUEST,
+                "params": {"id": 1}})
+        };
+        let served = tokio::time::timeout(Duration::from_secs(5), async {
+            let sleep = json!({"jsonrpc": "2.0", "id": 1, "method": "sleep"});
+            send(&mut connections[0], sleep).await;
+            // Served while the sleep is still running
+            let visits = json!({"jsonrpc": "2.0", "id": 2, "method": "visits"});
+            let reply = call(&mut connections[0], visits).await;
+            assert_eq!(Some(json!(1)), reply.into());
+
+            // The other connection has no request with the id 1 and
+            // its own session state
+            let reply = call(&mut connections[1], cancel(4)).await;
+            assert_eq!(Some(Value::Bool(false)), reply.into());
+            let visits = json!({"jsonrpc": "2.0", "id": 1, "method": "visits"});
+            let reply = call(&mut connections[1], visits).await;
+            assert_eq!(Some(json!(1)), reply.into());
+
+            send(&mut connections[0], cancel(3)).await;
+            let mut replies = Vec::new();
+            for _ in 0..2 {
+                replies.push(receive(&mut connections[0]).await);
+            }
+            replies.sort_by_key(|r| r.id().as_ref().and_then(Value::as_i64));
+            replies
+        });
+        let mut replies = served.await.expect("sleep was not cancelled");
+        assert_eq!(Some(Value::Bool(true)), replies.pop().unwrap().into());
+        assert_eq!(Some(cancelled()), replies.pop().unwrap().into());
+
+        drop(connections);
+        let _ = stop.send(());
+        let stats = serving.await.unwrap().unwrap();
+        assert_eq!(5, stats.requests);
+        assert_eq!(1, stats.errors);
+        assert_eq!(2, closed.load(Ordering::SeqCst));
+    }
+}
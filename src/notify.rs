@@ -0,0 +1,189 @@
+//! Server initiated notifications.
+//!
+//! A [Notifier](Notifier) sends notifications to a sink supplied by the
+//! transport; for blocking servers use [channel()](Notifier::channel)
+//! and for async servers pass a closure that sends to the channel of
+//! your runtime:
+//!
+//! ```
+//! use json_rpc2::notify::Notifier;
+//! let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+//! let notifier = Notifier::new(move |notification| {
+//!     let _ = tx.send(notification);
+//! });
+//! notifier.notify("ready", None);
+//! assert_eq!("ready", rx.try_recv().unwrap().method());
+//! ```
+//!
+//! Handlers usually obtain the notifier from the service context.
+
+use crate::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{mpsc, Arc};
+
+/// Method name for progress notifications.
+pub const PROGRESS: &str = "rpc.progress";
+
+/// Method name for the chunks of a [stream](crate::stream).
+pub const STREAM_CHUNK: &str = "rpc.stream.chunk";
+
+/// Method name for the end of a [stream](crate::stream).
+pub const STREAM_END: &str = "rpc.stream.end";
+
+type Sink = dyn Fn(Request) + Send + Sync;
+
+/// Handle for sending notifications.
+///
+/// Cloned notifiers share the same sink.
+#[derive(Clone)]
+pub struct Notifier {
+    sink: Arc<Sink>,
+}
+
+impl Notifier {
+    /// Create a notifier that passes notifications to a sink.
+    pub fn new<F>(sink: F) -> Self
+    where
+        F: Fn(Request) + Send + Sync + 'static,
+    {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Create a notifier and the receiver for the notifications.
+    pub fn channel() -> (Self, mpsc::Receiver<Request>) {
+        let (tx, rx) = mpsc::channel();
+        let notifier = Notifier::new(move |notification| {
+            let _ = tx.send(notification);
+        });
+        (notifier, rx)
+    }
+
+    /// Send a notification.
+    pub fn notify(&self, method: &str, params: Option<Value>) {
+        (self.sink)(Request::new_notification(method, params));
+    }
+
+    /// Create a progress handle for a request.
+    pub fn progress(&self, request: &Request) -> Progress {
+        Progress {
+            id: request.id().clone(),
+            notifier: self.clone(),
+        }
+    }
+}
+
+/// Parameters for the `rpc.progress` notification.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct ProgressParams {
+    /// The id of the request that is making progress.
+    pub id: Value,
+    /// Percentage complete between zero and one hundred.
+    pub percent: u8,
+    /// Message describing the current progress.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Reports progress for a pending request.
+pub struct Progress {
+    id: Option<Value>,
+    notifier: Notifier,
+}
+
+impl Progress {
+    /// The id of the request.
+    pub fn id(&self) -> &Option<Value> {
+        &self.id
+    }
+
+    /// Send a progress notification.
+    ///
+    /// Values of `percent` above one hundred are clamped; a request
+    /// without an id cannot be correlated so nothing is sent.
+    pub fn report(&self, percent: u8, message: &str) {
+        if let Some(id) = &self.id {
+            let params = ProgressParams {
+                id: id.clone(),
+                percent: percent.min(100),
+                message: if message.is_empty() {
+                    None
+                } else {
+                    Some(message.to_string())
+                },
+            };
+            self.notifier
+                .notify(PROGRESS, serde_json::to_value(params).ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Response, Result, Server, Service};
+
+    struct CountService;
+    impl Service for CountService {
+        type Data = Notifier;
+        fn handle(
+            &self,
+            request: &Request,
+            ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "count" => {
+                    let progress = ctx.progress(request);
+                    progress.report(50, "half way");
+                    progress.report(101, "");
+                    Ok(Some((request, Value::Bool(true)).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[test]
+    fn progress_reports() {
+        let service: Box<dyn Service<Data = Notifier>> = Box::new(CountService);
+        let server = Server::new(vec![&service]);
+        let (notifier, rx) = Notifier::channel();
+        let request =
+            Request::new(Some(Value::from(3)), "count".to_string(), None);
+        server.serve(&request, &notifier);
+
+        let reports: Vec<ProgressParams> = rx
+            .try_iter()
+            .map(|n| {
+                assert_eq!(PROGRESS, n.method());
+                n.deserialize().unwrap()
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ProgressParams {
+                    id: Value::from(3),
+                    percent: 50,
+                    message: Some("half way".to_string()),
+                },
+                ProgressParams {
+                    id: Value::from(3),
+                    percent: 100,
+                    message: None,
+                },
+            ],
+            reports
+        );
+    }
+
+    #[test]
+    fn progress_notification_ignored() {
+        let service: Box<dyn Service<Data = Notifier>> = Box::new(CountService);
+        let server = Server::new(vec![&service]);
+        let (notifier, rx) = Notifier::channel();
+        server.serve(&Request::new_notification("count", None), &notifier);
+        assert_eq!(0, rx.try_iter().count());
+    }
+}
@@ -0,0 +1,342 @@
+//! Funnel every outgoing message of a connection through one writer,
+//! requires the `net` feature.
+//!
+//! When a connection carries both the responses of a server and the
+//! requests and notifications it sends to the peer, writes from several
+//! tasks interleave and corrupt the frames. An
+//! [OutboundWriter](OutboundWriter) owns the write half of the
+//! connection and writes the [messages](crate::peer::Message) queued on
+//! a bounded channel one at a time; the cloneable [Outbound](Outbound)
+//! handle gives every producer its way in:
+//!
+//! * [responder()](Outbound::responder) for the `write` callback of
+//!   [serve_until()](crate::futures::serve_until).
+//! * [notifier()](Outbound::notifier) for a
+//!   [Notifier](crate::notify::Notifier).
+//! * [sender()](Outbound::sender) for the outbound channel of a
+//!   [MultiplexClient](crate::multiplex::MultiplexClient).
+//!
+//! ```
+//! use json_rpc2::{outbound::{Framing, OutboundWriter}, Request, Response};
+//! use serde_json::json;
+//! use tokio::io::AsyncReadExt;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let (connection, mut peer) = tokio::io::duplex(1024);
+//! let (writer, outbound) = OutboundWriter::new(connection, 16);
+//! let writing = tokio::spawn(writer.framing(Framing::Lines).run());
+//!
+//! let request = Request::new_reply("ping", None);
+//! let response: Response = (&request, json!("pong")).into();
+//! (outbound.responder())(response);
+//! outbound.notifier().notify("ready", None);
+//! drop(outbound);
+//! assert_eq!(2, writing.await.unwrap()?);
+//!
+//! let mut received = String::new();
+//! peer.read_to_string(&mut received).await?;
+//! assert_eq!(2, received.lines().count());
+//! # Ok::<(), std::io::Error>(())
+//! # }).unwrap();
+//! ```
+//!
+//! The channel applies backpressure to [send()](Outbound::send) and to
+//! the outbound calls of a client. The serve loop and notifier write
+//! from synchronous callbacks so they cannot wait: a response that does
+//! not fit is queued from a spawned task and a notification that does
+//! not fit is dropped. The writer stops when every handle is dropped or
+//! a write fails.
+
+use crate::{
+    notify::Notifier, peer::Message, truncate, Error, Response, Result,
+};
+use std::io;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// How messages are delimited on the connection.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Framing {
+    /// Every message on a single line.
+    #[default]
+    Lines,
+    /// Every message after a `Content-Length` header, as used by the
+    /// language server protocol.
+    ContentLength,
+}
+
+/// When buffered messages are flushed to the connection.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Flush {
+    /// Flush after every message.
+    #[default]
+    PerMessage,
+    /// Write the messages already queued and flush once the queue is
+    /// empty.
+    Coalesced,
+}
+
+/// Cloneable handle for queueing messages on an
+/// [OutboundWriter](OutboundWriter).
+#[derive(Clone)]
+pub struct Outbound {
+    sender: mpsc::Sender<Message>,
+}
+
+impl Outbound {
+    /// Queue a message, waiting while the channel is full.
+    ///
+    /// Fails with `Error::ConnectionLost` once the writer has stopped.
+    pub async fn send(&self, message: Message) -> Result<()> {
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| Error::ConnectionLost)
+    }
+
+    /// The channel of the writer, for the outbound calls of a
+    /// [MultiplexClient](crate::multiplex::MultiplexClient).
+    pub fn sender(&self) -> mpsc::Sender<Message> {
+        self.sender.clone()
+    }
+
+    /// Notifier writing to the connection.
+    ///
+    /// Notifications are dropped when the channel is full.
+    pub fn notifier(&self) -> Notifier {
+        let sender = self.sender.clone();
+        Notifier::new(move |notification| {
+            let _ = sender.try_send(Message::Request(notification));
+        })
+    }
+
+    /// Callback writing responses for
+    /// [serve_until()](crate::futures::serve_until).
+    ///
+    /// A response that does not fit in the channel is queued from a
+    /// spawned task so it is never dropped but may be written after
+    /// later responses; must be called within a tokio runtime.
+    pub fn responder(&self) -> impl FnMut(Response) + Send + 'static {
+        let sender = self.sender.clone();
+        move |response| {
+            if let Err(TrySendError::Full(message)) =
+                sender.try_send(Message::Response(response))
+            {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let _ = sender.send(message).await;
+                });
+            }
+        }
+    }
+}
+
+/// Writes the queued messages to a connection one at a time.
+pub struct OutboundWriter<W> {
+    writer: W,
+    messages: mpsc::Receiver<Message>,
+    framing: Framing,
+    flush: Flush,
+    max_response: Option<usize>,
+}
+
+impl<W: AsyncWrite + Unpin> OutboundWriter<W> {
+    /// Create a writer for a connection with a channel holding at most
+    /// `capacity` messages and the handle for queueing them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(writer: W, capacity: usize) -> (Self, Outbound) {
+        let (sender, messages) = mpsc::channel(capacity);
+        let writer = Self {
+            writer,
+            messages,
+            framing: Default::default(),
+            flush: Default::default(),
+            max_response: None,
+        };
+        (writer, Outbound { sender })
+    }
+
+    /// Set how messages are delimited, one per line by default.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Set when messages are flushed, after every message by default.
+    pub fn flush(mut self, flush: Flush) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    /// Replace responses larger than `max` bytes when serialized with
+    /// an internal error, usually the
+    /// [limit of the server](crate::futures::Server::max_response_size).
+    ///
+    /// Requests and batches are written whatever their size.
+    pub fn max_response_size(mut self, max: usize) -> Self {
+        self.max_response = Some(max);
+        self
+    }
+
+    /// Write messages until every handle is dropped, returning the
+    /// number of messages written.
+    ///
+    /// Stops at the first failed write; the handles then fail to send.
+    pub async fn run(mut self) -> io::Result<usize> {
+        let (framing, max) = (self.framing, self.max_response);
+        let mut writer = BufWriter::new(self.writer);
+        let mut written = 0;
+        while let Some(message) = self.messages.recv().await {
+            write_frame(&mut writer, framing, max, &message).await?;
+            written += 1;
+            if self.flush == Flush::Coalesced {
+                while let Ok(message) = self.messages.try_recv() {
+                    write_frame(&mut writer, framing, max, &message).await?;
+                    written += 1;
+                }
+            }
+            writer.flush().await?;
+        }
+        writer.flush().await?;
+        Ok(written)
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    framing: Framing,
+    max: Option<usize>,
+    message: &Message,
+) -> io::Result<()> {
+    let body = match message {
+        Message::Response(response) => {
+            truncate::to_vec(response, response.id(), max)?
+        }
+        _ => serde_json::to_vec(message)?,
+    };
+    match framing {
+        Framing::Lines => {
+            writer.write_all(&body).await?;
+            writer.write_all(b"\n").await
+        }
+        Framing::ContentLength => {
+            let header = format!("Content-Length: {}\r\n\r\n", body.len());
+            writer.write_all(header.as_bytes()).await?;
+            writer.write_all(&body).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{peer::parse_message_str, Request};
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn outbound_concurrent_writers() {
+        let (connection, mut peer) = tokio::io::duplex(64);
+        let (writer, outbound) = OutboundWriter::new(connection, 4);
+        let writing = tokio::spawn(writer.flush(Flush::Coalesced).run());
+        let reading = tokio::spawn(async move {
+            let mut received = String::new();
+            peer.read_to_string(&mut received).await.unwrap();
+            received
+        });
+
+        let mut tasks = Vec::new();
+        for index in 0..20 {
+            let outbound = outbound.clone();
+            tasks.push(tokio::spawn(async move {
+                let request = Request::new_reply("call", Some(json!([index])));
+                let response = (&request, json!("x".repeat(100))).into();
+                (outbound.responder())(response);
+                outbound.send(Message::Request(request)).await.unwrap();
+                let client = outbound.sender();
+                let notification = Request::new_notification("n", None);
+                client.send(Message::Request(notification)).await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        drop(outbound);
+        assert_eq!(60, writing.await.unwrap().unwrap());
+
+        let received = reading.await.unwrap();
+        let messages: Vec<Message> = received
+            .lines()
+            .map(|line| parse_message_str(line).unwrap())
+            .collect();
+        assert_eq!(60, messages.len());
+        let responses = messages
+            .iter()
+            .filter(|message| matches!(message, Message::Response(_)))
+            .count();
+        assert_eq!(20, responses);
+    }
+
+    #[tokio::test]
+    async fn outbound_content_length() {
+        let (connection, mut peer) = tokio::io::duplex(1024);
+        let (writer, outbound) = OutboundWriter::new(connection, 1);
+        let writing =
+            tokio::spawn(writer.framing(Framing::ContentLength).run());
+        outbound.notifier().notify("ready", None);
+        drop(outbound);
+        assert_eq!(1, writing.await.unwrap().unwrap());
+
+        let mut received = String::new();
+        peer.read_to_string(&mut received).await.unwrap();
+        let (header, body) = received.split_once("\r\n\r\n").unwrap();
+        assert_eq!(format!("Content-Length: {}", body.len()), header);
+        match parse_message_str(body).unwrap() {
+            Message::Request(request) => assert_eq!("ready", request.method()),
+            message => panic!("expected a notification, got {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn outbound_max_response_size() {
+        let (connection, mut peer) = tokio::io::duplex(1024);
+        let (writer, outbound) = OutboundWriter::new(connection, 4);
+        let writing = tokio::spawn(writer.max_response_size(64).run());
+        let params = Some(json!(["x".repeat(100)]));
+        let request = Request::new(Some(json!(1)), "call".to_string(), params);
+        let response = (&request, json!("x".repeat(100))).into();
+        outbound.send(Message::Response(response)).await.unwrap();
+        outbound.send(Message::Request(request)).await.unwrap();
+        drop(outbound);
+        assert_eq!(2, writing.await.unwrap().unwrap());
+
+        let mut received = String::new();
+        peer.read_to_string(&mut received).await.unwrap();
+        let mut lines = received.lines().map(|l| parse_message_str(l).unwrap());
+        match lines.next().unwrap() {
+            Message::Response(response) => {
+                let error = response.error().as_ref().unwrap();
+                assert_eq!(Some(json!({"size": 136, "limit": 64})), error.data);
+            }
+            message => panic!("expected a response, got {:?}", message),
+        }
+        assert!(matches!(lines.next(), Some(Message::Request(_))));
+    }
+
+    #[tokio::test]
+    async fn outbound_writer_stopped() {
+        let (connection, peer) = tokio::io::duplex(64);
+        drop(peer);
+        let (writer, outbound) = OutboundWriter::new(connection, 1);
+        outbound.notifier().notify("lost", None);
+        assert!(writer.run().await.is_err());
+        let notification = Request::new_notification("late", None);
+        assert!(matches!(
+            outbound.send(Message::Request(notification)).await,
+            Err(Error::ConnectionLost)
+        ));
+    }
+}
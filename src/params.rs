@@ -0,0 +1,261 @@
+//! Positional and named parameters.
+//!
+//! Many APIs accept both calling conventions for a method, an array of
+//! positional parameters or an object of named parameters.
+//! [Request::params_kind()](crate::Request::params_kind) tells them
+//! apart and
+//! [Request::deserialize_either()](crate::Request::deserialize_either)
+//! converts either into one type, reading named parameters with a
+//! struct and positional parameters with a tuple:
+//!
+//! ```
+//! use json_rpc2::Request;
+//! use serde::Deserialize;
+//! use serde_json::json;
+//!
+//! #[derive(Deserialize)]
+//! struct Transfer {
+//!     to: String,
+//!     amount: u64,
+//! }
+//!
+//! impl From<(String, u64)> for Transfer {
+//!     fn from((to, amount): (String, u64)) -> Self {
+//!         Self { to, amount }
+//!     }
+//! }
+//!
+//! let named = Request::new_reply(
+//!     "transfer",
+//!     Some(json!({"to": "alice", "amount": 5})),
+//! );
+//! let positional = Request::new_reply("transfer", Some(json!(["bob", 7])));
+//! let first: Transfer = named.deserialize_either::<_, (String, u64)>()?;
+//! let second: Transfer = positional.deserialize_either::<_, (String, u64)>()?;
+//! assert_eq!(("alice", 5), (first.to.as_str(), first.amount));
+//! assert_eq!(("bob", 7), (second.to.as_str(), second.amount));
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+
+use crate::{deserialize_params, Error, Request, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+/// Parameters of a request by calling convention.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Params {
+    /// Positional parameters.
+    Array(Vec<Value>),
+    /// Named parameters.
+    Map(Map<String, Value>),
+    /// No parameters.
+    None,
+}
+
+impl Params {
+    /// Determine if the parameters are positional.
+    pub fn is_positional(&self) -> bool {
+        matches!(self, Params::Array(_))
+    }
+
+    /// Determine if the parameters are named.
+    pub fn is_named(&self) -> bool {
+        matches!(self, Params::Map(_))
+    }
+
+    /// Convert into the `params` of a request.
+    pub fn into_value(self) -> Option<Value> {
+        self.into()
+    }
+}
+
+impl From<Params> for Option<Value> {
+    fn from(params: Params) -> Self {
+        match params {
+            Params::Array(items) => Some(Value::Array(items)),
+            Params::Map(map) => Some(Value::Object(map)),
+            Params::None => None,
+        }
+    }
+}
+
+impl From<Vec<Value>> for Params {
+    fn from(items: Vec<Value>) -> Self {
+        Params::Array(items)
+    }
+}
+
+impl From<Map<String, Value>> for Params {
+    fn from(map: Map<String, Value>) -> Self {
+        Params::Map(map)
+    }
+}
+
+/// Which convention [deserialize_either_with()](Request::deserialize_either_with)
+/// tries first.
+///
+/// Derived structs also read arrays of their fields in order, so the
+/// order matters when both types accept the parameters.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ParamsOrder {
+    /// Try the named type and then the positional type.
+    #[default]
+    NamedFirst,
+    /// Try the positional type and then the named type.
+    PositionalFirst,
+}
+
+impl Request {
+    /// Create a request that expects a reply with structured parameters.
+    pub fn new_reply_params(method: &str, params: Params) -> Self {
+        Request::new_reply(method, params.into())
+    }
+
+    /// The parameters by calling convention.
+    ///
+    /// Parameters that are neither an array nor an object, which the
+    /// specification does not allow, yield `Params::None`.
+    pub fn params_kind(&self) -> Params {
+        match self.params() {
+            Some(Value::Array(items)) => Params::Array(items.clone()),
+            Some(Value::Object(map)) => Params::Map(map.clone()),
+            _ => Params::None,
+        }
+    }
+
+    /// Deserialize named parameters into `T` or positional parameters
+    /// into `P` converted to `T`, trying the named type first.
+    ///
+    /// See [deserialize_either_with()](Self::deserialize_either_with).
+    pub fn deserialize_either<T, P>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+        P: DeserializeOwned + Into<T>,
+    {
+        self.deserialize_either_with::<T, P>(ParamsOrder::NamedFirst)
+    }
+
+    /// Deserialize named parameters into `T` or positional parameters
+    /// into `P` converted to `T`, trying them in `order`.
+    ///
+    /// When both fail `Error::InvalidParams` includes the errors of both
+    /// attempts.
+    pub fn deserialize_either_with<T, P>(&self, order: ParamsOrder) -> Result<T>
+    where
+        T: DeserializeOwned,
+        P: DeserializeOwned + Into<T>,
+    {
+        let params = match self.params() {
+            Some(params) => params,
+            None => return self.deserialize(),
+        };
+        let attempt = |named: bool| {
+            if named {
+                deserialize_params::<T, _>(self, params, None)
+            } else {
+                deserialize_params::<P, _>(self, params, None).map(Into::into)
+            }
+        };
+        let named_first = order == ParamsOrder::NamedFirst;
+        let first_error = match attempt(named_first) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let second_error = match attempt(!named_first) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let (named_error, positional_error) = if named_first {
+            (first_error, second_error)
+        } else {
+            (second_error, first_error)
+        };
+        Err(Error::InvalidParams {
+            id: self.id().clone(),
+            data: format!(
+                "as named params: {}; as positional params: {}",
+                params_error(named_error),
+                params_error(positional_error)
+            )
+            .into(),
+        })
+    }
+}
+
+fn params_error(error: Error) -> String {
+    match error {
+        Error::InvalidParams { data, .. } => data.into(),
+        error => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Range {
+        start: u64,
+        end: u64,
+    }
+
+    impl From<(u64, u64)> for Range {
+        fn from((start, end): (u64, u64)) -> Self {
+            Self { start, end }
+        }
+    }
+
+    #[test]
+    fn params_kind() {
+        let request = Request::new_reply("range", Some(json!([1, 2])));
+        assert_eq!(
+            Params::Array(vec![json!(1), json!(2)]),
+            request.params_kind()
+        );
+        assert!(request.params_kind().is_positional());
+
+        let map = json!({"start": 1}).as_object().unwrap().clone();
+        let request = Request::new_reply_params("range", Params::from(map));
+        assert!(request.params_kind().is_named());
+        assert_eq!(Some(json!({"start": 1})), *request.params());
+
+        let request = Request::new_reply_params("range", Params::None);
+        assert_eq!(Params::None, request.params_kind());
+        assert!(!request.has_params());
+    }
+
+    #[test]
+    fn deserialize_either() {
+        let named =
+            Request::new_reply("range", Some(json!({"start": 1, "end": 2})));
+        let range: Range = named.deserialize_either::<_, (u64, u64)>().unwrap();
+        assert_eq!(Range { start: 1, end: 2 }, range);
+
+        let positional = Request::new_reply("range", Some(json!([3, 4])));
+        let range: Range = positional
+            .deserialize_either_with::<_, (u64, u64)>(
+                ParamsOrder::PositionalFirst,
+            )
+            .unwrap();
+        assert_eq!(Range { start: 3, end: 4 }, range);
+
+        let invalid =
+            Request::new_reply("range", Some(json!({"start": "one"})));
+        match invalid.deserialize_either::<Range, (u64, u64)>() {
+            Err(Error::InvalidParams { data, .. }) => {
+                assert!(data.starts_with("as named params: params.start"));
+                assert!(data.contains("; as positional params: "), "{}", data);
+            }
+            result => panic!("expected invalid params, got {:?}", result),
+        }
+
+        let missing = Request::new_reply("range", None);
+        assert!(matches!(
+            missing.deserialize_either::<Range, (u64, u64)>(),
+            Err(Error::InvalidParams { .. })
+        ));
+    }
+}
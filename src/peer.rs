@@ -0,0 +1,252 @@
+//! Messages exchanged over a bidirectional connection.
+//!
+//! When both sides of a connection send requests a payload may be a
+//! request, a notification, a response or a batch of those. Parse it
+//! into a [Message](Message) with [parse_message_str()](parse_message_str),
+//! [parse_message_slice()](parse_message_slice) or
+//! [parse_message_value()](parse_message_value):
+//!
+//! * An object with a `method` is a request or notification.
+//! * Any other object with a `result` or `error` is a response.
+//! * A non-empty array is a batch of requests and responses.
+//! * Anything else, including an empty or nested batch, is an invalid
+//!   request.
+//!
+//! Errors are mapped the same way as [from_str()](crate::from_str) and
+//! requests are validated, an invalid element fails the whole batch.
+//!
+//! ```
+//! use json_rpc2::peer::{parse_message_str, Message};
+//!
+//! let payload = r#"[
+//!     {"jsonrpc": "2.0", "id": 1, "result": 7},
+//!     {"jsonrpc": "2.0", "method": "progress", "params": [50]}
+//! ]"#;
+//! match parse_message_str(payload)? {
+//!     Message::Batch(messages) => {
+//!         assert!(matches!(messages[0], Message::Response(_)));
+//!         assert!(matches!(messages[1], Message::Request(_)));
+//!     }
+//!     _ => unreachable!(),
+//! }
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+
+use crate::{
+    map_json_error, map_payload_error, recover_id, validate, Request, Response,
+    Result,
+};
+use serde::{
+    de::{
+        self, value::MapAccessDeserializer, DeserializeSeed, MapAccess,
+        SeqAccess, Unexpected, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Message received from a peer.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Message {
+    /// Request or notification.
+    Request(Request),
+    /// Response to a request sent to the peer.
+    Response(Response),
+    /// Batch of requests and responses.
+    Batch(Vec<Message>),
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        MessageVisitor { nested: false }.deserialize(deserializer)
+    }
+}
+
+/// Classifies a message, elements of a batch may not be batches.
+#[derive(Clone, Copy)]
+struct MessageVisitor {
+    nested: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for MessageVisitor {
+    type Value = Message;
+
+    fn deserialize<D>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Message, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for MessageVisitor {
+    type Value = Message;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.nested {
+            f.write_str("a request or response")
+        } else {
+            f.write_str("a request, response or batch")
+        }
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Message, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        if self.nested {
+            return Err(de::Error::invalid_type(Unexpected::Seq, &self));
+        }
+        let element = MessageVisitor { nested: true };
+        let mut messages = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(message) = seq.next_element_seed(element)? {
+            messages.push(message);
+        }
+        if messages.is_empty() {
+            return Err(de::Error::invalid_length(0, &"a non-empty batch"));
+        }
+        Ok(Message::Batch(messages))
+    }
+
+    fn visit_map<A>(self, map: A) -> std::result::Result<Message, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let object = Map::deserialize(MapAccessDeserializer::new(map))?;
+        let message = if object.contains_key("method") {
+            <Request as Deserialize>::deserialize(Value::Object(object))
+                .map(Message::Request)
+        } else if object.contains_key("result") || object.contains_key("error")
+        {
+            <Response as Deserialize>::deserialize(Value::Object(object))
+                .map(Message::Response)
+        } else {
+            return Err(de::Error::custom(
+                "message must have a method, result or error",
+            ));
+        };
+        message.map_err(de::Error::custom)
+    }
+}
+
+/// Validate every request of a message.
+fn validate_message(message: Message) -> Result<Message> {
+    match message {
+        Message::Request(request) => validate(request).map(Message::Request),
+        Message::Response(_) => Ok(message),
+        Message::Batch(messages) => messages
+            .into_iter()
+            .map(validate_message)
+            .collect::<Result<Vec<_>>>()
+            .map(Message::Batch),
+    }
+}
+
+/// Parse a message from a string slice.
+pub fn parse_message_str(payload: &str) -> Result<Message> {
+    parse_message_slice(payload.as_bytes())
+}
+
+/// Parse a message from a byte slice.
+pub fn parse_message_slice(payload: &[u8]) -> Result<Message> {
+    serde_json::from_slice::<Message>(payload)
+        .map_err(|e| map_payload_error(e, payload))
+        .and_then(validate_message)
+}
+
+/// Parse a message from a [Value](serde_json::Value).
+pub fn parse_message_value(payload: Value) -> Result<Message> {
+    let id = recover_id(&payload);
+    serde_json::from_value::<Message>(payload)
+        .map_err(|e| map_json_error(e, None, || id))
+        .and_then(validate_message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+    use serde_json::json;
+
+    #[test]
+    fn peer_classify() -> Result<()> {
+        let message = parse_message_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"sum","params":[1,2]}"#,
+        )?;
+        assert!(matches!(message, Message::Request(r) if r.method() == "sum"));
+
+        let message =
+            parse_message_str(r#"{"jsonrpc":"2.0","method":"progress"}"#)?;
+        assert!(matches!(message, Message::Request(r) if r.id().is_none()));
+
+        let message = parse_message_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": {"code": -32601, "message": "Method not found"},
+        }))?;
+        assert!(matches!(message, Message::Response(r) if r.error().is_some()));
+
+        let message = parse_message_slice(
+            br#"[{"jsonrpc":"2.0","id":"a","result":null},
+                 {"jsonrpc":"2.0","method":"ping","id":2}]"#,
+        )?;
+        match message {
+            Message::Batch(messages) => {
+                assert_eq!(2, messages.len());
+                assert!(matches!(messages[0], Message::Response(_)));
+                assert!(matches!(messages[1], Message::Request(_)));
+            }
+            _ => panic!("expected a batch"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn peer_invalid() {
+        for payload in &[
+            r#"{"jsonrpc":"2.0","id":1}"#,
+            "[]",
+            r#"[[{"jsonrpc":"2.0","method":"ping"}]]"#,
+            "42",
+            r#"{"jsonrpc":"2.0","id":[1],"method":"ping"}"#,
+            r#"[{"jsonrpc":"2.0","method":"ping","params":1}]"#,
+        ] {
+            assert!(
+                matches!(
+                    parse_message_str(payload),
+                    Err(Error::InvalidRequest { .. })
+                ),
+                "{}",
+                payload
+            );
+        }
+
+        match parse_message_str(r#"{"jsonrpc":"2.0","id":7,"params":{}}"#) {
+            Err(Error::InvalidRequest { id, line, .. }) => {
+                assert_eq!(Some(json!(7)), id);
+                assert_eq!(Some(1), line);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_message_str(r#"[{"jsonrpc":"2.0","#),
+            Err(Error::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn peer_round_trip() -> Result<()> {
+        let payload = r#"[{"jsonrpc":"2.0","method":"ping","id":1},{"jsonrpc":"2.0","id":2,"result":true}]"#;
+        let message = parse_message_str(payload)?;
+        assert_eq!(payload, serde_json::to_string(&message).unwrap());
+        Ok(())
+    }
+}
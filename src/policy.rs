@@ -0,0 +1,238 @@
+//! Classify error codes for retries, logging and status mapping.
+//!
+//! An [ErrorPolicy](ErrorPolicy) maps each error code, or range of
+//! codes, to a [Category](Category) and a log level. Set a policy on a
+//! server with [with_policy()](crate::Server::with_policy) so transports
+//! read the same decisions from
+//! [server.policy()](crate::Server::policy), and pass it to
+//! [Logged::policy()](crate::logged::Logged::policy) to log error
+//! responses at the configured level.
+//!
+//! The default policy treats the spec codes for bad requests and a
+//! cancelled request as client faults, the internal error and any
+//! unknown code as a server fault and the implementation defined
+//! server error range `-32099..=-32000`, used for busy, overloaded and
+//! deadline exceeded errors, as retryable.
+//!
+//! The policy also sets the codes of `Error::Timeout` and
+//! `Error::Cancelled` in responses, `-32000` and `-32800` by default:
+//!
+//! ```
+//! use json_rpc2::{policy::ErrorPolicy, Error};
+//!
+//! let policy = ErrorPolicy::default().timeout_code(-32050);
+//! let error = Error::Timeout { method: None, elapsed: None, id: None };
+//! assert_eq!(-32050, policy.rpc_error(error).code);
+//! ```
+
+use crate::{
+    cancel::REQUEST_CANCELLED, deadline::DEADLINE_EXCEEDED, Error, RpcError,
+    INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND,
+    PARSE_ERROR,
+};
+use log::Level;
+use std::ops::RangeInclusive;
+
+/// How an error should be treated by the caller.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Category {
+    /// The request may succeed if sent again later.
+    Retryable,
+    /// The request is at fault and should not be sent again unchanged.
+    ClientFault,
+    /// The server failed to handle a valid request.
+    ServerFault,
+}
+
+/// Table of categories and log levels for error codes.
+#[derive(Debug, Clone)]
+pub struct ErrorPolicy {
+    rules: Vec<(RangeInclusive<isize>, Category, Level)>,
+    fallback: (Category, Level),
+    timeout_code: isize,
+    cancelled_code: isize,
+}
+
+impl ErrorPolicy {
+    /// Create a policy without any rules that treats every code as a
+    /// server fault logged at error level.
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            fallback: (Category::ServerFault, Level::Error),
+            timeout_code: DEADLINE_EXCEEDED,
+            cancelled_code: REQUEST_CANCELLED,
+        }
+    }
+
+    /// Set the category and log level for a code.
+    ///
+    /// Rules added later take precedence over earlier rules.
+    pub fn code(self, code: isize, category: Category, level: Level) -> Self {
+        self.range(code..=code, category, level)
+    }
+
+    /// Set the category and log level for a range of codes.
+    ///
+    /// Rules added later take precedence over earlier rules.
+    pub fn range(
+        mut self,
+        codes: RangeInclusive<isize>,
+        category: Category,
+        level: Level,
+    ) -> Self {
+        self.rules.push((codes, category, level));
+        self
+    }
+
+    /// Set the category and log level for codes that match no rule.
+    pub fn fallback(mut self, category: Category, level: Level) -> Self {
+        self.fallback = (category, level);
+        self
+    }
+
+    /// Set the code of `Error::Timeout` in responses.
+    pub fn timeout_code(mut self, code: isize) -> Self {
+        self.timeout_code = code;
+        self
+    }
+
+    /// Set the code of `Error::Cancelled` in responses.
+    pub fn cancelled_code(mut self, code: isize) -> Self {
+        self.cancelled_code = code;
+        self
+    }
+
+    /// Convert an error for a response using the codes of the policy.
+    pub fn rpc_error(&self, error: Error) -> RpcError {
+        let code = match &error {
+            Error::Timeout { .. } => Some(self.timeout_code),
+            Error::Cancelled { .. } => Some(self.cancelled_code),
+            _ => None,
+        };
+        let mut error = RpcError::from(error);
+        if let Some(code) = code {
+            error.code = code;
+        }
+        error
+    }
+
+    fn rule(&self, code: isize) -> (Category, Level) {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(codes, _, _)| codes.contains(&code))
+            .map(|(_, category, level)| (*category, *level))
+            .unwrap_or(self.fallback)
+    }
+
+    /// The category of an error code.
+    pub fn categorize(&self, code: isize) -> Category {
+        self.rule(code).0
+    }
+
+    /// The level to log an error code at.
+    pub fn level(&self, code: isize) -> Level {
+        self.rule(code).1
+    }
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self::new()
+            .range(-32099..=-32000, Category::Retryable, Level::Warn)
+            .code(PARSE_ERROR, Category::ClientFault, Level::Info)
+            .code(INVALID_REQUEST, Category::ClientFault, Level::Info)
+            .code(METHOD_NOT_FOUND, Category::ClientFault, Level::Info)
+            .code(INVALID_PARAMS, Category::ClientFault, Level::Info)
+            .code(REQUEST_CANCELLED, Category::ClientFault, Level::Debug)
+            .code(INTERNAL_ERROR, Category::ServerFault, Level::Error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{shed::SERVER_BUSY, Request, Response, Result, Server};
+
+    #[test]
+    fn policy_defaults() {
+        let policy = ErrorPolicy::default();
+        assert_eq!(Category::ClientFault, policy.categorize(METHOD_NOT_FOUND));
+        assert_eq!(Category::ServerFault, policy.categorize(INTERNAL_ERROR));
+        assert_eq!(Category::Retryable, policy.categorize(SERVER_BUSY));
+        assert_eq!(Category::Retryable, policy.categorize(-32099));
+        assert_eq!(Category::ServerFault, policy.categorize(42));
+        assert_eq!(Level::Error, policy.level(42));
+        assert_eq!(Level::Debug, policy.level(REQUEST_CANCELLED));
+    }
+
+    #[test]
+    fn policy_server_override() {
+        struct Nothing;
+        impl crate::Service for Nothing {
+            type Data = ();
+            fn handle(
+                &self,
+                _request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                Ok(None)
+            }
+        }
+
+        let service: Box<dyn crate::Service<Data = ()>> = Box::new(Nothing);
+        let server = Server::new(vec![&service]).with_policy(
+            ErrorPolicy::default()
+                .code(-32001, Category::ClientFault, Level::Debug)
+                .range(1000..=1999, Category::Retryable, Level::Info),
+        );
+        let policy = server.policy();
+        assert_eq!(Category::ClientFault, policy.categorize(-32001));
+        assert_eq!(Category::Retryable, policy.categorize(-32002));
+        assert_eq!(Category::Retryable, policy.categorize(1500));
+        assert_eq!(Level::Info, policy.level(1500));
+    }
+
+    #[test]
+    fn policy_timeout_cancelled_codes() {
+        struct Expire;
+        impl crate::Service for Expire {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                let id = request.id().clone();
+                Err(match request.method() {
+                    "timeout" => Error::Timeout {
+                        method: None,
+                        elapsed: None,
+                        id,
+                    },
+                    _ => Error::Cancelled { id },
+                })
+            }
+        }
+
+        let service: Box<dyn crate::Service<Data = ()>> = Box::new(Expire);
+        let code = |server: &Server<()>, method: &str| {
+            let request = Request::new_reply(method, None);
+            let response = server.serve(&request, &()).unwrap();
+            assert_eq!(request.id(), response.id());
+            response.error().as_ref().unwrap().code
+        };
+        let server = Server::new(vec![&service]);
+        assert_eq!(DEADLINE_EXCEEDED, code(&server, "timeout"));
+        assert_eq!(REQUEST_CANCELLED, code(&server, "cancel"));
+
+        let server = Server::new(vec![&service]).with_policy(
+            ErrorPolicy::default()
+                .timeout_code(-32050)
+                .cancelled_code(-32051),
+        );
+        assert_eq!(-32050, code(&server, "timeout"));
+        assert_eq!(-32051, code(&server, "cancel"));
+    }
+}
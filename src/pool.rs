@@ -0,0 +1,259 @@
+//! Serve requests from a pool of worker threads.
+//!
+//! For blocking servers that need to handle several connections
+//! without an async runtime, share a server between threads using a
+//! [ThreadPoolServer](ThreadPoolServer):
+//!
+//! ```
+//! use json_rpc2::{pool::ThreadPoolServer, *};
+//! use serde_json::Value;
+//! use std::sync::Arc;
+//!
+//! struct Echo;
+//! impl Service for Echo {
+//!     type Data = ();
+//!     fn handle(
+//!         &self,
+//!         request: &Request,
+//!         _ctx: &Self::Data,
+//!     ) -> Result<Option<Response>> {
+//!         let params = request.params().clone().unwrap_or(Value::Null);
+//!         Ok(Some((request, params).into()))
+//!     }
+//! }
+//!
+//! let service: Arc<dyn Service<Data = ()>> = Arc::new(Echo);
+//! let server = Arc::new(Server::new_shared(vec![service]));
+//! let pool = ThreadPoolServer::new(server, (), 2);
+//! let request = Request::new_reply("echo", Some(Value::from(1)));
+//! let response = pool.submit(request).recv().unwrap();
+//! assert_eq!(Some(Value::from(1)), response.unwrap().into());
+//! pool.shutdown();
+//! ```
+
+use crate::{shutdown::ServedStats, Error, Request, Response, Server};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+type Job = (Request, mpsc::Sender<Option<Response>>);
+
+/// Serve requests using a fixed number of worker threads.
+///
+/// Requests are queued and handled by the first idle worker. A handler
+/// that panics yields an internal error response and does not take
+/// down the worker.
+///
+/// Dropping the pool performs an orderly [shutdown](Self::shutdown).
+pub struct ThreadPoolServer {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    stats: Arc<Mutex<ServedStats>>,
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ThreadPoolServer {
+    /// Create a pool and start the worker threads.
+    ///
+    /// At least one worker is always started.
+    pub fn new<T: Send + Sync + 'static>(
+        server: Arc<Server<'static, T>>,
+        ctx: T,
+        num_threads: usize,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let ctx = Arc::new(ctx);
+        let stats = Arc::new(Mutex::new(ServedStats::default()));
+        let deadline = Arc::new(Mutex::new(None));
+        let workers = (0..num_threads.max(1))
+            .map(|_| {
+                let server = Arc::clone(&server);
+                let ctx = Arc::clone(&ctx);
+                let receiver = Arc::clone(&receiver);
+                let stats = Arc::clone(&stats);
+                let deadline = Arc::clone(&deadline);
+                thread::spawn(move || loop {
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => break,
+                    };
+                    let (request, reply) = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    if expired(&deadline) {
+                        // Dropping the reply disconnects the receiver.
+                        continue;
+                    }
+                    let response = serve(&server, &request, &ctx);
+                    if let Ok(mut stats) = stats.lock() {
+                        stats.record(response.as_ref());
+                    }
+                    let _ = reply.send(response);
+                })
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            workers,
+            stats,
+            deadline,
+        }
+    }
+
+    /// Queue a request and return a receiver for the response.
+    ///
+    /// The receiver yields `None` for notifications.
+    pub fn submit(&self, request: Request) -> mpsc::Receiver<Option<Response>> {
+        let (reply, receiver) = mpsc::channel();
+        if let Some(sender) = &self.sender {
+            let _ = sender.send((request, reply));
+        }
+        receiver
+    }
+
+    /// Stop accepting requests, drain the queue and join the workers.
+    pub fn shutdown(mut self) -> ServedStats {
+        self.stop()
+    }
+
+    /// Stop accepting requests and drain the queue for at most `grace`.
+    ///
+    /// Requests still queued when the grace period elapses are
+    /// discarded and their receivers are disconnected; handlers that
+    /// are already running cannot be interrupted and are always joined.
+    pub fn shutdown_timeout(mut self, grace: Duration) -> ServedStats {
+        if let Ok(mut deadline) = self.deadline.lock() {
+            *deadline = Some(Instant::now() + grace);
+        }
+        self.stop()
+    }
+
+    fn stop(&mut self) -> ServedStats {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.stats.lock().map(|stats| *stats).unwrap_or_default()
+    }
+}
+
+impl Drop for ThreadPoolServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn expired(deadline: &Mutex<Option<Instant>>) -> bool {
+    match deadline.lock() {
+        Ok(deadline) => deadline.map(|d| Instant::now() >= d).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+fn serve<T>(
+    server: &Server<'static, T>,
+    request: &Request,
+    ctx: &T,
+) -> Option<Response> {
+    match panic::catch_unwind(AssertUnwindSafe(|| server.serve(request, ctx))) {
+        Ok(response) => response,
+        Err(_) => request.id().as_ref().map(|_| {
+            let err = Error::from(Box::from("Service handler panicked"));
+            (request, err).into()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Result, RpcError, Service};
+    use serde_json::Value;
+
+    struct WorkService;
+    impl Service for WorkService {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "work" => {
+                    thread::sleep(Duration::from_millis(10));
+                    Ok(Some((request, Value::Bool(true)).into()))
+                }
+                "panic" => panic!("handler failed"),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    fn pool(num_threads: usize) -> ThreadPoolServer {
+        let service: Arc<dyn Service<Data = ()>> = Arc::new(WorkService);
+        let server = Arc::new(Server::new_shared(vec![service]));
+        ThreadPoolServer::new(server, (), num_threads)
+    }
+
+    #[test]
+    fn pool_drains_on_shutdown() {
+        let pool = pool(2);
+        let receivers: Vec<_> = (0..6)
+            .map(|_| pool.submit(Request::new_reply("work", None)))
+            .collect();
+        let notification = pool.submit(Request::new_notification("work", None));
+        let stats = pool.shutdown();
+        assert_eq!(
+            ServedStats {
+                requests: 7,
+                errors: 0
+            },
+            stats
+        );
+        for receiver in receivers {
+            let response = receiver.recv().unwrap().unwrap();
+            assert_eq!(Some(Value::Bool(true)), response.into());
+        }
+        assert!(notification.recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn pool_isolates_panics() {
+        let pool = pool(1);
+        let response = pool
+            .submit(Request::new_reply("panic", None))
+            .recv()
+            .unwrap();
+        let error: Option<RpcError> = response.unwrap().into();
+        let error = error.unwrap();
+        assert_eq!(-32603, error.code);
+        assert_eq!("Service handler panicked", error.message);
+
+        let response = pool
+            .submit(Request::new_reply("work", None))
+            .recv()
+            .unwrap();
+        assert_eq!(Some(Value::Bool(true)), response.unwrap().into());
+        assert_eq!(1, pool.shutdown().errors);
+    }
+
+    #[test]
+    fn pool_grace_period() {
+        let pool = pool(1);
+        let receivers: Vec<_> = (0..20)
+            .map(|_| pool.submit(Request::new_reply("work", None)))
+            .collect();
+        let stats = pool.shutdown_timeout(Duration::from_millis(0));
+        assert!(stats.requests < 20);
+        let answered = receivers
+            .into_iter()
+            .filter(|receiver| receiver.recv().is_ok())
+            .count();
+        assert_eq!(stats.requests, answered);
+    }
+}
@@ -0,0 +1,276 @@
+//! Serve a backlog of requests in priority order.
+//!
+//! A [PriorityQueueServer](PriorityQueueServer) reads requests from a
+//! stream into a binary heap keyed on a priority extracted from each
+//! request and dispatches the highest priority requests first, up to a
+//! concurrency limit. Requests with equal priority are served in the
+//! order they arrived.
+//!
+//! Low priority requests may wait indefinitely while higher priority
+//! requests keep arriving; set [aging()](PriorityQueueServer::aging) so
+//! a waiting request gains one priority level for each period it has
+//! been queued.
+//!
+//! Only available with the `async` feature.
+
+use crate::{futures::Server, Request, Response};
+use futures_util::{
+    future,
+    stream::{FuturesUnordered, Stream, StreamExt},
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    pin::pin,
+    sync::Arc,
+    task::Poll,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+type Extractor = dyn Fn(&Request) -> u8 + Send + Sync;
+
+/// The priority of a request from the `priority` field of its
+/// metadata, zero when missing.
+pub fn meta_priority(request: &Request) -> u8 {
+    request
+        .meta()
+        .and_then(|meta| meta.get("priority"))
+        .and_then(|priority| priority.as_u64())
+        .map(|priority| priority.min(u8::MAX as u64) as u8)
+        .unwrap_or(0)
+}
+
+struct Queued {
+    score: i128,
+    seq: Reverse<u64>,
+    request: Request,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.score, self.seq).cmp(&(other.score, other.seq))
+    }
+}
+
+/// Server adapter dispatching queued requests by priority.
+pub struct PriorityQueueServer<T: Send + Sync + 'static> {
+    server: Arc<Server<'static, T>>,
+    priority: Box<Extractor>,
+    limit: usize,
+    aging: Option<Duration>,
+}
+
+impl<T: Send + Sync + 'static> PriorityQueueServer<T> {
+    /// Create an adapter that ranks requests with `priority`, higher
+    /// values are served first.
+    ///
+    /// Requests are served one at a time until a limit is set.
+    pub fn new<F>(server: Arc<Server<'static, T>>, priority: F) -> Self
+    where
+        F: Fn(&Request) -> u8 + Send + Sync + 'static,
+    {
+        Self {
+            server,
+            priority: Box::new(priority),
+            limit: 1,
+            aging: None,
+        }
+    }
+
+    /// Serve up to `limit` requests concurrently, a limit of zero is
+    /// treated as one.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Raise the priority of a queued request by one level for each
+    /// `period` it has waited.
+    pub fn aging(mut self, period: Duration) -> Self {
+        self.aging = Some(period);
+        self
+    }
+
+    /// Serve requests from a stream until it ends and every queued
+    /// request has been served, responses are passed to `write` as
+    /// they complete.
+    ///
+    /// Requests that are ready on the stream are queued before any
+    /// are dispatched so a backlog is always served in priority order.
+    pub async fn run<S, W>(&self, ctx: &T, requests: S, mut write: W)
+    where
+        S: Stream<Item = Request>,
+        W: FnMut(Response),
+    {
+        let start = Instant::now();
+        let mut requests = pin!(requests);
+        let mut open = true;
+        let mut seq = 0;
+        let mut queue = BinaryHeap::new();
+        let mut pending = FuturesUnordered::new();
+        let server = &*self.server;
+
+        loop {
+            let response = future::poll_fn(|cx| {
+                while open {
+                    match requests.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(request)) => {
+                            queue.push(self.queued(request, start, seq));
+                            seq += 1;
+                        }
+                        Poll::Ready(None) => open = false,
+                        Poll::Pending => break,
+                    }
+                }
+                while pending.len() < self.limit {
+                    match queue.pop() {
+                        Some(Queued { request, .. }) => {
+                            pending.push(async move {
+                                server.serve(&request, ctx).await
+                            })
+                        }
+                        None => break,
+                    }
+                }
+                match pending.poll_next_unpin(cx) {
+                    Poll::Ready(Some(response)) => Poll::Ready(Some(response)),
+                    _ if !open && queue.is_empty() && pending.is_empty() => {
+                        Poll::Ready(None)
+                    }
+                    _ => Poll::Pending,
+                }
+            })
+            .await;
+            match response {
+                Some(Some(response)) => write(response),
+                Some(None) => {}
+                None => break,
+            }
+        }
+    }
+
+    fn queued(&self, request: Request, start: Instant, seq: u64) -> Queued {
+        let priority = (self.priority)(&request) as i128;
+        let score = match self.aging {
+            // Every request ages at the same rate so comparing the
+            // priority less the queued time scaled to levels gives the
+            // same order at any later instant.
+            Some(period) => {
+                priority * period.as_nanos().max(1) as i128
+                    - start.elapsed().as_nanos() as i128
+            }
+            None => priority,
+        };
+        Queued {
+            score,
+            seq: Reverse(seq),
+            request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{futures::Service, Result};
+    use async_trait::async_trait;
+    use futures_util::stream;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    struct Sleep;
+
+    #[async_trait]
+    impl Service for Sleep {
+        type Data = ();
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let secs =
+                request.params().as_ref().and_then(|p| p["secs"].as_u64());
+            tokio::time::sleep(Duration::from_secs(secs.unwrap_or(0))).await;
+            Ok(Some((request, Value::Null).into()))
+        }
+    }
+
+    fn request(id: u64, priority: u8, secs: u64) -> Request {
+        let mut request = Request::new(
+            Some(json!(id)),
+            "sleep".to_string(),
+            Some(json!({ "secs": secs })),
+        );
+        request.set_meta(json!({ "priority": priority })).unwrap();
+        request
+    }
+
+    fn server() -> Arc<Server<'static, ()>> {
+        Arc::new(Server::new_shared(vec![Arc::new(Sleep)]))
+    }
+
+    #[tokio::test]
+    async fn priority_order() {
+        let requests = vec![
+            request(1, 1, 0),
+            request(2, 5, 0),
+            request(3, 3, 0),
+            request(4, 5, 0),
+        ];
+        let mut ids = Vec::new();
+        PriorityQueueServer::new(server(), meta_priority)
+            .run(&(), stream::iter(requests), |response| {
+                ids.push(response.id().clone().unwrap())
+            })
+            .await;
+        assert_eq!(vec![json!(2), json!(4), json!(3), json!(1)], ids);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn priority_aging() {
+        async fn order(aging: Option<Duration>) -> Vec<Value> {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let requests = stream::unfold(rx, |mut rx| async {
+                rx.recv().await.map(|request| (request, rx))
+            });
+            let mut adapter = PriorityQueueServer::new(server(), meta_priority);
+            if let Some(period) = aging {
+                adapter = adapter.aging(period);
+            }
+            let ids = Mutex::new(Vec::new());
+            let feed = async move {
+                tx.send(request(1, 9, 10)).unwrap();
+                tokio::task::yield_now().await;
+                tx.send(request(2, 0, 0)).unwrap();
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                tx.send(request(3, 2, 0)).unwrap();
+            };
+            let run = adapter.run(&(), requests, |response| {
+                ids.lock().unwrap().push(response.id().clone().unwrap())
+            });
+            tokio::join!(feed, run);
+            ids.into_inner().unwrap()
+        }
+
+        assert_eq!(vec![json!(1), json!(3), json!(2)], order(None).await);
+        assert_eq!(
+            vec![json!(1), json!(2), json!(3)],
+            order(Some(Duration::from_secs(1))).await
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Forward requests to an upstream server.
+//!
+//! A [ProxyService](ProxyService) handles every request by sending it
+//! through a [Client](crate::client::Client) and replying with the
+//! upstream response, so it belongs after the services that handle
+//! methods locally. With the `async` feature use
+//! [AsyncProxyService](AsyncProxyService) which can also give up on
+//! slow upstream calls.
+//!
+//! Notifications are forwarded and never answered. When the upstream
+//! call fails the error response has the internal error code, or the
+//! code set with `error_code()`, and the upstream error message as the
+//! data.
+
+use crate::{
+    client::{Client, Transport},
+    random_id, Request, Response, Result, RpcError, Service, INTERNAL_ERROR,
+};
+use serde_json::Value;
+use std::marker::PhantomData;
+
+const UPSTREAM_FAILED: &str = "Upstream request failed";
+
+/// Options shared by the blocking and async proxies.
+struct Forward {
+    rewrite_ids: bool,
+    error_code: isize,
+}
+
+impl Default for Forward {
+    fn default() -> Self {
+        Self {
+            rewrite_ids: false,
+            error_code: INTERNAL_ERROR,
+        }
+    }
+}
+
+impl Forward {
+    /// The request to send upstream.
+    fn upstream(&self, request: &Request) -> Request {
+        let mut upstream = request.clone();
+        if self.rewrite_ids {
+            *upstream.id_mut() = Some(random_id());
+        }
+        upstream
+    }
+
+    /// The response to the original request.
+    fn reply(&self, request: &Request, result: Result<Response>) -> Response {
+        match result {
+            Ok(mut response) => {
+                response.id = request.id().clone();
+                response
+            }
+            Err(e) => {
+                let error = RpcError {
+                    code: self.error_code,
+                    message: UPSTREAM_FAILED.into(),
+                    data: Some(Value::String(e.to_string())),
+                };
+                (request, error).into()
+            }
+        }
+    }
+}
+
+/// Service that forwards every request to an upstream server.
+pub struct ProxyService<C, T> {
+    client: Client<C>,
+    forward: Forward,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<C: Transport, T> ProxyService<C, T> {
+    /// Create a proxy sending requests with a client.
+    pub fn new(client: Client<C>) -> Self {
+        Self {
+            client,
+            forward: Default::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Send requests upstream with a new id; the response is given
+    /// the id of the original request.
+    pub fn rewrite_ids(mut self) -> Self {
+        self.forward.rewrite_ids = true;
+        self
+    }
+
+    /// Set the error code used when the upstream call fails.
+    pub fn error_code(mut self, code: isize) -> Self {
+        self.forward.error_code = code;
+        self
+    }
+
+    /// The client used to send requests.
+    pub fn client(&self) -> &Client<C> {
+        &self.client
+    }
+}
+
+impl<C: Transport, T> Service for ProxyService<C, T> {
+    type Data = T;
+
+    fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        if request.id().is_none() {
+            let _ = self
+                .client
+                .notify(request.method(), request.params().clone());
+            return Ok(Some(request.into()));
+        }
+        let upstream = self.forward.upstream(request);
+        let result = self.client.request(&upstream);
+        Ok(Some(self.forward.reply(request, result)))
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+pub use self::asynchronous::AsyncProxyService;
+
+#[cfg(any(test, feature = "async"))]
+mod asynchronous {
+    use super::Forward;
+    use crate::{
+        futures::{Client, Service, Transport},
+        Error, Request, Response, Result,
+    };
+    use std::{marker::PhantomData, time::Duration};
+
+    /// Async service that forwards every request to an upstream server.
+    ///
+    /// Only available with the `async` feature.
+    pub struct AsyncProxyService<C, T> {
+        client: Client<C>,
+        forward: Forward,
+        timeout: Option<Duration>,
+        marker: PhantomData<fn() -> T>,
+    }
+
+    impl<C: Transport, T> AsyncProxyService<C, T> {
+        /// Create a proxy sending requests with a client.
+        pub fn new(client: Client<C>) -> Self {
+            Self {
+                client,
+                forward: Default::default(),
+                timeout: None,
+                marker: PhantomData,
+            }
+        }
+
+        /// Send requests upstream with a new id; the response is given
+        /// the id of the original request.
+        pub fn rewrite_ids(mut self) -> Self {
+            self.forward.rewrite_ids = true;
+            self
+        }
+
+        /// Set the error code used when the upstream call fails.
+        pub fn error_code(mut self, code: isize) -> Self {
+            self.forward.error_code = code;
+            self
+        }
+
+        /// Give up waiting for an upstream response after `timeout`.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = Some(timeout);
+            self
+        }
+
+        /// The client used to send requests.
+        pub fn client(&self) -> &Client<C> {
+            &self.client
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<C: Transport, T: Send + Sync> Service for AsyncProxyService<C, T> {
+        type Data = T;
+
+        async fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            if request.id().is_none() {
+                let _ = self
+                    .client
+                    .notify(request.method(), request.params().clone())
+                    .await;
+                return Ok(Some(request.into()));
+            }
+            let upstream = self.forward.upstream(request);
+            let call = self.client.request(&upstream);
+            let result = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, call)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(Error::Timeout {
+                            method: Some(request.method().to_string()),
+                            elapsed: Some(timeout),
+                            id: request.id().clone(),
+                        })
+                    }),
+                None => call.await,
+            };
+            Ok(Some(self.forward.reply(request, result)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Server;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    /// Upstream that echoes the params and records the requests.
+    #[derive(Clone, Default)]
+    struct Upstream(Arc<Mutex<Vec<Request>>>);
+
+    impl Upstream {
+        fn reply(&self, request: &Request) -> Result<Option<Response>> {
+            self.0.lock().unwrap().push(request.clone());
+            match request.method() {
+                "down" => {
+                    Err(crate::Error::from(Box::from("Connection refused")))
+                }
+                _ if request.id().is_none() => Ok(None),
+                _ => Ok(Some(
+                    (request, request.params().clone().unwrap_or(Value::Null))
+                        .into(),
+                )),
+            }
+        }
+    }
+
+    impl Transport for Upstream {
+        fn send(&self, request: &Request) -> Result<Option<Response>> {
+            self.reply(request)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl crate::futures::Transport for Upstream {
+        async fn send(&self, request: &Request) -> Result<Option<Response>> {
+            if request.method() == "slow" {
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            }
+            self.reply(request)
+        }
+    }
+
+    #[test]
+    fn proxy_forward() {
+        let upstream = Upstream::default();
+        let service: Box<dyn Service<Data = ()>> = Box::new(
+            ProxyService::new(Client::new(upstream.clone())).rewrite_ids(),
+        );
+        let server = Server::new(vec![&service]);
+
+        let request =
+            Request::new(Some(json!("a")), "echo".into(), Some(json!([1])));
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(&Some(json!("a")), response.id());
+        assert_eq!(&Some(json!([1])), response.result());
+        assert_ne!(upstream.0.lock().unwrap()[0].id(), request.id());
+
+        let request = Request::new_notification("event", None);
+        assert!(server.serve(&request, &()).is_none());
+        assert_eq!(2, upstream.0.lock().unwrap().len());
+    }
+
+    #[test]
+    fn proxy_upstream_failure() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(
+            ProxyService::new(Client::new(Upstream::default()))
+                .error_code(-32099),
+        );
+        let server = Server::new(vec![&service]);
+        let request = Request::new_reply("down", None);
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(request.id(), response.id());
+        let error = response.error().clone().unwrap();
+        assert_eq!(-32099, error.code);
+        assert_eq!(Some(json!("Connection refused")), error.data);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn proxy_async_timeout() {
+        use crate::futures::{Client, Server, Service};
+        let service: Box<dyn Service<Data = ()>> = Box::new(
+            AsyncProxyService::new(Client::new(Upstream::default()))
+                .timeout(std::time::Duration::from_secs(1)),
+        );
+        let server = Server::new(vec![&service]);
+
+        let request = Request::new_reply("echo", Some(json!({"a": 1})));
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(&Some(json!({"a": 1})), response.result());
+
+        let request = Request::new_reply("slow", None);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(request.id(), response.id());
+        let error = response.error().clone().unwrap();
+        assert_eq!(INTERNAL_ERROR, error.code);
+        assert_eq!(Some(json!("Call to slow timed out after 1s")), error.data);
+    }
+}
@@ -0,0 +1,281 @@
+//! Requests encoded in the query string of an HTTP GET.
+//!
+//! JSON-RPC over HTTP allows a request to be sent as a GET with the
+//! `method`, `params` and `id` query parameters, which lets caches and
+//! CDNs store the responses of read-only methods:
+//!
+//! * `method` is the method name.
+//! * `params` is the JSON parameters encoded as URL-safe base64
+//!   without padding; padded input is accepted.
+//! * `id` is the JSON text of the id, so string ids are quoted; a value
+//!   that is not JSON is taken as a string id. Without an `id` the
+//!   request is a notification.
+//! * `jsonrpc` is optional and must be `2.0` when present.
+//!
+//! Other query parameters are ignored so cache busting parameters may
+//! be added. Malformed values are `Error::InvalidRequest`.
+//!
+//! ```
+//! use json_rpc2::Request;
+//! use serde_json::json;
+//!
+//! let request = Request::new(Some(json!(1)), "sum".to_string(), Some(json!([1, 2])));
+//! let query = request.to_query_string();
+//! assert_eq!("method=sum&params=WzEsMl0&id=1", query);
+//! let decoded = Request::from_query_string(&query)?;
+//! assert_eq!(Some(&json!([1, 2])), decoded.params().as_ref());
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+//!
+//! Only available with the `query` feature.
+
+use crate::{validate, Error, Request, Result};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use serde_json::Value;
+use std::borrow::Cow;
+
+const PARAMS_ENGINE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+impl Request {
+    /// Encode the request as a query string without the leading `?`.
+    pub fn to_query_string(&self) -> String {
+        let mut query = format!("method={}", encode(self.method()));
+        if let Some(params) = self.params() {
+            query.push_str("&params=");
+            query.push_str(&PARAMS_ENGINE.encode(params.to_string()));
+        }
+        if let Some(id) = self.id() {
+            query.push_str("&id=");
+            query.push_str(&encode(&id.to_string()));
+        }
+        query
+    }
+
+    /// Decode a request from a query string, with or without the
+    /// leading `?`.
+    pub fn from_query_string(query: &str) -> Result<Request> {
+        let query = query.strip_prefix('?').unwrap_or(query);
+        let mut pairs = Vec::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            pairs.push((decode(name)?, decode(value)?));
+        }
+        Request::from_query_pairs(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.as_ref(), value.as_ref())),
+        )
+    }
+
+    /// Decode a request from query parameters that have already been
+    /// percent-decoded.
+    pub fn from_query_pairs<'a, I>(pairs: I) -> Result<Request>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut jsonrpc = None;
+        let mut method = None;
+        let mut params = None;
+        let mut id = None;
+        for (name, value) in pairs {
+            let slot = match name {
+                "jsonrpc" => &mut jsonrpc,
+                "method" => &mut method,
+                "params" => &mut params,
+                "id" => &mut id,
+                _ => continue,
+            };
+            if slot.replace(value).is_some() {
+                return Err(invalid(
+                    None,
+                    format!("duplicate field `{}`", name),
+                ));
+            }
+        }
+
+        let id = id.map(decode_id);
+        let method = method
+            .filter(|method| !method.is_empty())
+            .ok_or_else(|| invalid(id.clone(), "missing field `method`"))?;
+        let params = params
+            .map(|params| decode_params(params, &id))
+            .transpose()?;
+        let mut request = Request::new(id, method.to_string(), params);
+        if let Some(jsonrpc) = jsonrpc {
+            request.jsonrpc = Cow::Owned(jsonrpc.to_string());
+        }
+        validate(request)
+    }
+}
+
+/// Parse the id as JSON falling back to a string.
+fn decode_id(id: &str) -> Value {
+    serde_json::from_str(id).unwrap_or_else(|_| Value::String(id.to_string()))
+}
+
+fn decode_params(params: &str, id: &Option<Value>) -> Result<Value> {
+    let bytes = PARAMS_ENGINE.decode(params).map_err(|e| {
+        invalid(id.clone(), format!("params are not base64: {}", e))
+    })?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| invalid(id.clone(), format!("params are not JSON: {}", e)))
+}
+
+fn invalid(id: Option<Value>, data: impl Into<String>) -> Error {
+    Error::InvalidRequest {
+        id,
+        data: data.into(),
+        line: None,
+        column: None,
+        offset: None,
+    }
+}
+
+/// Percent-encode everything except unreserved characters.
+fn encode(value: &str) -> Cow<'_, str> {
+    let unreserved = |b: u8| {
+        b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+    };
+    if value.bytes().all(unreserved) {
+        return Cow::Borrowed(value);
+    }
+    let mut encoded = String::with_capacity(value.len() * 3);
+    for b in value.bytes() {
+        if unreserved(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// Decode percent escapes and `+` as a space.
+fn decode(value: &str) -> Result<Cow<'_, str>> {
+    if !value.contains(['%', '+']) {
+        return Ok(Cow::Borrowed(value));
+    }
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let byte = bytes
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| {
+                        invalid(
+                            None,
+                            format!("invalid percent escape in `{}`", value),
+                        )
+                    })?;
+                decoded.push(byte);
+                index += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            b => {
+                decoded.push(b);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded)
+        .map(Cow::Owned)
+        .map_err(|_| invalid(None, format!("invalid UTF-8 in `{}`", value)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn query_round_trip() -> Result<()> {
+        for request in &[
+            Request::new(
+                Some(json!(1)),
+                "sum".to_string(),
+                Some(json!([1, 2])),
+            ),
+            Request::new(
+                Some(json!("a b/c")),
+                "ns.get item".to_string(),
+                Some(json!({"key": "ü?&=", "n": null})),
+            ),
+            Request::new(Some(json!("7")), "ping".to_string(), None),
+            Request::new_notification("notify", None),
+        ] {
+            let query = request.to_query_string();
+            assert!(
+                !query.contains(|c: char| " ?/\"{}".contains(c)),
+                "{}",
+                query
+            );
+            let decoded = Request::from_query_string(&query)?;
+            assert_eq!(request.id(), decoded.id());
+            assert_eq!(request.method(), decoded.method());
+            assert_eq!(request.params(), decoded.params());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn query_pairs() -> Result<()> {
+        let request = Request::from_query_pairs(vec![
+            ("method", "sum"),
+            ("params", "WzEsMl0="),
+            ("id", "abc"),
+            ("jsonrpc", "2.0"),
+            ("_", "1700000000"),
+        ])?;
+        assert_eq!(&Some(json!("abc")), request.id());
+        assert_eq!(Some(&json!([1, 2])), request.params().as_ref());
+
+        let request = Request::from_query_string("?method=get+item&id=")?;
+        assert_eq!("get item", request.method());
+        assert_eq!(&Some(json!("")), request.id());
+        Ok(())
+    }
+
+    #[test]
+    fn query_errors() {
+        for (query, id, expected) in &[
+            ("id=1", Some(json!(1)), "missing field `method`"),
+            ("method=a&method=b", None, "duplicate field `method`"),
+            (
+                "method=a&id=2&params=!!",
+                Some(json!(2)),
+                "params are not base64",
+            ),
+            ("method=a&params=bm9wZQ", None, "params are not JSON"),
+            ("method=a&params=MQ&id=3", Some(json!(3)), "params must be"),
+            ("method=a&jsonrpc=1.0&id=4", Some(json!(4)), "jsonrpc"),
+            ("method=%zz", None, "invalid percent escape"),
+            ("method=%ff", None, "invalid UTF-8"),
+        ] {
+            match Request::from_query_string(query) {
+                Err(Error::InvalidRequest {
+                    id: actual, data, ..
+                }) => {
+                    assert_eq!(id, &actual, "{}", query);
+                    assert!(data.contains(expected), "{}: {}", query, data);
+                }
+                other => panic!("{}: unexpected {:?}", query, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Responses with a result that is already serialized.
+//!
+//! Converting a handler result into a [Response](crate::Response) builds
+//! a `Value` tree that is then walked again to produce the JSON text.
+//! For large results both steps are expensive; a
+//! [RawResponse](RawResponse) serializes the result once, straight to
+//! JSON text, and splices it into the response.
+//!
+//! Services opt in by implementing
+//! [Service::handle_raw()](crate::Service::handle_raw), which services
+//! created with [method()](crate::method::method) do. The raw replies
+//! are only used by [Server::serve_str()](crate::Server::serve_str) and
+//! [Server::serve_slice()](crate::Server::serve_slice), which parse a
+//! payload and return the serialized response:
+//!
+//! ```
+//! use json_rpc2::{method::method, Result, Server, Service};
+//!
+//! fn range((count,): (u32,), _: &()) -> Result<Vec<u32>> {
+//!     Ok((0..count).collect())
+//! }
+//!
+//! let service: Box<dyn Service<Data = ()>> =
+//!     Box::new(method("range", range));
+//! let server = Server::new(vec![&service]);
+//! let payload = r#"{"jsonrpc":"2.0","id":1,"method":"range","params":[3]}"#;
+//! assert_eq!(
+//!     Some(r#"{"jsonrpc":"2.0","id":1,"result":[0,1,2]}"#.to_string()),
+//!     server.serve_str(payload, &()),
+//! );
+//! ```
+//!
+//! Raw replies are passed to the
+//! [on_served()](crate::Server::on_served) hook without a response.
+//! To skip the intermediate buffer as well write the response directly
+//! with [Response::write_result()](crate::Response::write_result).
+
+use crate::{Error, Request, Response, Result, VERSION};
+use serde::Serialize;
+use serde_json::{value::RawValue, Value};
+use std::{borrow::Cow, io::Write};
+
+/// Successful response with a serialized result.
+#[derive(Serialize, Debug, Clone)]
+pub struct RawResponse {
+    jsonrpc: Cow<'static, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
+    result: Box<RawValue>,
+    /// Unknown top-level fields.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+impl RawResponse {
+    /// Create a response to the request serializing the result.
+    pub fn new<R: Serialize + ?Sized>(
+        request: &Request,
+        result: &R,
+    ) -> Result<Self> {
+        let result = serde_json::value::to_raw_value(result)
+            .map_err(|e| Error::from(Box::from(e)))?;
+        Ok(Self {
+            jsonrpc: Cow::Borrowed(VERSION),
+            id: request.id().clone(),
+            result,
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+        })
+    }
+
+    /// The id for the response.
+    pub fn id(&self) -> &Option<Value> {
+        &self.id
+    }
+
+    /// The serialized result.
+    pub fn result(&self) -> &RawValue {
+        &self.result
+    }
+
+    /// Unknown top-level fields of the response.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields(&self) -> &serde_json::Map<String, Value> {
+        &self.extra
+    }
+
+    /// The mutable unknown top-level fields of the response.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields_mut(&mut self) -> &mut serde_json::Map<String, Value> {
+        &mut self.extra
+    }
+}
+
+/// Reply of a service, either a response or a raw response.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Reply {
+    /// Response with the result as a `Value` or an error.
+    Value(Response),
+    /// Response with a serialized result.
+    Raw(RawResponse),
+}
+
+impl Reply {
+    /// The id for the reply.
+    pub fn id(&self) -> &Option<Value> {
+        match self {
+            Reply::Value(response) => response.id(),
+            Reply::Raw(response) => response.id(),
+        }
+    }
+
+    /// Whether the reply is an error response.
+    pub fn is_error(&self) -> bool {
+        match self {
+            Reply::Value(response) => response.error().is_some(),
+            Reply::Raw(_) => false,
+        }
+    }
+
+    /// The response unless the result is serialized.
+    pub fn response(&self) -> Option<&Response> {
+        match self {
+            Reply::Value(response) => Some(response),
+            Reply::Raw(_) => None,
+        }
+    }
+
+    /// The mutable unknown top-level fields of the reply.
+    ///
+    /// Only available with the `extra-fields` feature.
+    #[cfg(feature = "extra-fields")]
+    pub fn extra_fields_mut(&mut self) -> &mut serde_json::Map<String, Value> {
+        match self {
+            Reply::Value(response) => response.extra_fields_mut(),
+            Reply::Raw(response) => response.extra_fields_mut(),
+        }
+    }
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Self {
+        Reply::Value(response)
+    }
+}
+
+impl From<RawResponse> for Reply {
+    fn from(response: RawResponse) -> Self {
+        Reply::Raw(response)
+    }
+}
+
+/// Response borrowing the request id and the result.
+#[derive(Serialize)]
+struct Borrowed<'a, R: ?Sized> {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: &'a Option<Value>,
+    result: &'a R,
+}
+
+/// Write a response to the request serializing the result directly.
+pub(crate) fn write_result<R: Serialize + ?Sized, W: Write>(
+    request: &Request,
+    result: &R,
+    writer: W,
+) -> Result<()> {
+    let response = Borrowed {
+        jsonrpc: VERSION,
+        id: request.id(),
+        result,
+    };
+    serde_json::to_writer(writer, &response)
+        .map_err(|e| Error::from(Box::from(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{method::method, methods, Server, Service};
+    use serde::Serialize;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Serialize)]
+    struct Item {
+        index: u32,
+        name: String,
+    }
+
+    fn items((count,): (u32,), _: &()) -> Result<Vec<Item>> {
+        Ok((0..count)
+            .map(|index| Item {
+                index,
+                name: format!("item-{}", index),
+            })
+            .collect())
+    }
+
+    fn fail(_: (), _: &()) -> Result<()> {
+        Err(Error::from(Box::from("boom")))
+    }
+
+    #[test]
+    fn raw_serve_str_matches_serve() {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(methods![method("items", items), method("fail", fail)]);
+        let server = Server::new(vec![&service]);
+        for payload in &[
+            r#"{"jsonrpc":"2.0","id":1,"method":"items","params":[3]}"#,
+            r#"{"jsonrpc":"2.0","id":"a","method":"fail"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"missing"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"items","params":["x"]}"#,
+            r#"{"jsonrpc":"2.0","id":4"#,
+        ] {
+            let expected = match crate::from_str(payload) {
+                Ok(request) => server.serve(&request, &()),
+                Err(e) => Some(Response::from(e)),
+            }
+            .map(|response| serde_json::to_string(&response).unwrap());
+            assert_eq!(expected, server.serve_str(payload, &()), "{}", payload);
+            assert_eq!(
+                expected.map(String::into_bytes),
+                server.serve_slice(payload.as_bytes(), &()),
+            );
+        }
+
+        let notification = r#"{"jsonrpc":"2.0","method":"items","params":[1]}"#;
+        assert_eq!(None, server.serve_str(notification, &()));
+    }
+
+    #[test]
+    fn raw_reply_is_raw() {
+        let service = method("items", items);
+        let request = Request::new_reply("items", Some(json!([2])));
+        match service.handle_raw(&request, &()).unwrap() {
+            Some(Reply::Raw(response)) => {
+                assert_eq!(request.id(), response.id());
+                assert_eq!(
+                    r#"[{"index":0,"name":"item-0"},{"index":1,"name":"item-1"}]"#,
+                    response.result().get()
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn raw_served_hook() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook = Arc::clone(&seen);
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(method("items", items));
+        let server = Server::new(vec![&service]).on_served(
+            move |request, response, _| {
+                hook.lock()
+                    .unwrap()
+                    .push((request.method().to_string(), response.is_some()));
+            },
+        );
+        server.serve_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"items","params":[1]}"#,
+            &(),
+        );
+        server.serve_str(r#"{"jsonrpc":"2.0","id":1,"method":"nope"}"#, &());
+        assert_eq!(
+            vec![("items".to_string(), false), ("nope".to_string(), true)],
+            *seen.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn raw_write_result() -> Result<()> {
+        let request = Request::new_reply("items", None);
+        let mut buffer = Vec::new();
+        Response::write_result(&request, &items((2,), &())?, &mut buffer)?;
+        let expected: Response = (&request, json!(items((2,), &())?)).into();
+        assert_eq!(serde_json::to_vec(&expected).unwrap(), buffer);
+
+        let notification = Request::new_notification("items", None);
+        let mut buffer = Vec::new();
+        Response::write_result(&notification, &1, &mut buffer)?;
+        assert_eq!(br#"{"jsonrpc":"2.0","result":1}"#.to_vec(), buffer);
+        Ok(())
+    }
+
+    #[cfg(feature = "extra-fields")]
+    #[test]
+    fn raw_timing() {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(method("items", items));
+        let server = Server::new(vec![&service]).with_timing();
+        let payload = server
+            .serve_str(
+                r#"{"jsonrpc":"2.0","id":1,"method":"items","params":[0]}"#,
+                &(),
+            )
+            .unwrap();
+        let response: Response = serde_json::from_str(&payload).unwrap();
+        assert!(crate::timing::elapsed(&response).is_some());
+        assert_eq!(Some(json!([])), response.into());
+    }
+}
@@ -0,0 +1,514 @@
+//! Reconnect a transport that drops, requires the `async` feature.
+//!
+//! [Reconnecting](Reconnecting) wraps the transport of an async client
+//! and creates it with a [Connector](Connector), such as a closure
+//! opening a WebSocket. When sending fails the connection is dropped
+//! and the next call connects again, retrying with exponential backoff
+//! and jitter up to a maximum number of attempts:
+//!
+//! ```
+//! use json_rpc2::{futures::{Client, Transport}, reconnect::Reconnecting, *};
+//! use async_trait::async_trait;
+//! use serde_json::json;
+//!
+//! struct Loopback;
+//!
+//! #[async_trait]
+//! impl Transport for Loopback {
+//!     async fn send(&self, request: &Request) -> Result<Option<Response>> {
+//!         Ok(Some((request, json!("pong")).into()))
+//!     }
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let transport = Reconnecting::new(|| async { Ok(Loopback) })
+//!     .idempotent("ping")
+//!     .on_reconnect(|_transport| async { Ok(()) });
+//! let client = Client::new(transport);
+//! let pong: String = client.call("ping", None).await?;
+//! assert_eq!("pong", pong);
+//! # Ok::<(), Error>(())
+//! # }).unwrap();
+//! ```
+//!
+//! Nothing is replayed by default: a call whose request may have
+//! reached the server fails with the error of the transport. Calls to
+//! methods marked [idempotent](Reconnecting::idempotent) are sent again
+//! on the new connection. Server state tied to the connection, such as
+//! subscriptions, is restored by the
+//! [on_reconnect()](Reconnecting::on_reconnect) hook.
+//!
+//! While a connection is being made other calls fail fast with
+//! `Error::ConnectionLost` or wait for it, see
+//! [WhileDisconnected](WhileDisconnected).
+
+use crate::{futures::Transport, Error, Request, Response, Result};
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use rand::Rng;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+#[cfg(not(feature = "tracing"))]
+use log::warn as log_warn;
+#[cfg(feature = "tracing")]
+use tracing::warn as log_warn;
+
+/// Creates the connections of a [Reconnecting](Reconnecting)
+/// transport.
+///
+/// Implemented for closures returning a future of the transport.
+#[async_trait]
+pub trait Connector: Send + Sync {
+    /// Type of the transport.
+    type Transport: Transport;
+
+    /// Establish a connection.
+    async fn connect(&self) -> Result<Self::Transport>;
+}
+
+#[async_trait]
+impl<F, Fut, T> Connector for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<T>> + Send,
+    T: Transport,
+{
+    type Transport = T;
+    async fn connect(&self) -> Result<T> {
+        (self)().await
+    }
+}
+
+/// What calls do while a connection is being made.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WhileDisconnected {
+    /// Fail with `Error::ConnectionLost`.
+    FailFast,
+    /// Wait for the connection, at most this many calls wait and the
+    /// others fail fast.
+    Queue(usize),
+}
+
+type Hook<T> = dyn Fn(Arc<T>) -> BoxFuture<'static, Result<()>> + Send + Sync;
+
+/// Transport that connects again after it fails.
+///
+/// Any error from the inner transport drops the connection. The first
+/// connection is made by the first call.
+///
+/// Only available with the `async` feature.
+pub struct Reconnecting<C: Connector> {
+    connector: C,
+    current: Mutex<Option<(u64, Arc<C::Transport>)>>,
+    connecting: tokio::sync::Mutex<()>,
+    /// Number of connections made.
+    connections: AtomicU64,
+    /// Number of times connecting was given up.
+    failures: AtomicU64,
+    waiting: AtomicUsize,
+    on_reconnect: Option<Box<Hook<C::Transport>>>,
+    idempotent: HashSet<String>,
+    while_disconnected: WhileDisconnected,
+    max_retries: u32,
+    initial: Duration,
+    max_delay: Duration,
+    jitter: bool,
+}
+
+impl<C: Connector> Reconnecting<C> {
+    /// Create a transport connecting with `connector`.
+    ///
+    /// Connecting is retried 5 times with a backoff from 100ms up to
+    /// 10s with jitter, and calls fail fast while connecting.
+    pub fn new(connector: C) -> Self {
+        Self {
+            connector,
+            current: Mutex::new(None),
+            connecting: tokio::sync::Mutex::new(()),
+            connections: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            waiting: AtomicUsize::new(0),
+            on_reconnect: None,
+            idempotent: HashSet::new(),
+            while_disconnected: WhileDisconnected::FailFast,
+            max_retries: 5,
+            initial: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+        }
+    }
+
+    /// Send calls to a method again on the new connection when sending
+    /// fails.
+    pub fn idempotent(mut self, name: &str) -> Self {
+        self.idempotent.insert(name.to_string());
+        self
+    }
+
+    /// Call `hook` with every new connection after the first, for
+    /// example to subscribe again.
+    ///
+    /// A hook that fails counts as a failed attempt to connect.
+    pub fn on_reconnect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Arc<C::Transport>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_reconnect =
+            Some(Box::new(move |transport| Box::pin(hook(transport))));
+        self
+    }
+
+    /// Set what calls do while a connection is being made.
+    pub fn while_disconnected(mut self, policy: WhileDisconnected) -> Self {
+        self.while_disconnected = policy;
+        self
+    }
+
+    /// Set how many times connecting is retried before calls fail.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the initial and maximum delay between attempts to connect.
+    pub fn backoff(mut self, initial: Duration, max_delay: Duration) -> Self {
+        self.initial = initial;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Set whether the delays are randomized so clients that lost
+    /// their connections together do not reconnect together.
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    /// Determine if there is a connection.
+    pub fn is_connected(&self) -> bool {
+        self.current.lock().unwrap().is_some()
+    }
+
+    /// Number of connections made.
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Delay before the attempt to connect after `attempt` failures.
+    ///
+    /// Doubles for every attempt up to the maximum; with jitter it is
+    /// between half and all of that.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter {
+            let half = delay / 2;
+            half + half.mul_f64(rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+
+    fn connected(&self) -> Option<(u64, Arc<C::Transport>)> {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Drop the connection unless it was already replaced.
+    fn disconnect(&self, connection: u64) {
+        let mut current = self.current.lock().unwrap();
+        if matches!(&*current, Some((number, _)) if *number == connection) {
+            *current = None;
+        }
+    }
+
+    async fn transport(&self) -> Result<(u64, Arc<C::Transport>)> {
+        if let Some(connected) = self.connected() {
+            return Ok(connected);
+        }
+        let failures = self.failures.load(Ordering::SeqCst);
+        let _guard = match self.while_disconnected {
+            WhileDisconnected::FailFast => self
+                .connecting
+                .try_lock()
+                .map_err(|_| Error::ConnectionLost)?,
+            WhileDisconnected::Queue(max) => {
+                // Frees the slot even if the call is dropped while waiting
+                let waiting = Waiting(&self.waiting);
+                if self.waiting.fetch_add(1, Ordering::SeqCst) >= max {
+                    return Err(Error::ConnectionLost);
+                }
+                let guard = self.connecting.lock().await;
+                drop(waiting);
+                guard
+            }
+        };
+        // Another call may have connected, or given up, while we waited.
+        if let Some(connected) = self.connected() {
+            return Ok(connected);
+        }
+        if self.failures.load(Ordering::SeqCst) != failures {
+            return Err(Error::ConnectionLost);
+        }
+        self.connect().await
+    }
+
+    async fn connect(&self) -> Result<(u64, Arc<C::Transport>)> {
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(self.delay(attempt)).await;
+            }
+            let transport = match self.connector.connect().await {
+                Ok(transport) => Arc::new(transport),
+                Err(e) => {
+                    log_warn!("connect attempt {} failed: {}", attempt + 1, e);
+                    continue;
+                }
+            };
+            if self.connections() > 0 {
+                if let Some(hook) = &self.on_reconnect {
+                    if let Err(e) = hook(Arc::clone(&transport)).await {
+                        log_warn!("reconnect hook failed: {}", e);
+                        continue;
+                    }
+                }
+            }
+            let number = self.connections.fetch_add(1, Ordering::SeqCst) + 1;
+            let connected = (number, transport);
+            *self.current.lock().unwrap() = Some(connected.clone());
+            return Ok(connected);
+        }
+        self.failures.fetch_add(1, Ordering::SeqCst);
+        Err(Error::ConnectionLost)
+    }
+}
+
+/// Decrements the number of waiting calls when a call stops waiting.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl<C: Connector> Transport for Reconnecting<C> {
+    async fn send(&self, request: &Request) -> Result<Option<Response>> {
+        let replay = self.idempotent.contains(request.method());
+        let mut replays = 0;
+        loop {
+            let (connection, transport) = self.transport().await?;
+            match transport.send(request).await {
+                Err(e) => {
+                    self.disconnect(connection);
+                    if !replay || replays >= self.max_retries {
+                        return Err(e);
+                    }
+                    replays += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::Client;
+    use serde_json::json;
+
+    /// Shared counters of a [Flaky] transport and its connections.
+    #[derive(Default)]
+    struct Counters {
+        calls: AtomicUsize,
+        connects: AtomicUsize,
+    }
+
+    /// Transport that fails every `nth` call.
+    struct Flaky {
+        nth: usize,
+        counters: Arc<Counters>,
+    }
+
+    #[async_trait]
+    impl Transport for Flaky {
+        async fn send(&self, request: &Request) -> Result<Option<Response>> {
+            let call = self.counters.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if call.is_multiple_of(self.nth) {
+                return Err(Error::from(Box::from("connection reset")));
+            }
+            Ok(Some((request, json!(call)).into()))
+        }
+    }
+
+    fn flaky(
+        nth: usize,
+        counters: &Arc<Counters>,
+    ) -> Reconnecting<impl Connector<Transport = Flaky>> {
+        let counters = Arc::clone(counters);
+        Reconnecting::new(move || {
+            let counters = Arc::clone(&counters);
+            async move {
+                counters.connects.fetch_add(1, Ordering::SeqCst);
+                Ok(Flaky { nth, counters })
+            }
+        })
+        .backoff(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[tokio::test]
+    async fn reconnect_without_replay() {
+        let counters = Arc::new(Counters::default());
+        let reconnects = Arc::new(AtomicUsize::new(0));
+        let transport = flaky(2, &counters).on_reconnect({
+            let reconnects = Arc::clone(&reconnects);
+            move |_| {
+                reconnects.fetch_add(1, Ordering::SeqCst);
+                async { Ok(()) }
+            }
+        });
+        let client = Client::new(transport);
+
+        let first: usize = client.call("echo", None).await.unwrap();
+        assert_eq!(1, first);
+        assert!(client.call::<usize>("echo", None).await.is_err());
+        assert!(!client.transport().is_connected());
+        let third: usize = client.call("echo", None).await.unwrap();
+        assert_eq!(3, third);
+        assert_eq!(2, counters.connects.load(Ordering::SeqCst));
+        assert_eq!(1, reconnects.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn reconnect_replays_idempotent() {
+        let counters = Arc::new(Counters::default());
+        let client = Client::new(flaky(2, &counters).idempotent("echo"));
+        let results: Vec<usize> = vec![
+            client.call("echo", None).await.unwrap(),
+            client.call("echo", None).await.unwrap(),
+        ];
+        assert_eq!(vec![1, 3], results);
+        assert_eq!(2, client.transport().connections());
+    }
+
+    #[tokio::test]
+    async fn reconnect_gives_up() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let transport = Reconnecting::new({
+            let attempts = Arc::clone(&attempts);
+            move || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async { Err::<Flaky, _>(Error::from(Box::from("refused"))) }
+            }
+        })
+        .max_retries(2)
+        .backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let request = Request::new_reply("echo", None);
+        assert!(matches!(
+            transport.send(&request).await,
+            Err(Error::ConnectionLost)
+        ));
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+    }
+
+    fn slow(
+        policy: WhileDisconnected,
+    ) -> Arc<Reconnecting<impl Connector<Transport = Flaky>>> {
+        let counters = Arc::new(Counters::default());
+        Arc::new(
+            Reconnecting::new(move || {
+                let counters = Arc::clone(&counters);
+                async move {
+                    tokio::time::sleep(Duration::from_millis(30)).await;
+                    Ok(Flaky { nth: 100, counters })
+                }
+            })
+            .while_disconnected(policy),
+        )
+    }
+
+    async fn concurrent<C: Connector + 'static>(
+        transport: Arc<Reconnecting<C>>,
+    ) -> Vec<bool> {
+        let calls = (0..3).map(|_| {
+            let transport = Arc::clone(&transport);
+            tokio::spawn(async move {
+                let request = Request::new_reply("echo", None);
+                transport.send(&request).await.is_ok()
+            })
+        });
+        let mut results = Vec::new();
+        for call in calls.collect::<Vec<_>>() {
+            results.push(call.await.unwrap());
+            // Let the first call start connecting.
+            tokio::task::yield_now().await;
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn reconnect_while_disconnected() {
+        let results = concurrent(slow(WhileDisconnected::FailFast)).await;
+        assert_eq!(1, results.iter().filter(|ok| **ok).count());
+
+        let results = concurrent(slow(WhileDisconnected::Queue(1))).await;
+        assert_eq!(2, results.iter().filter(|ok| **ok).count());
+
+        let results = concurrent(slow(WhileDisconnected::Queue(8))).await;
+        assert_eq!(vec![true, true, true], results);
+    }
+
+    #[tokio::test]
+    async fn reconnect_cancelled_waiter() {
+        let transport = slow(WhileDisconnected::Queue(1));
+        let connecting = tokio::spawn({
+            let transport = Arc::clone(&transport);
+            async move {
+                let request = Request::new_reply("echo", None);
+                transport.send(&request).await.is_ok()
+            }
+        });
+        tokio::task::yield_now().await;
+        let request = Request::new_reply("echo", None);
+        let waiter = tokio::time::timeout(
+            Duration::from_millis(5),
+            transport.send(&request),
+        );
+        assert!(waiter.await.is_err());
+        assert_eq!(0, transport.waiting.load(Ordering::SeqCst));
+        assert!(connecting.await.unwrap());
+
+        // The slot of the cancelled call is free once disconnected
+        let (connection, _) = transport.connected().unwrap();
+        transport.disconnect(connection);
+        let results = concurrent(transport).await;
+        assert_eq!(2, results.iter().filter(|ok| **ok).count());
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        let transport = flaky(2, &Arc::new(Counters::default()))
+            .backoff(Duration::from_millis(100), Duration::from_millis(500));
+        for attempt in 1..=5 {
+            let delay = transport.delay(attempt);
+            let full = Duration::from_millis(100 * (1 << (attempt - 1)))
+                .min(Duration::from_millis(500));
+            assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+        }
+        let transport = transport.jitter(false);
+        assert_eq!(Duration::from_millis(400), transport.delay(3));
+        assert_eq!(Duration::from_millis(500), transport.delay(30));
+    }
+}
@@ -0,0 +1,155 @@
+//! Redact sensitive values before messages are logged.
+//!
+//! [RedactionRules](RedactionRules) match object keys anywhere in the
+//! parameters of a request or the result of a response, as well as
+//! explicit JSON pointers, and replace the matching values with `"***"`.
+//! Use [Request::redacted()](crate::Request::redacted) and
+//! [Response::redacted()](crate::Response::redacted) to serialize
+//! messages for logging or pass the rules to
+//! [Logged::redaction()](crate::logged::Logged::redaction).
+
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Replacement for redacted values.
+pub const REDACTED: &str = "***";
+
+/// Rules for the values that should be redacted.
+#[derive(Debug, Clone, Default)]
+pub struct RedactionRules {
+    keys: HashSet<String>,
+    pointers: Vec<String>,
+}
+
+impl RedactionRules {
+    /// Create empty rules that redact nothing.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Redact the value of every object key with this name.
+    pub fn key(mut self, name: &str) -> Self {
+        self.keys.insert(name.to_string());
+        self
+    }
+
+    /// Redact the value at a JSON pointer, for example `/auth/token`
+    /// or `/0` for the first positional parameter.
+    ///
+    /// Pointers are relative to the request parameters or the
+    /// response result.
+    pub fn pointer(mut self, pointer: &str) -> Self {
+        self.pointers.push(pointer.to_string());
+        self
+    }
+
+    /// Determine if the rules redact nothing.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.pointers.is_empty()
+    }
+
+    /// Return a copy of `value` with the matching values redacted.
+    pub fn apply(&self, value: &Value) -> Value {
+        let mut value = value.clone();
+        self.redact_keys(&mut value);
+        for pointer in self.pointers.iter() {
+            if let Some(target) = value.pointer_mut(pointer) {
+                *target = Value::String(REDACTED.to_string());
+            }
+        }
+        value
+    }
+
+    fn redact_keys(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.keys.contains(key) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_keys(value);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items.iter_mut() {
+                    self.redact_keys(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Request, Response, RpcError};
+    use serde_json::json;
+
+    fn rules() -> RedactionRules {
+        RedactionRules::new()
+            .key("password")
+            .key("private_key")
+            .pointer("/auth/token")
+            .pointer("/1")
+    }
+
+    #[test]
+    fn redact_keys_and_pointers() {
+        let params = json!({
+            "user": "muji",
+            "password": "secret",
+            "wallets": [{"name": "main", "private_key": "0xabc"}],
+            "auth": {"token": "t0k3n", "scheme": "bearer"},
+        });
+        assert_eq!(
+            json!({
+                "user": "muji",
+                "password": "***",
+                "wallets": [{"name": "main", "private_key": "***"}],
+                "auth": {"token": "***", "scheme": "bearer"},
+            }),
+            rules().apply(&params)
+        );
+        assert_eq!(
+            json!(["muji", "***", "keep"]),
+            rules().apply(&json!(["muji", "secret", "keep"]))
+        );
+    }
+
+    #[test]
+    fn redact_messages() {
+        let request = Request::new(
+            Some(json!(1)),
+            "login".to_string(),
+            Some(json!({"password": "secret"})),
+        );
+        assert_eq!(
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "login",
+                "params": {"password": "***"},
+            }),
+            request.redacted(&rules())
+        );
+
+        let response: Response =
+            (&request, json!({"private_key": "0xabc"})).into();
+        assert_eq!(
+            json!({"private_key": "***"}),
+            response.redacted(&rules())["result"]
+        );
+
+        let error = RpcError::new(
+            "denied".to_string(),
+            Some(json!({"password": "secret"})),
+        );
+        let response: Response = (&request, error).into();
+        assert_eq!(
+            json!({"password": "***"}),
+            response.redacted(&rules())["error"]["data"]
+        );
+    }
+}
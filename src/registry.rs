@@ -0,0 +1,256 @@
+//! Services that can be added and removed at runtime.
+//!
+//! A [Registry](Registry) is itself a [Service](crate::Service) so it
+//! can be given to a [Server](crate::Server) once; services added to or
+//! removed from the registry take effect for subsequent requests
+//! without rebuilding the server:
+//!
+//! ```
+//! use json_rpc2::{
+//!     health::PingService, registry::Registry, Request, Server, Service,
+//! };
+//! use std::sync::Arc;
+//!
+//! let registry = Arc::new(Registry::new());
+//! let service: Arc<dyn Service<Data = ()>> = registry.clone();
+//! let server = Server::new_shared(vec![service]);
+//!
+//! let request = Request::new_reply("rpc.ping", None);
+//! let id = registry.add(Arc::new(PingService::new()));
+//! assert!(server.serve(&request, &()).unwrap().error().is_none());
+//!
+//! registry.remove(&id);
+//! assert!(server.serve(&request, &()).unwrap().error().is_some());
+//! ```
+
+use crate::{
+    describe::MethodMeta,
+    routes::{self, Routable, Services},
+    Request, Response, Result, Service,
+};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+
+/// Handle identifying a service added to a [Registry](Registry).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct RegistrationId(u64);
+
+type Entry<T> = (RegistrationId, Arc<dyn Service<Data = T>>);
+type Entries<T> = Arc<Services<Entry<T>>>;
+
+impl<T> Routable for Entry<T> {
+    fn route(&self) -> Option<Vec<String>> {
+        routes::route(self.1.methods(), self.1.route_by_methods())
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.1.method_meta()
+    }
+}
+
+/// Collection of services that can change while serving requests.
+///
+/// Services are called in the order they were added. Requests are
+/// served from a snapshot of the services so a handler may add or
+/// remove services without deadlocking; the change applies to the
+/// next request. The snapshot keeps the dispatch table of its
+/// services, rebuilt on every change, so the registry tells the
+/// server it cannot be routed by its methods.
+pub struct Registry<T> {
+    next: AtomicU64,
+    entries: RwLock<Entries<T>>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            next: AtomicU64::new(0),
+            entries: RwLock::new(Default::default()),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a service and return the handle used to remove it.
+    pub fn add(&self, service: Arc<dyn Service<Data = T>>) -> RegistrationId {
+        let id = RegistrationId(self.next.fetch_add(1, Ordering::Relaxed));
+        let mut entries = self.entries.write().unwrap();
+        let updated = entries
+            .iter()
+            .cloned()
+            .chain(std::iter::once((id, service)))
+            .collect();
+        *entries = Arc::new(updated);
+        id
+    }
+
+    /// Remove a service, returns `false` if it was already removed.
+    pub fn remove(&self, id: &RegistrationId) -> bool {
+        let mut entries = self.entries.write().unwrap();
+        if !entries.iter().any(|(entry, _)| entry == id) {
+            return false;
+        }
+        let updated = entries
+            .iter()
+            .filter(|(entry, _)| entry != id)
+            .cloned()
+            .collect();
+        *entries = Arc::new(updated);
+        true
+    }
+
+    /// Remove all services.
+    pub fn clear(&self) {
+        *self.entries.write().unwrap() = Default::default();
+    }
+
+    /// The number of registered services.
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    /// Determine if no services are registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn snapshot(&self) -> Entries<T> {
+        Arc::clone(&self.entries.read().unwrap())
+    }
+}
+
+impl<T> Service for Registry<T> {
+    type Data = T;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let entries = self.snapshot();
+        for (_, service) in entries.candidates(request.method()) {
+            if let Some(response) = service.handle(request, ctx)? {
+                return Ok(Some(response));
+            }
+        }
+        Ok(None)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.snapshot()
+            .iter()
+            .flat_map(|(_, service)| service.methods())
+            .collect()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.snapshot()
+            .iter()
+            .flat_map(|(_, service)| service.method_meta())
+            .collect()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Server;
+    use serde_json::json;
+
+    struct Named(&'static str);
+
+    impl Service for Named {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            if request.method() == self.0 {
+                Ok(Some((request, json!(self.0)).into()))
+            } else {
+                Ok(None)
+            }
+        }
+
+        fn methods(&self) -> Vec<String> {
+            vec![self.0.to_string()]
+        }
+    }
+
+    #[test]
+    fn registry_add_remove() {
+        let registry = Arc::new(Registry::new());
+        let service: Arc<dyn Service<Data = ()>> = registry.clone();
+        let server = Server::new_shared(vec![service]);
+        let foo = Request::new_reply("foo", None);
+        let bar = Request::new_reply("bar", None);
+
+        let foo_id = registry.add(Arc::new(Named("foo")));
+        registry.add(Arc::new(Named("bar")));
+        assert_eq!(2, registry.len());
+        assert_eq!(
+            &Some(json!("foo")),
+            server.serve(&foo, &()).unwrap().result()
+        );
+
+        assert!(registry.remove(&foo_id));
+        assert!(!registry.remove(&foo_id));
+        assert!(server.serve(&foo, &()).unwrap().error().is_some());
+        assert_eq!(
+            &Some(json!("bar")),
+            server.serve(&bar, &()).unwrap().result()
+        );
+
+        assert_eq!(vec!["bar".to_string()], server.methods());
+
+        registry.clear();
+        assert!(registry.is_empty());
+        assert!(server.serve(&bar, &()).unwrap().error().is_some());
+    }
+
+    #[test]
+    fn registry_concurrent() {
+        let registry = Arc::new(Registry::new());
+        registry.add(Arc::new(Named("stable")));
+
+        let writer = {
+            let registry = Arc::clone(&registry);
+            std::thread::spawn(move || {
+                for _ in 0..1_000 {
+                    let id = registry.add(Arc::new(Named("plugin")));
+                    assert!(registry.remove(&id));
+                }
+            })
+        };
+
+        let reader = {
+            let registry = Arc::clone(&registry);
+            std::thread::spawn(move || {
+                let request = Request::new_reply("stable", None);
+                for _ in 0..1_000 {
+                    let response = registry.handle(&request, &()).unwrap();
+                    assert_eq!(
+                        &Some(json!("stable")),
+                        response.unwrap().result()
+                    );
+                }
+            })
+        };
+
+        writer.join().unwrap();
+        reader.join().unwrap();
+        assert_eq!(1, registry.len());
+    }
+}
@@ -0,0 +1,265 @@
+//! Dispatch table built from the methods listed by services.
+//!
+//! A server asks its services in order until one answers. Services
+//! that list their methods are only asked for those methods so a
+//! request costs one hash lookup instead of a call to every service;
+//! services that list nothing, or opt out with
+//! [route_by_methods()](crate::Service::route_by_methods), keep their
+//! place in the order and are asked for every method.
+
+use crate::{describe::MethodMeta, raw, Request, Response, Result};
+use std::{collections::HashMap, iter::FromIterator};
+
+/// Service that can be placed in a dispatch table.
+pub(crate) trait Routable {
+    /// The methods the service is asked for, `None` for every method.
+    fn route(&self) -> Option<Vec<String>>;
+
+    /// Descriptions of the methods of the service.
+    fn method_meta(&self) -> Vec<MethodMeta>;
+}
+
+/// Indices of the services to ask for each method, in order.
+#[derive(Debug, Default)]
+struct Routes {
+    methods: HashMap<String, Vec<usize>>,
+    unlisted: Vec<usize>,
+}
+
+impl Routes {
+    fn new<I>(services: I) -> Self
+    where
+        I: IntoIterator<Item = Option<Vec<String>>>,
+    {
+        let mut methods: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut unlisted = Vec::new();
+        for (index, route) in services.into_iter().enumerate() {
+            match route {
+                Some(names) => {
+                    for name in names {
+                        let indices = methods.entry(name).or_default();
+                        if indices.last() != Some(&index) {
+                            indices.push(index);
+                        }
+                    }
+                }
+                None => unlisted.push(index),
+            }
+        }
+        for indices in methods.values_mut() {
+            indices.extend(&unlisted);
+            indices.sort_unstable();
+            indices.dedup();
+        }
+        Self { methods, unlisted }
+    }
+
+    fn candidates(&self, method: &str) -> &[usize] {
+        self.methods.get(method).unwrap_or(&self.unlisted)
+    }
+}
+
+/// Whether services list a method.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Listing {
+    /// A service lists the method.
+    Listed,
+    /// Only services that do not list their methods may handle it.
+    Unlisted,
+    /// No service handles the method.
+    Unknown,
+}
+
+/// Services of a server in order with their dispatch table and the
+/// notes of deprecated methods, which are rebuilt whenever services are
+/// added.
+pub(crate) struct Services<S> {
+    list: Vec<S>,
+    routes: Routes,
+    deprecated: HashMap<String, String>,
+}
+
+impl<S: Routable> Services<S> {
+    /// The services to ask for `method` in order.
+    pub(crate) fn candidates<'s>(
+        &'s self,
+        method: &str,
+    ) -> impl Iterator<Item = &'s S> + 's {
+        self.routes
+            .candidates(method)
+            .iter()
+            .map(move |index| &self.list[*index])
+    }
+
+    pub(crate) fn push(&mut self, service: S) {
+        self.list.push(service);
+        self.reroute();
+    }
+
+    pub(crate) fn insert(&mut self, index: usize, service: S) {
+        self.list.insert(index, service);
+        self.reroute();
+    }
+
+    pub(crate) fn extend<I: IntoIterator<Item = S>>(&mut self, services: I) {
+        self.list.extend(services);
+        self.reroute();
+    }
+
+    /// Whether services list `method`.
+    pub(crate) fn listing(&self, method: &str) -> Listing {
+        if self.routes.methods.contains_key(method) {
+            Listing::Listed
+        } else if self.routes.unlisted.is_empty() {
+            Listing::Unknown
+        } else {
+            Listing::Unlisted
+        }
+    }
+
+    /// The deprecation note of `method`, from the first service that
+    /// describes it.
+    pub(crate) fn deprecation(&self, method: &str) -> Option<&str> {
+        self.deprecated.get(method).map(String::as_str)
+    }
+
+    fn reroute(&mut self) {
+        self.routes = Routes::new(self.list.iter().map(Routable::route));
+        self.deprecated.clear();
+        for meta in self.list.iter().flat_map(Routable::method_meta) {
+            if let Some(note) = meta.deprecated {
+                self.deprecated.entry(meta.name).or_insert(note);
+            }
+        }
+    }
+}
+
+impl<S> Default for Services<S> {
+    fn default() -> Self {
+        Self {
+            list: Vec::new(),
+            routes: Default::default(),
+            deprecated: Default::default(),
+        }
+    }
+}
+
+impl<S> std::ops::Deref for Services<S> {
+    type Target = [S];
+    fn deref(&self) -> &[S] {
+        &self.list
+    }
+}
+
+impl<S: Routable> FromIterator<S> for Services<S> {
+    fn from_iter<I: IntoIterator<Item = S>>(services: I) -> Self {
+        let mut services = Self {
+            list: services.into_iter().collect(),
+            routes: Default::default(),
+            deprecated: Default::default(),
+        };
+        services.reroute();
+        services
+    }
+}
+
+impl<S> IntoIterator for Services<S> {
+    type Item = S;
+    type IntoIter = std::vec::IntoIter<S>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.into_iter()
+    }
+}
+
+/// Service asked for every method whatever it lists, used for the
+/// fallback of a builder.
+pub(crate) struct Fallback<S>(pub(crate) S);
+
+impl<S: crate::Service> crate::Service for Fallback<S> {
+    type Data = S::Data;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.0.handle(request, ctx)
+    }
+
+    fn handle_raw(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<raw::Reply>> {
+        self.0.handle_raw(request, ctx)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.0.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.0.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<S: crate::futures::Service> crate::futures::Service for Fallback<S> {
+    type Data = S::Data;
+
+    async fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.0.handle(request, ctx).await
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.0.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.0.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        false
+    }
+}
+
+/// The route of a service from its methods.
+pub(crate) fn route(methods: Vec<String>, routed: bool) -> Option<Vec<String>> {
+    (routed && !methods.is_empty()).then_some(methods)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(methods: &[&str]) -> Option<Vec<String>> {
+        Some(methods.iter().map(|method| method.to_string()).collect())
+    }
+
+    #[test]
+    fn routes_keep_order() {
+        let routes = Routes::new(vec![
+            names(&["a", "b"]),
+            None,
+            names(&["b", "c", "c"]),
+            None,
+        ]);
+        assert_eq!(&[0, 1, 3], routes.candidates("a"));
+        assert_eq!(&[0, 1, 2, 3], routes.candidates("b"));
+        assert_eq!(&[1, 2, 3], routes.candidates("c"));
+        assert_eq!(&[1, 3], routes.candidates("missing"));
+
+        let routes = Routes::new(vec![names(&["a"])]);
+        assert!(routes.candidates("missing").is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! JSON Schemas for the envelope types, requires the `schemars` feature.
+//!
+//! [Request](crate::Request), [Response](crate::Response) and
+//! [RpcError](crate::RpcError) implement `JsonSchema` so their schemas
+//! can be embedded in API documentation:
+//!
+//! ```
+//! use json_rpc2::Response;
+//!
+//! let schema = schemars::schema_for!(Response);
+//! let schema = serde_json::to_value(&schema).unwrap();
+//! assert_eq!(2, schema["oneOf"].as_array().unwrap().len());
+//! ```
+//!
+//! The schemas describe the messages of the specification: an id is a
+//! string, a number or `null` and may be missing from a request, which
+//! makes it a notification; parameters are structured; and a response
+//! has either a `result` or an `error`. Unknown fields are allowed as
+//! they are with the `extra-fields` feature.
+
+use crate::{Request, Response, RpcError};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde_json::{json, Value};
+
+/// Description of the error code ranges.
+const CODES: &str = "-32700 parse error, -32600 invalid request, \
+    -32601 method not found, -32602 invalid params, -32603 internal \
+    error, -32000 to -32099 reserved for implementation-defined server \
+    errors; the remainder of the space is available for \
+    application-defined errors.";
+
+fn schema(value: Value) -> Schema {
+    serde_json::from_value(value).expect("schema is valid")
+}
+
+fn version() -> Value {
+    json!({"type": "string", "enum": ["2.0"]})
+}
+
+fn id(description: &str) -> Value {
+    json!({
+        "type": ["string", "number", "null"],
+        "description": description,
+    })
+}
+
+impl JsonSchema for Request {
+    fn schema_name() -> String {
+        "Request".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        schema(json!({
+            "description": "JSON-RPC 2.0 request, a notification when \
+                the id is missing.",
+            "type": "object",
+            "required": ["jsonrpc", "method"],
+            "properties": {
+                "jsonrpc": version(),
+                "method": {"type": "string"},
+                "id": id("Identifier echoed in the response."),
+                "params": {"type": ["array", "object"]},
+                "_meta": {"type": "object"},
+            },
+        }))
+    }
+}
+
+impl JsonSchema for RpcError {
+    fn schema_name() -> String {
+        "RpcError".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        schema(json!({
+            "description": "Error information for response messages.",
+            "type": "object",
+            "required": ["code", "message"],
+            "properties": {
+                "code": {"type": "integer", "description": CODES},
+                "message": {"type": "string"},
+                "data": {},
+            },
+        }))
+    }
+}
+
+impl JsonSchema for Response {
+    fn schema_name() -> String {
+        "Response".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let error = serde_json::to_value(gen.subschema_for::<RpcError>())
+            .expect("schema serializes");
+        schema(json!({
+            "description": "JSON-RPC 2.0 response with either a result \
+                or an error.",
+            "type": "object",
+            "required": ["jsonrpc", "id"],
+            "properties": {
+                "jsonrpc": version(),
+                "id": id("Identifier of the request, null when it could \
+                    not be determined."),
+                "result": {},
+                "error": error,
+            },
+            "oneOf": [
+                {"required": ["result"], "not": {"required": ["error"]}},
+                {"required": ["error"], "not": {"required": ["result"]}},
+            ],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Error, Request, Response, RpcError};
+    use jsonschema::Validator;
+    use serde_json::{json, Value};
+
+    fn validator(schema: schemars::schema::RootSchema) -> Validator {
+        let schema = serde_json::to_value(&schema).unwrap();
+        jsonschema::validator_for(&schema).unwrap()
+    }
+
+    #[test]
+    fn schema_request() {
+        let validator = validator(schemars::schema_for!(Request));
+        let requests = [
+            Request::new_reply("sum", Some(json!([1, 2]))),
+            Request::new_notification("update", Some(json!({"a": 1}))),
+            Request::new(Some(Value::Null), "null_id".to_string(), None),
+        ];
+        for request in requests.iter() {
+            let request = serde_json::to_value(request).unwrap();
+            assert!(validator.is_valid(&request), "{}", request);
+        }
+        let invalid = [
+            json!({"jsonrpc": "1.0", "method": "sum"}),
+            json!({"jsonrpc": "2.0", "method": "sum", "params": 1}),
+            json!({"jsonrpc": "2.0", "method": "sum", "id": [1]}),
+            json!({"jsonrpc": "2.0", "id": 1}),
+        ];
+        for request in invalid.iter() {
+            assert!(!validator.is_valid(request), "{}", request);
+        }
+    }
+
+    #[test]
+    fn schema_response() {
+        let validator = validator(schemars::schema_for!(Response));
+        let request = Request::new_reply("sum", None);
+        let result: Response = (&request, json!(3)).into();
+        let error: Response = (
+            &request,
+            RpcError::new("failed".to_string(), Some(json!({"a": 1}))),
+        )
+            .into();
+        let parse: Response = Error::from(Box::from("parse")).into();
+        for response in [result, error, parse].iter() {
+            let response = serde_json::to_value(response).unwrap();
+            assert!(validator.is_valid(&response), "{}", response);
+        }
+        let invalid = [
+            json!({"jsonrpc": "2.0", "id": 1}),
+            json!({"jsonrpc": "2.0", "id": 1, "result": 1,
+                "error": {"code": 1, "message": "m"}}),
+            json!({"jsonrpc": "2.0", "id": 1, "error": {"code": 1.5, "message": "m"}}),
+            json!({"jsonrpc": "2.0", "result": 1}),
+        ];
+        for response in invalid.iter() {
+            assert!(!validator.is_valid(response), "{}", response);
+        }
+    }
+}
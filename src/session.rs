@@ -0,0 +1,166 @@
+//! State that lives as long as a connection.
+//!
+//! Connections such as WebSockets carry state that outlives a request,
+//! for example the authenticated user or the subscriptions of the
+//! client. Putting it in the service context would make the context
+//! specific to one connection; instead build a [Session](Session) for
+//! each connection and pass it to a serving loop, which inserts the
+//! state into the [extensions](crate::extensions) of every request and
+//! calls the cleanup function once the connection is done:
+//!
+//! * [serve_lines_with()](crate::shutdown::serve_lines_with)
+//! * [serve_until_with()](crate::futures::serve_until_with), requires
+//!   the `async` feature.
+//! * [serve_tcp_with()](crate::net::serve_tcp_with) and
+//!   [serve_stream_with()](crate::net::serve_stream_with), require the
+//!   `net` feature.
+//!
+//! Handlers read the state with `request.extensions().get::<S>()`:
+//!
+//! ```
+//! use json_rpc2::{session::Session, shutdown::{serve_lines_with, ShutdownHandle}, *};
+//! use std::sync::{Arc, Mutex};
+//!
+//! #[derive(Default)]
+//! struct Auth {
+//!     user: Mutex<Option<String>>,
+//! }
+//!
+//! struct ServiceHandler;
+//! impl Service for ServiceHandler {
+//!     type Data = ();
+//!     fn handle(&self, request: &Request, _ctx: &()) -> Result<Option<Response>> {
+//!         let auth = request.extensions().get::<Auth>().unwrap();
+//!         let response = match request.method() {
+//!             "login" => {
+//!                 let (user,): (String,) = request.deserialize()?;
+//!                 *auth.user.lock().unwrap() = Some(user);
+//!                 Some((request, serde_json::Value::Bool(true)).into())
+//!             }
+//!             "whoami" => {
+//!                 let user = auth.user.lock().unwrap().clone();
+//!                 Some((request, serde_json::json!(user)).into())
+//!             }
+//!             _ => None,
+//!         };
+//!         Ok(response)
+//!     }
+//! }
+//!
+//! let service: Box<dyn Service<Data = ()>> = Box::new(ServiceHandler);
+//! let server = Server::new(vec![&service]);
+//! let closed = Arc::new(Mutex::new(None));
+//! let session = Session::new(Auth::default()).on_close({
+//!     let closed = Arc::clone(&closed);
+//!     move |auth: &Auth| *closed.lock().unwrap() = auth.user.lock().unwrap().clone()
+//! });
+//! let input = concat!(
+//!     r#"{"jsonrpc":"2.0","id":1,"method":"login","params":["alice"]}"#, "\n",
+//!     r#"{"jsonrpc":"2.0","id":2,"method":"whoami"}"#, "\n",
+//! );
+//! let mut output = Vec::new();
+//! serve_lines_with(&server, &(), input.as_bytes(), &mut output, &ShutdownHandle::new(), session)?;
+//! assert!(String::from_utf8(output).unwrap().contains(r#""result":"alice""#));
+//! assert_eq!(Some("alice".to_string()), *closed.lock().unwrap());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! For other loops insert the state with
+//! [attach()](Session::attach) and drop the session when the
+//! connection closes; for a stream of requests move the session into
+//! the closure that maps the stream.
+
+use crate::Request;
+use std::fmt;
+use std::sync::Arc;
+
+type Cleanup<S> = dyn FnOnce(&S) + Send;
+
+/// State of a connection and the function to call when it closes.
+///
+/// The cleanup function runs when the session is dropped, which the
+/// serving loops do when the connection ends for any reason. Requests
+/// still holding the state after that only keep it alive.
+pub struct Session<S> {
+    state: Arc<S>,
+    cleanup: Option<Box<Cleanup<S>>>,
+}
+
+impl<S: Send + Sync + 'static> Session<S> {
+    /// Create a session for the state of a connection.
+    pub fn new(state: S) -> Self {
+        Self {
+            state: Arc::new(state),
+            cleanup: None,
+        }
+    }
+
+    /// Call `cleanup` with the state when the connection closes.
+    pub fn on_close<F>(mut self, cleanup: F) -> Self
+    where
+        F: FnOnce(&S) + Send + 'static,
+    {
+        self.cleanup = Some(Box::new(cleanup));
+        self
+    }
+
+    /// The state of the connection.
+    pub fn state(&self) -> &Arc<S> {
+        &self.state
+    }
+
+    /// Insert the state into the extensions of a request.
+    pub fn attach(&self, request: &mut Request) {
+        request.extensions_mut().insert_arc(Arc::clone(&self.state));
+    }
+}
+
+impl<S> Drop for Session<S> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            cleanup(&self.state);
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Session<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn session_attach_and_close() {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let session = Session::new(AtomicUsize::new(0)).on_close({
+            let closed = Arc::clone(&closed);
+            move |count: &AtomicUsize| {
+                closed.store(count.load(Ordering::SeqCst), Ordering::SeqCst)
+            }
+        });
+        let mut requests = [
+            Request::new_reply("a", None),
+            Request::new_notification("b", None),
+        ];
+        for request in requests.iter_mut() {
+            session.attach(request);
+            request
+                .extensions()
+                .get::<AtomicUsize>()
+                .unwrap()
+                .fetch_add(1, Ordering::SeqCst);
+        }
+        assert_eq!(2, session.state().load(Ordering::SeqCst));
+        assert_eq!(0, closed.load(Ordering::SeqCst));
+        drop(session);
+        assert_eq!(2, closed.load(Ordering::SeqCst));
+        assert!(requests[0].extensions().contains::<AtomicUsize>());
+    }
+}
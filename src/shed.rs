@@ -0,0 +1,315 @@
+//! Reject requests while a service is saturated.
+//!
+//! Wrap a service in [LoadShed](LoadShed), or
+//! [AsyncLoadShed](AsyncLoadShed) with the `async` feature, to answer
+//! requests over the concurrency limit immediately with a `-32000`
+//! server busy error instead of queuing them. The error data contains
+//! the configured limit. Notifications over the limit are dropped and
+//! counted, see [ShedCounter::dropped()](ShedCounter::dropped).
+//!
+//! While saturated every request is rejected, including methods the
+//! inner service would not handle, so wrap only the services that
+//! need protecting.
+
+use crate::{
+    describe::MethodMeta, Request, Response, Result, RpcError, Service,
+};
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Error code for requests shed because the server is busy.
+pub const SERVER_BUSY: isize = -32000;
+
+#[derive(Default)]
+struct Counts {
+    in_flight: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+/// Handle for reading the counters of a load shedding service.
+#[derive(Clone)]
+pub struct ShedCounter {
+    counts: Arc<Counts>,
+    limit: usize,
+}
+
+impl ShedCounter {
+    fn new(limit: usize) -> Self {
+        Self {
+            counts: Default::default(),
+            limit,
+        }
+    }
+
+    /// Number of requests currently being handled.
+    pub fn in_flight(&self) -> usize {
+        self.counts.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Number of notifications dropped while saturated.
+    pub fn dropped(&self) -> u64 {
+        self.counts.dropped.load(Ordering::SeqCst)
+    }
+
+    /// The reply for a request over the limit.
+    fn shed(&self, request: &Request) -> Response {
+        if request.id().is_none() {
+            self.counts.dropped.fetch_add(1, Ordering::SeqCst);
+            return request.into();
+        }
+        let err = RpcError {
+            code: SERVER_BUSY,
+            message: "Server busy".into(),
+            data: Some(json!({"limit": self.limit})),
+        };
+        (request, err).into()
+    }
+}
+
+/// Decrements the in-flight count when the request finishes.
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Service that rejects requests once more than `limit` are being
+/// handled by an inner service.
+pub struct LoadShed<S> {
+    inner: S,
+    counter: ShedCounter,
+}
+
+impl<S> LoadShed<S> {
+    /// Shed requests to an inner service over `limit` concurrent
+    /// requests.
+    pub fn new(inner: S, limit: usize) -> Self {
+        Self {
+            inner,
+            counter: ShedCounter::new(limit),
+        }
+    }
+
+    /// Handle for reading the counters after the service has been
+    /// boxed.
+    pub fn counter(&self) -> ShedCounter {
+        self.counter.clone()
+    }
+}
+
+impl<S: Service> Service for LoadShed<S> {
+    type Data = S::Data;
+
+    fn handle(
+        &self,
+        request: &Request,
+        ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        let in_flight = &self.counter.counts.in_flight;
+        let count = in_flight.fetch_add(1, Ordering::SeqCst);
+        let _guard = InFlightGuard(in_flight);
+        if count >= self.counter.limit {
+            return Ok(Some(self.counter.shed(request)));
+        }
+        self.inner.handle(request, ctx)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.inner.methods()
+    }
+
+    fn method_meta(&self) -> Vec<MethodMeta> {
+        self.inner.method_meta()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        self.inner.route_by_methods()
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+pub use self::asynchronous::AsyncLoadShed;
+
+#[cfg(any(test, feature = "async"))]
+mod asynchronous {
+    use super::{InFlightGuard, ShedCounter};
+    use crate::{
+        describe::MethodMeta, futures::Service, Request, Response, Result,
+    };
+    use std::sync::atomic::Ordering;
+    use tokio::sync::Semaphore;
+
+    /// Async service that rejects requests once more than `limit` are
+    /// being handled by an inner service.
+    ///
+    /// Only available with the `async` feature.
+    pub struct AsyncLoadShed<S> {
+        inner: S,
+        semaphore: Semaphore,
+        counter: ShedCounter,
+    }
+
+    impl<S> AsyncLoadShed<S> {
+        /// Shed requests to an inner service over `limit` concurrent
+        /// requests.
+        pub fn new(inner: S, limit: usize) -> Self {
+            Self {
+                inner,
+                semaphore: Semaphore::new(limit),
+                counter: ShedCounter::new(limit),
+            }
+        }
+
+        /// Handle for reading the counters after the service has
+        /// been boxed.
+        pub fn counter(&self) -> ShedCounter {
+            self.counter.clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<S: Service> Service for AsyncLoadShed<S> {
+        type Data = S::Data;
+
+        async fn handle(
+            &self,
+            request: &Request,
+            ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let _permit = match self.semaphore.try_acquire() {
+                Ok(permit) => permit,
+                Err(_) => return Ok(Some(self.counter.shed(request))),
+            };
+            let in_flight = &self.counter.counts.in_flight;
+            in_flight.fetch_add(1, Ordering::SeqCst);
+            let _guard = InFlightGuard(in_flight);
+            self.inner.handle(request, ctx).await
+        }
+
+        fn methods(&self) -> Vec<String> {
+            self.inner.methods()
+        }
+
+        fn method_meta(&self) -> Vec<MethodMeta> {
+            self.inner.method_meta()
+        }
+
+        fn route_by_methods(&self) -> bool {
+            self.inner.route_by_methods()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Server;
+    use serde_json::Value;
+    use std::sync::Barrier;
+
+    #[test]
+    fn shed_when_saturated() {
+        struct Blocking(Arc<Barrier>);
+
+        impl Service for Blocking {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                if request.method() == "block" {
+                    self.0.wait();
+                    self.0.wait();
+                }
+                Ok(Some((request, Value::Bool(true)).into()))
+            }
+        }
+
+        let barrier = Arc::new(Barrier::new(2));
+        let shed = LoadShed::new(Blocking(Arc::clone(&barrier)), 1);
+        let counter = shed.counter();
+        let service: Box<dyn Service<Data = ()>> = Box::new(shed);
+        let server = Server::new(vec![&service]);
+
+        std::thread::scope(|scope| {
+            let blocked = scope.spawn(|| {
+                server.serve(&Request::new_reply("block", None), &())
+            });
+            barrier.wait();
+            assert_eq!(1, counter.in_flight());
+
+            let response =
+                server.serve(&Request::new_reply("other", None), &());
+            let error: Option<RpcError> = response.unwrap().into();
+            let error = error.unwrap();
+            assert_eq!(SERVER_BUSY, error.code);
+            assert_eq!(Some(json!({"limit": 1})), error.data);
+
+            let notification = Request::new_notification("event", None);
+            assert!(server.serve(&notification, &()).is_none());
+            assert_eq!(1, counter.dropped());
+
+            barrier.wait();
+            let response = blocked.join().unwrap();
+            assert_eq!(Some(Value::Bool(true)), response.unwrap().into());
+        });
+
+        assert_eq!(0, counter.in_flight());
+        let response = server.serve(&Request::new_reply("other", None), &());
+        assert_eq!(Some(Value::Bool(true)), response.unwrap().into());
+    }
+
+    #[tokio::test]
+    async fn shed_async_when_saturated() {
+        use crate::futures::{Server, Service};
+        use tokio::sync::Notify;
+
+        struct Gate(Arc<Notify>);
+
+        #[async_trait::async_trait]
+        impl Service for Gate {
+            type Data = ();
+            async fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                if request.method() == "block" {
+                    self.0.notified().await;
+                }
+                Ok(Some((request, Value::Bool(true)).into()))
+            }
+        }
+
+        let gate = Arc::new(Notify::new());
+        let shed = AsyncLoadShed::new(Gate(Arc::clone(&gate)), 1);
+        let counter = shed.counter();
+        let service: Box<dyn Service<Data = ()>> = Box::new(shed);
+        let server = Server::new(vec![&service]);
+        let block = Request::new_reply("block", None);
+        let other = Request::new_reply("other", None);
+        let notification = Request::new_notification("event", None);
+
+        let (a, (b, c)) = tokio::join!(server.serve(&block, &()), async {
+            while counter.in_flight() == 0 {
+                tokio::task::yield_now().await;
+            }
+            let b = server.serve(&other, &()).await;
+            let c = server.serve(&notification, &()).await;
+            gate.notify_one();
+            (b, c)
+        });
+        assert_eq!(Some(Value::Bool(true)), a.unwrap().into());
+        let error: Option<RpcError> = b.unwrap().into();
+        assert_eq!(SERVER_BUSY, error.unwrap().code);
+        assert!(c.is_none());
+        assert_eq!(1, counter.dropped());
+        assert_eq!(0, counter.in_flight());
+    }
+}
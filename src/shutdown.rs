@@ -0,0 +1,479 @@
+//! Graceful shutdown for the serving loops.
+//!
+//! Serving loops stop reading new requests once a
+//! [ShutdownHandle](ShutdownHandle) is triggered (or for async loops a
+//! shutdown future resolves), finish the requests that are in flight,
+//! flush the pending responses and return [ServedStats](ServedStats):
+//!
+//! * [serve_lines()](serve_lines) for newline delimited messages such
+//!   as stdio, or [serve_lines_with()](serve_lines_with) for a
+//!   connection with a [session](crate::session).
+//! * [ThreadPoolServer](crate::pool::ThreadPoolServer) using
+//!   [shutdown_timeout()](crate::pool::ThreadPoolServer::shutdown_timeout).
+//! * [serve_until()](crate::futures::serve_until) for a stream of
+//!   requests, or [serve_until_with()](crate::futures::serve_until_with)
+//!   with a session, requires the `async` feature.
+//!
+//! ## Draining
+//!
+//! During a rolling deploy a server should keep finishing the requests
+//! it started while telling clients to go elsewhere. Assign a
+//! [DrainGuard](DrainGuard) sharing the shutdown handle with
+//! [with_drain()](crate::Server::with_drain); once shutdown is triggered
+//! new calls are answered with the `-32000` [shutting
+//! down](shutting_down) error and new notifications are dropped, while
+//! the requests already inside a handler complete normally. The
+//! transport reads [is_draining()](crate::Server::is_draining) and
+//! [in_flight()](crate::Server::in_flight) to decide when to close its
+//! listeners:
+//!
+//! ```
+//! use json_rpc2::{shutdown::{DrainGuard, ShutdownHandle, SHUTTING_DOWN}, *};
+//!
+//! let shutdown = ShutdownHandle::new();
+//! let server = Server::<()>::new(vec![]).with_drain(DrainGuard::new(&shutdown));
+//! shutdown.shutdown();
+//! assert!(server.is_draining());
+//! assert_eq!(0, server.in_flight());
+//!
+//! let request = Request::new_reply("late", None);
+//! let response = server.serve(&request, &()).unwrap();
+//! assert_eq!(SHUTTING_DOWN, response.error().as_ref().unwrap().code);
+//! ```
+
+use crate::{
+    cancel::{CancellationToken, Cancelled},
+    from_str,
+    session::Session,
+    truncate, Request, Response, RpcError, Server,
+};
+use std::io::{self, BufRead, Write};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Error code for requests that arrive while the server is draining.
+pub const SHUTTING_DOWN: isize = -32000;
+
+/// Create the error for a request that arrived while the server is
+/// draining.
+pub fn shutting_down() -> RpcError {
+    RpcError {
+        code: SHUTTING_DOWN,
+        message: "Shutting down".into(),
+        data: None,
+    }
+}
+
+/// Summary of the requests handled by a serving loop.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ServedStats {
+    /// Number of requests handled, including notifications.
+    pub requests: usize,
+    /// Number of error responses.
+    pub errors: usize,
+}
+
+impl ServedStats {
+    pub(crate) fn record(&mut self, response: Option<&Response>) {
+        self.requests += 1;
+        if response.map(|r| r.error().is_some()).unwrap_or(false) {
+            self.errors += 1;
+        }
+    }
+
+    #[cfg(feature = "net")]
+    pub(crate) fn merge(&mut self, other: ServedStats) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+    }
+}
+
+/// Handle used to ask a serving loop to shut down.
+///
+/// Cloned handles share the same state.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+}
+
+impl ShutdownHandle {
+    /// Create a new handle.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Trigger shutdown.
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Determine if shutdown has been triggered.
+    pub fn is_shutdown(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Future that resolves when shutdown is triggered.
+    pub fn wait(&self) -> Cancelled {
+        self.token.cancelled()
+    }
+}
+
+/// Turns away new requests once shutdown is triggered and counts the
+/// requests in flight.
+///
+/// Cloned guards share the same state.
+#[derive(Clone, Default)]
+pub struct DrainGuard {
+    shutdown: ShutdownHandle,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl DrainGuard {
+    /// Create a guard that starts draining when `shutdown` is triggered.
+    pub fn new(shutdown: &ShutdownHandle) -> Self {
+        Self {
+            shutdown: shutdown.clone(),
+            in_flight: Default::default(),
+        }
+    }
+
+    /// Determine if new requests are turned away.
+    pub fn is_draining(&self) -> bool {
+        self.shutdown.is_shutdown()
+    }
+
+    /// Number of requests being handled.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Count a request in flight until the returned value is dropped,
+    /// `None` when the server is draining.
+    pub(crate) fn enter(&self) -> Option<InFlight> {
+        // Count before checking the flag so a transport that sees no
+        // requests in flight after shutdown never misses one.
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let entered = InFlight(Arc::clone(&self.in_flight));
+        (!self.is_draining()).then_some(entered)
+    }
+}
+
+/// The reply to a request that arrived while the server is draining,
+/// notifications are not answered.
+pub(crate) fn drained(request: &Request) -> Response {
+    match request.id() {
+        Some(_) => (request, shutting_down()).into(),
+        None => request.into(),
+    }
+}
+
+/// A request counted by a [DrainGuard](DrainGuard).
+pub(crate) struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serve newline delimited requests from a reader.
+///
+/// Each line is parsed and served and the response is written as a
+/// single line and flushed. Lines that fail to parse yield an error
+/// response. Returns when the reader is exhausted or, after the
+/// current line is handled, when shutdown has been triggered.
+///
+/// The handle is checked between lines so a blocking read is not
+/// interrupted; trigger shutdown before the next message arrives or
+/// close the reader.
+pub fn serve_lines<T, R: BufRead, W: Write>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    reader: R,
+    writer: W,
+    shutdown: &ShutdownHandle,
+) -> io::Result<ServedStats> {
+    lines(server, ctx, reader, writer, shutdown, |_| {})
+}
+
+/// Serve newline delimited requests from a reader for a connection
+/// with a [Session](Session).
+///
+/// Works like [serve_lines()](serve_lines) and inserts the state of the
+/// session into the extensions of every request; the session is dropped,
+/// calling its cleanup function, when the loop returns.
+pub fn serve_lines_with<T, R: BufRead, W: Write, S: Send + Sync + 'static>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    reader: R,
+    writer: W,
+    shutdown: &ShutdownHandle,
+    session: Session<S>,
+) -> io::Result<ServedStats> {
+    lines(server, ctx, reader, writer, shutdown, |request| {
+        session.attach(request)
+    })
+}
+
+fn lines<T, R: BufRead, W: Write>(
+    server: &Server<'_, T>,
+    ctx: &T,
+    reader: R,
+    mut writer: W,
+    shutdown: &ShutdownHandle,
+    attach: impl Fn(&mut Request),
+) -> io::Result<ServedStats> {
+    let mut stats = ServedStats::default();
+    let mut lines = reader.lines();
+    while !shutdown.is_shutdown() {
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match from_str(&line) {
+            Ok(mut request) => {
+                attach(&mut request);
+                server.serve(&request, ctx)
+            }
+            Err(e) => Some(e.into()),
+        };
+        stats.record(response.as_ref());
+        if let Some(response) = response {
+            let max = server.max_response_size();
+            let bytes = truncate::to_vec(&response, response.id(), max)?;
+            writer.write_all(&bytes)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Result, Service};
+    use serde_json::Value;
+    use std::io::Cursor;
+
+    struct StopService;
+    impl Service for StopService {
+        type Data = ShutdownHandle;
+        fn handle(
+            &self,
+            request: &Request,
+            ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            match request.method() {
+                "echo" => Ok(Some((request, Value::Bool(true)).into())),
+                "stop" => {
+                    ctx.shutdown();
+                    Ok(Some((request, Value::Bool(false)).into()))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[test]
+    fn lines_stop_on_shutdown() {
+        let service: Box<dyn Service<Data = ShutdownHandle>> =
+            Box::new(StopService);
+        let server = Server::new(vec![&service]);
+        let shutdown = ShutdownHandle::new();
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"echo"}"#,
+            "\n\n",
+            r#"{"jsonrpc":"2.0","method":"echo"}"#,
+            "\n",
+            "{bad json\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"missing"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":3,"method":"stop"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":4,"method":"echo"}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        let stats = serve_lines(
+            &server,
+            &shutdown,
+            Cursor::new(input),
+            &mut output,
+            &shutdown,
+        )
+        .unwrap();
+        assert_eq!(
+            ServedStats {
+                requests: 5,
+                errors: 2
+            },
+            stats
+        );
+        let responses: Vec<Response> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(4, responses.len());
+        assert_eq!(&Some(Value::from(3)), responses[3].id());
+    }
+
+    #[test]
+    fn lines_with_session() {
+        struct Calls(std::sync::Mutex<u64>);
+        struct SessionService;
+        impl Service for SessionService {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                let calls = request.extensions().get::<Calls>().unwrap();
+                let mut calls = calls.0.lock().unwrap();
+                *calls += 1;
+                Ok(Some((request, Value::from(*calls)).into()))
+            }
+        }
+
+        let service: Box<dyn Service<Data = ()>> = Box::new(SessionService);
+        let server = Server::new(vec![&service]);
+        let closed = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let session = Session::new(Calls(Default::default())).on_close({
+            let closed = std::sync::Arc::clone(&closed);
+            move |calls: &Calls| {
+                *closed.lock().unwrap() = *calls.0.lock().unwrap()
+            }
+        });
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"count"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"count"}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        serve_lines_with(
+            &server,
+            &(),
+            Cursor::new(input),
+            &mut output,
+            &ShutdownHandle::new(),
+            session,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.lines().last().unwrap().contains(r#""result":2"#));
+        assert_eq!(2, *closed.lock().unwrap());
+    }
+
+    #[test]
+    fn drain_in_flight_complete() {
+        use std::sync::{mpsc, Mutex};
+
+        struct Gate {
+            entered: Mutex<mpsc::Sender<()>>,
+            release: Mutex<mpsc::Receiver<()>>,
+        }
+        impl Service for Gate {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                if request.method() == "wait" {
+                    self.entered.lock().unwrap().send(()).unwrap();
+                    self.release.lock().unwrap().recv().unwrap();
+                }
+                Ok(Some((request, Value::Bool(true)).into()))
+            }
+        }
+
+        let (entered, entered_rx) = mpsc::channel();
+        let (release_tx, release) = mpsc::channel();
+        let service: Box<dyn Service<Data = ()>> = Box::new(Gate {
+            entered: Mutex::new(entered),
+            release: Mutex::new(release),
+        });
+        let shutdown = ShutdownHandle::new();
+        let server =
+            Server::new(vec![&service]).with_drain(DrainGuard::new(&shutdown));
+
+        let before = Request::new_reply("echo", None);
+        let response = server.serve(&before, &()).unwrap();
+        assert_eq!(&Some(Value::Bool(true)), response.result());
+        assert!(!server.is_draining());
+
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| {
+                let request = Request::new_reply("wait", None);
+                server.serve(&request, &()).unwrap()
+            });
+            entered_rx.recv().unwrap();
+            assert_eq!(1, server.in_flight());
+
+            shutdown.shutdown();
+            assert!(server.is_draining());
+            let after = Request::new_reply("echo", None);
+            let response = server.serve(&after, &()).unwrap();
+            assert_eq!(after.id(), response.id());
+            assert_eq!(SHUTTING_DOWN, response.error().as_ref().unwrap().code);
+            let notification = Request::new_notification("echo", None);
+            assert!(server.serve(&notification, &()).is_none());
+            assert_eq!(1, server.in_flight());
+
+            release_tx.send(()).unwrap();
+            let response = waiting.join().unwrap();
+            assert_eq!(&Some(Value::Bool(true)), response.result());
+        });
+        assert_eq!(0, server.in_flight());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_async_server() {
+        struct Sleep;
+        #[async_trait::async_trait]
+        impl crate::futures::Service for Sleep {
+            type Data = ();
+            async fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                Ok(Some((request, Value::Bool(true)).into()))
+            }
+        }
+
+        let shutdown = ShutdownHandle::new();
+        let server = Arc::new(
+            crate::futures::Server::new_shared(vec![Arc::new(Sleep)])
+                .with_drain(DrainGuard::new(&shutdown)),
+        );
+        let serving = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move {
+                let request = Request::new_reply("sleep", None);
+                server.serve(&request, &()).await.unwrap()
+            }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(1, server.in_flight());
+
+        shutdown.shutdown();
+        let request = Request::new_reply("sleep", None);
+        let response = server.serve(&request, &()).await.unwrap();
+        assert_eq!(SHUTTING_DOWN, response.error().as_ref().unwrap().code);
+
+        let response = serving.await.unwrap();
+        assert_eq!(&Some(Value::Bool(true)), response.result());
+        assert_eq!(0, server.in_flight());
+    }
+}
@@ -0,0 +1,415 @@
+//! Sign requests with HMAC-SHA256 and verify the signatures.
+//!
+//! The signature covers the [canonical form](canonicalize) of the
+//! request and is sent as lowercase hex in the `sig` field of the
+//! `_meta` field of the request, which is excluded from the canonical
+//! form. Other metadata, such as an identifier for the key, is signed
+//! along with the request:
+//!
+//! ```
+//! use json_rpc2::{signing::{sign_request, verify_signature}, Request};
+//! use serde_json::json;
+//!
+//! let mut request = Request::new_reply("transfer", Some(json!({"amount": 1.5})));
+//! request.set_meta(json!({"kid": "partner-1"}))?;
+//! sign_request(&mut request, b"secret")?;
+//!
+//! let lookup = |request: &Request| match request.meta()?.get("kid")?.as_str()? {
+//!     "partner-1" => Some(b"secret".to_vec()),
+//!     _ => None,
+//! };
+//! assert!(verify_signature(&request, lookup).is_ok());
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+//!
+//! Servers install the check with
+//! [validator()](validator) and
+//! [Server::with_validator()](crate::Server::with_validator).
+//!
+//! The canonical form follows the JSON Canonicalization Scheme (RFC
+//! 8785) so other languages can produce it: the JSON text of the
+//! request without whitespace and with the keys of every object sorted
+//! by their UTF-16 code units, so it does not depend on the order the
+//! fields were received in or on whether `serde_json` preserves that
+//! order. Strings escape only quotes, backslashes and control
+//! characters. Floats are written in the shortest form that round
+//! trips using the ECMAScript rules, so `1.0` is written as `1` and
+//! `1e21` as `1e+21`; unlike RFC 8785 integers are written as received
+//! rather than converted to a double, which only differs for integers
+//! beyond 2^53.
+//!
+//! Only available with the `signing` feature.
+
+use crate::{Error, Request, Result, RpcError, INVALID_REQUEST};
+use hmac::{Hmac, Mac};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+/// Name of the signature field in the metadata.
+pub const SIGNATURE: &str = "sig";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The canonical form of a request without its signature.
+pub fn canonicalize(request: &Request) -> String {
+    let mut value = serde_json::to_value(request).unwrap_or_default();
+    if let Value::Object(map) = &mut value {
+        let empty = match map.get_mut(crate::meta::META) {
+            Some(Value::Object(meta)) => {
+                meta.remove(SIGNATURE);
+                meta.is_empty()
+            }
+            _ => false,
+        };
+        if empty {
+            map.remove(crate::meta::META);
+        }
+    }
+    canonical_json(&value)
+}
+
+/// The canonical form of a JSON value.
+pub fn canonical_json(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+/// Sign a request with `key`, adding the signature to the `_meta`
+/// field of the request.
+///
+/// Fails when the request has `_meta` that is not an object.
+pub fn sign_request(request: &mut Request, key: &[u8]) -> Result<()> {
+    let mut meta = match request.meta.take() {
+        Some(Value::Object(meta)) => meta,
+        None => Map::new(),
+        Some(other) => {
+            request.meta = Some(other);
+            return Err(Error::from(Box::from("_meta is not an object")));
+        }
+    };
+    meta.remove(SIGNATURE);
+    request.meta = Some(Value::Object(meta));
+    let signature = hex(&mac(key, request).finalize().into_bytes());
+    if let Some(Value::Object(meta)) = &mut request.meta {
+        meta.insert(SIGNATURE.to_string(), Value::String(signature));
+    }
+    Ok(())
+}
+
+/// Verify the signature of a request with the key returned by
+/// `key_lookup`.
+///
+/// The error to send is returned when the request is not signed, no
+/// key is found or the signature does not match; signatures are
+/// compared in constant time.
+pub fn verify_signature<F, K>(
+    request: &Request,
+    key_lookup: F,
+) -> std::result::Result<(), RpcError>
+where
+    F: FnOnce(&Request) -> Option<K>,
+    K: AsRef<[u8]>,
+{
+    let signature = match &request.meta {
+        Some(Value::Object(meta)) => meta.get(SIGNATURE),
+        _ => None,
+    };
+    let signature = match signature {
+        Some(Value::String(signature)) => signature,
+        _ => return Err(invalid("missing signature")),
+    };
+    let key = key_lookup(request).ok_or_else(|| invalid("unknown key"))?;
+    let signature =
+        unhex(signature).ok_or_else(|| invalid("invalid signature"))?;
+    mac(key.as_ref(), request)
+        .verify_slice(&signature)
+        .map_err(|_| invalid("invalid signature"))
+}
+
+/// Validator that verifies signatures, use with
+/// [Server::with_validator()](crate::Server::with_validator).
+pub fn validator<T, F, K>(
+    key_lookup: F,
+) -> impl Fn(&Request, &T) -> std::result::Result<(), RpcError> + Send + Sync
+where
+    F: Fn(&Request) -> Option<K> + Send + Sync,
+    K: AsRef<[u8]>,
+{
+    move |request, _| verify_signature(request, &key_lookup)
+}
+
+fn mac(key: &[u8], request: &Request) -> HmacSha256 {
+    // HMAC accepts keys of any length.
+    let mut mac = HmacSha256::new_from_slice(key).unwrap();
+    mac.update(canonicalize(request).as_bytes());
+    mac
+}
+
+fn invalid(data: &str) -> RpcError {
+    RpcError {
+        code: INVALID_REQUEST,
+        message: "Invalid request".into(),
+        data: Some(Value::String(data.to_string())),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&value[index..index + 2], 16).ok())
+        .collect()
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(value) => {
+            out.push_str(if *value { "true" } else { "false" })
+        }
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => write_float(out, float),
+            _ => out.push_str(&number.to_string()),
+        },
+        Value::String(value) => write_string(out, value),
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+            out.push('{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Write a finite float as ECMAScript `Number.prototype.toString()`.
+fn write_float(out: &mut String, value: f64) {
+    if value == 0.0 {
+        out.push('0');
+        return;
+    }
+    if value < 0.0 {
+        out.push('-');
+    }
+    // Shortest round trip digits, such as `1.25e-7`.
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exponent: i32 = exponent.parse().unwrap();
+    let length = digits.len() as i32;
+    // Position of the decimal point relative to the digits.
+    let point = exponent + 1;
+    if length <= point && point <= 21 {
+        out.push_str(&digits);
+        out.push_str(&"0".repeat((point - length) as usize));
+    } else if 0 < point && point <= 21 {
+        out.push_str(&digits[..point as usize]);
+        out.push('.');
+        out.push_str(&digits[point as usize..]);
+    } else if -6 < point && point <= 0 {
+        out.push_str("0.");
+        out.push_str(&"0".repeat(-point as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if length > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if point > 0 { '+' } else { '-' });
+        out.push_str(&(point - 1).abs().to_string());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{method::method, Server, Service};
+    use serde_json::json;
+
+    fn float(value: f64) -> String {
+        let mut out = String::new();
+        write_float(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn signing_float_format() {
+        // Expectations from RFC 8785 and ECMAScript.
+        for (value, expected) in &[
+            (1.0, "1"),
+            (-1.5, "-1.5"),
+            (-0.0, "0"),
+            (0.1, "0.1"),
+            (1e21, "1e+21"),
+            (1e20, "100000000000000000000"),
+            (123456789012345680000.0, "123456789012345680000"),
+            (1e-6, "0.000001"),
+            (1e-7, "1e-7"),
+            (1.25e-7, "1.25e-7"),
+            (4.50, "4.5"),
+            (2e-3, "0.002"),
+            (333333333.3333332, "333333333.3333332"),
+            (1e23, "1e+23"),
+            (5e-324, "5e-324"),
+            (1.7976931348623157e308, "1.7976931348623157e+308"),
+            (9007199254740992.0, "9007199254740992"),
+            (295147905179352830000.0, "295147905179352830000"),
+        ] {
+            assert_eq!(*expected, float(*value), "{:e}", value);
+        }
+    }
+
+    #[test]
+    fn signing_canonical_json() {
+        let value: Value = serde_json::from_str(
+            r#"{ "b": [1, 2.0, -0.0, 1E2, 18446744073709551615, -9007199254740993],
+                 "a": {"z": null, "é": "€\t\"\\\u0001", "A": true},
+                 "\ue000": "", "\ud83d\ude00": false }"#,
+        )
+        .unwrap();
+        // U+1F600 is a surrogate pair that sorts before U+E000.
+        assert_eq!(
+            concat!(
+                r#"{"a":{"A":true,"z":null,"é":"€\t\"\\\u0001"},"#,
+                r#""b":[1,2,0,100,18446744073709551615,-9007199254740993],"#,
+                "\"\u{1f600}\":false,\"\u{e000}\":\"\"}"
+            ),
+            canonical_json(&value)
+        );
+
+        // Field order of the input does not matter.
+        let a: Request = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"m","params":{"x":1,"y":[2]}}"#,
+        )
+        .unwrap();
+        let b: Request = serde_json::from_str(
+            r#"{"params":{"y":[2],"x":1.0},"method":"m","id":1,"jsonrpc":"2.0"}"#,
+        )
+        .unwrap();
+        assert_eq!(canonicalize(&a), canonicalize(&b));
+        assert_eq!(
+            r#"{"id":1,"jsonrpc":"2.0","method":"m","params":{"x":1,"y":[2]}}"#,
+            canonicalize(&a)
+        );
+    }
+
+    #[test]
+    fn signing_round_trip() -> Result<()> {
+        let key = b"secret";
+        let mut request =
+            Request::new_reply("transfer", Some(json!({"amount": 1.5})));
+        let unsigned = canonicalize(&request);
+        sign_request(&mut request, key)?;
+        assert_eq!(unsigned, canonicalize(&request));
+        let signature = request.meta().unwrap()[SIGNATURE].as_str().unwrap();
+        assert_eq!(64, signature.len());
+        assert!(verify_signature(&request, |_: &Request| Some(key)).is_ok());
+
+        // Survives serialization with reordered fields.
+        let text = serde_json::to_string(&request).unwrap();
+        let received: Request = serde_json::from_str(&text).unwrap();
+        assert!(verify_signature(&received, |_: &Request| Some(key)).is_ok());
+
+        // Signing again replaces the signature.
+        let mut again = request.clone();
+        sign_request(&mut again, key)?;
+        assert_eq!(request.meta(), again.meta());
+
+        let error = |request: &Request, key: Option<&[u8]>| {
+            verify_signature(request, |_: &Request| key)
+                .unwrap_err()
+                .data
+                .unwrap()
+        };
+        assert_eq!(json!("invalid signature"), error(&request, Some(b"other")));
+        assert_eq!(json!("unknown key"), error(&request, None));
+
+        let mut tampered = request.clone();
+        tampered.params = Some(json!({"amount": 15}));
+        assert_eq!(json!("invalid signature"), error(&tampered, Some(key)));
+
+        let mut tampered = request.clone();
+        tampered.meta = Some(json!({SIGNATURE: "zz"}));
+        assert_eq!(json!("invalid signature"), error(&tampered, Some(key)));
+
+        let unsigned = Request::new_reply("transfer", None);
+        assert_eq!(json!("missing signature"), error(&unsigned, Some(key)));
+
+        let mut invalid = Request::new_reply("transfer", None);
+        invalid.set_meta(json!([1]))?;
+        assert!(sign_request(&mut invalid, key).is_err());
+        assert_eq!(Some(&json!([1])), invalid.meta());
+        Ok(())
+    }
+
+    #[test]
+    fn signing_validator() -> Result<()> {
+        fn echo((value,): (u32,), _: &()) -> Result<u32> {
+            Ok(value)
+        }
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(method("echo", echo));
+        let server = Server::new(vec![&service]).with_validator(validator(
+            |request: &Request| match request.meta()?.get("kid")?.as_str()? {
+                "a" => Some(b"key-a".to_vec()),
+                _ => None,
+            },
+        ));
+
+        let mut request = Request::new_reply("echo", Some(json!([7])));
+        request.set_meta(json!({"kid": "a"}))?;
+        sign_request(&mut request, b"key-a")?;
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(&Some(json!(7)), response.result());
+
+        // Changing the key id invalidates the signature.
+        request.set_meta(json!({"kid": "b", SIGNATURE: "00"}))?;
+        let response = server.serve(&request, &()).unwrap();
+        assert_eq!(INVALID_REQUEST, response.error().as_ref().unwrap().code);
+        Ok(())
+    }
+}
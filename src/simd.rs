@@ -0,0 +1,241 @@
+//! Parse requests with [simd-json](https://docs.rs/simd-json).
+//!
+//! With the `simd` feature [from_str()](crate::from_str) and
+//! [from_slice()](crate::from_slice) call the functions of this module
+//! to parse into the same [Request](crate::Request); parameters and ids
+//! are still `serde_json` values.
+//!
+//! simd-json rewrites its input in place so both functions copy the
+//! payload into a new buffer before parsing, an extra allocation the
+//! `serde_json` backend does not need.
+//!
+//! simd-json parses floats to the nearest value, so the feature also
+//! enables the `float_roundtrip` feature of `serde_json` for floats to
+//! be the same whichever backend parses them.
+//!
+//! A payload simd-json rejects is parsed again with `serde_json` so
+//! errors are the same as for `serde_json`, and payloads simd-json
+//! cannot represent, such as numbers out of the range of a float with
+//! the `arbitrary-precision` feature, are still accepted. The slower
+//! path is only taken for invalid payloads, for payloads escaping
+//! UTF-16 surrogates, which simd-json does not validate, for payloads
+//! holding the integer `-0` and, with `arbitrary-precision`, for
+//! payloads holding floats, whose digits simd-json does not keep.
+//!
+//! Errors are not mapped from simd-json, whose messages and positions
+//! differ, so a rejected payload costs both parses; the copy and the
+//! scan for the values above are paid by every payload. simd-json only
+//! pays off for large requests, compare the backends with
+//! `cargo bench --features simd --bench simd`.
+
+use crate::{validate, Request, Result};
+use simd_json::{Node, StaticNode};
+
+/// Parse a JSON payload from a string slice into a request.
+pub fn from_str(payload: &str) -> Result<Request> {
+    from_slice(payload.as_bytes())
+}
+
+/// Parse a JSON payload from a byte slice into a request.
+///
+/// When simd-json fails the payload is parsed again with `serde_json`.
+pub fn from_slice(payload: &[u8]) -> Result<Request> {
+    if parses_differently(payload) {
+        return crate::json_from_slice(payload);
+    }
+    let mut buffer = payload.to_vec();
+    let request = simd_json::to_tape(&mut buffer)
+        .ok()
+        .filter(|tape| depth(&tape.0) < RECURSION_LIMIT)
+        .filter(|tape| keeps_digits(&tape.0))
+        .and_then(|tape| tape.deserialize::<Request>().ok())
+        .map(validate);
+    match request {
+        Some(Ok(request)) => Ok(request),
+        _ => crate::json_from_slice(payload),
+    }
+}
+
+/// Determine if a payload may hold values simd-json parses differently
+/// from `serde_json`, in a single pass.
+///
+/// simd-json accepts escaped UTF-16 surrogates that `serde_json`
+/// rejects and replaces them, and parses the integer `-0` as `0` where
+/// `serde_json` yields `-0.0`, so these payloads are left to
+/// `serde_json`. Matches inside strings, such as an escaped backslash
+/// followed by `ud800`, only cost the slower parse.
+fn parses_differently(payload: &[u8]) -> bool {
+    let at = |index: usize| payload.get(index).copied().unwrap_or(0);
+    payload.iter().enumerate().any(|(index, byte)| match byte {
+        b'\\' => {
+            at(index + 1) == b'u'
+                && at(index + 2) | 0x20 == b'd'
+                && matches!(at(index + 3) | 0x20, b'8'..=b'9' | b'a'..=b'f')
+        }
+        b'-' => {
+            at(index + 1) == b'0'
+                && !matches!(at(index + 2), b'.' | b'e' | b'E')
+        }
+        _ => false,
+    })
+}
+
+/// Determine if simd-json keeps the digits of the numbers on a tape.
+///
+/// With the `arbitrary-precision` feature `serde_json` keeps floats as
+/// written, which simd-json does not, so payloads with floats are left
+/// to `serde_json`.
+fn keeps_digits(tape: &[Node]) -> bool {
+    !cfg!(feature = "arbitrary-precision")
+        || !tape
+            .iter()
+            .any(|node| matches!(node, Node::Static(StaticNode::F64(_))))
+}
+
+/// Nesting depth `serde_json` refuses to parse.
+const RECURSION_LIMIT: usize = 128;
+
+/// Deepest nesting of arrays and objects on a tape.
+///
+/// simd-json deserializes nested values recursively without a limit so
+/// deeply nested payloads are left to `serde_json`.
+fn depth(tape: &[Node]) -> usize {
+    let mut ends: Vec<usize> = Vec::new();
+    let mut deepest = 0;
+    for (index, node) in tape.iter().enumerate() {
+        while ends.last().is_some_and(|end| *end <= index) {
+            ends.pop();
+        }
+        if let Node::Array { count, .. } | Node::Object { count, .. } = node {
+            ends.push(index + count + 1);
+            deepest = deepest.max(ends.len());
+        }
+    }
+    deepest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+    use serde_json::json;
+
+    #[test]
+    fn simd_parse() -> Result<()> {
+        let request = from_str(
+            r#"{"jsonrpc":"2.0","id":"a","method":"sum","params":[1,2.5,"x"]}"#,
+        )?;
+        assert_eq!("sum", request.method());
+        assert_eq!(&Some(json!("a")), request.id());
+        assert_eq!(Some(&json!([1, 2.5, "x"])), request.params().as_ref());
+        Ok(())
+    }
+
+    #[test]
+    fn simd_matches_serde_json_categories() {
+        for payload in &[
+            r#"{"jsonrpc":"2.0","method":"ping","id":1}"#,
+            r#"{"jsonrpc":"2.0","method":"ping"}"#,
+            r#"{"jsonrpc":"2.0","id":7}"#,
+            r#"{"jsonrpc":"1.0","method":"ping","id":7}"#,
+            r#"{"jsonrpc":2,"method":"ping","id":"b"}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","params":1}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","id":[1]}"#,
+            r#"[1, 2]"#,
+            r#""ping""#,
+            r#"{"jsonrpc":"2.0","#,
+            r#"{"jsonrpc":"2.0",}"#,
+            r#"{"jsonrpc":"2.0","method":"ping"} x"#,
+            r#"{jsonrpc:"2.0"}"#,
+            r#"{"jsonrpc":"2.0\ud800","method":"ping","id":1}"#,
+            r#"{"jsonrpc":"2.0","method":"a\ud800"}"#,
+            r#"{"jsonrpc":"2.0","method":"a\uDC00","id":1}"#,
+            r#"{"jsonrpc":"2.0","method":"a\ud83e\udd80","id":1}"#,
+            r#"{"jsonrpc":"2.0","method":"a\\ud800","id":1}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","id":1,"params":[n ull]}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","id":1,"params":[2.5e310]}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","id":1,"params":[1.50,1E2]}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","id":-0,"params":[-0,-0.0]}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","params":[1234567890e-500]}"#,
+            r#"{"jsonrpc":"2.0","method":"ping","params":[2.54e300,0.1]}"#,
+            "",
+        ] {
+            let expected = crate::json_from_slice(payload.as_bytes());
+            let actual = from_str(payload);
+            match (&expected, &actual) {
+                (Ok(expected), Ok(actual)) => assert_eq!(
+                    serde_json::to_string(expected).unwrap(),
+                    serde_json::to_string(actual).unwrap(),
+                    "{}",
+                    payload
+                ),
+                (
+                    Err(Error::InvalidRequest { id: expected, .. }),
+                    Err(Error::InvalidRequest { id: actual, .. }),
+                ) => assert_eq!(expected, actual, "{}", payload),
+                (Err(Error::Parse { .. }), Err(Error::Parse { .. })) => {}
+                _ => panic!("{}: {:?} != {:?}", payload, expected, actual),
+            }
+        }
+    }
+
+    #[test]
+    fn simd_matches_serde_json_floats() {
+        use rand::Rng;
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20_000 {
+            let float = format!(
+                "{}.{:016}e{}",
+                rng.gen_range(1..10),
+                rng.gen_range(0..10_000_000_000_000_000u64),
+                rng.gen_range(-320..308)
+            );
+            let payload = format!(
+                r#"{{"jsonrpc":"2.0","method":"m","id":1,"params":[{}]}}"#,
+                float
+            );
+            let expected = crate::json_from_slice(payload.as_bytes()).unwrap();
+            let actual = from_str(&payload).unwrap();
+            assert_eq!(expected.params(), actual.params(), "{}", float);
+        }
+    }
+
+    #[test]
+    fn simd_depth() {
+        let nested = |open: &str, close: &str, depth: usize| {
+            format!(
+                r#"{{"jsonrpc":"2.0","method":"m","id":1,"params":{}1{}}}"#,
+                open.repeat(depth),
+                close.repeat(depth)
+            )
+        };
+        for depth in &[1, 2, 10, 100] {
+            let payload = nested("[", "]", *depth);
+            let mut buffer = payload.clone().into_bytes();
+            let tape = simd_json::to_tape(&mut buffer).unwrap();
+            assert_eq!(depth + 1, super::depth(&tape.0), "{}", payload);
+            let payload = nested(r#"{"a":["#, "]}", *depth);
+            let mut buffer = payload.clone().into_bytes();
+            let tape = simd_json::to_tape(&mut buffer).unwrap();
+            assert_eq!(2 * depth + 1, super::depth(&tape.0), "{}", payload);
+        }
+        for depth in &[126, 127, 128, 129, 100_000] {
+            let payload = nested("[", "]", *depth);
+            let expected = crate::json_from_slice(payload.as_bytes());
+            let actual = from_str(&payload);
+            assert_eq!(expected.is_ok(), actual.is_ok(), "{}", depth);
+        }
+    }
+
+    #[test]
+    fn simd_error_position() {
+        match from_str("{\n  \"jsonrpc\": \"2.0\",\n  x") {
+            Err(Error::Parse { line, offset, .. }) => {
+                assert_eq!(Some(3), line);
+                assert_eq!(Some(24), offset);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
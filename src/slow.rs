@@ -0,0 +1,189 @@
+//! Report requests that take longer than a threshold.
+//!
+//! A server built with
+//! [with_slow_request_log()](crate::Server::with_slow_request_log)
+//! times the services handling each request, from when the first
+//! service is called until a response or an error is returned, and
+//! calls a function for requests slower than the threshold. Failed
+//! requests are reported too.
+//!
+//! [warn()](warn) logs a warning with the method, id and elapsed time;
+//! [warn_redacted()](warn_redacted) also logs the parameters with
+//! sensitive values replaced:
+//!
+//! ```
+//! use json_rpc2::{redact::RedactionRules, slow, Server, Service};
+//! use std::time::Duration;
+//!
+//! # let service: Box<dyn Service<Data = ()>> = Box::new(json_rpc2::method::Methods::new());
+//! let server = Server::new(vec![&service]).with_slow_request_log(
+//!     Duration::from_millis(250),
+//!     slow::warn_redacted(RedactionRules::new().key("password")),
+//! );
+//! ```
+//!
+//! Uses the `log` facade by default or `tracing` when the `tracing`
+//! feature is enabled.
+
+use crate::{redact::RedactionRules, Request, Response, Result};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "tracing"))]
+use log::warn as log_warn;
+#[cfg(feature = "tracing")]
+use tracing::warn as log_warn;
+
+/// Function called for a slow request.
+pub type SlowHook = dyn Fn(&SlowRequest<'_>) + Send + Sync;
+
+/// Request that took longer than the threshold.
+#[derive(Debug)]
+pub struct SlowRequest<'a> {
+    /// The request.
+    pub request: &'a Request,
+    /// Time taken by the services.
+    pub elapsed: Duration,
+    /// Whether the request failed with an error or error response.
+    pub failed: bool,
+}
+
+/// Log a warning for a slow request without the parameters.
+pub fn warn(slow: &SlowRequest<'_>) {
+    let id = slow.request.id().as_ref().unwrap_or(&Value::Null);
+    log_warn!(
+        "slow request {} id={} {:?}{}",
+        slow.request.method(),
+        id,
+        slow.elapsed,
+        if slow.failed { " failed" } else { "" }
+    );
+}
+
+/// Log a warning for a slow request with the parameters redacted.
+pub fn warn_redacted(
+    rules: RedactionRules,
+) -> impl Fn(&SlowRequest<'_>) + Send + Sync + 'static {
+    move |slow| {
+        let id = slow.request.id().as_ref().unwrap_or(&Value::Null);
+        let params = slow
+            .request
+            .params()
+            .as_ref()
+            .map(|params| rules.apply(params))
+            .unwrap_or(Value::Null);
+        log_warn!(
+            "slow request {} id={} {:?}{} params={}",
+            slow.request.method(),
+            id,
+            slow.elapsed,
+            if slow.failed { " failed" } else { "" },
+            params
+        );
+    }
+}
+
+/// Threshold and function for slow requests held by a server.
+pub(crate) struct SlowLog {
+    threshold: Duration,
+    hook: Box<SlowHook>,
+}
+
+impl SlowLog {
+    pub(crate) fn new(threshold: Duration, hook: Box<SlowHook>) -> Self {
+        Self { threshold, hook }
+    }
+
+    /// Report the request when the services took too long.
+    pub(crate) fn check(
+        &self,
+        request: &Request,
+        started: Instant,
+        failed: bool,
+    ) {
+        let elapsed = started.elapsed();
+        if elapsed >= self.threshold {
+            (self.hook)(&SlowRequest {
+                request,
+                elapsed,
+                failed,
+            });
+        }
+    }
+}
+
+/// Whether the services failed to handle a request.
+pub(crate) fn failed(result: &Result<Response>) -> bool {
+    result
+        .as_ref()
+        .map_or(true, |response| response.error().is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{method::method, methods, Error, Server, Service};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn sleep((millis,): (u64,), _: &()) -> Result<()> {
+        std::thread::sleep(Duration::from_millis(millis));
+        Ok(())
+    }
+
+    fn fail((millis,): (u64,), _: &()) -> Result<()> {
+        std::thread::sleep(Duration::from_millis(millis));
+        Err(Error::from(Box::from("failed")))
+    }
+
+    #[test]
+    fn slow_request_log() {
+        let slow = Arc::new(Mutex::new(Vec::new()));
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(methods![method("sleep", sleep), method("fail", fail)]);
+        let server = Server::new(vec![&service]).with_slow_request_log(
+            Duration::from_millis(5),
+            {
+                let slow = Arc::clone(&slow);
+                move |request: &SlowRequest<'_>| {
+                    assert!(request.elapsed >= Duration::from_millis(5));
+                    slow.lock().unwrap().push((
+                        request.request.method().to_string(),
+                        request.request.id().clone(),
+                        request.failed,
+                    ));
+                }
+            },
+        );
+
+        let requests = [
+            Request::new_reply("sleep", Some(json!([0]))),
+            Request::new_reply("sleep", Some(json!([10]))),
+            Request::new_reply("fail", Some(json!([10]))),
+            Request::new_notification("sleep", Some(json!([10]))),
+        ];
+        for request in requests.iter() {
+            server.serve(request, &());
+        }
+        assert_eq!(
+            vec![
+                ("sleep".to_string(), requests[1].id().clone(), false),
+                ("fail".to_string(), requests[2].id().clone(), true),
+                ("sleep".to_string(), None, false),
+            ],
+            *slow.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn slow_request_warn() {
+        let request = Request::new_reply("login", Some(json!({"password": 1})));
+        let slow = SlowRequest {
+            request: &request,
+            elapsed: Duration::from_secs(1),
+            failed: true,
+        };
+        warn(&slow);
+        warn_redacted(RedactionRules::new().key("password"))(&slow);
+    }
+}
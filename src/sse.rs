@@ -0,0 +1,266 @@
+//! Stream notifications to browsers as Server-Sent Events.
+//!
+//! Clients that cannot hold a WebSocket send requests with POST and
+//! receive notifications on an `text/event-stream` response.
+//! [channel()](channel) creates a [Notifier](crate::notify::Notifier)
+//! for the handlers and the [Events](Events) for the transport, which
+//! yields each notification as an event whose data is the serialized
+//! notification, and a comment when idle for the keep-alive interval:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use futures_util::StreamExt;
+//! use json_rpc2::sse::{channel, Overflow};
+//! use std::time::Duration;
+//!
+//! let (notifier, events) = channel(64, Overflow::DropOldest);
+//! notifier.notify("ready", None);
+//! drop(notifier);
+//!
+//! let frames: Vec<String> = events
+//!     .keep_alive(Duration::from_secs(15))
+//!     .into_stream()
+//!     .collect()
+//!     .await;
+//! assert_eq!(
+//!     vec!["data: {\"jsonrpc\":\"2.0\",\"method\":\"ready\"}\n\n".to_string()],
+//!     frames
+//! );
+//! # }
+//! ```
+//!
+//! At most `capacity` notifications are buffered for a slow client.
+//! With [Overflow::DropOldest](Overflow::DropOldest) the oldest are
+//! discarded, counted by [dropped()](Events::dropped) and reported to
+//! the client as a `: dropped N` comment; with
+//! [Overflow::Disconnect](Overflow::Disconnect) the stream ends so the
+//! client reconnects. The stream also ends, after the buffered
+//! notifications, once every clone of the notifier is dropped.
+//!
+//! Only available with the `sse` feature.
+
+use crate::{notify::Notifier, Request};
+use futures_util::{stream, Stream};
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Comment sent when the stream is idle.
+pub const KEEP_ALIVE: &str = ": keep-alive\n\n";
+
+/// What happens when the buffer of a slow client is full.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Overflow {
+    /// Discard the oldest notification and count it.
+    #[default]
+    DropOldest,
+    /// End the stream.
+    Disconnect,
+}
+
+/// Buffer shared by the notifier and the events.
+struct Shared {
+    queue: Mutex<VecDeque<Request>>,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: AtomicU64,
+    /// Every notifier was dropped.
+    closed: AtomicBool,
+    /// The buffer overflowed with `Overflow::Disconnect`.
+    disconnected: AtomicBool,
+    notify: Notify,
+}
+
+impl Shared {
+    fn push(&self, notification: Request) {
+        if self.disconnected.load(Ordering::Acquire) {
+            return;
+        }
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= self.capacity {
+                match self.overflow {
+                    Overflow::DropOldest => {
+                        queue.pop_front();
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Overflow::Disconnect => {
+                        queue.clear();
+                        self.disconnected.store(true, Ordering::Release);
+                    }
+                }
+            }
+            if !self.disconnected.load(Ordering::Acquire) {
+                queue.push_back(notification);
+            }
+        }
+        self.notify.notify_one();
+    }
+}
+
+/// Marks the buffer closed when the last notifier is dropped.
+struct Guard(Arc<Shared>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.notify.notify_one();
+    }
+}
+
+/// Create a notifier and the events for one client.
+///
+/// A `capacity` of zero is treated as one.
+pub fn channel(capacity: usize, overflow: Overflow) -> (Notifier, Events) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        capacity: capacity.max(1),
+        overflow,
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        disconnected: AtomicBool::new(false),
+        notify: Notify::new(),
+    });
+    let guard = Guard(Arc::clone(&shared));
+    let notifier =
+        Notifier::new(move |notification| guard.0.push(notification));
+    let events = Events {
+        shared,
+        keep_alive: None,
+    };
+    (notifier, events)
+}
+
+/// Notifications for one client waiting to be streamed.
+pub struct Events {
+    shared: Arc<Shared>,
+    keep_alive: Option<Duration>,
+}
+
+impl Events {
+    /// Send a keep-alive comment when no event was sent for `interval`.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    /// Number of notifications discarded because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Convert into a stream of Server-Sent Events frames.
+    pub fn into_stream(self) -> impl Stream<Item = String> + Send + 'static {
+        stream::unfold((self, 0u64), |(events, reported)| async move {
+            events
+                .next_frame(reported)
+                .await
+                .map(|(frame, reported)| (frame, (events, reported)))
+        })
+    }
+
+    /// The next frame and the number of dropped notifications reported
+    /// so far.
+    async fn next_frame(&self, reported: u64) -> Option<(String, u64)> {
+        let shared = &self.shared;
+        loop {
+            if shared.disconnected.load(Ordering::Acquire) {
+                return None;
+            }
+            let dropped = shared.dropped.load(Ordering::Relaxed);
+            if dropped > reported {
+                return Some((format!(": dropped {}\n\n", dropped), dropped));
+            }
+            let notification = shared.queue.lock().unwrap().pop_front();
+            if let Some(notification) = notification {
+                return Some((event(&notification), reported));
+            }
+            if shared.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            match self.keep_alive {
+                Some(interval) => {
+                    let notified = shared.notify.notified();
+                    if tokio::time::timeout(interval, notified).await.is_err() {
+                        return Some((KEEP_ALIVE.to_string(), reported));
+                    }
+                }
+                None => shared.notify.notified().await,
+            }
+        }
+    }
+}
+
+/// Format a notification as an event.
+fn event(notification: &Request) -> String {
+    let data = serde_json::to_string(notification).unwrap_or_default();
+    format!("data: {}\n\n", data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn sse_events_and_close() {
+        let (notifier, events) = channel(8, Overflow::DropOldest);
+        let mut stream = Box::pin(events.into_stream());
+        let handle = notifier.clone();
+        tokio::spawn(async move {
+            handle.notify("tick", Some(json!([1])));
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            "data: {\"jsonrpc\":\"2.0\",\"method\":\"tick\",\"params\":[1]}\n\n",
+            stream.next().await.unwrap()
+        );
+        notifier.notify("tock", None);
+        drop(notifier);
+        assert!(stream.next().await.unwrap().contains("tock"));
+        assert_eq!(None, stream.next().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sse_keep_alive() {
+        let (notifier, events) = channel(8, Overflow::DropOldest);
+        let mut stream =
+            Box::pin(events.keep_alive(Duration::from_secs(15)).into_stream());
+        assert_eq!(KEEP_ALIVE, stream.next().await.unwrap());
+        notifier.notify("tick", None);
+        assert!(stream.next().await.unwrap().starts_with("data: "));
+        assert_eq!(KEEP_ALIVE, stream.next().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sse_drop_oldest() {
+        let (notifier, events) = channel(2, Overflow::DropOldest);
+        for index in 0..5 {
+            notifier.notify("tick", Some(json!([index])));
+        }
+        assert_eq!(3, events.dropped());
+        drop(notifier);
+        let frames: Vec<String> = events.into_stream().collect().await;
+        assert_eq!(": dropped 3\n\n", frames[0]);
+        assert!(frames[1].contains("[3]"));
+        assert!(frames[2].contains("[4]"));
+        assert_eq!(3, frames.len());
+    }
+
+    #[tokio::test]
+    async fn sse_disconnect() {
+        let (notifier, events) = channel(2, Overflow::Disconnect);
+        for index in 0..3 {
+            notifier.notify("tick", Some(json!([index])));
+        }
+        let frames: Vec<String> = events.into_stream().collect().await;
+        assert!(frames.is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! Counters for the requests served by a server.
+//!
+//! A server built with [with_stats()](crate::Server::with_stats) counts
+//! every request it serves and [stats()](crate::Server::stats) returns a
+//! [Stats](Stats) snapshot of the counters:
+//!
+//! ```
+//! use json_rpc2::{method::method, Request, Result, Server, Service};
+//!
+//! fn version(_: (), _: &()) -> Result<&'static str> {
+//!     Ok("1.0")
+//! }
+//!
+//! let service: Box<dyn Service<Data = ()>> =
+//!     Box::new(method("version", version));
+//! let server = Server::new(vec![&service]).with_stats();
+//! server.serve(&Request::new_reply("version", None), &());
+//! server.serve(&Request::new_reply("missing", None), &());
+//!
+//! let stats = server.stats().unwrap();
+//! assert_eq!(2, stats.requests);
+//! assert_eq!(Some(&1), stats.methods.get("version"));
+//! assert_eq!(1, stats.errors.client_fault);
+//! ```
+//!
+//! Errors are counted by the [Category](crate::policy::Category) the
+//! server [policy](crate::Server::with_policy) assigns to their code.
+//! Methods listed by a service are counted by name. Other methods, such
+//! as those answered by a fallback, are counted by name up to
+//! [MAX_UNLISTED](MAX_UNLISTED) of them and under [OTHER](OTHER) after
+//! that, so clients cannot grow the table without bound; requests
+//! answered with method not found are not counted per method.
+//!
+//! The snapshot is serializable so a service can return it, for
+//! example from a health endpoint. Without `with_stats()` nothing is
+//! recorded.
+
+use crate::{
+    policy::{Category, ErrorPolicy},
+    routes::Listing,
+    Request, Response, METHOD_NOT_FOUND,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Mutex,
+};
+use std::time::Duration;
+
+/// Most methods not listed by a service that are counted by name.
+pub const MAX_UNLISTED: usize = 64;
+
+/// Name under which methods beyond [MAX_UNLISTED](MAX_UNLISTED) are
+/// counted.
+pub const OTHER: &str = "(other)";
+
+/// Snapshot of the counters of a server.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Number of requests served, including notifications.
+    pub requests: u64,
+    /// Number of notifications served.
+    pub notifications: u64,
+    /// Number of requests served per method.
+    pub methods: BTreeMap<String, u64>,
+    /// Number of error responses by category.
+    pub errors: ErrorCounts,
+    /// Mean time taken to serve a request in milliseconds.
+    pub mean_ms: f64,
+    /// Longest time taken to serve a request in milliseconds.
+    pub max_ms: f64,
+}
+
+/// Number of error responses by category.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct ErrorCounts {
+    /// Errors the caller may retry.
+    pub retryable: u64,
+    /// Errors caused by the request.
+    pub client_fault: u64,
+    /// Errors caused by the server.
+    pub server_fault: u64,
+}
+
+/// Counters updated as requests are served.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    requests: AtomicU64,
+    notifications: AtomicU64,
+    retryable: AtomicU64,
+    client_fault: AtomicU64,
+    server_fault: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+    methods: Mutex<HashMap<String, u64>>,
+    /// Number of methods counted by name that no service lists.
+    unlisted: AtomicUsize,
+}
+
+impl Recorder {
+    /// Count a served request to a method with the `listing` of the
+    /// services.
+    pub(crate) fn record(
+        &self,
+        request: &Request,
+        response: Option<&Response>,
+        elapsed: Duration,
+        policy: &ErrorPolicy,
+        listing: Listing,
+    ) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if request.id().is_none() {
+            self.notifications.fetch_add(1, Ordering::Relaxed);
+        }
+        let micros = elapsed.as_micros() as u64;
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+
+        let error = response.and_then(|response| response.error().as_ref());
+        if let Some(error) = error {
+            let counter = match policy.categorize(error.code) {
+                Category::Retryable => &self.retryable,
+                Category::ClientFault => &self.client_fault,
+                Category::ServerFault => &self.server_fault,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        let not_found = error.map(|error| error.code) == Some(METHOD_NOT_FOUND);
+        if listing == Listing::Unknown
+            || (not_found && listing != Listing::Listed)
+        {
+            return;
+        }
+        let mut methods = self.methods.lock().unwrap();
+        if let Some(count) = methods.get_mut(request.method()) {
+            *count += 1;
+            return;
+        }
+        let name = if listing == Listing::Listed
+            || self.unlisted.fetch_add(1, Ordering::Relaxed) < MAX_UNLISTED
+        {
+            request.method()
+        } else {
+            OTHER
+        };
+        *methods.entry(name.to_string()).or_insert(0) += 1;
+    }
+
+    /// Copy the counters.
+    pub(crate) fn snapshot(&self) -> Stats {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let total_micros = self.total_micros.load(Ordering::Relaxed);
+        let mean_ms = if requests == 0 {
+            0.0
+        } else {
+            total_micros as f64 / requests as f64 / 1000.0
+        };
+        Stats {
+            requests,
+            notifications: self.notifications.load(Ordering::Relaxed),
+            methods: self
+                .methods
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(name, count)| (name.clone(), *count))
+                .collect(),
+            errors: ErrorCounts {
+                retryable: self.retryable.load(Ordering::Relaxed),
+                client_fault: self.client_fault.load(Ordering::Relaxed),
+                server_fault: self.server_fault.load(Ordering::Relaxed),
+            },
+            mean_ms,
+            max_ms: self.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{method::method, Error, Result, Server, Service};
+    use serde_json::json;
+
+    fn sleep(_: (), _: &()) -> Result<()> {
+        std::thread::sleep(Duration::from_millis(2));
+        Ok(())
+    }
+
+    fn fail(_: (), _: &()) -> Result<()> {
+        Err(Error::from(Box::from("failed")))
+    }
+
+    #[test]
+    fn stats_counts() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(crate::methods![
+            method("sleep", sleep),
+            method("fail", fail)
+        ]);
+        let server = Server::new(vec![&service]);
+        assert_eq!(None, server.stats());
+
+        let server = Server::new(vec![&service]).with_stats();
+        assert_eq!(Some(Stats::default()), server.stats());
+        server.serve(&Request::new_reply("sleep", None), &());
+        server.serve(&Request::new_notification("sleep", None), &());
+        server.serve(&Request::new_reply("fail", None), &());
+        server.serve(&Request::new_reply("missing", None), &());
+        server.serve(&Request::new_reply("sleep", Some(json!(["x"]))), &());
+
+        let stats = server.stats().unwrap();
+        assert_eq!(5, stats.requests);
+        assert_eq!(1, stats.notifications);
+        let methods: Vec<_> = stats
+            .methods
+            .iter()
+            .map(|(k, v)| (k.as_str(), *v))
+            .collect();
+        assert_eq!(vec![("fail", 1), ("sleep", 3)], methods);
+        assert_eq!(
+            ErrorCounts {
+                retryable: 0,
+                client_fault: 2,
+                server_fault: 1,
+            },
+            stats.errors
+        );
+        assert!(stats.max_ms >= 2.0);
+        assert!(stats.mean_ms > 0.0 && stats.mean_ms <= stats.max_ms);
+
+        let value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json!(2), value["errors"]["client_fault"]);
+        assert_eq!(json!(3), value["methods"]["sleep"]);
+    }
+
+    #[test]
+    fn stats_unlisted_methods() {
+        let service: Box<dyn Service<Data = ()>> =
+            Box::new(method("sleep", sleep));
+        let server = Server::new(vec![&service])
+            .with_conformance(crate::conformance::Conformance::strict())
+            .with_stats();
+        for index in 0..5 {
+            let method = format!("junk{}", index);
+            server.serve(&Request::new_notification(&method, None), &());
+        }
+        assert_eq!(0, server.stats().unwrap().methods.len());
+
+        /// Answers every method without listing them.
+        struct Fallback;
+        impl Service for Fallback {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                Ok(Some((request, json!(null)).into()))
+            }
+        }
+
+        let fallback: Box<dyn Service<Data = ()>> = Box::new(Fallback);
+        let server = Server::new(vec![&service, &fallback])
+            .with_conformance(crate::conformance::Conformance::strict())
+            .with_stats();
+        for index in 0..MAX_UNLISTED + 10 {
+            let method = format!("junk{}", index);
+            server.serve(&Request::new_notification(&method, None), &());
+        }
+        server.serve(&Request::new_notification("sleep", None), &());
+        server.serve(&Request::new_notification("junk0", None), &());
+
+        let stats = server.stats().unwrap();
+        assert_eq!(MAX_UNLISTED + 2, stats.methods.len());
+        assert_eq!(Some(&1), stats.methods.get("sleep"));
+        assert_eq!(Some(&2), stats.methods.get("junk0"));
+        assert_eq!(Some(&10), stats.methods.get(OTHER));
+    }
+}
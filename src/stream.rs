@@ -0,0 +1,584 @@
+//! Stream a large result in chunks, requires the `async` feature.
+//!
+//! A result that is too big for one response, such as a log tail or an
+//! export, is sent as a stream: the handler answers with
+//! `{"stream": id}` and then sends notifications for the stream:
+//!
+//! * `rpc.stream.chunk` with `{"stream": id, "seq": n, "data": ...}`
+//!   for each chunk, `seq` counts from zero.
+//! * `rpc.stream.end` with `{"stream": id, "chunks": count}` once all
+//!   chunks are sent, and an `error` when the stream failed.
+//!
+//! On the server a [StreamWriter](StreamWriter) generates the id and
+//! numbers the chunks; give it a bounded channel so a slow connection
+//! makes [send()](StreamWriter::send) wait rather than buffer the whole
+//! result. On the client pass every notification to
+//! [Streams::dispatch()](Streams::dispatch) and read the chunks of a
+//! stream in order from a [StreamReader](StreamReader):
+//!
+//! ```
+//! use futures_util::StreamExt;
+//! use json_rpc2::{stream::{StreamWriter, Streams}, Request};
+//! use serde_json::json;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+//! let request = Request::new_reply("export", None);
+//! let mut writer = StreamWriter::new(tx);
+//! let response = writer.response(&request);
+//! writer.send(json!("first")).await?;
+//! writer.send(json!("second")).await?;
+//! writer.finish().await?;
+//!
+//! let streams = Streams::new();
+//! let reader = streams.open(&response)?;
+//! while let Ok(notification) = rx.try_recv() {
+//!     assert!(streams.dispatch(&notification));
+//! }
+//! let chunks: Vec<_> = reader.map(|chunk| chunk.unwrap()).collect().await;
+//! assert_eq!(vec![json!("first"), json!("second")], chunks);
+//! # Ok::<(), json_rpc2::Error>(())
+//! # }).unwrap();
+//! ```
+//!
+//! Chunks that arrive out of order are buffered until the missing ones
+//! arrive and duplicates are dropped. A chunk that is still missing
+//! when the end notification has been read and no more notifications
+//! are queued ends the stream with an error.
+
+pub use crate::notify::{STREAM_CHUNK, STREAM_END};
+
+use crate::{notify::Notifier, Error, Request, Response, Result, RpcError};
+use futures_util::stream::Stream;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// Most chunks a reader buffers waiting for a missing chunk.
+const MAX_PENDING: usize = 1024;
+
+/// Most notifications kept for streams that are not open yet.
+const MAX_EARLY: usize = 1024;
+
+/// Parameters for the `rpc.stream.chunk` notification.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct ChunkParams {
+    /// The id of the stream.
+    pub stream: String,
+    /// Position of the chunk in the stream, starting at zero.
+    pub seq: u64,
+    /// The chunk.
+    pub data: Value,
+}
+
+/// Parameters for the `rpc.stream.end` notification.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct EndParams {
+    /// The id of the stream.
+    pub stream: String,
+    /// Number of chunks sent.
+    pub chunks: u64,
+    /// Why the stream failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+enum Sink {
+    Channel(mpsc::Sender<Request>),
+    Notifier(Notifier),
+}
+
+/// Sends the chunks of a stream as notifications.
+///
+/// A writer dropped before [finish()](StreamWriter::finish) or
+/// [fail()](StreamWriter::fail) ends the stream with an internal error
+/// if the channel has room.
+pub struct StreamWriter {
+    id: String,
+    seq: u64,
+    sink: Sink,
+    done: bool,
+}
+
+impl StreamWriter {
+    /// Create a writer that sends to a channel, waiting when it is
+    /// full.
+    pub fn new(sender: mpsc::Sender<Request>) -> Self {
+        Self::with_sink(Sink::Channel(sender))
+    }
+
+    /// Create a writer that sends with a notifier, which never waits.
+    pub fn with_notifier(notifier: Notifier) -> Self {
+        Self::with_sink(Sink::Notifier(notifier))
+    }
+
+    fn with_sink(sink: Sink) -> Self {
+        Self {
+            id: format!("0x{:016x}", rand::thread_rng().gen::<u64>()),
+            seq: 0,
+            sink,
+            done: false,
+        }
+    }
+
+    /// The id of the stream.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Number of chunks sent.
+    pub fn chunks(&self) -> u64 {
+        self.seq
+    }
+
+    /// The response to the request that started the stream, the result
+    /// is `{"stream": id}`.
+    pub fn response(&self, request: &Request) -> Response {
+        (request, json!({"stream": self.id})).into()
+    }
+
+    /// Send a chunk.
+    ///
+    /// Fails when the receiver of the channel was dropped, the
+    /// producer should stop.
+    pub async fn send(&mut self, data: Value) -> Result<()> {
+        let params = ChunkParams {
+            stream: self.id.clone(),
+            seq: self.seq,
+            data,
+        };
+        self.notify(STREAM_CHUNK, serde_json::to_value(params).ok())
+            .await?;
+        self.seq += 1;
+        Ok(())
+    }
+
+    /// End the stream after the last chunk.
+    pub async fn finish(mut self) -> Result<()> {
+        self.end(None).await
+    }
+
+    /// End the stream with an error.
+    pub async fn fail(mut self, error: RpcError) -> Result<()> {
+        self.end(Some(error)).await
+    }
+
+    async fn end(&mut self, error: Option<RpcError>) -> Result<()> {
+        self.done = true;
+        let params = self.end_params(error);
+        self.notify(STREAM_END, params).await
+    }
+
+    fn end_params(&self, error: Option<RpcError>) -> Option<Value> {
+        let params = EndParams {
+            stream: self.id.clone(),
+            chunks: self.seq,
+            error,
+        };
+        serde_json::to_value(params).ok()
+    }
+
+    async fn notify(&self, method: &str, params: Option<Value>) -> Result<()> {
+        match &self.sink {
+            Sink::Channel(sender) => sender
+                .send(Request::new_notification(method, params))
+                .await
+                .map_err(|_| Error::from(Box::from("Stream receiver closed"))),
+            Sink::Notifier(notifier) => {
+                notifier.notify(method, params);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for StreamWriter {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let error = RpcError::new("Stream abandoned".to_string(), None);
+        let params = self.end_params(Some(error));
+        match &self.sink {
+            Sink::Channel(sender) => {
+                let notification =
+                    Request::new_notification(STREAM_END, params);
+                let _ = sender.try_send(notification);
+            }
+            Sink::Notifier(notifier) => notifier.notify(STREAM_END, params),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Routes {
+    readers: HashMap<String, mpsc::UnboundedSender<Request>>,
+    early: HashMap<String, Vec<Request>>,
+    early_len: usize,
+}
+
+/// Routes stream notifications received by a client to the readers.
+///
+/// Notifications for a stream that is not open yet, because they
+/// arrived before the response, are kept until it is opened; at most
+/// 1024 are kept across all streams.
+#[derive(Clone, Default)]
+pub struct Streams {
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl Streams {
+    /// Create a router without streams.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Open a reader for the stream started by a response.
+    ///
+    /// An error response yields `Error::Rpc`.
+    pub fn open(&self, response: &Response) -> Result<StreamReader> {
+        if let Some(error) = response.error() {
+            return Err(Error::Rpc(error.clone()));
+        }
+        let id = response
+            .result()
+            .as_ref()
+            .and_then(|result| result.get("stream"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                Error::from(Box::from("Response has no stream id"))
+            })?;
+        Ok(self.reader(id))
+    }
+
+    /// Open a reader for a stream id.
+    pub fn reader(&self, id: &str) -> StreamReader {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(early) = routes.early.remove(id) {
+            routes.early_len -= early.len();
+            for notification in early {
+                let _ = sender.send(notification);
+            }
+        }
+        routes.readers.insert(id.to_string(), sender);
+        StreamReader {
+            id: id.to_string(),
+            receiver,
+            next: 0,
+            pending: BTreeMap::new(),
+            end: None,
+            done: false,
+            routes: Arc::downgrade(&self.routes),
+        }
+    }
+
+    /// Pass a notification to the reader of its stream.
+    ///
+    /// Returns `false` when it is not a stream notification.
+    pub fn dispatch(&self, notification: &Request) -> bool {
+        let method = notification.method();
+        if method != STREAM_CHUNK && method != STREAM_END {
+            return false;
+        }
+        let stream = notification
+            .params()
+            .as_ref()
+            .and_then(|params| params.get("stream"))
+            .and_then(Value::as_str);
+        let stream = match stream {
+            Some(stream) => stream,
+            None => return true,
+        };
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(sender) = routes.readers.get(stream) {
+            let _ = sender.send(notification.clone());
+        } else if routes.early_len < MAX_EARLY {
+            routes.early_len += 1;
+            routes
+                .early
+                .entry(stream.to_string())
+                .or_default()
+                .push(notification.clone());
+        }
+        true
+    }
+
+    /// Number of open streams.
+    pub fn len(&self) -> usize {
+        self.routes.lock().unwrap().readers.len()
+    }
+
+    /// Determine if there are no open streams.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The chunks of a stream in order.
+///
+/// Yields the data of each chunk and ends after the last chunk; a
+/// stream that failed or has missing chunks yields an error last.
+pub struct StreamReader {
+    id: String,
+    receiver: mpsc::UnboundedReceiver<Request>,
+    next: u64,
+    pending: BTreeMap<u64, Value>,
+    end: Option<EndParams>,
+    done: bool,
+    routes: Weak<Mutex<Routes>>,
+}
+
+impl StreamReader {
+    /// The id of the stream.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn fail(&mut self, message: String) -> Poll<Option<Result<Value>>> {
+        self.done = true;
+        Poll::Ready(Some(Err(Error::from(Box::from(message)))))
+    }
+
+    /// Buffer a notification, returns an error message when the stream
+    /// cannot continue.
+    fn receive(&mut self, notification: Request) -> Option<String> {
+        match notification.method() {
+            STREAM_CHUNK => match notification.deserialize::<ChunkParams>() {
+                Ok(chunk) if chunk.seq < self.next => None,
+                Ok(_) if self.pending.len() >= MAX_PENDING => Some(format!(
+                    "Stream {} is missing chunk {}",
+                    self.id, self.next
+                )),
+                Ok(chunk) => {
+                    self.pending.insert(chunk.seq, chunk.data);
+                    None
+                }
+                Err(e) => Some(format!("Invalid chunk: {}", e)),
+            },
+            _ => match notification.deserialize::<EndParams>() {
+                Ok(end) => {
+                    self.end = Some(end);
+                    None
+                }
+                Err(e) => Some(format!("Invalid stream end: {}", e)),
+            },
+        }
+    }
+}
+
+impl Stream for StreamReader {
+    type Item = Result<Value>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            if let Some(data) = this.pending.remove(&this.next) {
+                this.next += 1;
+                return Poll::Ready(Some(Ok(data)));
+            }
+            if let Some(end) = &this.end {
+                if this.next >= end.chunks {
+                    this.done = true;
+                    return Poll::Ready(
+                        end.error.clone().map(|e| Err(Error::Rpc(e))),
+                    );
+                }
+            }
+            match this.receiver.poll_recv(cx) {
+                Poll::Ready(Some(notification)) => {
+                    if let Some(message) = this.receive(notification) {
+                        return this.fail(message);
+                    }
+                }
+                Poll::Ready(None) => {
+                    let message =
+                        format!("Stream {} closed before the end", this.id);
+                    return this.fail(message);
+                }
+                Poll::Pending => {
+                    return match this.end.as_ref().map(|end| end.chunks) {
+                        Some(chunks) => {
+                            let message = format!(
+                                "Stream {} is missing chunk {} of {}",
+                                this.id, this.next, chunks
+                            );
+                            this.fail(message)
+                        }
+                        None => Poll::Pending,
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl Drop for StreamReader {
+    fn drop(&mut self) {
+        if let Some(routes) = self.routes.upgrade() {
+            if let Ok(mut routes) = routes.lock() {
+                routes.readers.remove(&self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::futures::{Server, Service};
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+
+    /// Streams the numbers up to the parameter.
+    struct Export;
+
+    #[async_trait]
+    impl Service for Export {
+        type Data = mpsc::Sender<Request>;
+        async fn handle(
+            &self,
+            request: &Request,
+            ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            let (count,): (u64,) = request.deserialize()?;
+            let mut writer = StreamWriter::new(ctx.clone());
+            let response = writer.response(request);
+            tokio::spawn(async move {
+                for n in 0..count {
+                    if writer.send(json!(n)).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = writer.finish().await;
+            });
+            Ok(Some(response))
+        }
+    }
+
+    fn chunk(stream: &str, seq: u64) -> Request {
+        Request::new_notification(
+            STREAM_CHUNK,
+            Some(json!({"stream": stream, "seq": seq, "data": seq})),
+        )
+    }
+
+    fn end(stream: &str, chunks: u64) -> Request {
+        Request::new_notification(
+            STREAM_END,
+            Some(json!({"stream": stream, "chunks": chunks})),
+        )
+    }
+
+    async fn read(
+        reader: StreamReader,
+    ) -> Vec<std::result::Result<Value, String>> {
+        reader
+            .map(|chunk| chunk.map_err(|e| e.to_string()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn stream_round_trip() {
+        let service: Box<dyn Service<Data = mpsc::Sender<Request>>> =
+            Box::new(Export);
+        let server = Server::new(vec![&service]);
+        // A small channel makes the writer wait for the client.
+        let (tx, mut rx) = mpsc::channel(2);
+        let request = Request::new_reply("export", Some(json!([50])));
+        let response = server.serve(&request, &tx).await.unwrap();
+
+        let streams = Streams::new();
+        let reader = streams.open(&response).unwrap();
+        let router = streams.clone();
+        tokio::spawn(async move {
+            while let Some(notification) = rx.recv().await {
+                router.dispatch(&notification);
+            }
+        });
+        let chunks: Vec<Value> =
+            reader.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!((0..50).map(|n| json!(n)).collect::<Vec<_>>(), chunks);
+        assert!(streams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_out_of_order_and_early() {
+        let streams = Streams::new();
+        // Notifications that arrive before the stream is opened are kept.
+        for notification in [chunk("s", 2), chunk("s", 0)].iter() {
+            assert!(streams.dispatch(notification));
+        }
+        let reader = streams.reader("s");
+        for notification in [chunk("s", 0), end("s", 3), chunk("s", 1)].iter() {
+            streams.dispatch(notification);
+        }
+        assert!(!streams.dispatch(&Request::new_notification("other", None)));
+        assert_eq!(
+            vec![Ok(json!(0)), Ok(json!(1)), Ok(json!(2))],
+            read(reader).await
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_missing_chunk() {
+        let streams = Streams::new();
+        let reader = streams.reader("s");
+        for notification in [chunk("s", 0), chunk("s", 2), end("s", 3)].iter() {
+            streams.dispatch(notification);
+        }
+        let chunks = read(reader).await;
+        assert_eq!(2, chunks.len());
+        assert_eq!(Ok(json!(0)), chunks[0]);
+        assert_eq!(
+            Err("Stream s is missing chunk 1 of 3".to_string()),
+            chunks[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_failed_and_abandoned() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut writer = StreamWriter::new(tx.clone());
+        writer.send(json!("partial")).await.unwrap();
+        let error = RpcError::new("export failed".to_string(), None);
+        let streams = Streams::new();
+        let reader = streams.reader(writer.id());
+        writer.fail(error).await.unwrap();
+
+        let abandoned = StreamWriter::new(tx);
+        let other = streams.reader(abandoned.id());
+        drop(abandoned);
+        while let Ok(notification) = rx.try_recv() {
+            streams.dispatch(&notification);
+        }
+        assert_eq!(
+            vec![Ok(json!("partial")), Err("export failed".to_string())],
+            read(reader).await
+        );
+        assert_eq!(
+            vec![Err("Stream abandoned".to_string())],
+            read(other).await
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_receiver_closed() {
+        let (tx, rx) = mpsc::channel(1);
+        let mut writer = StreamWriter::new(tx);
+        drop(rx);
+        assert!(writer.send(json!(1)).await.is_err());
+    }
+}
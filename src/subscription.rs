@@ -0,0 +1,272 @@
+//! Bookkeeping for subscriptions.
+//!
+//! Publish and subscribe APIs, such as Ethereum's `eth_subscribe`,
+//! return a subscription id from a subscribe method and then send
+//! notifications tagged with that id until the client unsubscribes or
+//! disconnects. [Subscriptions](Subscriptions) generates the ids, maps
+//! them to the [sink](SubscriptionSink) of the connection and forgets a
+//! subscription once its sink fails:
+//!
+//! ```
+//! use json_rpc2::subscription::Subscriptions;
+//! use serde_json::json;
+//! use std::sync::mpsc;
+//!
+//! let subscriptions = Subscriptions::new("counter_subscription");
+//! let (tx, rx) = mpsc::channel();
+//! let id = subscriptions.create(tx);
+//!
+//! assert!(subscriptions.notify(&id, json!(1)));
+//! let notification = rx.try_recv().unwrap();
+//! assert_eq!("counter_subscription", notification.method());
+//! assert_eq!(
+//!     Some(&json!({"subscription": id, "result": 1})),
+//!     notification.params().as_ref()
+//! );
+//!
+//! drop(rx);
+//! assert!(!subscriptions.notify(&id, json!(2)));
+//! assert!(subscriptions.is_empty());
+//! ```
+//!
+//! Handlers get the subscriptions and the sink of the connection from
+//! the service context, see the `subscribe` example.
+
+use crate::{notify::Notifier, Error, Request, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{mpsc, Mutex};
+
+/// Identifier of a subscription, a random hex string such as
+/// `0x1f4a9c0d3b2e8f71`.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(transparent)]
+pub struct SubscriptionId(String);
+
+impl SubscriptionId {
+    /// The id as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for SubscriptionId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl From<&str> for SubscriptionId {
+    fn from(id: &str) -> Self {
+        Self(id.to_string())
+    }
+}
+
+/// Destination for the notifications of a subscription, usually the
+/// connection of the subscriber.
+pub trait SubscriptionSink: Send {
+    /// Send a notification; an error means the subscriber is gone.
+    fn send(&self, notification: Request) -> Result<()>;
+}
+
+impl SubscriptionSink for mpsc::Sender<Request> {
+    fn send(&self, notification: Request) -> Result<()> {
+        mpsc::Sender::send(self, notification)
+            .map_err(|e| Error::from(Box::from(e.to_string())))
+    }
+}
+
+#[cfg(feature = "async")]
+impl SubscriptionSink for tokio::sync::mpsc::UnboundedSender<Request> {
+    fn send(&self, notification: Request) -> Result<()> {
+        tokio::sync::mpsc::UnboundedSender::send(self, notification)
+            .map_err(|e| Error::from(Box::from(e.to_string())))
+    }
+}
+
+/// A notifier never fails so the subscription is only removed by
+/// [remove()](Subscriptions::remove).
+impl SubscriptionSink for Notifier {
+    fn send(&self, notification: Request) -> Result<()> {
+        self.notify(notification.method(), notification.params().clone());
+        Ok(())
+    }
+}
+
+/// Subscriptions and their sinks.
+///
+/// Share between handlers with an `Arc` in the service context.
+pub struct Subscriptions<T> {
+    method: String,
+    sinks: Mutex<HashMap<SubscriptionId, T>>,
+}
+
+impl<T: SubscriptionSink> Subscriptions<T> {
+    /// Create subscriptions whose notifications use `method`.
+    pub fn new(method: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            sinks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The method name of the notifications.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Add a subscription sending to `sink` and return its id.
+    pub fn create(&self, sink: T) -> SubscriptionId {
+        let mut sinks = self.sinks.lock().unwrap();
+        let mut rng = rand::thread_rng();
+        let id = loop {
+            let id = SubscriptionId(format!("0x{:016x}", rng.gen::<u64>()));
+            if !sinks.contains_key(&id) {
+                break id;
+            }
+        };
+        sinks.insert(id.clone(), sink);
+        id
+    }
+
+    /// Send a notification with the `subscription` id and the `result`
+    /// as parameters.
+    ///
+    /// Returns `false` when there is no such subscription or the sink
+    /// failed, in which case the subscription is removed; a task
+    /// producing the results should stop.
+    pub fn notify(&self, id: &SubscriptionId, result: Value) -> bool {
+        let mut sinks = self.sinks.lock().unwrap();
+        let sink = match sinks.get(id) {
+            Some(sink) => sink,
+            None => return false,
+        };
+        let notification = Request::new_notification(
+            &self.method,
+            Some(json!({"subscription": id, "result": result})),
+        );
+        if sink.send(notification).is_err() {
+            sinks.remove(id);
+            return false;
+        }
+        true
+    }
+
+    /// Remove a subscription, returns `false` when it does not exist.
+    pub fn remove(&self, id: &SubscriptionId) -> bool {
+        self.sinks.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Determine if a subscription exists.
+    pub fn contains(&self, id: &SubscriptionId) -> bool {
+        self.sinks.lock().unwrap().contains_key(id)
+    }
+
+    /// Number of subscriptions.
+    pub fn len(&self) -> usize {
+        self.sinks.lock().unwrap().len()
+    }
+
+    /// Determine if there are no subscriptions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{method::method, methods, Server, Service};
+    use std::sync::Arc;
+
+    struct Connection {
+        subscriptions: Arc<Subscriptions<mpsc::Sender<Request>>>,
+        sink: mpsc::Sender<Request>,
+    }
+
+    fn subscribe(_: (), ctx: &Connection) -> Result<SubscriptionId> {
+        Ok(ctx.subscriptions.create(ctx.sink.clone()))
+    }
+
+    fn unsubscribe((id,): (SubscriptionId,), ctx: &Connection) -> Result<bool> {
+        Ok(ctx.subscriptions.remove(&id))
+    }
+
+    #[test]
+    fn subscription_lifecycle() -> Result<()> {
+        let service: Box<dyn Service<Data = Connection>> = Box::new(methods![
+            method("counter_subscribe", subscribe),
+            method("counter_unsubscribe", unsubscribe)
+        ]);
+        let server = Server::new(vec![&service]);
+        let subscriptions =
+            Arc::new(Subscriptions::new("counter_subscription"));
+        let (tx, rx) = mpsc::channel();
+        let connection = Connection {
+            subscriptions: Arc::clone(&subscriptions),
+            sink: tx,
+        };
+
+        let response = server
+            .serve(&Request::new_reply("counter_subscribe", None), &connection)
+            .unwrap();
+        let id: SubscriptionId =
+            serde_json::from_value(response.result().clone().unwrap()).unwrap();
+        assert!(id.as_str().starts_with("0x"));
+        assert_eq!(18, id.as_str().len());
+        let other = subscriptions.create(connection.sink.clone());
+        assert_ne!(id, other);
+        assert_eq!(2, subscriptions.len());
+
+        assert!(subscriptions.notify(&id, json!(1)));
+        let notification = rx.try_recv().unwrap();
+        let params: Value = notification.deserialize()?;
+        assert_eq!(json!({"subscription": id, "result": 1}), params);
+
+        let response = server
+            .serve(
+                &Request::new_reply(
+                    "counter_unsubscribe",
+                    Some(json!([id.as_str()])),
+                ),
+                &connection,
+            )
+            .unwrap();
+        assert_eq!(&Some(json!(true)), response.result());
+        assert!(!subscriptions.contains(&id));
+        assert!(!subscriptions.notify(&id, json!(2)));
+        assert!(!subscriptions.remove(&id));
+        assert!(subscriptions.contains(&other));
+        Ok(())
+    }
+
+    #[test]
+    fn subscription_sink_failure() {
+        let subscriptions = Subscriptions::new("tick");
+        let (tx, rx) = mpsc::channel();
+        let first = subscriptions.create(tx);
+        let (closed_tx, closed_rx) = mpsc::channel();
+        let closed = subscriptions.create(closed_tx);
+        drop(closed_rx);
+        assert!(subscriptions.notify(&first, Value::Null));
+        assert!(!subscriptions.notify(&closed, Value::Null));
+        assert_eq!(1, subscriptions.len());
+        assert_eq!(1, rx.try_iter().count());
+
+        let (notifier, notifications) = Notifier::channel();
+        let subscriptions = Subscriptions::new("tick");
+        let id = subscriptions.create(notifier);
+        drop(notifications);
+        assert!(subscriptions.notify(&id, Value::Null));
+        assert!(subscriptions.contains(&id));
+    }
+}
@@ -0,0 +1,304 @@
+//! Record the requests a server handles and replay them later.
+//!
+//! A [Recorder](Recorder) appends one JSON line per served request to a
+//! writer, holding the time, the request and the response. Install it
+//! with [Server::on_served()](crate::Server::on_served):
+//!
+//! ```
+//! use json_rpc2::{method::method, tape::{Recorder, Replayer}, *};
+//!
+//! fn add((a, b): (u64, u64), _: &()) -> Result<u64> {
+//!     Ok(a + b)
+//! }
+//!
+//! let service: Box<dyn Service<Data = ()>> = Box::new(method("add", add));
+//! let recorder = Recorder::new(Vec::new());
+//! let server = Server::new(vec![&service]).on_served(recorder.hook());
+//! server.serve(&Request::new_reply("add", Some(serde_json::json!([1, 2]))), &());
+//! drop(server);
+//!
+//! let tape = recorder.into_inner().unwrap();
+//! let server = Server::new(vec![&service]);
+//! let report = Replayer::new().replay(&tape[..], &server, &())?;
+//! assert_eq!(1, report.entries);
+//! assert!(report.passed());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! A [Replayer](Replayer) serves the recorded requests again, for
+//! example with a new build, and reports the responses that differ.
+//! Values that change between runs, such as timestamps or the
+//! [processing time](crate::timing), are excluded with
+//! [RedactionRules](crate::redact::RedactionRules) applied to both
+//! responses; pointers are relative to the whole response, for example
+//! `/_meta/elapsed_ms`.
+
+use crate::{redact::RedactionRules, Request, Response, Server};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Recorded request and response.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Entry {
+    /// Milliseconds since the Unix epoch when the request was served.
+    pub timestamp_ms: u64,
+    /// The request.
+    pub request: Request,
+    /// The response, `None` for a notification.
+    pub response: Option<Response>,
+}
+
+/// Entry borrowing the request and response for writing.
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    timestamp_ms: u64,
+    request: &'a Request,
+    response: Option<&'a Response>,
+}
+
+/// Appends served requests and their responses to a writer.
+///
+/// Clones share the writer.
+pub struct Recorder<W> {
+    writer: Arc<Mutex<W>>,
+}
+
+impl<W> Clone for Recorder<W> {
+    fn clone(&self) -> Self {
+        Self {
+            writer: Arc::clone(&self.writer),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Recorder<W> {
+    /// Create a recorder writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    /// Append an entry for a served request.
+    pub fn record(
+        &self,
+        request: &Request,
+        response: Option<&Response>,
+    ) -> io::Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+        let mut line = serde_json::to_vec(&EntryRef {
+            timestamp_ms,
+            request,
+            response,
+        })?;
+        line.push(b'\n');
+        self.writer.lock().unwrap().write_all(&line)
+    }
+
+    /// Function for [Server::on_served()](crate::Server::on_served)
+    /// that records every request, write errors are logged.
+    pub fn hook(
+        &self,
+    ) -> impl Fn(&Request, Option<&Response>, Duration) + Send + Sync + 'static
+    {
+        let recorder = self.clone();
+        move |request, response, _| {
+            if let Err(e) = recorder.record(request, response) {
+                log::warn!("failed to record {}: {}", request.method(), e);
+            }
+        }
+    }
+
+    /// Flush the writer.
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+
+    /// The writer, `None` while a clone or hook is still alive.
+    pub fn into_inner(self) -> Option<W> {
+        Arc::try_unwrap(self.writer)
+            .ok()
+            .map(|writer| writer.into_inner().unwrap())
+    }
+}
+
+/// Response that differs from the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// One-based line of the entry in the tape.
+    pub line: usize,
+    /// The method of the request.
+    pub method: String,
+    /// The recorded response with the ignored values redacted.
+    pub expected: Option<Value>,
+    /// The new response with the ignored values redacted.
+    pub actual: Option<Value>,
+}
+
+/// Outcome of replaying a tape.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Number of entries replayed.
+    pub entries: usize,
+    /// Responses that differ from the recording.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Report {
+    /// Determine if every response matched the recording.
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Serves recorded requests and compares the responses.
+#[derive(Debug, Clone, Default)]
+pub struct Replayer {
+    ignore: RedactionRules,
+}
+
+impl Replayer {
+    /// Create a replayer that compares whole responses.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Exclude the values matched by the rules from the comparison.
+    pub fn ignore(mut self, rules: RedactionRules) -> Self {
+        self.ignore = rules;
+        self
+    }
+
+    /// Serve every entry of the tape and report the differences.
+    ///
+    /// Blank lines are skipped; a line that is not an entry is an
+    /// `InvalidData` error.
+    pub fn replay<R: BufRead, T>(
+        &self,
+        tape: R,
+        server: &Server<'_, T>,
+        ctx: &T,
+    ) -> io::Result<Report> {
+        let mut report = Report::default();
+        for (index, line) in tape.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line)?;
+            let response = server.serve(&entry.request, ctx);
+            let expected = self.normalize(entry.response.as_ref());
+            let actual = self.normalize(response.as_ref());
+            if expected != actual {
+                report.mismatches.push(Mismatch {
+                    line: index + 1,
+                    method: entry.request.method().to_string(),
+                    expected,
+                    actual,
+                });
+            }
+            report.entries += 1;
+        }
+        Ok(report)
+    }
+
+    fn normalize(&self, response: Option<&Response>) -> Option<Value> {
+        response
+            .and_then(|response| serde_json::to_value(response).ok())
+            .map(|value| self.ignore.apply(&value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{method::method, methods, Result, Service};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn add((a, b): (u64, u64), _: &AtomicU64) -> Result<u64> {
+        Ok(a + b)
+    }
+
+    fn tick(_: (), ctx: &AtomicU64) -> Result<Value> {
+        let count = ctx.fetch_add(1, Ordering::Relaxed);
+        Ok(json!({"count": count, "at": count * 1000}))
+    }
+
+    fn record(server: &Server<'_, AtomicU64>, ctx: &AtomicU64) -> Vec<u8> {
+        let recorder = Recorder::new(Vec::new());
+        let hook = recorder.hook();
+        for request in [
+            Request::new_reply("add", Some(json!([1, 2]))),
+            Request::new_notification("add", Some(json!([3, 4]))),
+            Request::new_reply("tick", None),
+            Request::new_reply("missing", None),
+        ]
+        .iter()
+        {
+            let response = server.serve(request, ctx);
+            hook(request, response.as_ref(), Duration::default());
+        }
+        drop(hook);
+        recorder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn tape_record_replay() -> io::Result<()> {
+        let service: Box<dyn Service<Data = AtomicU64>> =
+            Box::new(methods![method("add", add), method("tick", tick)]);
+        let server = Server::new(vec![&service]);
+        let tape = record(&server, &AtomicU64::new(0));
+
+        let entries: Vec<Entry> = tape
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(4, entries.len());
+        assert!(entries[1].response.is_none());
+        assert!(entries[0].timestamp_ms > 0);
+
+        let report =
+            Replayer::new().replay(&tape[..], &server, &AtomicU64::new(0))?;
+        assert_eq!(4, report.entries);
+        assert!(report.passed());
+
+        let report =
+            Replayer::new().replay(&tape[..], &server, &AtomicU64::new(5))?;
+        assert_eq!(1, report.mismatches.len());
+        let mismatch = &report.mismatches[0];
+        assert_eq!((3, "tick"), (mismatch.line, mismatch.method.as_str()));
+        assert_eq!(
+            json!(0),
+            mismatch.expected.as_ref().unwrap()["result"]["count"]
+        );
+        assert_eq!(
+            json!(5),
+            mismatch.actual.as_ref().unwrap()["result"]["count"]
+        );
+
+        let replayer = Replayer::new()
+            .ignore(RedactionRules::new().key("at").pointer("/result/count"));
+        let report = replayer.replay(&tape[..], &server, &AtomicU64::new(5))?;
+        assert!(report.passed());
+        Ok(())
+    }
+
+    #[test]
+    fn tape_invalid_line() {
+        let service: Box<dyn Service<Data = AtomicU64>> =
+            Box::new(method("add", add));
+        let server = Server::new(vec![&service]);
+        let error = Replayer::new()
+            .replay(&b"\n{\"nope\":1}\n"[..], &server, &AtomicU64::new(0))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
@@ -0,0 +1,232 @@
+//! Fake services for tests.
+//!
+//! A [MockService](MockService) answers methods with closures or fixed
+//! errors and records every request it sees so a test can check what
+//! reached it, for example through middleware under test. It implements
+//! both the sync and the async `Service` traits:
+//!
+//! ```
+//! use json_rpc2::{testing::MockService, *};
+//! use serde_json::json;
+//!
+//! let mock = MockService::<()>::new()
+//!     .on("hello", |_params| Ok(json!("hi")))
+//!     .on_error("boom", RpcError::new("boom".to_string(), None));
+//! let requests = mock.requests();
+//! let service: Box<dyn Service<Data = ()>> = Box::new(mock);
+//! let server = Server::new(vec![&service]);
+//!
+//! let request = Request::new_reply("hello", None);
+//! let response = server.serve(&request, &()).unwrap();
+//! assert_eq!(Some(json!("hi")), response.into());
+//! assert_eq!("hello", requests.lock().unwrap()[0].method());
+//! ```
+
+use crate::{Error, Request, Response, Result, RpcError, Service};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+/// Closure answering a method of a [MockService](MockService) with the
+/// params of the request.
+pub type MockHandler = dyn Fn(Option<&Value>) -> Result<Value> + Send + Sync;
+
+enum Reply {
+    Result(Box<MockHandler>),
+    Error(RpcError),
+}
+
+/// Service for tests answering each method with a closure or an error.
+///
+/// Methods without an answer yield `Error::MethodNotFound` unless
+/// [passthrough_default()](MockService::passthrough_default) lets the
+/// next service handle them.
+pub struct MockService<T = ()> {
+    replies: HashMap<String, Reply>,
+    passthrough: bool,
+    requests: Arc<Mutex<Vec<Request>>>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> MockService<T> {
+    /// Create a mock that answers no methods.
+    pub fn new() -> Self {
+        Self {
+            replies: HashMap::new(),
+            passthrough: false,
+            requests: Arc::new(Mutex::new(Vec::new())),
+            marker: PhantomData,
+        }
+    }
+
+    /// Answer `method` with the result of `handler`, an error is
+    /// converted to an error response as for any service.
+    pub fn on<F>(mut self, method: &str, handler: F) -> Self
+    where
+        F: Fn(Option<&Value>) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.replies
+            .insert(method.to_string(), Reply::Result(Box::new(handler)));
+        self
+    }
+
+    /// Answer `method` with `error`.
+    pub fn on_error(mut self, method: &str, error: RpcError) -> Self {
+        self.replies.insert(method.to_string(), Reply::Error(error));
+        self
+    }
+
+    /// Leave methods without an answer to the next service.
+    pub fn passthrough_default(mut self) -> Self {
+        self.passthrough = true;
+        self
+    }
+
+    /// The requests seen by the mock, including those passed through.
+    ///
+    /// The list is shared so it can be inspected after the mock was
+    /// moved into a server.
+    pub fn requests(&self) -> Arc<Mutex<Vec<Request>>> {
+        Arc::clone(&self.requests)
+    }
+
+    fn reply(&self, request: &Request) -> Result<Option<Response>> {
+        self.requests.lock().unwrap().push(request.clone());
+        let result = match self.replies.get(request.method()) {
+            Some(Reply::Result(handler)) => handler(request.params().as_ref()),
+            Some(Reply::Error(error)) => Err(Error::Rpc(error.clone())),
+            None if self.passthrough => return Ok(None),
+            None => {
+                return Err(Error::MethodNotFound {
+                    id: request.id().clone(),
+                    name: request.method().to_string(),
+                })
+            }
+        }?;
+        Ok(Some((request, result).into()))
+    }
+
+    fn method_names(&self) -> Vec<String> {
+        let mut methods: Vec<String> = self.replies.keys().cloned().collect();
+        methods.sort();
+        methods
+    }
+}
+
+impl<T> Default for MockService<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Service for MockService<T> {
+    type Data = T;
+    fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.reply(request)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.method_names()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+#[async_trait::async_trait]
+impl<T: Send + Sync> crate::futures::Service for MockService<T> {
+    type Data = T;
+    async fn handle(
+        &self,
+        request: &Request,
+        _ctx: &Self::Data,
+    ) -> Result<Option<Response>> {
+        self.reply(request)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.method_names()
+    }
+
+    fn route_by_methods(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Server, METHOD_NOT_FOUND};
+    use serde_json::json;
+
+    fn mock() -> MockService {
+        MockService::new()
+            .on("add", |params| {
+                let (a, b): (i64, i64) =
+                    serde_json::from_value(params.cloned().unwrap()).unwrap();
+                Ok(json!(a + b))
+            })
+            .on_error("boom", RpcError::new("boom".to_string(), None))
+    }
+
+    #[test]
+    fn mock_service_sync() {
+        let mock = mock();
+        let requests = mock.requests();
+        let service: Box<dyn Service<Data = ()>> = Box::new(mock);
+        let server = Server::new(vec![&service]);
+        assert_eq!(vec!["add", "boom"], server.methods());
+
+        let add = Request::new_reply("add", Some(json!([1, 2])));
+        let response = server.serve(&add, &()).unwrap();
+        assert_eq!(Some(json!(3)), response.into());
+
+        let boom = Request::new_reply("boom", None);
+        let error = server.serve(&boom, &()).unwrap().error().clone().unwrap();
+        assert_eq!("boom", error.message);
+
+        let unknown = Request::new_reply("unknown", None);
+        let error = server.serve(&unknown, &()).unwrap().error().clone();
+        assert_eq!(METHOD_NOT_FOUND, error.unwrap().code);
+
+        let notification =
+            Request::new_notification("add", Some(json!([1, 1])));
+        assert!(server.serve(&notification, &()).is_none());
+
+        let seen: Vec<String> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.method().to_string())
+            .collect();
+        assert_eq!(vec!["add", "boom", "unknown", "add"], seen);
+    }
+
+    #[tokio::test]
+    async fn mock_service_async_passthrough() {
+        let first = MockService::<()>::new()
+            .on("ping", |_| Ok(json!("first")))
+            .passthrough_default();
+        let second =
+            MockService::<()>::new().on("pong", |_| Ok(json!("second")));
+        let (first_requests, second_requests) =
+            (first.requests(), second.requests());
+        let server = crate::futures::Server::new_shared(vec![
+            Arc::new(first),
+            Arc::new(second),
+        ]);
+
+        let pong = Request::new_reply("pong", None);
+        let response = server.serve(&pong, &()).await.unwrap();
+        assert_eq!(Some(json!("second")), response.into());
+        assert_eq!(1, first_requests.lock().unwrap().len());
+        assert_eq!(1, second_requests.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,113 @@
+//! Server processing time attached to responses.
+//!
+//! A server built with [with_timing()](crate::Server::with_timing)
+//! stamps every response with the time taken to serve the request in a
+//! top-level `_meta` object, mirroring the `_meta` field of requests:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "id": 1, "result": 7, "_meta": {"elapsed_ms": 0.42}}
+//! ```
+//!
+//! The time is in fractional milliseconds from when the transport
+//! received the request, see
+//! [Request::set_received()](crate::Request::set_received), or from when
+//! serving started, until the response was built. Other keys in an
+//! existing `_meta` object are kept. Clients that do not know the field
+//! ignore it; read it back with [elapsed()](elapsed).
+//!
+//! Only available with the `extra-fields` feature.
+
+use crate::{meta::META, Response};
+use serde_json::{Map, Number, Value};
+use std::time::{Duration, Instant};
+
+/// Name of the processing time field in the response metadata.
+pub const ELAPSED_MS: &str = "elapsed_ms";
+
+/// The processing time the server attached to a response.
+pub fn elapsed(response: &Response) -> Option<Duration> {
+    let elapsed = response.extra_fields().get(META)?.get(ELAPSED_MS)?;
+    let millis = elapsed.as_f64()?;
+    (millis >= 0.0).then(|| Duration::from_secs_f64(millis / 1000.0))
+}
+
+/// Attach the time since `since` to the response metadata.
+///
+/// A `_meta` field that is not an object is left untouched.
+pub(crate) fn stamp(response: &mut Response, since: Instant) {
+    stamp_fields(response.extra_fields_mut(), since)
+}
+
+/// Attach the time since `since` to the metadata in the top-level
+/// fields of a response.
+pub(crate) fn stamp_fields(fields: &mut Map<String, Value>, since: Instant) {
+    let millis = since.elapsed().as_secs_f64() * 1000.0;
+    let meta = fields
+        .entry(META)
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let (Value::Object(meta), Some(millis)) =
+        (meta, Number::from_f64(millis))
+    {
+        meta.insert(ELAPSED_MS.to_string(), Value::Number(millis));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Request, Result, Server, Service};
+    use serde_json::json;
+
+    struct Echo;
+    impl Service for Echo {
+        type Data = ();
+        fn handle(
+            &self,
+            request: &Request,
+            _ctx: &Self::Data,
+        ) -> Result<Option<Response>> {
+            std::thread::sleep(Duration::from_millis(5));
+            Ok(Some((request, json!(7)).into()))
+        }
+    }
+
+    #[test]
+    fn timing_stamp() {
+        let service: Box<dyn Service<Data = ()>> = Box::new(Echo);
+        let request = Request::new_reply("echo", None);
+
+        let server = Server::new(vec![&service]);
+        let response = server.serve(&request, &()).unwrap();
+        assert!(response.extra_fields().is_empty());
+        assert_eq!(None, elapsed(&response));
+
+        let server = Server::new(vec![&service]).with_timing();
+        let mut request = request;
+        request.set_received(Instant::now() - Duration::from_secs(1));
+        let response = server.serve(&request, &()).unwrap();
+        assert!(elapsed(&response).unwrap() >= Duration::from_secs(1));
+
+        let payload = serde_json::to_value(&response).unwrap();
+        assert!(payload["_meta"]["elapsed_ms"].as_f64().unwrap() >= 1000.0);
+        assert_eq!(json!(7), payload["result"]);
+    }
+
+    #[test]
+    fn timing_keeps_meta() {
+        let mut response: Response = json!({"ok": true}).into();
+        response
+            .extra_fields_mut()
+            .insert(META.to_string(), json!({"region": "eu"}));
+        stamp(&mut response, Instant::now());
+        let meta = &response.extra_fields()[META];
+        assert_eq!(json!("eu"), meta["region"]);
+        assert!(meta[ELAPSED_MS].is_number());
+
+        let mut response: Response = json!({"ok": true}).into();
+        response
+            .extra_fields_mut()
+            .insert(META.to_string(), json!("opaque"));
+        stamp(&mut response, Instant::now());
+        assert_eq!(json!("opaque"), response.extra_fields()[META]);
+    }
+}
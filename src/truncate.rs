@@ -0,0 +1,333 @@
+//! Limit the size of error data sent to clients.
+//!
+//! Parse, invalid request and invalid params errors carry the message
+//! of the JSON parser as data, which may quote large parts of a bad
+//! payload. When these errors are converted to an
+//! [RpcError](crate::RpcError) data longer than the limit keeps its
+//! first bytes, cut on a character boundary, followed by a
+//! `… [truncated N bytes]` suffix.
+//!
+//! The limit is [DEFAULT_MAX_DATA](DEFAULT_MAX_DATA) bytes; a server
+//! answers with another limit when set with
+//! [Server::with_max_error_data()](crate::Server::with_max_error_data).
+//! Data set explicitly on an `Error::Rpc` is never truncated.
+//!
+//! ## Responses
+//!
+//! A handler returning an enormous result can overwhelm every buffer
+//! downstream, so a server can cap the size of serialized responses
+//! with
+//! [Server::with_max_response_size()](crate::Server::with_max_response_size).
+//! A response larger than the limit is replaced with the
+//! [too_large()](too_large) internal error noting its size and the limit.
+//! The check happens when the response is serialized, by
+//! [serve_str()](crate::Server::serve_str),
+//! [serve_slice()](crate::Server::serve_slice),
+//! [serve_lines()](crate::shutdown::serve_lines) and, with the `net`
+//! feature, the TCP serve loop, which applies the limit to every
+//! response of a batch and then to the whole batch, replacing a batch
+//! that is still too large with a single error.
+//!
+//! With the `async` feature
+//! [serve_until()](crate::futures::serve_until) and
+//! [respond()](crate::futures::respond) check the responses they pass
+//! on against the limit of the async server, and an
+//! [OutboundWriter](crate::outbound::OutboundWriter) given a
+//! [limit](crate::outbound::OutboundWriter::max_response_size) checks
+//! the responses it writes.
+
+use crate::{Error, Response, RpcError, INTERNAL_ERROR};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::io::Write;
+
+#[cfg(not(feature = "tracing"))]
+use log::warn as log_warn;
+#[cfg(feature = "tracing")]
+use tracing::warn as log_warn;
+
+/// Default limit for the data of an error in bytes.
+pub const DEFAULT_MAX_DATA: usize = 4 * 1024;
+
+/// Keep at most `max` bytes of `text`, ending on a character
+/// boundary, and note how many bytes were removed.
+pub fn truncate(text: &str, max: usize) -> Cow<'_, str> {
+    if text.len() <= max {
+        return Cow::Borrowed(text);
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!(
+        "{}… [truncated {} bytes]",
+        &text[..end],
+        text.len() - end
+    ))
+}
+
+/// Error data for a message, truncated to the default limit.
+pub(crate) fn data(text: &str) -> Value {
+    Value::String(truncate(text, DEFAULT_MAX_DATA).into_owned())
+}
+
+/// Error data taking ownership of a message within the default limit.
+pub(crate) fn data_owned(text: String) -> Value {
+    match truncate(&text, DEFAULT_MAX_DATA) {
+        Cow::Borrowed(_) => Value::String(text),
+        Cow::Owned(truncated) => Value::String(truncated),
+    }
+}
+
+/// Error data for the message of a parse, invalid request or invalid
+/// params error truncated to `max` bytes, `None` for other errors.
+pub(crate) fn error_data(error: &Error, max: usize) -> Option<Value> {
+    let text = match error {
+        Error::InvalidParams { data, .. } => data.as_str(),
+        Error::Parse { data, .. } | Error::InvalidRequest { data, .. } => data,
+        _ => return None,
+    };
+    Some(Value::String(truncate(text, max).into_owned()))
+}
+
+/// Create the error replacing a response of `size` bytes that exceeds
+/// the `limit`.
+pub fn too_large(size: usize, limit: usize) -> RpcError {
+    RpcError {
+        code: INTERNAL_ERROR,
+        message: "Response too large".into(),
+        data: Some(json!({ "size": size, "limit": limit })),
+    }
+}
+
+/// Writer that keeps at most `max` bytes and counts the rest.
+struct Capped {
+    buffer: Vec<u8>,
+    size: usize,
+    max: usize,
+}
+
+impl Write for Capped {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.size += buf.len();
+        if self.size <= self.max {
+            self.buffer.extend_from_slice(buf);
+        } else if !self.buffer.is_empty() {
+            self.buffer = Vec::new();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serialize a reply, replacing it with the response too large error
+/// for `id` when it exceeds `max` bytes.
+pub(crate) fn to_vec<R: Serialize>(
+    reply: &R,
+    id: &Option<Value>,
+    max: Option<usize>,
+) -> serde_json::Result<Vec<u8>> {
+    let max = match max {
+        Some(max) => max,
+        None => return serde_json::to_vec(reply),
+    };
+    let mut capped = Capped {
+        buffer: Vec::new(),
+        size: 0,
+        max,
+    };
+    serde_json::to_writer(&mut capped, reply)?;
+    if capped.size <= max {
+        return Ok(capped.buffer);
+    }
+    serde_json::to_vec(&replace(capped.size, max, id.clone()))
+}
+
+/// Keep a response that is handed on rather than serialized, replacing
+/// it with the response too large error when it exceeds `max` bytes.
+#[cfg(feature = "async")]
+pub(crate) fn limit(response: Response, max: Option<usize>) -> Response {
+    let max = match max {
+        Some(max) => max,
+        None => return response,
+    };
+    // Only count the bytes.
+    let mut counted = Capped {
+        buffer: Vec::new(),
+        size: 0,
+        max: 0,
+    };
+    match serde_json::to_writer(&mut counted, &response) {
+        Ok(_) if counted.size > max => {
+            replace(counted.size, max, response.id().clone())
+        }
+        _ => response,
+    }
+}
+
+fn replace(size: usize, max: usize, id: Option<Value>) -> Response {
+    log_warn!(
+        "response of {} bytes exceeds the limit of {} bytes",
+        size,
+        max
+    );
+    let mut response = Response::from(crate::Error::Rpc(too_large(size, max)));
+    response.id = id;
+    response
+}
+
+/// Serialize a reply as a string, see [to_vec()](to_vec).
+pub(crate) fn to_string<R: Serialize>(
+    reply: &R,
+    id: &Option<Value>,
+    max: Option<usize>,
+) -> serde_json::Result<String> {
+    // serde_json only writes valid UTF-8
+    to_vec(reply, id, max)
+        .map(|bytes| String::from_utf8(bytes).expect("JSON is UTF-8"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Request, Result, Server, Service, INVALID_PARAMS};
+    use serde_json::json;
+
+    #[test]
+    fn truncate_char_boundary() {
+        assert_eq!("héllo", truncate("héllo", 6));
+        assert_eq!("h… [truncated 5 bytes]", truncate("héllo", 2));
+        assert_eq!("hé… [truncated 3 bytes]", truncate("héllo", 3));
+        assert_eq!("… [truncated 2 bytes]", truncate("é", 1));
+    }
+
+    #[test]
+    fn truncate_enormous_error() {
+        struct Sum;
+        impl Service for Sum {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                let value: u32 = request.deserialize()?;
+                Ok(Some((request, json!(value)).into()))
+            }
+        }
+
+        let service: Box<dyn Service<Data = ()>> = Box::new(Sum);
+        let server =
+            Server::new(vec![&service]).with_max_error_data(DEFAULT_MAX_DATA);
+        let huge = "🦀".repeat(1024 * 1024);
+        let request = Request::new_reply("sum", Some(json!(huge)));
+        let response = server.serve(&request, &()).unwrap();
+        let error = response.error().as_ref().unwrap();
+        assert_eq!(INVALID_PARAMS, error.code);
+        let data = error.data.as_ref().unwrap().as_str().unwrap();
+        assert!(data.len() < DEFAULT_MAX_DATA + 32, "{}", data.len());
+        assert!(data.starts_with("invalid type: string \"🦀"));
+        assert!(data.ends_with(" bytes]"));
+    }
+
+    #[test]
+    fn truncate_per_server() {
+        struct Sum;
+        impl Service for Sum {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                let value: u32 = request.deserialize()?;
+                Ok(Some((request, json!(value)).into()))
+            }
+        }
+
+        let service: Box<dyn Service<Data = ()>> = Box::new(Sum);
+        let small = Server::new(vec![&service]).with_max_error_data(16);
+        let large = Server::new(vec![&service])
+            .with_max_error_data(2 * DEFAULT_MAX_DATA);
+        let default = Server::new(vec![&service]);
+        let text = "x".repeat(DEFAULT_MAX_DATA + 100);
+        let request = Request::new_reply("sum", Some(json!(text)));
+        let data = |server: &Server<'_, ()>| {
+            let response = server.serve(&request, &()).unwrap();
+            let error = response.error().clone().unwrap();
+            error.data.unwrap().as_str().unwrap().to_string()
+        };
+        assert!(data(&small).starts_with("invalid type: st… [truncated "));
+        assert!(!data(&large).ends_with(" bytes]"));
+        assert!(data(&default).ends_with(" bytes]"));
+        assert!(data(&default).len() < DEFAULT_MAX_DATA + 32);
+
+        let payload = format!("{{{}", text);
+        let response = small.serve_str(&payload, &()).unwrap();
+        let response: Response = serde_json::from_str(&response).unwrap();
+        let error = response.error().clone().unwrap();
+        assert_eq!(crate::PARSE_ERROR, error.code);
+        assert!(error.data.unwrap().as_str().unwrap().len() < 48);
+    }
+
+    #[test]
+    fn response_too_large() {
+        struct Huge;
+        impl Service for Huge {
+            type Data = ();
+            fn handle(
+                &self,
+                request: &Request,
+                _ctx: &Self::Data,
+            ) -> Result<Option<Response>> {
+                let (size,): (usize,) = request.deserialize()?;
+                Ok(Some((request, json!("x".repeat(size))).into()))
+            }
+        }
+
+        let service: Box<dyn Service<Data = ()>> = Box::new(Huge);
+        let server =
+            Server::new(vec![&service]).with_max_response_size(1024 * 1024);
+        let check = |payload: &str| {
+            let response: Response = serde_json::from_str(payload).unwrap();
+            assert_eq!(&Some(json!(1)), response.id());
+            let error = response.error().as_ref().unwrap();
+            assert_eq!(INTERNAL_ERROR, error.code);
+            assert_eq!("Response too large", error.message);
+            let size = 4 * 1024 * 1024 + 36;
+            assert_eq!(
+                Some(json!({"size": size, "limit": 1024 * 1024})),
+                error.data
+            );
+        };
+
+        let huge =
+            r#"{"jsonrpc":"2.0","method":"huge","params":[4194304],"id":1}"#;
+        check(&server.serve_str(huge, &()).unwrap());
+        let bytes = server.serve_slice(huge.as_bytes(), &()).unwrap();
+        check(std::str::from_utf8(&bytes).unwrap());
+
+        let mut output = Vec::new();
+        let input = format!("{}\n", huge);
+        let shutdown = crate::shutdown::ShutdownHandle::new();
+        crate::shutdown::serve_lines(
+            &server,
+            &(),
+            input.as_bytes(),
+            &mut output,
+            &shutdown,
+        )
+        .unwrap();
+        check(std::str::from_utf8(&output).unwrap().trim_end());
+
+        let small = r#"{"jsonrpc":"2.0","method":"huge","params":[3],"id":1}"#;
+        let response: Response =
+            serde_json::from_str(&server.serve_str(small, &()).unwrap())
+                .unwrap();
+        assert_eq!(&Some(json!("xxx")), response.result());
+    }
+}
@@ -0,0 +1,368 @@
+//! Requests and responses with typed parameters and results.
+//!
+//! A [TypedRequest](TypedRequest) serializes its parameters once when
+//! it is created and a [TypedResponse](TypedResponse) deserializes the
+//! result when it is converted from a [Response](crate::Response):
+//!
+//! ```
+//! use json_rpc2::{typed::{TypedRequest, TypedResponse}, Response};
+//! use serde::{Deserialize, Serialize};
+//! use std::convert::TryFrom;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct SumParams {
+//!     a: u64,
+//!     b: u64,
+//! }
+//!
+//! let request = TypedRequest::new("sum", SumParams { a: 1, b: 2 })?;
+//! let params = request.params()?;
+//! let response: Response =
+//!     (request.as_request(), serde_json::json!(params.a + params.b)).into();
+//! let response = TypedResponse::<u64>::try_from(response)?;
+//! assert_eq!(&3, response.result());
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+//!
+//! When both ends are written in Rust declare each method once as a
+//! [Method](Method) constant and use it to create the service and to
+//! call the method so the name and types cannot drift apart:
+//!
+//! ```
+//! use json_rpc2::{
+//!     client::{Client, Transport},
+//!     typed::Method,
+//!     Request, Response, Result, Server,
+//! };
+//! use serde::{Deserialize, Serialize};
+//! use std::sync::Arc;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct SumParams {
+//!     a: u64,
+//!     b: u64,
+//! }
+//!
+//! const SUM: Method<SumParams, u64> = Method::new("sum");
+//!
+//! fn sum(params: SumParams, _: &()) -> Result<u64> {
+//!     Ok(params.a + params.b)
+//! }
+//!
+//! struct Local(Server<'static, ()>);
+//! impl Transport for Local {
+//!     fn send(&self, request: &Request) -> Result<Option<Response>> {
+//!         Ok(self.0.serve(request, &()))
+//!     }
+//! }
+//!
+//! let server = Server::new_shared(vec![Arc::new(SUM.service(sum))]);
+//! let client = Client::new(Local(server));
+//! assert_eq!(3, client.call_method(SUM, SumParams { a: 1, b: 2 })?);
+//! # Ok::<(), json_rpc2::Error>(())
+//! ```
+
+use crate::{method, Error, Request, Response, Result, VERSION};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{borrow::Cow, convert::TryFrom, marker::PhantomData};
+
+/// Request with parameters of type `P`.
+#[derive(Debug)]
+pub struct TypedRequest<P> {
+    request: Request,
+    marker: PhantomData<fn() -> P>,
+}
+
+impl<P> Clone for TypedRequest<P> {
+    fn clone(&self) -> Self {
+        Self {
+            request: self.request.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<P: Serialize> TypedRequest<P> {
+    /// Create a new request that expects a reply.
+    ///
+    /// A random number is generated for the message id. If the
+    /// parameters cannot be serialized this will return
+    /// `Error::InvalidParams`.
+    pub fn new(method: &str, params: P) -> Result<Self> {
+        let mut request = Request::new_reply(method, None);
+        let params =
+            serde_json::to_value(params).map_err(|e| Error::InvalidParams {
+                id: request.id().clone(),
+                data: e.to_string().into(),
+            })?;
+        *request.params_mut() = Some(params);
+        Ok(Self {
+            request,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<P> TypedRequest<P> {
+    /// The id for the request.
+    pub fn id(&self) -> &Option<Value> {
+        self.request.id()
+    }
+
+    /// The request service method name.
+    pub fn method(&self) -> &str {
+        self.request.method()
+    }
+
+    /// The underlying request.
+    pub fn as_request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Convert into the underlying request.
+    pub fn into_request(self) -> Request {
+        self.request
+    }
+}
+
+impl<P: DeserializeOwned> TypedRequest<P> {
+    /// Deserialize the parameters.
+    pub fn params(&self) -> Result<P> {
+        self.request.deserialize()
+    }
+}
+
+impl<P: DeserializeOwned> TryFrom<Request> for TypedRequest<P> {
+    type Error = Error;
+
+    /// Validate that the parameters deserialize to `P`, otherwise
+    /// this will return `Error::InvalidParams`.
+    fn try_from(request: Request) -> Result<Self> {
+        request.deserialize::<P>()?;
+        Ok(Self {
+            request,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<P> From<TypedRequest<P>> for Request {
+    fn from(request: TypedRequest<P>) -> Self {
+        request.request
+    }
+}
+
+/// Response with a result of type `R`.
+#[derive(Debug, Clone)]
+pub struct TypedResponse<R> {
+    id: Option<Value>,
+    result: R,
+}
+
+impl<R> TypedResponse<R> {
+    /// Create a response to a request.
+    pub fn new(request: &Request, result: R) -> Self {
+        Self {
+            id: request.id().clone(),
+            result,
+        }
+    }
+
+    /// The id for the response.
+    pub fn id(&self) -> &Option<Value> {
+        &self.id
+    }
+
+    /// The result of the call.
+    pub fn result(&self) -> &R {
+        &self.result
+    }
+
+    /// Consume the response and take the result.
+    pub fn into_result(self) -> R {
+        self.result
+    }
+}
+
+impl<R: DeserializeOwned> TryFrom<Response> for TypedResponse<R> {
+    type Error = Error;
+
+    /// An error response yields `Error::Rpc` and a result that cannot
+    /// be converted to `R` yields an internal error.
+    fn try_from(response: Response) -> Result<Self> {
+        let id = response.id().clone();
+        let value = response.into_result()?;
+        let result = serde_json::from_value(value)
+            .map_err(|e| Error::from(Box::from(e)))?;
+        Ok(Self { id, result })
+    }
+}
+
+impl<R: Serialize> TryFrom<TypedResponse<R>> for Response {
+    type Error = Error;
+
+    /// A result that cannot be serialized yields an internal error.
+    fn try_from(response: TypedResponse<R>) -> Result<Self> {
+        let result = serde_json::to_value(response.result)
+            .map_err(|e| Error::from(Box::from(e)))?;
+        Ok(Response {
+            jsonrpc: Cow::Borrowed(VERSION),
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+            id: response.id,
+            result: Some(result),
+            error: None,
+        })
+    }
+}
+
+/// Name of a method with the types of its parameters and result.
+pub struct Method<P, R> {
+    name: &'static str,
+    marker: PhantomData<fn(P) -> R>,
+}
+
+impl<P, R> Clone for Method<P, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P, R> Copy for Method<P, R> {}
+
+impl<P, R> std::fmt::Debug for Method<P, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Method").field(&self.name).finish()
+    }
+}
+
+impl<P, R> Method<P, R> {
+    /// Declare a method.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            marker: PhantomData,
+        }
+    }
+
+    /// The method name.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<P: Serialize, R> Method<P, R> {
+    /// Create a request to call the method.
+    ///
+    /// See [TypedRequest::new()](TypedRequest::new).
+    pub fn request(&self, params: P) -> Result<TypedRequest<P>> {
+        TypedRequest::new(self.name, params)
+    }
+}
+
+impl<P: DeserializeOwned, R: Serialize> Method<P, R> {
+    /// Create a service that calls `handler` for the method.
+    ///
+    /// See [method()](crate::method::method).
+    pub fn service<F, T>(&self, handler: F) -> method::Method<F, P, R, T>
+    where
+        F: Fn(P, &T) -> Result<R> + Send + Sync,
+    {
+        method::method(self.name, handler)
+    }
+
+    /// Create an async service that calls `handler` for the method.
+    ///
+    /// Only available with the `async` feature.
+    #[cfg(any(test, feature = "async"))]
+    pub fn async_service<F, T>(
+        &self,
+        handler: F,
+    ) -> method::AsyncMethod<F, P, R, T>
+    where
+        F: for<'a> method::AsyncHandler<'a, P, T, R>,
+    {
+        method::async_method(self.name, handler)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RpcError;
+    use serde::{Deserialize, Serializer};
+    use serde_json::json;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct SumParams {
+        a: u64,
+        b: u64,
+    }
+
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(
+            &self,
+            _serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unserializable"))
+        }
+    }
+
+    #[test]
+    fn typed_request() -> Result<()> {
+        let request = TypedRequest::new("sum", SumParams { a: 1, b: 2 })?;
+        assert_eq!("sum", request.method());
+        assert!(request.id().is_some());
+        assert_eq!(SumParams { a: 1, b: 2 }, request.params()?);
+
+        let untyped: Request = request.into();
+        assert_eq!(&Some(json!({"a": 1, "b": 2})), untyped.params());
+        let request = TypedRequest::<SumParams>::try_from(untyped)?;
+        assert_eq!(SumParams { a: 1, b: 2 }, request.params()?);
+
+        let untyped = Request::new_reply("sum", Some(json!({"a": 1})));
+        assert!(matches!(
+            TypedRequest::<SumParams>::try_from(untyped),
+            Err(Error::InvalidParams { .. })
+        ));
+        assert!(matches!(
+            TypedRequest::new("sum", Unserializable),
+            Err(Error::InvalidParams { id: Some(_), .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn typed_response() -> Result<()> {
+        let request = Request::new_reply("sum", None);
+        let response: Response = (&request, json!(3)).into();
+        let response = TypedResponse::<u64>::try_from(response)?;
+        assert_eq!(request.id(), response.id());
+        assert_eq!(3, response.into_result());
+
+        let response = TypedResponse::new(&request, 3u64);
+        let response = Response::try_from(response)?;
+        assert_eq!(request.id(), response.id());
+        assert_eq!(&Some(json!(3)), response.result());
+
+        let response: Response = (&request, json!("three")).into();
+        assert!(matches!(
+            TypedResponse::<u64>::try_from(response),
+            Err(Error::Boxed(_))
+        ));
+
+        let error = RpcError::new("Failed".to_string(), None);
+        let response: Response = (&request, error).into();
+        assert!(matches!(
+            TypedResponse::<u64>::try_from(response),
+            Err(Error::Rpc(_))
+        ));
+
+        let response = TypedResponse::new(&request, Unserializable);
+        assert!(matches!(Response::try_from(response), Err(Error::Boxed(_))));
+        Ok(())
+    }
+}